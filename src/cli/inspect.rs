@@ -0,0 +1,115 @@
+use std::io::Write;
+
+use crate::parser::{Data, Elem, ExportDesc, ImportDesc, Module, Mutable};
+
+fn mutability(mutable: &Mutable) -> &'static str {
+    match mutable {
+        Mutable::Const => "const",
+        Mutable::Var => "mut",
+    }
+}
+
+/// Writes a readable summary of `module`, section by section.
+pub fn inspect(module: &Module, out: &mut impl Write) -> std::io::Result<()> {
+    let types = &module.types.function_types;
+    let type_of = |idx: u32| {
+        types
+            .get(idx as usize)
+            .map_or_else(|| format!("<unknown type {idx}>"), |t| t.to_string())
+    };
+
+    writeln!(out, "types ({}):", types.len())?;
+    for (i, t) in types.iter().enumerate() {
+        writeln!(out, "  {i}: {t}")?;
+    }
+
+    writeln!(out, "imports ({}):", module.imports.imports.len())?;
+    for import in &module.imports.imports {
+        let desc = match &import.desc {
+            ImportDesc::Func(idx) => format!("func {}", type_of(idx.0)),
+            ImportDesc::Table(t) => format!("table {} {:?}", t.lim, t.et),
+            ImportDesc::Mem(m) => format!("memory {}", m.0),
+            ImportDesc::Global(g) => format!("global {} {}", mutability(&g.mutable), g.t),
+        };
+        writeln!(out, "  {}.{}: {desc}", import.module.0, import.name.0)?;
+    }
+
+    let imported_funcs = module
+        .imports
+        .imports
+        .iter()
+        .filter(|i| matches!(i.desc, ImportDesc::Func(_)))
+        .count();
+    writeln!(out, "functions ({}):", module.funcs.functions.len())?;
+    for (i, (idx, code)) in module
+        .funcs
+        .functions
+        .iter()
+        .zip(&module.code.code)
+        .enumerate()
+    {
+        let locals = code.code.t.iter().map(|l| l.n as u64).sum::<u64>();
+        writeln!(
+            out,
+            "  {}: {}, {locals} locals, {} instructions",
+            i + imported_funcs,
+            type_of(idx.0),
+            code.code.e.instrs.len()
+        )?;
+    }
+
+    writeln!(out, "tables ({}):", module.tables.tables.len())?;
+    for (i, t) in module.tables.tables.iter().enumerate() {
+        writeln!(out, "  {i}: {} {:?}", t.lim, t.et)?;
+    }
+
+    writeln!(out, "memories ({}):", module.mems.mems.len())?;
+    for (i, m) in module.mems.mems.iter().enumerate() {
+        writeln!(out, "  {i}: {} pages", m.limits)?;
+    }
+
+    writeln!(out, "globals ({}):", module.globals.globals.len())?;
+    for (i, g) in module.globals.globals.iter().enumerate() {
+        writeln!(out, "  {i}: {} {}", mutability(&g.gt.mutable), g.gt.t)?;
+    }
+
+    let mut exports = module.exports.exports.iter().collect::<Vec<_>>();
+    exports.sort_by_key(|e| e.1);
+    writeln!(out, "exports ({}):", exports.len())?;
+    for (name, desc) in exports {
+        let desc = match desc {
+            ExportDesc::Func(idx) => format!("func {}", idx.0),
+            ExportDesc::Table(idx) => format!("table {}", idx.0),
+            ExportDesc::Mem(idx) => format!("memory {}", idx.0),
+            ExportDesc::Global(idx) => format!("global {}", idx.0),
+        };
+        writeln!(out, "  {name}: {desc}")?;
+    }
+
+    if let Some(start) = module.start {
+        writeln!(out, "start: func {start}")?;
+    }
+
+    writeln!(out, "elements ({}):", module.elems.elems.len())?;
+    for (i, e) in module.elems.elems.iter().enumerate() {
+        let (mode, len) = match e {
+            Elem::E0(_, f) | Elem::E2(_, _, _, f) => ("active", f.len()),
+            Elem::E1(_, f) => ("passive", f.len()),
+            Elem::E3(_, f) => ("declarative", f.len()),
+            Elem::E4(_, e) | Elem::E6(_, _, _, e) => ("active", e.len()),
+            Elem::E5(_, e) => ("passive", e.len()),
+            Elem::E7(_, e) => ("declarative", e.len()),
+        };
+        writeln!(out, "  {i}: {mode}, {len} entries")?;
+    }
+
+    writeln!(out, "data ({}):", module.datas.data.len())?;
+    for (i, d) in module.datas.data.iter().enumerate() {
+        let (mode, len) = match d {
+            Data::Active(_, b) | Data::ActiveX(_, _, b) => ("active", b.len()),
+            Data::Passive(b) => ("passive", b.len()),
+        };
+        writeln!(out, "  {i}: {mode}, {len} bytes")?;
+    }
+    Ok(())
+}
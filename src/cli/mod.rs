@@ -0,0 +1,282 @@
+use crate::fuzz::Target;
+use std::path::PathBuf;
+mod inspect;
+pub use inspect::inspect;
+
+pub const USAGE: &str = "\
+usage: wasp [--log FILTER] <command> [options]
+
+commands:
+    run <module.wasm> [options] [-- args...]
+        run `main`/`_start`, or the export given to --invoke
+    debug <module.wasm> [options] [-- args...]
+        like run, but stop before the first instruction and take debugger
+        commands from stdin, `help` lists them
+    validate <module.wasm> [options]
+        parse and instantiate the module without running it
+    preinit <module.wasm> <out.wasm> [options]
+        run the module's init export, wizer.initialize or the one given to
+        --init, and write a module starting out with the memory and globals
+        it left to out.wasm
+    inspect <module.wasm>
+        list the sections, imports and exports of the module
+    test <file.wast|dir>... [--json FILE] [--junit FILE]
+        run spec test scripts, every .wast under a directory, and report
+        what passed, failed and was skipped
+    fuzz [parse|load|run] [iterations] [seed]
+        fuzz the parser, the loader or the interpreter
+    help
+        show this message
+
+`wasp <module.wasm>` and `wasp <file.wast>` are short for `run` and `test`.
+
+options:
+    --invoke NAME            call the export NAME with the arguments after `--`
+                             and print its results, arguments are read as the
+                             parameter types: `-1`, `0xff`, `0x1p-3`, `inf`, `nan:0x1`
+    --dir HOST[::GUEST]      preopen the directory HOST, seen by WASI as GUEST
+    --env KEY[=VALUE]        set a WASI environment variable, without a value
+                             it is taken from the host
+    --preload NAME=PATH      make the module at PATH importable as NAME
+    --fuel N                 trap after N instructions
+    --profile FILE           count the instructions each call stack runs and
+                             write them to FILE as folded stacks for flamegraph
+                             tools, host call time to FILE.host, and print a
+                             table of them per function
+    --coverage FILE          count which instructions and branches run and write
+                             them to FILE, as LCOV if the module has DWARF line
+                             info and as JSON by function and offset otherwise
+    --strace                 log every call into the host to stderr, like strace,
+                             with the arguments of WASI calls decoded
+    --snapshot FILE          when the fuel runs out, save the program's memories,
+                             globals, tables and call stack to FILE
+    --restore FILE           resume the program saved to FILE by --snapshot
+                             instead of starting it, open files are not restored
+    --deterministic          give every float operation resulting in NaN the
+                             canonical NaN, and make the WASI clocks and random
+                             bytes virtual, so that runs repeat bit for bit
+    --init NAME              the export `preinit` runs
+    --gdb ADDR               make `debug` wait for gdb or lldb on the TCP
+                             address ADDR, like `localhost:1234`, or talk to it
+                             over stdin and stdout for `-`
+    --json FILE              write the results of `test` to FILE as JSON
+    --junit FILE             write the results of `test` to FILE as JUnit XML
+    --log FILTER             set the log filter, like RUST_LOG
+
+Without --invoke the arguments after `--` are passed to the program as argv.";
+
+pub struct Cli {
+    pub log: Option<String>,
+    pub command: Command,
+}
+
+pub enum Command {
+    Run(RunOptions),
+    Debug(RunOptions),
+    Validate(RunOptions),
+    Preinit {
+        opts: RunOptions,
+        init: String,
+        output: PathBuf,
+    },
+    Inspect(PathBuf),
+    Test(TestOptions),
+    Fuzz {
+        targets: Vec<Target>,
+        iterations: u64,
+        seed: u64,
+    },
+    Help,
+}
+
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    pub path: PathBuf,
+    pub invoke: Option<String>,
+    pub args: Vec<String>,
+    pub dirs: Vec<(String, PathBuf)>,
+    pub env: Vec<(String, String)>,
+    pub preload: Vec<(String, PathBuf)>,
+    pub fuel: Option<u64>,
+    /// Where `run` writes the folded stacks of a profile.
+    pub profile: Option<PathBuf>,
+    /// Where `run` writes the coverage report.
+    pub coverage: Option<PathBuf>,
+    /// Whether `run` logs the calls into the host.
+    pub strace: bool,
+    /// Where `run` saves the program when it runs out of fuel.
+    pub snapshot: Option<PathBuf>,
+    /// The snapshot `run` resumes instead of starting the program.
+    pub restore: Option<PathBuf>,
+    /// Whether NaNs, clocks and randomness are the same on every run.
+    pub deterministic: bool,
+    /// Where `debug` serves the GDB remote protocol instead of a prompt.
+    pub gdb: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct TestOptions {
+    pub paths: Vec<PathBuf>,
+    pub json: Option<PathBuf>,
+    pub junit: Option<PathBuf>,
+}
+
+/// Splits `--name=value` and `--name value` forms, taking the value from
+/// `rest` when it is not attached.
+fn value(
+    arg: &str,
+    name: &str,
+    rest: &mut impl Iterator<Item = String>,
+) -> Option<Result<String, String>> {
+    let tail = arg.strip_prefix(name)?;
+    Some(if let Some(v) = tail.strip_prefix('=') {
+        Ok(v.to_string())
+    } else if tail.is_empty() {
+        rest.next().ok_or_else(|| format!("{name} expects a value"))
+    } else {
+        return None;
+    })
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+    let mut rest = args.into_iter();
+    let mut log = None;
+    let mut command = None;
+    let mut opts = RunOptions::default();
+    let mut test = TestOptions::default();
+    let mut positional = Vec::new();
+    let mut init = None;
+
+    while let Some(arg) = rest.next() {
+        if arg == "--" {
+            opts.args.extend(&mut rest);
+        } else if let Some(v) = value(&arg, "--log", &mut rest) {
+            log = Some(v?);
+        } else if let Some(v) = value(&arg, "--invoke", &mut rest) {
+            opts.invoke = Some(v?);
+        } else if let Some(v) = value(&arg, "--fuel", &mut rest) {
+            let v = v?;
+            let fuel = v
+                .parse()
+                .map_err(|_| format!("--fuel expects a number, got {v:?}"))?;
+            opts.fuel = Some(fuel);
+        } else if let Some(v) = value(&arg, "--profile", &mut rest) {
+            opts.profile = Some(v?.into());
+        } else if let Some(v) = value(&arg, "--coverage", &mut rest) {
+            opts.coverage = Some(v?.into());
+        } else if let Some(v) = value(&arg, "--snapshot", &mut rest) {
+            opts.snapshot = Some(v?.into());
+        } else if let Some(v) = value(&arg, "--restore", &mut rest) {
+            opts.restore = Some(v?.into());
+        } else if let Some(v) = value(&arg, "--init", &mut rest) {
+            init = Some(v?);
+        } else if let Some(v) = value(&arg, "--gdb", &mut rest) {
+            opts.gdb = Some(v?);
+        } else if let Some(v) = value(&arg, "--dir", &mut rest) {
+            let v = v?;
+            let (host, guest) = v.split_once("::").unwrap_or((&v, &v));
+            opts.dirs.push((guest.to_string(), PathBuf::from(host)));
+        } else if let Some(v) = value(&arg, "--env", &mut rest) {
+            let v = v?;
+            let (key, val) = match v.split_once('=') {
+                Some((key, val)) => (key.to_string(), val.to_string()),
+                None => (v.clone(), std::env::var(&v).unwrap_or_default()),
+            };
+            opts.env.push((key, val));
+        } else if let Some(v) = value(&arg, "--preload", &mut rest) {
+            let v = v?;
+            let (name, path) = v
+                .split_once('=')
+                .ok_or_else(|| format!("--preload expects NAME=PATH, got {v:?}"))?;
+            opts.preload.push((name.to_string(), PathBuf::from(path)));
+        } else if let Some(v) = value(&arg, "--json", &mut rest) {
+            test.json = Some(v?.into());
+        } else if let Some(v) = value(&arg, "--junit", &mut rest) {
+            test.junit = Some(v?.into());
+        } else if arg == "--strace" {
+            opts.strace = true;
+        } else if arg == "--deterministic" {
+            opts.deterministic = true;
+        } else if matches!(&*arg, "-h" | "--help") {
+            command = Some("help".to_string());
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option {arg:?}"));
+        } else if command.is_none() && positional.is_empty() {
+            command = Some(arg);
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    let mut command = command.unwrap_or_else(|| "help".to_string());
+    if command.ends_with(".wasm") || command.ends_with(".wast") {
+        positional.insert(0, command.clone());
+        command = if command.ends_with(".wast") {
+            "test"
+        } else {
+            "run"
+        }
+        .to_string();
+    }
+    let mut positional = positional.into_iter();
+    let file = positional.next();
+    let path = |what: &str| file.clone().ok_or_else(|| format!("{what} expects a file"));
+
+    if opts.restore.is_some() && opts.invoke.is_some() {
+        return Err("--restore resumes a program, it can't be combined with --invoke".into());
+    }
+    let command = match &*command {
+        "run" => {
+            opts.path = path("run")?.into();
+            Command::Run(opts)
+        }
+        "debug" => {
+            opts.path = path("debug")?.into();
+            Command::Debug(opts)
+        }
+        "validate" => {
+            opts.path = path("validate")?.into();
+            Command::Validate(opts)
+        }
+        "preinit" => {
+            opts.path = path("preinit")?.into();
+            Command::Preinit {
+                opts,
+                init: init.unwrap_or_else(|| crate::preinit::DEFAULT_INIT.to_string()),
+                output: positional
+                    .next()
+                    .ok_or("preinit expects a file to write")?
+                    .into(),
+            }
+        }
+        "inspect" => Command::Inspect(path("inspect")?.into()),
+        "test" => {
+            test.paths.push(path("test")?.into());
+            test.paths.extend(positional.map(PathBuf::from));
+            Command::Test(test)
+        }
+        "fuzz" => {
+            let mut targets = Target::ALL.to_vec();
+            let mut nums = Vec::new();
+            for arg in file.clone().into_iter().chain(positional) {
+                if let Some(target) = Target::from_name(&arg) {
+                    targets = vec![target];
+                } else {
+                    nums.push(
+                        arg.parse()
+                            .map_err(|_| format!("fuzz expects a number, got {arg:?}"))?,
+                    );
+                }
+            }
+            let mut nums = nums.into_iter();
+            Command::Fuzz {
+                targets,
+                iterations: nums.next().unwrap_or(100_000),
+                seed: nums.next().unwrap_or(0x5eed),
+            }
+        }
+        "help" => Command::Help,
+        other => return Err(format!("unknown command {other:?}")),
+    };
+    Ok(Cli { log, command })
+}
@@ -0,0 +1,384 @@
+//! `wasp run --coverage`: counts how often each instruction runs and which
+//! way each `br_if` and `if` goes. Modules with DWARF line info are
+//! reported as LCOV, others as JSON by function and code offset.
+
+use crate::{
+    parser::Instr,
+    runtime::{
+        clean_model::Function, function_label, instance_name, Instance, Observer, Runtime, Site,
+        Value, MAIN_MODULE,
+    },
+};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{self, Write},
+};
+
+/// What ran of one function.
+#[derive(Debug, Default)]
+struct FuncCoverage {
+    calls: u64,
+    /// Hits by pc, the function's final `end` last.
+    hits: Vec<u64>,
+    /// By the pc of each `br_if` and `if` that ran, how often its
+    /// condition held and how often not.
+    branches: BTreeMap<usize, [u64; 2]>,
+}
+
+/// Coverage of everything a [`Runtime`] ran, by instance and function.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    instances: HashMap<String, HashMap<u32, FuncCoverage>>,
+}
+
+/// A function's coverage by code offset, from the start of the code
+/// section's contents as in DWARF. Several instructions of the flattened
+/// code can share an offset, as an `if` and the start of its then branch
+/// do, and count as the one that ran the most.
+struct Report<'c> {
+    index: u32,
+    name: Option<&'c str>,
+    calls: u64,
+    hits: BTreeMap<u32, u64>,
+    /// By offset: whether the function ever ran, and the branch counts.
+    branches: BTreeMap<u32, Option<[u64; 2]>>,
+}
+
+impl Observer for Coverage {
+    fn before_instruction(&mut self, rt: &Runtime, site: Site, instr: &Instr) {
+        let func = self.func(rt, site);
+        if let Some(hit) = func.hits.get_mut(site.pc) {
+            *hit += 1;
+        }
+        if let Instr::x0d_br_if(_) | Instr::if_then_else(_) = instr {
+            let top = rt.stack.last().and_then(|f| f.stack.last());
+            let held = !matches!(top, Some(Value::I32(0)));
+            func.branches.entry(site.pc).or_default()[!held as usize] += 1;
+        }
+    }
+
+    fn on_call(&mut self, rt: &Runtime, site: Site) {
+        self.func(rt, site).calls += 1;
+    }
+
+    fn on_return(&mut self, rt: &Runtime, site: Site, _: &[Value]) {
+        // falling off the end runs the function's final `end`, which is no
+        // instruction of its own
+        let func = self.func(rt, site);
+        if site.pc + 1 == func.hits.len() {
+            func.hits[site.pc] += 1;
+        }
+    }
+}
+
+impl Coverage {
+    fn func(&mut self, rt: &Runtime, site: Site) -> &mut FuncCoverage {
+        let instance = self.instances.entry(site.instance.to_string()).or_default();
+        instance.entry(site.func).or_insert_with(|| {
+            let len = match rt
+                .instance(site.instance)
+                .ok()
+                .and_then(|i| i.function(site.func))
+            {
+                Some(Function::WS { code, .. }) => code.len() + 1,
+                _ => 0,
+            };
+            FuncCoverage {
+                hits: vec![0; len],
+                ..FuncCoverage::default()
+            }
+        })
+    }
+
+    /// The coverage of every function defined in the instance `name`,
+    /// those that never ran included.
+    fn reports<'c>(&self, name: &str, instance: &Instance<'c>) -> Vec<Report<'c>> {
+        let none = HashMap::new();
+        let ran = self.instances.get(name).unwrap_or(&none);
+        (0..instance.functions() as u32)
+            .filter(|&index| !instance.imports_function(index))
+            .filter_map(|index| {
+                let Some(Function::WS { code, offsets, .. }) = instance.function(index) else {
+                    return None;
+                };
+                let func = ran.get(&index);
+                let mut report = Report {
+                    index,
+                    name: instance.function_name(index),
+                    calls: func.map_or(0, |f| f.calls),
+                    hits: BTreeMap::new(),
+                    branches: BTreeMap::new(),
+                };
+                for (pc, &offset) in offsets.iter().enumerate() {
+                    let hits = func.and_then(|f| f.hits.get(pc)).copied().unwrap_or(0);
+                    let count = report.hits.entry(offset).or_default();
+                    *count = (*count).max(hits);
+                    if let Some(Instr::x0d_br_if(_) | Instr::if_then_else(_)) = code.get(pc) {
+                        let taken = func.map(|f| f.branches.get(&pc).copied().unwrap_or_default());
+                        report.branches.insert(offset, taken);
+                    }
+                }
+                Some(report)
+            })
+            .collect()
+    }
+
+    /// Whether the report should be LCOV, which it is when the main
+    /// module has line info to give the source positions.
+    pub fn has_lines(rt: &Runtime) -> bool {
+        rt.instance(MAIN_MODULE).is_ok_and(|i| i.has_lines())
+    }
+
+    /// Writes LCOV tracefile records for every source file the instances
+    /// with line info have code from.
+    pub fn write_lcov(&self, rt: &Runtime, out: &mut impl Write) -> io::Result<()> {
+        #[derive(Default)]
+        struct File {
+            /// The first line and calls of each function.
+            functions: Vec<(u32, String, u64)>,
+            lines: BTreeMap<u32, u64>,
+            branches: Vec<(u32, Option<[u64; 2]>)>,
+        }
+        let mut files: BTreeMap<String, File> = BTreeMap::new();
+        let mut names: Vec<_> = rt.instances().collect();
+        names.sort();
+        for name in names {
+            let Ok(instance) = rt.instance(name) else {
+                continue;
+            };
+            if !instance.has_lines() {
+                continue;
+            }
+            for report in self.reports(name, &instance) {
+                let Some(Function::WS { offsets, .. }) = instance.function(report.index) else {
+                    continue;
+                };
+                let mut first = None;
+                let mut seen = HashSet::new();
+                for (pc, offset) in offsets.iter().enumerate() {
+                    if !seen.insert(offset) {
+                        continue;
+                    }
+                    // line 0 is code the compiler made up, with no source
+                    let Some(location) =
+                        instance.location(report.index, pc).filter(|l| l.line != 0)
+                    else {
+                        continue;
+                    };
+                    let file = files.entry(location.file.to_string()).or_default();
+                    let hits = report.hits.get(offset).copied().unwrap_or(0);
+                    let line = file.lines.entry(location.line).or_default();
+                    *line = (*line).max(hits);
+                    if let Some(&taken) = report.branches.get(offset) {
+                        file.branches.push((location.line, taken));
+                    }
+                    first = first.or(Some((location.file.to_string(), location.line)));
+                }
+                if let Some((path, line)) = first {
+                    let name = function_label(name, report.index, report.name);
+                    files
+                        .entry(path)
+                        .or_default()
+                        .functions
+                        .push((line, name, report.calls));
+                }
+            }
+        }
+
+        writeln!(out, "TN:")?;
+        for (path, file) in files {
+            writeln!(out, "SF:{path}")?;
+            for (line, name, _) in &file.functions {
+                writeln!(out, "FN:{line},{name}")?;
+            }
+            for (_, name, calls) in &file.functions {
+                writeln!(out, "FNDA:{calls},{name}")?;
+            }
+            let hit = file.functions.iter().filter(|f| f.2 > 0).count();
+            writeln!(out, "FNF:{}\nFNH:{hit}", file.functions.len())?;
+            for (block, (line, taken)) in file.branches.iter().enumerate() {
+                // a function that never ran has no branch counts at all
+                for branch in 0..2 {
+                    match taken {
+                        Some(taken) => {
+                            writeln!(out, "BRDA:{line},{block},{branch},{}", taken[branch])?
+                        }
+                        None => writeln!(out, "BRDA:{line},{block},{branch},-")?,
+                    }
+                }
+            }
+            let taken = file
+                .branches
+                .iter()
+                .flat_map(|(_, t)| t.unwrap_or_default())
+                .filter(|&c| c > 0)
+                .count();
+            writeln!(out, "BRF:{}\nBRH:{taken}", file.branches.len() * 2)?;
+            for (line, hits) in &file.lines {
+                writeln!(out, "DA:{line},{hits}")?;
+            }
+            let hit = file.lines.values().filter(|&&h| h > 0).count();
+            writeln!(out, "LF:{}\nLH:{hit}", file.lines.len())?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the hits of every instruction and branch by instance,
+    /// function and code offset as JSON.
+    pub fn write_json(&self, rt: &Runtime, out: &mut impl Write) -> io::Result<()> {
+        let mut names: Vec<_> = rt.instances().collect();
+        names.sort();
+        let instances = names
+            .into_iter()
+            .filter_map(|name| Some((name, rt.instance(name).ok()?)))
+            .map(|(name, instance)| {
+                let functions = self
+                    .reports(name, &instance)
+                    .into_iter()
+                    .map(|r| {
+                        let covered = r.hits.values().filter(|&&h| h > 0).count();
+                        let instructions = r
+                            .hits
+                            .iter()
+                            .map(|(offset, count)| json!({ "offset": offset, "count": count }))
+                            .collect::<Vec<_>>();
+                        let branches = r
+                            .branches
+                            .iter()
+                            .map(|(offset, taken)| {
+                                let [taken, not_taken] = taken.unwrap_or_default();
+                                json!({
+                                    "offset": offset,
+                                    "taken": taken,
+                                    "not_taken": not_taken,
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        json!({
+                            "index": r.index,
+                            "name": r.name,
+                            "calls": r.calls,
+                            "covered": covered,
+                            "total": r.hits.len(),
+                            "instructions": instructions,
+                            "branches": branches,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({ "name": instance_name(name), "functions": functions })
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_writer_pretty(&mut *out, &json!({ "instances": instances }))?;
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wast::text_module;
+    use serde_json::Value as Json;
+
+    /// Counts up to its argument, which takes the loop's `br_if` all but
+    /// once, then tells with an `if` whether that was 3. `early` returns
+    /// before its final `end`.
+    const BRANCHES: &str = r#"(module
+        (func (export "count") (param i32) (result i32)
+            (local i32)
+            (loop $l
+                (local.set 1 (i32.add (local.get 1) (i32.const 1)))
+                (br_if $l (i32.lt_u (local.get 1) (local.get 0))))
+            (if (result i32) (i32.eq (local.get 1) (i32.const 3))
+                (then (i32.const 1))
+                (else (i32.const 0))))
+        (func (export "early") (result i32)
+            (return (i32.const 0)))
+        (func (export "never")))"#;
+
+    fn covered(rt: &mut Runtime, calls: &[(&str, &[Value])]) -> Coverage {
+        // the program's own `main`, not yet run, is not what is measured
+        rt.stack.clear();
+        rt.observe(Coverage::default());
+        for (name, args) in calls {
+            rt.invoke(name, args).expect("the call returns");
+        }
+        rt.take_observers()
+            .pop()
+            .and_then(|o| (o as Box<dyn std::any::Any>).downcast().ok())
+            .map(|c: Box<Coverage>| *c)
+            .expect("the coverage is still there")
+    }
+
+    #[test]
+    fn json_has_instruction_and_branch_counts() {
+        let mut rt = Runtime::builder()
+            .add_module(MAIN_MODULE, &text_module(BRANCHES))
+            .build()
+            .expect("the runtime builds");
+        let coverage = covered(
+            &mut rt,
+            &[
+                ("count", &[Value::I32(3)]),
+                ("count", &[Value::I32(1)]),
+                ("early", &[]),
+            ],
+        );
+        assert!(!Coverage::has_lines(&rt));
+        let mut out = Vec::new();
+        coverage
+            .write_json(&rt, &mut out)
+            .expect("writing to memory works");
+        let json: Json = serde_json::from_slice(&out).expect("the report is JSON");
+        let functions = &json["instances"][0]["functions"];
+        let [count, early, never] = [0, 1, 2].map(|i| &functions[i]);
+        assert_eq!(count["calls"], 2);
+        assert_eq!(
+            count["branches"],
+            serde_json::json!([
+                { "offset": 19, "taken": 2, "not_taken": 2 },
+                { "offset": 27, "taken": 1, "not_taken": 1 },
+            ])
+        );
+        let counts = |f: &Json| -> Vec<u64> {
+            (f["instructions"].as_array().into_iter().flatten())
+                .filter_map(|i| i["count"].as_u64())
+                .collect()
+        };
+        // the loop's body ran four times, the two arms of the `if` once
+        // each, and both calls fell off the final `end`
+        assert_eq!(
+            counts(count),
+            [4, 4, 4, 4, 4, 4, 4, 4, 4, 2, 2, 2, 2, 2, 1, 1, 1, 1, 2]
+        );
+        // `return` leaves before the final `end`
+        assert_eq!(early["calls"], 1);
+        assert_eq!(counts(early), [1, 1, 0]);
+        assert_eq!(
+            (&never["calls"], &never["covered"]),
+            (&Json::from(0), &Json::from(0))
+        );
+    }
+
+    #[test]
+    fn lcov_follows_the_line_info() {
+        let mut rt = Runtime::build("examples/debug_lines.wasm")
+            .build()
+            .expect("the runtime builds");
+        let coverage = covered(&mut rt, &[("main", &[])]);
+        assert!(Coverage::has_lines(&rt));
+        let mut out = Vec::new();
+        coverage
+            .write_lcov(&rt, &mut out)
+            .expect("writing to memory works");
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "TN:\n\
+             SF:src/add.c\nFN:8,main:$1\nFNDA:1,main:$1\nFNF:1\nFNH:1\nBRF:0\nBRH:0\n\
+             DA:8,1\nDA:9,1\nLF:2\nLH:2\nend_of_record\n\
+             SF:src/main.c\nFN:2,main\nFNDA:1,main\nFNF:1\nFNH:1\nBRF:0\nBRH:0\n\
+             DA:2,1\nDA:3,1\nDA:4,1\nLF:3\nLH:3\nend_of_record\n"
+        );
+    }
+}
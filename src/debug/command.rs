@@ -0,0 +1,229 @@
+use crate::runtime::literal::parse_i64;
+
+pub const HELP: &str = "\
+break FUNC[@PC]         stop before instruction PC (0 by default) of FUNC, a
+                        function index or export name, `INSTANCE:FUNC` for
+                        another instance than the main one
+delete N                remove breakpoint N
+watch memory ADDR [LEN] stop when any of the LEN bytes (1 by default) at ADDR
+                        change
+watch global G          stop when the global G, an index or export name,
+                        changes
+unwatch N               remove watchpoint N
+info                    list breakpoints and watchpoints
+step [N]                run N instructions (1 by default), into calls
+next                    run one instruction, over calls
+finish                  run until the current function returns
+continue                run until a breakpoint, watchpoint or the end
+stack                   show the operand stack of the current frame
+locals                  show the locals of the current frame
+blocks                  show the open blocks of the current frame
+backtrace               show the call stack
+x ADDR [LEN]            dump LEN bytes (64 by default) of memory at ADDR
+global G                show the global G
+list [FUNC]             show the code of FUNC, the current function by default
+quit                    stop debugging
+
+Commands can be shortened to their first letter where that is unambiguous
+(b, d, w, i, s, n, f, c, bt, l, q), an empty line repeats the last one.";
+
+/// A function as the user wrote it, resolved against an instance later.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FuncRef {
+    Index(u32),
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// The instance, the main module's when `None`.
+    pub instance: Option<String>,
+    pub func: FuncRef,
+    pub pc: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Break(Location),
+    Delete(usize),
+    WatchMemory { addr: usize, len: usize },
+    WatchGlobal(FuncRef),
+    Unwatch(usize),
+    Info,
+    Step(usize),
+    Next,
+    Finish,
+    Continue,
+    Stack,
+    Locals,
+    Blocks,
+    Backtrace,
+    Examine { addr: usize, len: usize },
+    Global(FuncRef),
+    List(Option<Location>),
+    Help,
+    Quit,
+}
+
+fn number(s: &str) -> Result<usize, String> {
+    parse_i64(s)
+        .filter(|n| *n >= 0)
+        .map(|n| n as usize)
+        .ok_or_else(|| format!("expected a number, got {s:?}"))
+}
+
+fn index_or_name(s: &str) -> FuncRef {
+    match number(s) {
+        Ok(i) => FuncRef::Index(i as u32),
+        Err(_) => FuncRef::Name(s.to_string()),
+    }
+}
+
+fn location(s: &str) -> Result<Location, String> {
+    let (instance, rest) = match s.split_once(':') {
+        Some((instance, rest)) => (Some(instance.to_string()), rest),
+        None => (None, s),
+    };
+    let (func, pc) = match rest.split_once('@') {
+        Some((func, pc)) => (func, number(pc)?),
+        None => (rest, 0),
+    };
+    Ok(Location {
+        instance,
+        func: index_or_name(func),
+        pc,
+    })
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let arg = |i: usize| {
+        words
+            .get(i)
+            .copied()
+            .ok_or_else(|| format!("{} expects more arguments, see `help`", words[0]))
+    };
+    let Some(&command) = words.first() else {
+        return Err("empty command".to_string());
+    };
+    Ok(match command {
+        "break" | "b" => Command::Break(location(arg(1)?)?),
+        "delete" | "d" => Command::Delete(number(arg(1)?)?),
+        "watch" | "w" => match arg(1)? {
+            "memory" | "m" => Command::WatchMemory {
+                addr: number(arg(2)?)?,
+                len: words.get(3).map_or(Ok(1), |l| number(l))?,
+            },
+            "global" | "g" => Command::WatchGlobal(index_or_name(arg(2)?)),
+            other => return Err(format!("can only watch memory or a global, not {other:?}")),
+        },
+        "unwatch" => Command::Unwatch(number(arg(1)?)?),
+        "info" | "i" => Command::Info,
+        "step" | "s" => match words.get(1).map_or(Ok(1), |n| number(n))? {
+            0 => return Err("step expects a count of at least 1".to_string()),
+            n => Command::Step(n),
+        },
+        "next" | "n" => Command::Next,
+        "finish" | "f" => Command::Finish,
+        "continue" | "c" => Command::Continue,
+        "stack" => Command::Stack,
+        "locals" => Command::Locals,
+        "blocks" => Command::Blocks,
+        "backtrace" | "bt" => Command::Backtrace,
+        "x" => Command::Examine {
+            addr: number(arg(1)?)?,
+            len: words.get(2).map_or(Ok(64), |l| number(l))?,
+        },
+        "global" => Command::Global(index_or_name(arg(1)?)),
+        "list" | "l" => Command::List(words.get(1).map(|f| location(f)).transpose()?),
+        "help" | "h" | "?" => Command::Help,
+        "quit" | "q" => Command::Quit,
+        other => return Err(format!("unknown command {other:?}, see `help`")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        assert_eq!(
+            parse("break 3"),
+            Ok(Command::Break(Location {
+                instance: None,
+                func: FuncRef::Index(3),
+                pc: 0,
+            }))
+        );
+        assert_eq!(
+            parse("b env:main@0x10"),
+            Ok(Command::Break(Location {
+                instance: Some("env".to_string()),
+                func: FuncRef::Name("main".to_string()),
+                pc: 16,
+            }))
+        );
+        assert_eq!(parse("list"), Ok(Command::List(None)));
+        assert!(parse("break f@pc").is_err());
+    }
+
+    #[test]
+    fn counts_and_lengths_default() {
+        assert_eq!(parse("step"), Ok(Command::Step(1)));
+        assert_eq!(parse("  s   5 "), Ok(Command::Step(5)));
+        assert_eq!(
+            parse("x 0x100"),
+            Ok(Command::Examine { addr: 256, len: 64 })
+        );
+        assert_eq!(parse("w m 8"), Ok(Command::WatchMemory { addr: 8, len: 1 }));
+        assert_eq!(
+            parse("watch memory 8 4"),
+            Ok(Command::WatchMemory { addr: 8, len: 4 })
+        );
+        assert_eq!(
+            parse("watch global counter"),
+            Ok(Command::WatchGlobal(FuncRef::Name("counter".to_string())))
+        );
+        assert_eq!(parse("global 2"), Ok(Command::Global(FuncRef::Index(2))));
+    }
+
+    #[test]
+    fn short_forms() {
+        let cases = [
+            ("i", Command::Info),
+            ("n", Command::Next),
+            ("f", Command::Finish),
+            ("c", Command::Continue),
+            ("bt", Command::Backtrace),
+            ("?", Command::Help),
+            ("q", Command::Quit),
+            ("d 2", Command::Delete(2)),
+            ("unwatch 1", Command::Unwatch(1)),
+        ];
+        for (line, command) in cases {
+            assert_eq!(parse(line), Ok(command), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn mistakes_are_errors() {
+        for line in [
+            "",
+            "step 0",
+            "step -1",
+            "delete",
+            "watch",
+            "watch table 1",
+            "x",
+            "x 0x10 many",
+            "frobnicate",
+        ] {
+            assert!(parse(line).is_err(), "{line:?}");
+        }
+        assert_eq!(
+            parse("step 0"),
+            Err("step expects a count of at least 1".to_string())
+        );
+    }
+}
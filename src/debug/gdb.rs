@@ -0,0 +1,573 @@
+//! A stub for the GDB remote serial protocol, so gdb, lldb or anything else
+//! that speaks it can drive [`Debugger`] instead of the prompt.
+//!
+//! Wasm has no registers, so the one register there is, the pc, is where the
+//! instruction about to run starts, as lldb expects it: a module id in the
+//! high 32 bits and the offset from the start of its code section, which is
+//! also what DWARF uses, in the low ones. The main instance is module 0, the
+//! other wasm instances follow in the order of their names. Breakpoints take
+//! pcs in the same form. Memory is the main instance's linear memory. Frames, locals, globals and operand stack values are read with
+//! lldb's `qWasmCallStack`, `qWasmLocal`, `qWasmGlobal`, `qWasmStackValue`
+//! and `qWasmMem` packets. Running programs cannot be interrupted, the stub
+//! only reads the next packet once it stopped.
+
+use super::{Breakpoint, Debugger, Stop, Watch};
+use crate::runtime::{clean_model::Function, FuncId, Runtime, RuntimeError, Value, MAIN_MODULE};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::TcpListener,
+};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.wasm.core">
+    <reg name="pc" bitsize="64" type="code_ptr" regnum="0"/>
+  </feature>
+</target>"#;
+
+/// Signals stop replies report.
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn number(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+/// `addr,len`, as in `m` and `Z` packets.
+fn range(s: &str) -> Option<(u64, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((number(addr)?, number(len)? as usize))
+}
+
+/// A value as it is laid out in memory.
+fn value_bytes(v: &Value) -> Vec<u8> {
+    match v {
+        Value::I32(v) => v.to_le_bytes().to_vec(),
+        Value::I64(v) => v.to_le_bytes().to_vec(),
+        Value::F32(v) => v.to_bits().to_le_bytes().to_vec(),
+        Value::F64(v) => v.to_bits().to_le_bytes().to_vec(),
+        Value::Externref(v) | Value::FuncRef(v) => v.to_le_bytes().to_vec(),
+        Value::BlockLock => Vec::new(),
+    }
+}
+
+/// Reads one packet, acknowledging it. `None` once the other side is gone.
+fn read_packet(input: &mut impl BufRead, out: &mut impl Write) -> io::Result<Option<String>> {
+    let mut byte = [0];
+    loop {
+        // acks, and interrupts, which only come in while we are stopped
+        // anyway, are skipped until a packet starts
+        loop {
+            if input.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut packet = Vec::new();
+        if input.read_until(b'#', &mut packet)? == 0 {
+            return Ok(None);
+        }
+        packet.pop();
+        let mut checksum = [0; 2];
+        input.read_exact(&mut checksum)?;
+        let sum = packet.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|c| u8::from_str_radix(c, 16).ok());
+        if expected != Some(sum) {
+            out.write_all(b"-")?;
+            out.flush()?;
+            continue;
+        }
+        out.write_all(b"+")?;
+        // the checksum covers the escaped bytes, `}` then the byte xored
+        // with 0x20
+        let mut bytes = packet.into_iter();
+        let mut data = Vec::with_capacity(bytes.len());
+        while let Some(b) = bytes.next() {
+            match b {
+                b'}' => data.extend(bytes.next().map(|b| b ^ 0x20)),
+                b => data.push(b),
+            }
+        }
+        return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+    }
+}
+
+fn write_packet(out: &mut impl Write, data: &str) -> io::Result<()> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for b in data.bytes() {
+        if matches!(b, b'$' | b'#' | b'}' | b'*') {
+            escaped.extend([b'}', b ^ 0x20]);
+        } else {
+            escaped.push(b);
+        }
+    }
+    let sum = escaped.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+    out.write_all(b"$")?;
+    out.write_all(&escaped)?;
+    write!(out, "#{sum:02x}")?;
+    out.flush()
+}
+
+struct Stub {
+    debugger: Debugger,
+    /// The reply to `?`, the last stop.
+    stopped: String,
+    /// What resuming replies once the program finished or trapped.
+    ended: Option<String>,
+}
+
+impl Stub {
+    /// The frame `n` calls down from the top.
+    fn frame(&self, n: &str) -> Option<&crate::runtime::Frame> {
+        let n = number(n)? as usize;
+        let stack = &self.debugger.rt.stack;
+        stack.get(stack.len().checked_sub(n + 1)?)
+    }
+
+    /// The wasm instances, by module id.
+    fn modules(&self) -> Vec<&str> {
+        let mut others: Vec<_> = (self.debugger.rt.instances())
+            .filter(|name| *name != MAIN_MODULE)
+            .collect();
+        others.sort_unstable();
+        [MAIN_MODULE].into_iter().chain(others).collect()
+    }
+
+    /// The pc of instruction `pc` of the function `func` of `instance`.
+    fn code_pc(&self, instance: &str, func: u32, pc: usize) -> Option<u64> {
+        let module = self.modules().iter().position(|m| *m == instance)?;
+        let inst = self.debugger.rt.instance(instance).ok()?;
+        let Some(Function::WS { offsets, .. }) = inst.function(func) else {
+            return None;
+        };
+        Some((module as u64) << 32 | u64::from(*offsets.get(pc)?))
+    }
+
+    /// The instance, function and instruction index the pc `addr` is in,
+    /// if an instruction starts there.
+    fn resolve_pc(&self, addr: u64) -> Option<(String, u32, usize)> {
+        let instance = *self.modules().get((addr >> 32) as usize)?;
+        let inst = self.debugger.rt.instance(instance).ok()?;
+        // imported functions are the other instance's code, if they are
+        // code at all
+        (0..inst.functions() as u32)
+            .filter(|func| inst.function_import(*func).is_none())
+            .find_map(|func| match inst.function(func)? {
+                Function::WS { offsets, .. } => {
+                    let pc = offsets
+                        .iter()
+                        .position(|o| u64::from(*o) == addr & 0xffff_ffff)?;
+                    Some((instance.to_string(), func, pc))
+                }
+                Function::IO { .. } => None,
+            })
+    }
+
+    fn frame_pc(&self, frame: &crate::runtime::Frame) -> u64 {
+        let (instance, id) = match &frame.func_id {
+            FuncId::Id(id) => (&frame.module, *id),
+            FuncId::Foreign { module, id } => (module, *id),
+        };
+        self.code_pc(instance, id, frame.pc).unwrap_or_default()
+    }
+
+    fn register(&self) -> String {
+        let pc = self
+            .debugger
+            .rt
+            .stack
+            .last()
+            .map_or(0, |f| self.frame_pc(f));
+        hex(&pc.to_le_bytes())
+    }
+
+    fn read_memory(&self, instance: &str, addr: u64, len: usize) -> String {
+        let Ok(instance) = self.debugger.rt.instance(instance) else {
+            return "E01".to_string();
+        };
+        let memory = instance.own_memory();
+        // the length comes from the client, check it before allocating
+        if !memory.contains(addr as usize, len) {
+            return "E01".to_string();
+        }
+        let mut buf = vec![0; len];
+        match memory.read(addr as usize, &mut buf) {
+            Ok(()) => hex(&buf),
+            Err(_) => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&self, args: &str) -> Option<()> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = range.split_once(',')?;
+        let data = unhex(data).filter(|d| Some(d.len() as u64) == number(len))?;
+        let instance = self.debugger.rt.instance(MAIN_MODULE).ok()?;
+        instance
+            .own_memory()
+            .write(number(addr)? as usize, &data)
+            .ok()
+    }
+
+    /// `Z`/`z` packets, software breakpoints and write watchpoints.
+    fn breakpoint(&mut self, set: bool, args: &str) -> Option<()> {
+        let (kind, range) = args.split_once(',')?;
+        let (addr, len) = self::range(range)?;
+        match (kind, set) {
+            ("0" | "1", true) => {
+                let (instance, func, pc) = self.resolve_pc(addr)?;
+                self.debugger
+                    .breakpoints
+                    .push(Breakpoint { instance, func, pc });
+            }
+            ("0" | "1", false) => {
+                let (instance, func, pc) = self.resolve_pc(addr)?;
+                self.debugger
+                    .breakpoints
+                    .retain(|b| !(b.instance == instance && b.func == func && b.pc == pc));
+            }
+            ("2", true) => {
+                let debugger = &mut self.debugger;
+                let old = debugger.read_memory(addr as usize, len).ok()?;
+                debugger.watches.push(Watch::Memory {
+                    addr: addr as usize,
+                    len,
+                    old,
+                });
+            }
+            ("2", false) => self.debugger.watches.retain(|w| {
+                !matches!(w, Watch::Memory { addr: a, len: l, .. } if *a as u64 == addr && *l == len)
+            }),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Runs until the next stop and returns the reply for it.
+    fn resume(&mut self, step: bool) -> String {
+        if let Some(ended) = &self.ended {
+            return ended.clone();
+        }
+        let stop = self.debugger.run(|_| !step);
+        let reply = match stop {
+            Stop::Done | Stop::Breakpoint(_) => format!("T{SIGTRAP:02x}thread:1;"),
+            Stop::Watch(w, _) => match self.debugger.watches.get(w) {
+                Some(Watch::Memory { addr, .. }) => {
+                    format!("T{SIGTRAP:02x}watch:{addr:x};thread:1;")
+                }
+                _ => format!("T{SIGTRAP:02x}thread:1;"),
+            },
+            Stop::Finished(_) | Stop::Error(RuntimeError::Exit(0)) => {
+                self.ended = Some("W00".to_string());
+                "W00".to_string()
+            }
+            Stop::Error(RuntimeError::Exit(code)) => {
+                let reply = format!("W{:02x}", code as u8);
+                self.ended = Some(reply.clone());
+                reply
+            }
+            Stop::Error(e) => {
+                // stop rather than end, the frames that trapped are still
+                // there to be looked at
+                info!("guest trapped: {e}");
+                self.ended = Some(format!("X{SIGILL:02x}"));
+                format!("T{SIGILL:02x}thread:1;")
+            }
+            Stop::Over => format!("X{SIGILL:02x}"),
+        };
+        self.stopped.clone_from(&reply);
+        reply
+    }
+
+    /// lldb's `qWasm*` packets, `args` being what follows the colon.
+    fn wasm_query(&self, query: &str, args: &str) -> Option<String> {
+        let mut args = args.split(';');
+        if query == "CallStack" {
+            let pcs = self
+                .debugger
+                .rt
+                .stack
+                .iter()
+                .rev()
+                .map(|f| self.frame_pc(f));
+            return Some(pcs.map(|pc| hex(&pc.to_le_bytes())).collect());
+        }
+        let frame = self.frame(args.next()?)?;
+        let mut index = || Some(number(args.next()?)? as u32);
+        let value = match query {
+            "Local" => *frame.locals.get(&index()?)?,
+            "StackValue" => *frame.stack.get(index()? as usize)?,
+            "Global" => {
+                let instance = self.debugger.rt.instance(&frame.module).ok()?;
+                instance.global_at(index()?)?.get()
+            }
+            "Mem" => {
+                let addr = number(args.next()?)?;
+                let len = number(args.next()?)? as usize;
+                return Some(self.read_memory(&frame.module, addr, len));
+            }
+            _ => return None,
+        };
+        Some(hex(&value_bytes(&value)))
+    }
+
+    fn handle(&mut self, packet: &str) -> String {
+        let ok = |r: Option<()>| r.map_or("E01", |()| "OK").to_string();
+        let (head, rest) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        match head {
+            "?" => self.stopped.clone(),
+            "g" => self.register(),
+            "p" if number(rest) == Some(0) => self.register(),
+            "p" => "E01".to_string(),
+            "m" => match range(rest) {
+                Some((addr, len)) => self.read_memory(MAIN_MODULE, addr, len),
+                None => "E01".to_string(),
+            },
+            "M" => ok(self.write_memory(rest)),
+            "Z" => ok(self.breakpoint(true, rest)),
+            "z" => ok(self.breakpoint(false, rest)),
+            "s" => self.resume(true),
+            "c" => self.resume(false),
+            "H" | "T" => "OK".to_string(),
+            "v" => match rest {
+                "Cont?" => "vCont;c;C;s;S".to_string(),
+                _ if rest.starts_with("Cont;s") || rest.starts_with("Cont;S") => self.resume(true),
+                _ if rest.starts_with("Cont;c") || rest.starts_with("Cont;C") => self.resume(false),
+                _ => String::new(),
+            },
+            "q" => match rest.split_once(':').unwrap_or((rest, "")) {
+                ("Supported", _) => "PacketSize=4000;qXfer:features:read+".to_string(),
+                ("Xfer", args) if args.starts_with("features:read:target.xml:") => {
+                    let (offset, len) = args
+                        .rsplit_once(':')
+                        .and_then(|(_, r)| range(r))
+                        .unwrap_or((0, 0));
+                    let data = TARGET_XML.get(offset as usize..).unwrap_or_default();
+                    match data.get(..len) {
+                        Some(part) => format!("m{part}"),
+                        None => format!("l{data}"),
+                    }
+                }
+                ("Attached", _) => "1".to_string(),
+                ("C", _) => "QC1".to_string(),
+                ("fThreadInfo", _) => "m1".to_string(),
+                ("sThreadInfo", _) => "l".to_string(),
+                (query, args) if query.starts_with("Wasm") => self
+                    .wasm_query(&query["Wasm".len()..], args)
+                    .unwrap_or_else(|| "E01".to_string()),
+                _ => String::new(),
+            },
+            // anything else is unsupported, which an empty reply says
+            _ => String::new(),
+        }
+    }
+}
+
+/// Serves one debugger session on `input` and `out` until it kills or
+/// detaches from the program, which ends it either way.
+fn serve(rt: Runtime, mut input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+    let mut stub = Stub {
+        debugger: Debugger::new(rt),
+        stopped: format!("S{SIGTRAP:02x}"),
+        ended: None,
+    };
+    while let Some(packet) = read_packet(&mut input, &mut out)? {
+        match &*packet {
+            // no reply is expected
+            "k" => break,
+            "D" => return write_packet(&mut out, "OK"),
+            _ => write_packet(&mut out, &stub.handle(&packet))?,
+        }
+    }
+    Ok(())
+}
+
+/// Waits for a debugger on the TCP address `addr`, or talks to one over
+/// stdin and stdout for `-`, and serves it.
+pub fn gdb(rt: Runtime, addr: &str) -> io::Result<()> {
+    if addr == "-" {
+        return serve(rt, io::stdin().lock(), io::stdout().lock());
+    }
+    let listener = TcpListener::bind(addr)?;
+    info!("waiting for a debugger on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    info!("debugger connected from {peer}");
+    stream.set_nodelay(true)?;
+    serve(rt, BufReader::new(stream.try_clone()?), stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wast::text_module;
+
+    const ADD: &str = r#"(module
+        (memory 1)
+        (func (export "add") (result i32)
+            (i32.add (i32.const 1) (i32.const 2))))"#;
+
+    /// The runtime about to run `add`, and the pcs of its instructions.
+    fn runtime() -> (Runtime, Vec<u64>) {
+        let module = text_module(ADD);
+        let mut rt = Runtime::builder()
+            .add_module(MAIN_MODULE, &module)
+            .build()
+            .expect("the runtime builds");
+        rt.stack.clear();
+        rt.enter_in(MAIN_MODULE, "add", &[])
+            .expect("add can be called");
+        let instance = rt.instance(MAIN_MODULE).expect("the module is loaded");
+        let pcs = match instance.function(0) {
+            Some(Function::WS { offsets, .. }) => offsets.iter().map(|o| u64::from(*o)).collect(),
+            _ => panic!("add is a wasm function"),
+        };
+        (rt, pcs)
+    }
+
+    fn packet(data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |a, b| a.wrapping_add(b));
+        format!("${data}#{sum:02x}")
+    }
+
+    /// Serves `packets` and returns what the stub sent back, acks included.
+    fn session(rt: Runtime, packets: &[&str]) -> String {
+        let input: String = packets.iter().map(|p| packet(p)).collect();
+        let mut out = Vec::new();
+        serve(rt, input.as_bytes(), &mut out).expect("the session runs");
+        String::from_utf8(out).expect("replies are text")
+    }
+
+    /// The replies of a session, checking their framing and checksums.
+    fn replies(session: &str) -> Vec<&str> {
+        let mut replies = Vec::new();
+        let mut rest = session;
+        while let Some(start) = rest.find('$') {
+            assert!(rest[..start].chars().all(|c| c == '+'), "{session}");
+            let (data, after) = rest[start + 1..]
+                .split_once('#')
+                .expect("replies are terminated");
+            assert_eq!(&packet(data)[1..], &rest[start + 1..start + data.len() + 4]);
+            replies.push(data);
+            rest = &after[2..];
+        }
+        replies
+    }
+
+    fn pc_hex(pc: u64) -> String {
+        hex(&pc.to_le_bytes())
+    }
+
+    #[test]
+    fn packets_are_framed_acknowledged_and_checked() {
+        let (rt, _) = runtime();
+        let mut input = b"+garbage$g#00".to_vec();
+        input.extend(packet("?").bytes());
+        let mut out = Vec::new();
+        serve(rt, &input[..], &mut out).expect("the session runs");
+        // the corrupted `g` is refused and dropped, the `?` answered
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            format!("-+{}", packet("S05"))
+        );
+    }
+
+    #[test]
+    fn escaped_bytes_are_restored() {
+        // `}` then `0` xored with 0x20, which the checksum is taken over
+        let raw = b"m}\x10,4";
+        let sum = raw.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+        let mut input = b"$".to_vec();
+        input.extend(raw);
+        input.extend(format!("#{sum:02x}").bytes());
+        let mut out = Vec::new();
+        let read = read_packet(&mut &input[..], &mut out).expect("the packet is read");
+        assert_eq!(read.as_deref(), Some("m0,4"));
+        assert_eq!(out, b"+");
+    }
+
+    #[test]
+    fn memory_outside_the_instance_is_refused() {
+        let (rt, _) = runtime();
+        let out = session(
+            rt,
+            &[
+                "M10,2:abcd",
+                "m10,2",
+                "m10000,1",
+                "mfffe,4",
+                "mffffffffffffffff,2",
+                "M10000,1:00",
+                "Mffff,2:0000",
+                "M10,2:ab",
+            ],
+        );
+        assert_eq!(
+            replies(&out),
+            ["OK", "abcd", "E01", "E01", "E01", "E01", "E01", "E01"]
+        );
+    }
+
+    #[test]
+    fn pcs_are_code_offsets() {
+        let (rt, pcs) = runtime();
+        let out = session(rt, &["g", "p0", "qWasmCallStack", "s", "g"]);
+        // the code section starts with the function count, a single byte,
+        // then the body's size and its locals
+        assert_eq!(pcs[0], 3);
+        assert_eq!(
+            replies(&out),
+            [
+                pc_hex(pcs[0]),
+                pc_hex(pcs[0]),
+                pc_hex(pcs[0]),
+                "T05thread:1;".to_string(),
+                pc_hex(pcs[1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn breakpoints_round_trip() {
+        let (rt, pcs) = runtime();
+        let set = format!("Z0,{:x},1", pcs[2]);
+        let unset = format!("z0,{:x},1", pcs[2]);
+        // inside the first `i32.const`, and in a module that is not there
+        let between = format!("Z0,{:x},1", pcs[0] + 1);
+        let elsewhere = format!("Z0,{:x},1", 1u64 << 32 | pcs[0]);
+        let out = session(
+            rt,
+            &[&set, &between, &elsewhere, "c", "g", &unset, "c", "c"],
+        );
+        assert_eq!(
+            replies(&out),
+            [
+                "OK".to_string(),
+                "E01".to_string(),
+                "E01".to_string(),
+                "T05thread:1;".to_string(),
+                pc_hex(pcs[2]),
+                "OK".to_string(),
+                "W00".to_string(),
+                "W00".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,589 @@
+//! `wasp debug`: runs a module one instruction at a time under the control
+//! of a small gdb-like prompt, or of gdb itself through [`gdb`].
+
+use crate::{
+    hex::Hex,
+    runtime::{
+        clean_model::Function, instance_name, FuncId, Instance, InternalError, InternalErrorKind,
+        Runtime, RuntimeError, Value, MAIN_MODULE,
+    },
+};
+use std::io::{self, BufRead, Write};
+mod command;
+mod gdb;
+use command::{Command, FuncRef, Location, HELP};
+pub use gdb::gdb;
+
+/// A breakpoint, resolved to the instance and function index it stops in.
+struct Breakpoint {
+    instance: String,
+    func: u32,
+    pc: usize,
+}
+
+enum Watch {
+    Memory {
+        addr: usize,
+        len: usize,
+        old: Vec<u8>,
+    },
+    Global {
+        index: u32,
+        old: Value,
+    },
+}
+
+/// Why running stopped.
+enum Stop {
+    /// As many steps as asked for were taken.
+    Done,
+    Breakpoint(usize),
+    Watch(usize, String),
+    Finished(Vec<Value>),
+    Error(RuntimeError),
+    /// It had already finished or trapped.
+    Over,
+}
+
+struct Debugger {
+    rt: Runtime,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    /// Set once the program ran to its end or trapped, after which it can
+    /// only be looked at.
+    over: bool,
+}
+
+impl Debugger {
+    fn new(rt: Runtime) -> Self {
+        Self {
+            rt,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            over: false,
+        }
+    }
+
+    fn instance(&self, name: Option<&str>) -> Result<Instance<'_>, String> {
+        let name = match name {
+            Some("main") | None => MAIN_MODULE,
+            Some(name) => name,
+        };
+        self.rt.instance(name).map_err(|e| e.to_string())
+    }
+
+    /// The instance, function and pc about to run.
+    fn position(&self) -> Option<(&str, u32, usize)> {
+        let frame = self.rt.stack.last()?;
+        let FuncId::Id(id) = frame.func_id else {
+            return None;
+        };
+        Some((&frame.module, id, frame.pc))
+    }
+
+    fn resolve(&self, location: &Location) -> Result<Breakpoint, String> {
+        let instance = match &location.instance {
+            Some(name) => name.clone(),
+            None => MAIN_MODULE.to_string(),
+        };
+        let func = self.function(Some(&instance), &location.func)?;
+        Ok(Breakpoint {
+            instance,
+            func,
+            pc: location.pc,
+        })
+    }
+
+    fn function(&self, instance: Option<&str>, func: &FuncRef) -> Result<u32, String> {
+        let inst = self.instance(instance)?;
+        match func {
+            FuncRef::Index(i) if inst.function(*i).is_some() => Ok(*i),
+            FuncRef::Index(i) => Err(format!("no function {i}")),
+            FuncRef::Name(name) => inst
+                .function_index(name)
+                .ok_or_else(|| format!("no function exported as {name:?}")),
+        }
+    }
+
+    fn global(&self, global: &FuncRef) -> Result<u32, String> {
+        let inst = self.instance(None)?;
+        match global {
+            FuncRef::Index(i) if (*i as usize) < inst.globals() => Ok(*i),
+            FuncRef::Index(i) => Err(format!("no global {i}")),
+            FuncRef::Name(name) => inst
+                .global_index(name)
+                .ok_or_else(|| format!("no global exported as {name:?}")),
+        }
+    }
+
+    /// The function `func` of `instance`, as `$3` or `$3 "name"`.
+    fn describe(&self, instance: &str, func: u32) -> String {
+        let name = self
+            .rt
+            .instance(instance)
+            .ok()
+            .and_then(|i| i.function_name(func).map(str::to_string));
+        let instance = instance_name(instance);
+        match name {
+            Some(name) => format!("{instance}:${func} {name:?}"),
+            None => format!("{instance}:${func}"),
+        }
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, String> {
+        let instance = self.instance(None)?;
+        let memory = instance.own_memory();
+        // the range is typed in, check it before allocating for it
+        if !memory.contains(addr, len) {
+            return Err(format!("{addr:#x}+{len:#x} is out of bounds"));
+        }
+        let mut buf = vec![0; len];
+        memory.read(addr, &mut buf).map_err(|e| e.to_string())?;
+        Ok(buf)
+    }
+
+    fn read_global(&self, index: u32) -> Result<Value, String> {
+        self.instance(None)?
+            .global_at(index)
+            .map(|g| g.get())
+            .ok_or_else(|| format!("no global {index}"))
+    }
+
+    /// The first watchpoint whose value changed, taking the new value as the
+    /// one to compare against next time.
+    fn check_watches(&mut self) -> Option<Stop> {
+        for i in 0..self.watches.len() {
+            let (old, new) = match &self.watches[i] {
+                Watch::Memory { addr, len, old } => {
+                    let Ok(new) = self.read_memory(*addr, *len) else {
+                        continue;
+                    };
+                    if new == *old {
+                        continue;
+                    }
+                    let message = format!("{:?} -> {:?}", Bytes(old), Bytes(&new));
+                    if let Watch::Memory { old, .. } = &mut self.watches[i] {
+                        *old = new;
+                    }
+                    (i, message)
+                }
+                Watch::Global { index, old } => {
+                    let Ok(new) = self.read_global(*index) else {
+                        continue;
+                    };
+                    if same(&new, old) {
+                        continue;
+                    }
+                    let message = format!("{old} -> {new}");
+                    if let Watch::Global { old, .. } = &mut self.watches[i] {
+                        *old = new;
+                    }
+                    (i, message)
+                }
+            };
+            return Some(Stop::Watch(old, new));
+        }
+        None
+    }
+
+    fn at_breakpoint(&self) -> Option<usize> {
+        let (instance, func, pc) = self.position()?;
+        self.breakpoints
+            .iter()
+            .position(|b| b.instance == instance && b.func == func && b.pc == pc)
+    }
+
+    /// Runs one instruction.
+    fn step(&mut self) -> Option<Stop> {
+        if self.over {
+            return Some(Stop::Over);
+        }
+        match self.rt.step() {
+            Ok(()) => self.check_watches(),
+            Err(RuntimeError::ReturnedToNoFrame(results)) => {
+                self.over = true;
+                Some(Stop::Finished(results))
+            }
+            Err(RuntimeError::Internal(InternalError {
+                kind: InternalErrorKind::NoFrame,
+                ..
+            })) => {
+                self.over = true;
+                Some(Stop::Finished(Vec::new()))
+            }
+            Err(e) => {
+                self.over = true;
+                Some(Stop::Error(e))
+            }
+        }
+    }
+
+    /// Steps while `more` says so, stopping early at breakpoints and
+    /// watchpoints. The instruction it starts on always runs, so running
+    /// again from a breakpoint does not stop right where it is.
+    fn run(&mut self, mut more: impl FnMut(&Runtime) -> bool) -> Stop {
+        if let Some(stop) = self.step() {
+            return stop;
+        }
+        while more(&self.rt) {
+            if let Some(b) = self.at_breakpoint() {
+                return Stop::Breakpoint(b);
+            }
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+        }
+        Stop::Done
+    }
+
+    fn execute(&mut self, command: Command, out: &mut impl Write) -> io::Result<bool> {
+        let stop = match command {
+            Command::Break(location) => {
+                match self.resolve(&location) {
+                    Ok(b) => {
+                        writeln!(
+                            out,
+                            "breakpoint {} at {} @{}",
+                            self.breakpoints.len(),
+                            self.describe(&b.instance, b.func),
+                            b.pc
+                        )?;
+                        self.breakpoints.push(b);
+                    }
+                    Err(e) => writeln!(out, "{e}")?,
+                }
+                return Ok(true);
+            }
+            Command::Delete(n) if n < self.breakpoints.len() => {
+                self.breakpoints.remove(n);
+                return Ok(true);
+            }
+            Command::Unwatch(n) if n < self.watches.len() => {
+                self.watches.remove(n);
+                return Ok(true);
+            }
+            Command::Delete(n) | Command::Unwatch(n) => {
+                writeln!(out, "there is no number {n}")?;
+                return Ok(true);
+            }
+            Command::WatchMemory { addr, len } => {
+                match self.read_memory(addr, len) {
+                    Ok(old) => {
+                        writeln!(
+                            out,
+                            "watchpoint {} on memory {addr:#x}..{:#x}",
+                            self.watches.len(),
+                            addr + len
+                        )?;
+                        self.watches.push(Watch::Memory { addr, len, old });
+                    }
+                    Err(e) => writeln!(out, "{e}")?,
+                }
+                return Ok(true);
+            }
+            Command::WatchGlobal(global) => {
+                match self
+                    .global(&global)
+                    .and_then(|i| Ok((i, self.read_global(i)?)))
+                {
+                    Ok((index, old)) => {
+                        writeln!(out, "watchpoint {} on global {index}", self.watches.len())?;
+                        self.watches.push(Watch::Global { index, old });
+                    }
+                    Err(e) => writeln!(out, "{e}")?,
+                }
+                return Ok(true);
+            }
+            Command::Info => {
+                for (i, b) in self.breakpoints.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "breakpoint {i}: {} @{}",
+                        self.describe(&b.instance, b.func),
+                        b.pc
+                    )?;
+                }
+                for (i, w) in self.watches.iter().enumerate() {
+                    match w {
+                        // only ranges that could be read are watched, their
+                        // ends fit
+                        Watch::Memory { addr, len, .. } => {
+                            writeln!(out, "watchpoint {i}: memory {addr:#x}..{:#x}", addr + len)?
+                        }
+                        Watch::Global { index, .. } => {
+                            writeln!(out, "watchpoint {i}: global {index}")?
+                        }
+                    }
+                }
+                return Ok(true);
+            }
+            Command::Step(n) => {
+                let mut left = n;
+                self.run(|_| {
+                    left -= 1;
+                    left > 0
+                })
+            }
+            Command::Next => {
+                let depth = self.rt.stack.len();
+                self.run(|rt| rt.stack.len() > depth)
+            }
+            Command::Finish => {
+                let depth = self.rt.stack.len();
+                self.run(|rt| rt.stack.len() >= depth)
+            }
+            Command::Continue => self.run(|_| true),
+            Command::Stack => {
+                match self.rt.stack.last() {
+                    Some(frame) => {
+                        for (i, v) in frame.stack.iter().enumerate().rev() {
+                            writeln!(out, "{i:>4}: {} {v}", v.as_str())?;
+                        }
+                    }
+                    None => writeln!(out, "no frame")?,
+                }
+                return Ok(true);
+            }
+            Command::Locals => {
+                match self.rt.stack.last() {
+                    Some(frame) => {
+                        let mut locals = frame.locals.iter().collect::<Vec<_>>();
+                        locals.sort_by_key(|(i, _)| **i);
+                        for (i, v) in locals {
+                            writeln!(out, "{i:>4}: {} {v}", v.as_str())?;
+                        }
+                    }
+                    None => writeln!(out, "no frame")?,
+                }
+                return Ok(true);
+            }
+            Command::Blocks => {
+                match self.rt.stack.last() {
+                    Some(frame) => {
+                        for (i, block) in frame.depth_stack.iter().enumerate().rev() {
+                            writeln!(out, "{i:>4}: {block:?}")?;
+                        }
+                    }
+                    None => writeln!(out, "no frame")?,
+                }
+                return Ok(true);
+            }
+            Command::Backtrace => {
+                writeln!(out, "{}", self.rt.backtrace())?;
+                return Ok(true);
+            }
+            Command::Examine { addr, len } => {
+                self.dump(addr, len, out)?;
+                return Ok(true);
+            }
+            Command::Global(global) => {
+                match self.global(&global).and_then(|i| self.read_global(i)) {
+                    Ok(v) => writeln!(out, "{} {v}", v.as_str())?,
+                    Err(e) => writeln!(out, "{e}")?,
+                }
+                return Ok(true);
+            }
+            Command::List(location) => {
+                self.list(location, out)?;
+                return Ok(true);
+            }
+            Command::Help => {
+                writeln!(out, "{HELP}")?;
+                return Ok(true);
+            }
+            Command::Quit => return Ok(false),
+        };
+
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint(b) => writeln!(out, "breakpoint {b}")?,
+            Stop::Watch(w, change) => writeln!(out, "watchpoint {w}: {change}")?,
+            Stop::Finished(results) => {
+                writeln!(out, "program finished")?;
+                for v in results {
+                    writeln!(out, "{} {v}", v.as_str())?;
+                }
+                return Ok(true);
+            }
+            Stop::Error(e) => {
+                writeln!(out, "stopped: {e}")?;
+                return Ok(true);
+            }
+            Stop::Over => {
+                writeln!(out, "the program is not running")?;
+                return Ok(true);
+            }
+        }
+        self.show_position(out)?;
+        Ok(true)
+    }
+
+    /// Prints where execution is and the instruction about to run.
+    fn show_position(&self, out: &mut impl Write) -> io::Result<()> {
+        let Some((instance, func, pc)) = self.position() else {
+            return writeln!(out, "no frame");
+        };
+        let instr = self
+            .rt
+            .instance(instance)
+            .ok()
+            .and_then(|i| match i.function(func) {
+                Some(Function::WS { code, .. }) => code.get(pc).map(|i| format!("{i:?}")),
+                _ => None,
+            })
+            .unwrap_or_else(|| "end".to_string());
+        let source = self
+            .rt
+            .instance(instance)
+            .ok()
+            .and_then(|i| i.location(func, pc))
+            .map_or(String::new(), |l| format!(" at {l}"));
+        writeln!(
+            out,
+            "{} @{pc}{source}: {instr}",
+            self.describe(instance, func)
+        )
+    }
+
+    fn list(&self, location: Option<Location>, out: &mut impl Write) -> io::Result<()> {
+        let (instance, func) = match location {
+            Some(location) => match self.resolve(&location) {
+                Ok(b) => (b.instance, b.func),
+                Err(e) => return writeln!(out, "{e}"),
+            },
+            None => match self.position() {
+                Some((instance, func, _)) => (instance.to_string(), func),
+                None => return writeln!(out, "no frame"),
+            },
+        };
+        let current = self
+            .position()
+            .filter(|(i, f, _)| *i == instance && *f == func);
+        let Ok(inst) = self.rt.instance(&instance) else {
+            return writeln!(out, "no instance {instance:?}");
+        };
+        match inst.function(func) {
+            Some(Function::WS { ty, code, .. }) => {
+                writeln!(out, "{} {ty}", self.describe(&instance, func))?;
+                let mut last_line = None;
+                for (pc, instr) in code.iter().enumerate() {
+                    // the source line, each time a new one starts
+                    if let Some(location) = inst.location(func, pc) {
+                        let line = Some((location.file, location.line));
+                        if last_line != line {
+                            writeln!(out, "{location}:")?;
+                            last_line = line;
+                        }
+                    }
+                    let marker = if current.is_some_and(|(_, _, p)| p == pc) {
+                        "=>"
+                    } else {
+                        "  "
+                    };
+                    let breakpoint = self
+                        .breakpoints
+                        .iter()
+                        .any(|b| b.instance == instance && b.func == func && b.pc == pc);
+                    let breakpoint = if breakpoint { "*" } else { " " };
+                    writeln!(out, "{marker}{breakpoint}{pc:>5}: {instr:?}")?;
+                }
+                Ok(())
+            }
+            Some(Function::IO { ty, .. }) => {
+                writeln!(
+                    out,
+                    "{} {ty} is a host function",
+                    self.describe(&instance, func)
+                )
+            }
+            None => writeln!(out, "no function {func}"),
+        }
+    }
+
+    /// Dumps memory in rows of 16 bytes, widened to whole rows.
+    fn dump(&self, addr: usize, len: usize, out: &mut impl Write) -> io::Result<()> {
+        let start = addr & !15;
+        let Some(end) = addr
+            .checked_add(len)
+            .and_then(|end| end.checked_next_multiple_of(16))
+        else {
+            return writeln!(out, "{addr:#x}+{len:#x} is out of bounds");
+        };
+        let bytes = match self.read_memory(start, end - start) {
+            Ok(bytes) => bytes,
+            Err(e) => return writeln!(out, "{e}"),
+        };
+        for (i, row) in bytes.chunks_exact(16).enumerate() {
+            let Ok(row) = <[u8; 16]>::try_from(row) else {
+                continue;
+            };
+            let ascii = row
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            writeln!(out, "{:08x}: {:?} |{ascii}|", start + i * 16, Hex(row))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `a` and `b` are the same value, down to the bits of NaNs.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::F32(a), Value::F32(b)) => a.to_bits() == b.to_bits(),
+        (Value::F64(a), Value::F64(b)) => a.to_bits() == b.to_bits(),
+        (a, b) => a == b,
+    }
+}
+
+/// Bytes in [`Hex`] style, of any length.
+struct Bytes<'a>(&'a [u8]);
+impl std::fmt::Debug for Bytes<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = self
+            .0
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>();
+        write!(f, "<{}>", hex.join(" "))
+    }
+}
+
+/// Debugs `rt`, which has the call to debug on top of its stack, reading
+/// commands from `input` until it ends or `quit`.
+pub fn debug(rt: Runtime, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+    let mut debugger = Debugger::new(rt);
+    debugger.show_position(out)?;
+    let mut last = None;
+    let mut lines = input.lines();
+    loop {
+        write!(out, "(wasp) ")?;
+        out.flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        let command = if line.trim().is_empty() {
+            match &last {
+                Some(command) => Ok(Command::clone(command)),
+                None => continue,
+            }
+        } else {
+            command::parse(&line)
+        };
+        match command {
+            Ok(command) => {
+                last = Some(command.clone());
+                if !debugger.execute(command, out)? {
+                    return Ok(());
+                }
+            }
+            Err(e) => writeln!(out, "{e}")?,
+        }
+    }
+}
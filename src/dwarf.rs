@@ -0,0 +1,758 @@
+//! Just enough DWARF to map code offsets back to source lines: the line
+//! number programs of `.debug_line`, versions 2 to 5.
+//!
+//! https://dwarfstd.org/doc/DWARF5.pdf, section 6.2
+
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DwarfError {
+    /// Where in `.debug_line` it went wrong.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for DwarfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at .debug_line+{:#x}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for DwarfError {}
+
+type Result<T> = std::result::Result<T, DwarfError>;
+
+/// A source position as a line table gives it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location<'t> {
+    pub file: &'t str,
+    pub line: u32,
+    /// 0 when the compiler did not say.
+    pub column: u32,
+}
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    address: u64,
+    file: usize,
+    line: u32,
+    column: u32,
+}
+
+/// Rows of contiguous code, sorted by address, the last of which marks the
+/// first address after it.
+#[derive(Debug, Clone)]
+struct Sequence {
+    rows: Vec<Row>,
+}
+
+/// Where the code at an address came from, for every unit of a
+/// `.debug_line` section.
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    files: Vec<String>,
+    sequences: Vec<Sequence>,
+}
+
+// Standard opcodes
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_SET_COLUMN: u8 = 5;
+const DW_LNS_NEGATE_STMT: u8 = 6;
+const DW_LNS_SET_BASIC_BLOCK: u8 = 7;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+// Extended opcodes
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+// Content types of version 5 directory and file entries
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+// Forms version 5 entries may use
+const DW_FORM_BLOCK2: u64 = 0x03;
+const DW_FORM_BLOCK4: u64 = 0x04;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_BLOCK1: u64 = 0x0a;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_SDATA: u64 = 0x0d;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+struct Reader<'d> {
+    data: &'d [u8],
+    pos: usize,
+}
+
+impl<'d> Reader<'d> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        Err(DwarfError {
+            offset: self.pos,
+            message: message.into(),
+        })
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'d [u8]> {
+        match self.data.get(self.pos..self.pos.saturating_add(n)) {
+            Some(bytes) => {
+                self.pos += n;
+                Ok(bytes)
+            }
+            None => self.error("unexpected end of section"),
+        }
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self.bytes(N)?;
+        let mut out = [0; N];
+        out.copy_from_slice(bytes);
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.fixed::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.fixed()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.fixed()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.fixed()?))
+    }
+
+    /// A 4 or 8 byte value, as offsets are in 32 and 64-bit DWARF.
+    fn offset(&mut self, wide: bool) -> Result<u64> {
+        if wide {
+            self.u64()
+        } else {
+            self.u32().map(u64::from)
+        }
+    }
+
+    fn uleb(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..).step_by(7) {
+            let b = self.u8()?;
+            if shift < 64 {
+                value |= u64::from(b & 0x7f) << shift;
+            }
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+
+    fn sleb(&mut self) -> Result<i64> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                value |= i64::from(b & 0x7f) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                if shift < 64 && b & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> Result<&'d str> {
+        let rest = self.data.get(self.pos..).unwrap_or_default();
+        let Some(len) = rest.iter().position(|b| *b == 0) else {
+            return self.error("unterminated string");
+        };
+        let s = std::str::from_utf8(&rest[..len]);
+        match s {
+            Ok(s) => {
+                self.pos += len + 1;
+                Ok(s)
+            }
+            Err(_) => self.error("string is not UTF-8"),
+        }
+    }
+}
+
+/// The string at `offset` in a string section.
+fn string_at(section: &[u8], offset: u64) -> Option<&str> {
+    let rest = section.get(offset as usize..)?;
+    std::str::from_utf8(&rest[..rest.iter().position(|b| *b == 0)?]).ok()
+}
+
+/// The string sections strings in version 5 headers point into.
+#[derive(Clone, Copy)]
+struct Strings<'d> {
+    debug_str: &'d [u8],
+    debug_line_str: &'d [u8],
+}
+
+/// The value of an entry field, as far as lines care about it.
+enum Field<'d> {
+    Str(&'d str),
+    Num(u64),
+    Other,
+}
+
+impl<'d> Reader<'d> {
+    fn field(&mut self, form: u64, wide: bool, strings: Strings<'d>) -> Result<Field<'d>> {
+        Ok(match form {
+            DW_FORM_STRING => Field::Str(self.cstr()?),
+            DW_FORM_LINE_STRP | DW_FORM_STRP => {
+                let (section, name) = match form {
+                    DW_FORM_LINE_STRP => (strings.debug_line_str, ".debug_line_str"),
+                    _ => (strings.debug_str, ".debug_str"),
+                };
+                let offset = self.offset(wide)?;
+                match string_at(section, offset) {
+                    Some(s) => Field::Str(s),
+                    None => return self.error(format!("no string at {name}+{offset:#x}")),
+                }
+            }
+            DW_FORM_DATA1 => Field::Num(self.u8()?.into()),
+            DW_FORM_DATA2 => Field::Num(self.u16()?.into()),
+            DW_FORM_DATA4 => Field::Num(self.u32()?.into()),
+            DW_FORM_DATA8 => Field::Num(self.u64()?),
+            DW_FORM_UDATA => Field::Num(self.uleb()?),
+            DW_FORM_SDATA => Field::Num(self.sleb()? as u64),
+            DW_FORM_DATA16 => {
+                self.bytes(16)?;
+                Field::Other
+            }
+            DW_FORM_BLOCK1 | DW_FORM_BLOCK2 | DW_FORM_BLOCK4 | DW_FORM_BLOCK => {
+                let len = match form {
+                    DW_FORM_BLOCK1 => self.u8()?.into(),
+                    DW_FORM_BLOCK2 => self.u16()?.into(),
+                    DW_FORM_BLOCK4 => self.u32()?.into(),
+                    _ => self.uleb()?,
+                };
+                self.bytes(len as usize)?;
+                Field::Other
+            }
+            form => return self.error(format!("unsupported form {form:#x}")),
+        })
+    }
+
+    /// A version 5 directory or file table, as paths and directory indices.
+    fn entries(&mut self, wide: bool, strings: Strings<'d>) -> Result<Vec<(&'d str, u64)>> {
+        let format_count = self.u8()?;
+        let mut format = Vec::new();
+        for _ in 0..format_count {
+            format.push((self.uleb()?, self.uleb()?));
+        }
+        let count = self.uleb()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let (mut path, mut dir) = ("", 0);
+            for &(content, form) in &format {
+                match (content, self.field(form, wide, strings)?) {
+                    (DW_LNCT_PATH, Field::Str(s)) => path = s,
+                    (DW_LNCT_DIRECTORY_INDEX, Field::Num(n)) => dir = n,
+                    _ => {}
+                }
+            }
+            entries.push((path, dir));
+        }
+        Ok(entries)
+    }
+}
+
+/// `line` moved by `delta`, unless that leaves the range of line numbers.
+fn advance_line(line: u32, delta: i64) -> Option<u32> {
+    i64::from(line)
+        .checked_add(delta)
+        .and_then(|line| u32::try_from(line).ok())
+}
+
+/// `name` in the directory `dir`, unless it is absolute already.
+fn join(dir: Option<&str>, name: &str) -> String {
+    match dir {
+        Some(dir) if !dir.is_empty() && !name.starts_with('/') => {
+            format!("{}/{name}", dir.trim_end_matches('/'))
+        }
+        _ => name.to_string(),
+    }
+}
+
+impl LineTable {
+    /// Reads every unit of the `.debug_line` section `debug_line`, whose
+    /// version 5 headers may refer to strings in `debug_str` and
+    /// `debug_line_str`.
+    pub fn parse(debug_line: &[u8], debug_str: &[u8], debug_line_str: &[u8]) -> Result<Self> {
+        let strings = Strings {
+            debug_str,
+            debug_line_str,
+        };
+        let mut table = LineTable::default();
+        let mut reader = Reader {
+            data: debug_line,
+            pos: 0,
+        };
+        while reader.pos < debug_line.len() {
+            table.unit(&mut reader, strings)?;
+        }
+        table.sequences.sort_by_key(|s| s.rows[0].address);
+        Ok(table)
+    }
+
+    /// Reads the unit at `reader`, leaving it at the next.
+    fn unit<'d>(&mut self, reader: &mut Reader<'d>, strings: Strings<'d>) -> Result<()> {
+        let (length, wide) = match reader.u32()? {
+            0xffff_ffff => (reader.u64()?, true),
+            length @ 0xffff_fff0.. => return reader.error(format!("reserved length {length:#x}")),
+            length => (length.into(), false),
+        };
+        let end = reader.pos.saturating_add(length as usize);
+        if end > reader.data.len() {
+            return reader.error("unit runs past the end of the section");
+        }
+        let version = reader.u16()?;
+        if !(2..=5).contains(&version) {
+            return reader.error(format!("unsupported version {version}"));
+        }
+        if version >= 5 {
+            let _address_size = reader.u8()?;
+            let _segment_selector_size = reader.u8()?;
+        }
+        let header_length = reader.offset(wide)?;
+        let program = reader.pos.saturating_add(header_length as usize);
+        let min_inst_length = reader.u8()?;
+        if version >= 4 {
+            let _max_ops_per_inst = reader.u8()?;
+        }
+        let _default_is_stmt = reader.u8()?;
+        let line_base = reader.u8()? as i8;
+        let line_range = reader.u8()?;
+        let opcode_base = reader.u8()?;
+        if line_range == 0 {
+            return reader.error("line range of 0");
+        }
+        let mut opcode_lengths = Vec::new();
+        for _ in 1..opcode_base {
+            opcode_lengths.push(reader.u8()?);
+        }
+
+        // the unit's files, as indices into `self.files`
+        let mut files = Vec::new();
+        if version >= 5 {
+            let dirs = reader.entries(wide, strings)?;
+            for (name, dir) in reader.entries(wide, strings)? {
+                let dir = dirs.get(dir as usize).map(|(d, _)| *d);
+                files.push(self.file(join(dir, name)));
+            }
+        } else {
+            let mut dirs = Vec::new();
+            loop {
+                match reader.cstr()? {
+                    "" => break,
+                    dir => dirs.push(dir),
+                }
+            }
+            // files count from 1, and directories too, 0 being the
+            // compilation directory which only `.debug_info` knows
+            files.push(self.file("<unknown>".to_string()));
+            loop {
+                let name = reader.cstr()?;
+                if name.is_empty() {
+                    break;
+                }
+                let dir = reader.uleb()?;
+                let (_mtime, _size) = (reader.uleb()?, reader.uleb()?);
+                let dir = (dir as usize).checked_sub(1).and_then(|d| dirs.get(d));
+                files.push(self.file(join(dir.copied(), name)));
+            }
+        }
+
+        reader.pos = program;
+        let new_row = |file| Row {
+            address: 0,
+            file,
+            line: 1,
+            column: 0,
+        };
+        let unit_file =
+            |files: &[usize], index: u64| files.get(index as usize).copied().unwrap_or(usize::MAX);
+        let mut row = new_row(unit_file(&files, 1));
+        let mut rows = Vec::new();
+        while reader.pos < end {
+            let opcode = reader.u8()?;
+            match opcode {
+                // a unit may have fewer standard opcodes than this version
+                // knows, the rest are special
+                opcode if opcode != 0 && opcode >= opcode_base => {
+                    let adjusted = opcode - opcode_base;
+                    let advance = u64::from(adjusted / line_range) * u64::from(min_inst_length);
+                    row.address = row.address.wrapping_add(advance);
+                    let delta = i64::from(line_base) + i64::from(adjusted % line_range);
+                    row.line = match advance_line(row.line, delta) {
+                        Some(line) => line,
+                        None => return reader.error("line number out of range"),
+                    };
+                    rows.push(row);
+                }
+                0 => {
+                    let len = reader.uleb()? as usize;
+                    let next = reader.pos.saturating_add(len);
+                    match reader.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            rows.push(row);
+                            self.sequence(std::mem::take(&mut rows));
+                            row = new_row(unit_file(&files, 1));
+                        }
+                        DW_LNE_SET_ADDRESS => {
+                            row.address = match len {
+                                5 => reader.u32()?.into(),
+                                9 => reader.u64()?,
+                                _ => return reader.error("address of unusual size"),
+                            }
+                        }
+                        DW_LNE_DEFINE_FILE => {
+                            let name = reader.cstr()?;
+                            files.push(self.file(name.to_string()));
+                        }
+                        _ => {}
+                    }
+                    reader.pos = next;
+                }
+                DW_LNS_COPY => rows.push(row),
+                DW_LNS_ADVANCE_PC => {
+                    let n = reader.uleb()?;
+                    let Some(advance) = n.checked_mul(min_inst_length.into()) else {
+                        return reader.error("address advance out of range");
+                    };
+                    row.address = row.address.wrapping_add(advance);
+                }
+                DW_LNS_ADVANCE_LINE => {
+                    row.line = match advance_line(row.line, reader.sleb()?) {
+                        Some(line) => line,
+                        None => return reader.error("line number out of range"),
+                    };
+                }
+                DW_LNS_SET_FILE => row.file = unit_file(&files, reader.uleb()?),
+                DW_LNS_SET_COLUMN => row.column = reader.uleb()? as u32,
+                // only say where a debugger would best stop, every row still
+                // says where its code came from
+                DW_LNS_NEGATE_STMT | DW_LNS_SET_BASIC_BLOCK => {}
+                DW_LNS_CONST_ADD_PC => {
+                    let adjusted = 255 - opcode_base;
+                    let advance = u64::from(adjusted / line_range) * u64::from(min_inst_length);
+                    row.address = row.address.wrapping_add(advance);
+                }
+                DW_LNS_FIXED_ADVANCE_PC => {
+                    row.address = row.address.wrapping_add(reader.u16()?.into())
+                }
+                opcode => {
+                    // prologue and epilogue markers, isa, and whatever later
+                    // versions add, skipped by their operand count
+                    for _ in 0..opcode_lengths[opcode as usize - 1] {
+                        reader.uleb()?;
+                    }
+                }
+            }
+        }
+        reader.pos = end;
+        Ok(())
+    }
+
+    fn file(&mut self, name: String) -> usize {
+        self.files.push(name);
+        self.files.len() - 1
+    }
+
+    fn sequence(&mut self, mut rows: Vec<Row>) {
+        // a sequence's rows only ever go up in address, but some are there
+        // twice, the last of which counts
+        rows.dedup_by(|later, kept| {
+            let same = later.address == kept.address;
+            if same {
+                *kept = *later;
+            }
+            same
+        });
+        // linkers point the line info of code they dropped at -1 or 0
+        if rows.len() > 1 && rows[0].address < u64::from(u32::MAX) - 1 {
+            self.sequences.push(Sequence { rows });
+        }
+    }
+
+    /// Where the code at `address` came from.
+    pub fn lookup(&self, address: u64) -> Option<Location<'_>> {
+        let sequence = self
+            .sequences
+            .iter()
+            .rev()
+            .find(|s| s.rows[0].address <= address)?;
+        let last = sequence.rows.last()?;
+        if address >= last.address {
+            return None;
+        }
+        let i = sequence.rows.partition_point(|r| r.address <= address);
+        let row = sequence.rows.get(i.checked_sub(1)?)?;
+        Some(Location {
+            file: self.files.get(row.file)?,
+            line: row.line,
+            column: row.column,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE_BASE: i8 = -5;
+    const LINE_RANGE: u8 = 14;
+
+    fn uleb(mut v: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                out.push(b);
+                return out;
+            }
+            out.push(b | 0x80);
+        }
+    }
+
+    fn sleb(mut v: i64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+                out.push(b);
+                return out;
+            }
+            out.push(b | 0x80);
+        }
+    }
+
+    /// A line number program, opcode by opcode.
+    #[derive(Default)]
+    struct Program(Vec<u8>);
+
+    impl Program {
+        fn set_address(mut self, address: u32) -> Self {
+            self.0.extend([0, 5, DW_LNE_SET_ADDRESS]);
+            self.0.extend(address.to_le_bytes());
+            self
+        }
+        fn end_sequence(mut self) -> Self {
+            self.0.extend([0, 1, DW_LNE_END_SEQUENCE]);
+            self
+        }
+        fn op(mut self, opcode: u8, operand: Vec<u8>) -> Self {
+            self.0.push(opcode);
+            self.0.extend(operand);
+            self
+        }
+        fn copy(self) -> Self {
+            self.op(DW_LNS_COPY, Vec::new())
+        }
+        fn advance_pc(self, n: u64) -> Self {
+            self.op(DW_LNS_ADVANCE_PC, uleb(n))
+        }
+        fn advance_line(self, n: i64) -> Self {
+            self.op(DW_LNS_ADVANCE_LINE, sleb(n))
+        }
+        fn special(self, opcode_base: u8, address: u8, line: i8) -> Self {
+            let opcode = (line - LINE_BASE) as u8 + LINE_RANGE * address + opcode_base;
+            self.op(opcode, Vec::new())
+        }
+    }
+
+    /// The fields of a header after its length, up to the opcode lengths.
+    fn header(min_inst_length: u8, line_range: u8, opcode_base: u8) -> Vec<u8> {
+        let lengths = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+        let mut out = vec![
+            min_inst_length,
+            1,
+            1,
+            LINE_BASE as u8,
+            line_range,
+            opcode_base,
+        ];
+        out.extend(&lengths[..opcode_base as usize - 1]);
+        out
+    }
+
+    /// Wraps `header` and `program` in a unit of `version`.
+    fn unit(version: u16, header: Vec<u8>, program: Program) -> Vec<u8> {
+        let mut body = version.to_le_bytes().to_vec();
+        if version >= 5 {
+            body.extend([4, 0]);
+        }
+        body.extend((header.len() as u32).to_le_bytes());
+        body.extend(header);
+        body.extend(program.0);
+        let mut out = (body.len() as u32).to_le_bytes().to_vec();
+        out.extend(body);
+        out
+    }
+
+    /// A version 4 unit of `src/main.c`.
+    fn v4(min_inst_length: u8, opcode_base: u8, program: Program) -> Vec<u8> {
+        let mut header = header(min_inst_length, LINE_RANGE, opcode_base);
+        header.extend(b"src\0\0main.c\0\x01\0\0\0");
+        unit(4, header, program)
+    }
+
+    fn line(table: &LineTable, address: u64) -> Option<(String, u32)> {
+        table.lookup(address).map(|l| (l.file.to_string(), l.line))
+    }
+
+    fn at(file: &str, line: u32) -> Option<(String, u32)> {
+        Some((file.to_string(), line))
+    }
+
+    #[test]
+    fn version_4_headers_and_special_opcodes() {
+        let program = Program::default()
+            .set_address(0x10)
+            .copy()
+            .special(13, 2, 1)
+            .advance_pc(3)
+            .end_sequence();
+        let table = LineTable::parse(&v4(1, 13, program), &[], &[]).expect("the unit parses");
+        assert_eq!(line(&table, 0x0f), None);
+        assert_eq!(line(&table, 0x10), at("src/main.c", 1));
+        assert_eq!(line(&table, 0x12), at("src/main.c", 2));
+        assert_eq!(line(&table, 0x14), at("src/main.c", 2));
+        assert_eq!(line(&table, 0x15), None);
+    }
+
+    #[test]
+    fn version_5_headers_name_files_through_debug_line_str() {
+        let mut header = header(1, LINE_RANGE, 13);
+        // directories: paths as .debug_line_str offsets
+        header.extend([
+            1,
+            DW_LNCT_PATH as u8,
+            DW_FORM_LINE_STRP as u8,
+            1,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        // files: a path and a directory index
+        header.extend([2, DW_LNCT_PATH as u8, DW_FORM_LINE_STRP as u8]);
+        header.extend([DW_LNCT_DIRECTORY_INDEX as u8, DW_FORM_UDATA as u8]);
+        header.extend([1, 4, 0, 0, 0, 0]);
+        let program = Program::default()
+            .set_address(0x20)
+            .op(DW_LNS_SET_FILE, uleb(0))
+            .advance_line(6)
+            .copy()
+            .advance_pc(2)
+            .end_sequence();
+        let debug_line = unit(5, header, program);
+        let table = LineTable::parse(&debug_line, &[], b"src\0add.c\0").expect("the unit parses");
+        assert_eq!(line(&table, 0x21), at("src/add.c", 7));
+        // the same offsets in .debug_str are not what line_strp means
+        assert!(LineTable::parse(&debug_line, b"src\0add.c\0", &[]).is_err());
+    }
+
+    #[test]
+    fn units_with_few_standard_opcodes_have_more_special_ones() {
+        // with an opcode base of 4, 5 is no longer DW_LNS_set_column
+        let program = Program::default()
+            .set_address(0)
+            .advance_line(9)
+            .copy()
+            .advance_pc(2)
+            .special(4, 0, -4)
+            .advance_pc(1)
+            .end_sequence();
+        let table = LineTable::parse(&v4(1, 4, program), &[], &[]).expect("the unit parses");
+        assert_eq!(line(&table, 0), at("src/main.c", 10));
+        assert_eq!(line(&table, 2), at("src/main.c", 6));
+    }
+
+    #[test]
+    fn end_sequence_starts_the_rows_over() {
+        let program = Program::default()
+            .set_address(0)
+            .advance_line(4)
+            .copy()
+            .advance_pc(2)
+            .end_sequence()
+            .set_address(0x10)
+            .copy()
+            .advance_pc(1)
+            .end_sequence();
+        let table = LineTable::parse(&v4(1, 13, program), &[], &[]).expect("the unit parses");
+        assert_eq!(line(&table, 1), at("src/main.c", 5));
+        assert_eq!(line(&table, 5), None);
+        assert_eq!(line(&table, 0x10), at("src/main.c", 1));
+    }
+
+    #[test]
+    fn malformed_and_overflowing_programs_are_errors() {
+        let row = || Program::default().set_address(0).copy();
+        let mut truncated = v4(1, 13, row().end_sequence());
+        truncated.pop();
+        let mut version_6 = v4(1, 13, row());
+        version_6[4] = 6;
+        let cases = [
+            ("truncated", truncated),
+            ("version 6", version_6),
+            ("line range 0", unit(4, header(1, 0, 13), row())),
+            (
+                "address advance",
+                v4(4, 13, row().advance_pc(u64::MAX / 2).copy()),
+            ),
+            ("line past u32", v4(1, 13, row().advance_line(i64::MAX))),
+            ("line below 0", v4(1, 13, row().advance_line(-2))),
+            ("line below i64", v4(1, 13, row().advance_line(i64::MIN))),
+            ("reserved length", vec![0xf0, 0xff, 0xff, 0xff]),
+        ];
+        for (what, debug_line) in cases {
+            assert!(LineTable::parse(&debug_line, &[], &[]).is_err(), "{what}");
+        }
+    }
+
+    #[test]
+    fn the_debug_lines_example_resolves() {
+        // `main` calls `add`, described by a version 4 and a version 5 unit
+        let bytes = std::fs::read("examples/debug_lines.wasm").expect("the example exists");
+        let module = crate::parser::Module::from_bytes(&bytes).expect("the example parses");
+        let section = |name: &str| {
+            (module.customs.iter())
+                .find(|c| &*c.name == name)
+                .map(|c| c.sections.concat())
+                .unwrap_or_default()
+        };
+        let table = LineTable::parse(&section(".debug_line"), &[], &section(".debug_line_str"))
+            .expect("the lines parse");
+        assert_eq!(line(&table, 7), at("src/main.c", 3));
+        assert_eq!(line(&table, 17), at("src/add.c", 9));
+    }
+}
@@ -0,0 +1,494 @@
+use super::Rng;
+
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+const F32: u8 = 0x7d;
+const F64: u8 = 0x7c;
+const NUM_TYPES: [u8; 4] = [I32, I64, F32, F64];
+
+/// How deep expressions may nest before only leaves are generated.
+const MAX_DEPTH: usize = 5;
+
+fn uleb(out: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut v: i64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, body: Vec<u8>) {
+    out.push(id);
+    uleb(out, body.len() as u64);
+    out.extend(body);
+}
+
+struct FuncSig {
+    params: Vec<u8>,
+    result: Option<u8>,
+}
+
+/// Builds modules that pass validation, so anything other than a trap or a
+/// normal return while running them is an interpreter bug.
+pub struct Generator<'r> {
+    rng: &'r mut Rng,
+    sigs: Vec<FuncSig>,
+    memory: bool,
+    locals: Vec<u8>,
+}
+
+impl<'r> Generator<'r> {
+    pub fn new(rng: &'r mut Rng) -> Self {
+        Self {
+            rng,
+            sigs: Vec::new(),
+            memory: false,
+            locals: Vec::new(),
+        }
+    }
+
+    fn chance(&mut self, n: usize) -> bool {
+        self.rng.below(n) == 0
+    }
+
+    fn num_type(&mut self) -> u8 {
+        NUM_TYPES[self.rng.below(NUM_TYPES.len())]
+    }
+
+    fn int(&mut self) -> i64 {
+        let interesting = [
+            0,
+            1,
+            -1,
+            i32::MIN as i64,
+            i32::MAX as i64,
+            i64::MIN,
+            i64::MAX,
+        ];
+        if self.chance(2) {
+            interesting[self.rng.below(interesting.len())]
+        } else {
+            self.rng.next() as i64 >> self.rng.below(64)
+        }
+    }
+
+    fn float(&mut self) -> f64 {
+        let interesting = [
+            0.0,
+            -0.0,
+            1.0,
+            f64::NAN,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            1e300,
+        ];
+        if self.chance(2) {
+            interesting[self.rng.below(interesting.len())]
+        } else {
+            self.int() as f64 / (1 + self.rng.below(1000)) as f64
+        }
+    }
+
+    /// Emits a memory immediate, keeping addresses near the start of memory
+    /// so that both in- and out-of-bounds accesses happen.
+    fn memarg(&mut self, out: &mut Vec<u8>, max_align: u64) {
+        uleb(out, self.rng.below(max_align as usize + 1) as u64);
+        let range = if self.chance(8) { 70000 } else { 64 };
+        uleb(out, self.rng.below(range) as u64);
+    }
+
+    fn address(&mut self, out: &mut Vec<u8>, depth: usize) {
+        if self.chance(2) {
+            out.push(0x41);
+            sleb(out, self.rng.below(256) as i64);
+        } else {
+            self.expr(out, I32, depth + 1);
+        }
+    }
+
+    fn local(&mut self, t: u8) -> Option<u32> {
+        let matching = (0..self.locals.len())
+            .filter(|i| self.locals[*i] == t)
+            .collect::<Vec<_>>();
+        matching
+            .get(self.rng.below(matching.len()))
+            .map(|i| *i as u32)
+    }
+
+    fn call(&mut self, out: &mut Vec<u8>, result: Option<u8>, depth: usize) -> bool {
+        let candidates = (0..self.sigs.len())
+            .filter(|i| self.sigs[*i].result == result)
+            .collect::<Vec<_>>();
+        let Some(f) = candidates.get(self.rng.below(candidates.len())).copied() else {
+            return false;
+        };
+        for p in self.sigs[f].params.clone() {
+            self.expr(out, p, depth + 1);
+        }
+        out.push(0x10);
+        uleb(out, f as u64);
+        true
+    }
+
+    fn constant(&mut self, out: &mut Vec<u8>, t: u8) {
+        match t {
+            I32 => {
+                out.push(0x41);
+                sleb(out, self.int() as i32 as i64);
+            }
+            I64 => {
+                out.push(0x42);
+                sleb(out, self.int());
+            }
+            F32 => {
+                out.push(0x43);
+                out.extend((self.float() as f32).to_le_bytes());
+            }
+            _ => {
+                out.push(0x44);
+                out.extend(self.float().to_le_bytes());
+            }
+        }
+    }
+
+    /// Emits instructions leaving exactly one value of type `t` on the stack.
+    fn expr(&mut self, out: &mut Vec<u8>, t: u8, depth: usize) {
+        if depth >= MAX_DEPTH || self.chance(4) {
+            match self.local(t) {
+                Some(l) if self.chance(2) => {
+                    out.push(0x20);
+                    uleb(out, l as u64);
+                }
+                _ => self.constant(out, t),
+            }
+            return;
+        }
+        let d = depth + 1;
+        match self.rng.below(10) {
+            0 => {
+                out.extend([0x02, t]);
+                self.expr(out, t, d);
+                if self.chance(2) {
+                    self.expr(out, I32, d);
+                    out.extend([0x0d, 0x00]);
+                }
+                out.push(0x0b);
+            }
+            1 => {
+                self.expr(out, I32, d);
+                out.extend([0x04, t]);
+                self.expr(out, t, d);
+                out.push(0x05);
+                self.expr(out, t, d);
+                out.push(0x0b);
+            }
+            2 => {
+                self.expr(out, t, d);
+                self.expr(out, t, d);
+                self.expr(out, I32, d);
+                out.push(0x1b);
+            }
+            3 if self.call(out, Some(t), depth) => {}
+            4 if self.local(t).is_some() => {
+                let l = self.local(t).unwrap_or_default();
+                self.expr(out, t, d);
+                out.push(0x22);
+                uleb(out, l as u64);
+            }
+            5 if self.memory => {
+                self.address(out, depth);
+                let (op, align) =
+                    match t {
+                        I32 => [(0x28, 2), (0x2c, 0), (0x2d, 0), (0x2e, 1), (0x2f, 1)]
+                            [self.rng.below(5)],
+                        I64 => [(0x29, 3), (0x30, 0), (0x31, 0), (0x32, 1), (0x34, 2)]
+                            [self.rng.below(5)],
+                        F32 => (0x2a, 2),
+                        _ => (0x2b, 3),
+                    };
+                out.push(op);
+                self.memarg(out, align);
+            }
+            6 if t == I32 && self.memory => {
+                if self.chance(2) {
+                    out.extend([0x3f, 0x00]);
+                } else {
+                    out.push(0x41);
+                    sleb(out, self.rng.below(3) as i64);
+                    out.extend([0x40, 0x00]);
+                }
+            }
+            7 => self.conversion(out, t, d),
+            _ => self.numeric(out, t, d),
+        }
+    }
+
+    fn numeric(&mut self, out: &mut Vec<u8>, t: u8, d: usize) {
+        let (unary, binary, compare): (&[u8], &[u8], bool) = match t {
+            I32 => (&[0x45, 0x67, 0x68, 0x69, 0xc0, 0xc1], &[0x6a, 0x78], true),
+            I64 => (&[0x79, 0x7a, 0x7b, 0xc2, 0xc3, 0xc4], &[0x7c, 0x8a], false),
+            F32 => (
+                &[0x8b, 0x8c, 0x8d, 0x8e, 0x8f, 0x90, 0x91],
+                &[0x92, 0x98],
+                false,
+            ),
+            _ => (
+                &[0x99, 0x9a, 0x9b, 0x9c, 0x9d, 0x9e, 0x9f],
+                &[0xa0, 0xa6],
+                false,
+            ),
+        };
+        match self.rng.below(if compare { 3 } else { 2 }) {
+            0 => {
+                self.expr(out, t, d);
+                out.push(unary[self.rng.below(unary.len())]);
+            }
+            1 => {
+                self.expr(out, t, d);
+                self.expr(out, t, d);
+                let [lo, hi] = [binary[0] as usize, binary[1] as usize];
+                out.push((lo + self.rng.below(hi - lo + 1)) as u8);
+            }
+            _ => {
+                let (operand, lo, hi) = [
+                    (I32, 0x46, 0x4f),
+                    (I64, 0x51, 0x5a),
+                    (F32, 0x5b, 0x60),
+                    (F64, 0x61, 0x66),
+                ][self.rng.below(4)];
+                self.expr(out, operand, d);
+                self.expr(out, operand, d);
+                out.push((lo + self.rng.below(hi - lo + 1)) as u8);
+            }
+        }
+    }
+
+    fn conversion(&mut self, out: &mut Vec<u8>, t: u8, d: usize) {
+        let options: &[(u8, &[u8])] = match t {
+            I32 => &[
+                (I64, &[0xa7]),
+                (F32, &[0xa8]),
+                (F32, &[0xa9]),
+                (F64, &[0xaa]),
+                (F64, &[0xab]),
+                (F32, &[0xbc]),
+                (F32, &[0xfc, 0x00]),
+                (F64, &[0xfc, 0x03]),
+            ],
+            I64 => &[
+                (I32, &[0xac]),
+                (I32, &[0xad]),
+                (F32, &[0xae]),
+                (F64, &[0xb1]),
+                (F64, &[0xbd]),
+                (F64, &[0xfc, 0x06]),
+            ],
+            F32 => &[
+                (I32, &[0xb2]),
+                (I32, &[0xb3]),
+                (I64, &[0xb4]),
+                (F64, &[0xb6]),
+                (I32, &[0xbe]),
+            ],
+            _ => &[
+                (I32, &[0xb7]),
+                (I64, &[0xb9]),
+                (I64, &[0xba]),
+                (F32, &[0xbb]),
+                (I64, &[0xbf]),
+            ],
+        };
+        let (from, op) = options[self.rng.below(options.len())];
+        self.expr(out, from, d);
+        out.extend(op);
+    }
+
+    /// Emits instructions that leave the stack as they found it.
+    fn stmt(&mut self, out: &mut Vec<u8>, depth: usize) {
+        let d = depth + 1;
+        match self.rng.below(12) {
+            0 | 1 if !self.locals.is_empty() => {
+                let l = self.rng.below(self.locals.len());
+                let t = self.locals[l];
+                self.expr(out, t, d);
+                out.push(0x21);
+                uleb(out, l as u64);
+            }
+            2 | 3 if self.memory => {
+                self.address(out, depth);
+                let t = self.num_type();
+                self.expr(out, t, d);
+                let (op, align) = match t {
+                    I32 => [(0x36, 2), (0x3a, 0), (0x3b, 1)][self.rng.below(3)],
+                    I64 => [(0x37, 3), (0x3c, 0), (0x3d, 1), (0x3e, 2)][self.rng.below(4)],
+                    F32 => (0x38, 2),
+                    _ => (0x39, 3),
+                };
+                out.push(op);
+                self.memarg(out, align);
+            }
+            4 if self.memory => {
+                self.address(out, depth);
+                self.expr(out, I32, d);
+                out.push(0x41);
+                sleb(out, self.rng.below(128) as i64);
+                if self.chance(2) {
+                    out.extend([0xfc, 0x0b, 0x00]);
+                } else {
+                    out.extend([0xfc, 0x0a, 0x00, 0x00]);
+                }
+            }
+            5 if depth < MAX_DEPTH => {
+                // a loop that runs until its condition is false, the step
+                // budget takes care of the ones that never stop
+                out.extend([0x03, 0x40]);
+                self.stmt(out, d);
+                self.expr(out, I32, d);
+                out.extend([0x0d, 0x00, 0x0b]);
+            }
+            6 if depth < MAX_DEPTH => {
+                out.extend([0x02, 0x40]);
+                self.stmt(out, d);
+                self.expr(out, I32, d);
+                out.extend([0x0d, 0x00]);
+                self.stmt(out, d);
+                out.push(0x0b);
+            }
+            7 if self.chance(4) => {
+                self.call(out, None, depth);
+            }
+            8 if self.chance(16) => out.push(0x00),
+            _ => {
+                let t = self.num_type();
+                self.expr(out, t, d);
+                out.push(0x1a);
+            }
+        }
+    }
+
+    fn body(&mut self, sig: usize) -> Vec<u8> {
+        let mut declared = Vec::new();
+        for _ in 0..self.rng.below(4) {
+            declared.push((1 + self.rng.below(3) as u32, self.num_type()));
+        }
+        self.locals = self.sigs[sig].params.clone();
+        for (n, t) in &declared {
+            self.locals.extend((0..*n).map(|_| *t));
+        }
+
+        let mut body = Vec::new();
+        uleb(&mut body, declared.len() as u64);
+        for (n, t) in declared {
+            uleb(&mut body, n as u64);
+            body.push(t);
+        }
+        for _ in 0..self.rng.below(6) {
+            self.stmt(&mut body, 0);
+        }
+        if let Some(t) = self.sigs[sig].result {
+            self.expr(&mut body, t, 0);
+            if self.chance(8) {
+                body.push(0x0f);
+            }
+        }
+        body.push(0x0b);
+        body
+    }
+
+    /// Generates a module whose function 0 is exported as `main` and takes
+    /// no parameters.
+    pub fn module(mut self) -> Vec<u8> {
+        let count = 1 + self.rng.below(4);
+        for i in 0..count {
+            let params = if i == 0 {
+                Vec::new()
+            } else {
+                (0..self.rng.below(3)).map(|_| self.num_type()).collect()
+            };
+            let result = (!self.chance(3)).then(|| self.num_type());
+            self.sigs.push(FuncSig { params, result });
+        }
+        self.memory = !self.chance(4);
+
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+
+        let mut types = Vec::new();
+        uleb(&mut types, count as u64);
+        for FuncSig { params, result } in &self.sigs {
+            types.push(0x60);
+            uleb(&mut types, params.len() as u64);
+            types.extend(params);
+            uleb(&mut types, result.is_some() as u64);
+            types.extend(result);
+        }
+        section(&mut out, 1, types);
+
+        let mut funcs = Vec::new();
+        uleb(&mut funcs, count as u64);
+        for i in 0..count {
+            uleb(&mut funcs, i as u64);
+        }
+        section(&mut out, 3, funcs);
+
+        let pages = self.rng.below(3) as u64;
+        if self.memory {
+            let mut mems = vec![0x01];
+            if self.chance(2) {
+                mems.push(0x00);
+                uleb(&mut mems, pages);
+            } else {
+                mems.push(0x01);
+                uleb(&mut mems, pages);
+                uleb(&mut mems, pages + self.rng.below(3) as u64);
+            }
+            section(&mut out, 5, mems);
+        }
+
+        let mut exports = vec![if self.memory { 0x02 } else { 0x01 }];
+        exports.extend([0x04, b'm', b'a', b'i', b'n', 0x00, 0x00]);
+        if self.memory {
+            exports.extend([0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00]);
+        }
+        section(&mut out, 7, exports);
+
+        let mut code = Vec::new();
+        uleb(&mut code, count as u64);
+        for i in 0..count {
+            let body = self.body(i);
+            uleb(&mut code, body.len() as u64);
+            code.extend(body);
+        }
+        section(&mut out, 10, code);
+
+        if self.memory && self.chance(2) {
+            let mut data = vec![0x01, 0x00, 0x41];
+            sleb(
+                &mut data,
+                self.rng.below(if pages == 0 { 4 } else { 1024 }) as i64,
+            );
+            data.push(0x0b);
+            let len = self.rng.below(16);
+            uleb(&mut data, len as u64);
+            data.extend((0..len).map(|_| self.rng.next() as u8));
+            section(&mut out, 11, data);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{
+    parser::{Module, Parsable},
+    runtime::{
+        clean_model::Model, Import, InternalError, InternalErrorKind, ResourceLimiter, Runtime,
+        RuntimeError, TrapCode, MAIN_MODULE,
+    },
+};
+use std::{collections::HashMap, io::Cursor, panic::AssertUnwindSafe, path::PathBuf, sync::Mutex};
+mod gen;
+pub use gen::Generator;
+
+/// How many instructions a generated module may execute before it is
+/// considered to be looping forever.
+const FUEL: u64 = 200_000;
+
+/// The message of the last panic, recorded by the hook installed in [`fuzz`].
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+/// A small xorshift generator, good enough to drive mutations without
+/// pulling in a dependency.
+pub struct Rng(u64);
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            (self.next() % n as u64) as usize
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Arbitrary bytes straight into [`Module::parse`].
+    Parse,
+    /// Mutated example modules, parsed and instantiated.
+    Load,
+    /// Generated valid modules, built and executed.
+    Run,
+}
+impl Target {
+    pub const ALL: [Target; 3] = [Target::Parse, Target::Load, Target::Run];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "parse" => Some(Self::Parse),
+            "load" => Some(Self::Load),
+            "run" => Some(Self::Run),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::Load => "load",
+            Self::Run => "run",
+        }
+    }
+}
+
+/// An input that broke one of the fuzzing invariants.
+#[derive(Debug)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// What running a generated module came to.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Finished,
+    Trapped(TrapCode),
+    OutOfFuel,
+    Broken(String),
+}
+
+pub fn parse(bytes: &[u8]) {
+    let mut cursor = Cursor::new(bytes);
+    let _ = Module::parse(&mut cursor, &mut Vec::new());
+}
+
+/// Parses and instantiates `bytes` against the spectest imports. Errors are
+/// fine, this only cares about the loader returning at all.
+pub fn load(bytes: &[u8]) {
+    let mut cursor = Cursor::new(bytes);
+    let mut stack = Vec::new();
+    let Ok(module) = Module::parse(&mut cursor, &mut stack) else {
+        return;
+    };
+    let mut imports = HashMap::new();
+    imports.insert("spectest".to_string(), Import::IO(Import::spectest()));
+    let _ = Model::try_from((&imports, module));
+}
+
+/// Runs `module` with [`FUEL`] handed out `chunk` at a time, refueling
+/// whenever it runs dry.
+fn run(module: &Module, chunk: u64) -> Outcome {
+    // keep runaway recursion and memory.grow from eating the machine
+    let limits = ResourceLimiter {
+        max_call_depth: 1000,
+        max_memory_pages: 256,
+        ..ResourceLimiter::default()
+    };
+    let builder = Runtime::builder().add_module(MAIN_MODULE, module);
+    let mut runtime = match builder.fuel(chunk).limits(limits).build() {
+        Ok(runtime) => runtime,
+        Err(RuntimeError::Trap(code)) => return Outcome::Trapped(code),
+        Err(e) => return Outcome::Broken(format!("failed to load: {e}")),
+    };
+    let mut given = chunk;
+    loop {
+        match runtime.step() {
+            Ok(()) => {}
+            Err(RuntimeError::Trap(TrapCode::OutOfFuel)) if given < FUEL => {
+                if runtime.fuel() != Some(0) {
+                    return Outcome::Broken("ran out of fuel with fuel left".to_string());
+                }
+                let more = chunk.min(FUEL - given);
+                runtime.add_fuel(more);
+                given += more;
+            }
+            Err(RuntimeError::Trap(TrapCode::OutOfFuel)) => return Outcome::OutOfFuel,
+            Err(RuntimeError::Trap(code)) => return Outcome::Trapped(code),
+            Err(
+                RuntimeError::Internal(InternalError {
+                    kind: InternalErrorKind::NoFrame,
+                    ..
+                })
+                | RuntimeError::ReturnedToNoFrame(_)
+                | RuntimeError::Exit(_),
+            ) => return Outcome::Finished,
+            Err(e) => return Outcome::Broken(format!("{e}")),
+        }
+    }
+}
+
+/// Runs a generated module three times, it should either finish, trap or run
+/// out of fuel, and do the same thing every time, including when it is paused
+/// and refueled along the way.
+fn check_run(bytes: &[u8]) -> Option<String> {
+    let module = match Module::from_bytes(bytes) {
+        Ok(module) => module,
+        Err(e) => return Some(format!("failed to parse: {e}")),
+    };
+    let first = run(&module, FUEL);
+    debug!("{first:?}");
+    if let Outcome::Broken(reason) = first {
+        return Some(reason);
+    }
+    [run(&module, FUEL), run(&module, 997)]
+        .into_iter()
+        .find(|other| *other != first)
+        .map(|other| format!("inconsistent outcome: {first:?} then {other:?}"))
+}
+
+fn mutate(rng: &mut Rng, seeds: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = seeds
+        .get(rng.below(seeds.len()))
+        .cloned()
+        .unwrap_or_default();
+    if data.is_empty() || rng.below(8) == 0 {
+        // start from the header so the fuzzer gets past the magic number
+        data = b"\0asm\x01\0\0\0".to_vec();
+        data.extend((0..rng.below(256)).map(|_| rng.next() as u8));
+    }
+    for _ in 0..=rng.below(8) {
+        let i = rng.below(data.len());
+        match rng.below(5) {
+            0 => data[i] = rng.next() as u8,
+            1 => data[i] ^= 1 << rng.below(8),
+            2 => data.insert(i, rng.next() as u8),
+            3 => {
+                data.remove(i);
+            }
+            _ => {
+                let interesting = [0x00, 0x01, 0x7f, 0x80, 0xff];
+                data[i] = interesting[rng.below(interesting.len())];
+            }
+        }
+        if data.is_empty() {
+            data.push(0);
+        }
+    }
+    data
+}
+
+/// Feeds `iterations` inputs to `target` and stops at the first one that
+/// panics or otherwise misbehaves, writing it to
+/// `crash-<target>-<seed>-<iteration>.wasm`.
+pub fn fuzz(target: Target, iterations: u64, seed: u64) -> Result<(), Failure> {
+    let seeds = std::fs::read_dir("examples")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "wasm"))
+        .filter_map(|p| std::fs::read(p).ok())
+        .collect::<Vec<_>>();
+
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|info| {
+        if let Ok(mut last) = LAST_PANIC.lock() {
+            *last = Some(info.to_string());
+        }
+    }));
+    let mut rng = Rng::new(seed);
+    let mut failure = None;
+    for i in 0..iterations {
+        let input = match target {
+            Target::Parse => (0..rng.below(512)).map(|_| rng.next() as u8).collect(),
+            Target::Load => mutate(&mut rng, &seeds),
+            Target::Run => Generator::new(&mut rng).module(),
+        };
+        let check = || match target {
+            Target::Parse => {
+                parse(&input);
+                None
+            }
+            Target::Load => {
+                load(&input);
+                None
+            }
+            Target::Run => check_run(&input),
+        };
+        let reason = match std::panic::catch_unwind(AssertUnwindSafe(check)) {
+            Ok(None) => continue,
+            Ok(Some(reason)) => reason,
+            Err(_) => LAST_PANIC
+                .lock()
+                .ok()
+                .and_then(|mut last| last.take())
+                .unwrap_or_else(|| "panicked".to_string()),
+        };
+        let path = PathBuf::from(format!("crash-{}-{seed}-{i}.wasm", target.name()));
+        let _ = std::fs::write(&path, &input);
+        failure = Some(Failure { path, reason });
+        break;
+    }
+    std::panic::set_hook(hook);
+    failure.map_or(Ok(()), Err)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs the fuzzer saved, each kept as it was found.
+    const CRASHES: [(&str, &[u8]); 1] = [(
+        "crash-run-12-52.wasm",
+        include_bytes!("crashes/crash-run-12-52.wasm"),
+    )];
+
+    #[test]
+    fn saved_crashes_run_cleanly() {
+        for (name, bytes) in CRASHES {
+            if let Some(reason) = check_run(bytes) {
+                panic!("{name}: {reason}");
+            }
+        }
+    }
+
+    #[test]
+    fn memory_copy_with_a_negative_length_traps() {
+        // `(func (memory.copy (i32.const 0) (i32.const 1) (i32.const -1)))`
+        // exported as `main` with one page of memory, the shape of
+        // crash-run-11-91.wasm, which overflowed the same addition
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type
+            0x03, 0x02, 0x01, 0x00, // function
+            0x05, 0x03, 0x01, 0x00, 0x01, // memory
+            0x07, 0x08, 0x01, 0x04, b'm', b'a', b'i', b'n', 0x00, 0x00, // export
+            0x0a, 0x0e, 0x01, 0x0c, 0x00, 0x41, 0x00, 0x41, 0x01, 0x41, 0x7f, 0xfc, 0x0a, 0x00,
+            0x00, 0x0b, // code
+        ];
+        let module = Module::from_bytes(&bytes).expect("the module parses");
+        assert_eq!(
+            run(&module, FUEL),
+            Outcome::Trapped(TrapCode::OutOfBoundsMemoryAccess)
+        );
+    }
+}
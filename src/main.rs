@@ -2,112 +2,361 @@
 #![forbid(clippy::unwrap_used)]
 #![deny(clippy::print_stdout)]
 #![deny(clippy::print_stderr)]
+use cli::{Cli, Command, RunOptions};
+use coverage::Coverage;
 use hex::Hex;
-use runtime::{Runtime, RuntimeError};
-use std::{env::args, mem::MaybeUninit};
+use parser::{Module, Parsable};
+use profile::Profiler;
+use runtime::{
+    Backtrace, Import, InternalError, InternalErrorKind, Runtime, RuntimeError, TrapCode, Value,
+    WasiConfig,
+};
+use std::{
+    env::args,
+    io::{Cursor, Write},
+};
+use strace::Strace;
+mod cli;
+mod coverage;
+mod debug;
+mod dwarf;
+mod fuzz;
 mod hex;
 mod parser;
+mod preinit;
+mod profile;
 mod ptr;
 mod runtime;
+mod strace;
 mod testsuite;
+mod wast;
 
 #[allow(unused_imports)]
 #[macro_use]
 extern crate log;
 
 fn alloc<const N: usize>() -> Hex<N> {
-    #[allow(clippy::uninit_assumed_init)]
-    #[allow(invalid_value)]
-    unsafe {
-        MaybeUninit::uninit().assume_init()
+    Hex([0; N])
+}
+
+fn build(opts: &RunOptions) -> Result<Runtime, RuntimeError> {
+    let mut wasi = WasiConfig {
+        args: vec![opts.path.display().to_string()],
+        env: opts.env.clone(),
+        dirs: opts.dirs.clone(),
+        deterministic: opts.deterministic,
+    };
+    if opts.invoke.is_none() {
+        wasi.args.extend(opts.args.iter().cloned());
+    }
+    let mut builder = Runtime::build(&opts.path)
+        .add_io(runtime::WASI_MODULE, Import::wasi(wasi))
+        .deterministic(opts.deterministic);
+    for (name, path) in &opts.preload {
+        builder = builder.add_ws(name, path);
+    }
+    if let Some(fuel) = opts.fuel {
+        builder = builder.fuel(fuel);
+    }
+    builder.build()
+}
+
+fn exit_on(e: RuntimeError) -> ! {
+    match e {
+        RuntimeError::Exit(x) => std::process::exit(x),
+        e => {
+            error!("{e}");
+            std::process::exit(1)
+        }
+    }
+}
+
+/// [`exit_on`], also showing where the guest was when it failed.
+fn exit_at(e: RuntimeError, backtrace: Option<&Backtrace>) -> ! {
+    match backtrace {
+        Some(bt) if !bt.frames.is_empty() && !matches!(e, RuntimeError::Exit(_)) => {
+            error!("{e}\nbacktrace:\n{bt}");
+            std::process::exit(1)
+        }
+        _ => exit_on(e),
+    }
+}
+
+/// Parses `args` against the parameter types of the export `name`.
+fn parse_args(runtime: &Runtime, name: &str, args: &[String]) -> Vec<Value> {
+    let ty = runtime.export_type(name).unwrap_or_else(|e| exit_on(e));
+    if args.len() != ty.input.types.len() {
+        error!(
+            "{name:?} takes {} arguments ({ty}), got {}",
+            ty.input.types.len(),
+            args.len()
+        );
+        std::process::exit(2)
+    }
+    args.iter()
+        .zip(&ty.input.types)
+        .map(|(a, t)| Value::parse_as(a, t))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| {
+            error!("{e}");
+            std::process::exit(2)
+        })
+}
+
+/// Calls the export `name` with `args` parsed against its parameter types and
+/// prints the results, one per line.
+fn invoke(runtime: &mut Runtime, name: &str, args: &[String]) {
+    let args = parse_args(runtime, name, args);
+    let results = runtime
+        .invoke(name, &args)
+        .unwrap_or_else(|e| exit_at(e, runtime.trap_backtrace()));
+    let mut out = std::io::stdout().lock();
+    for v in results {
+        if writeln!(out, "{v}").is_err() {
+            break;
+        }
+    }
+}
+
+fn run(opts: RunOptions) {
+    let mut runtime = build(&opts).unwrap_or_else(|e| {
+        error!("failed to load runtime: {e}");
+        std::process::exit(1)
+    });
+    if let Some(path) = &opts.restore {
+        let restored = std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|snapshot| runtime.restore(&snapshot).map_err(|e| e.to_string()));
+        if let Err(e) = restored {
+            error!("failed to restore {path:?}: {e}");
+            std::process::exit(1)
+        }
+    }
+    if opts.profile.is_some() || opts.coverage.is_some() || opts.strace {
+        return observed(runtime, &opts);
+    }
+    // `Runtime::invoke` unwinds the stack on errors, which leaves nothing
+    // to save
+    if let (Some(name), None) = (&opts.invoke, &opts.snapshot) {
+        return invoke(&mut runtime, name, &opts.args);
+    }
+    enter(&mut runtime, &opts);
+    let result = run_to_end(&mut runtime);
+    finish(&runtime, &opts, result)
+}
+
+/// Steps `runtime` until its outermost frame returns.
+fn run_to_end(runtime: &mut Runtime) -> Result<Vec<Value>, RuntimeError> {
+    loop {
+        match runtime.step() {
+            Ok(()) => {}
+            Err(RuntimeError::ReturnedToNoFrame(results)) => return Ok(results),
+            Err(RuntimeError::Internal(InternalError {
+                kind: InternalErrorKind::NoFrame,
+                ..
+            })) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs like [`run`] with the observers `--profile`, `--coverage` and
+/// `--strace` ask for, and writes their reports even when the program fails.
+fn observed(mut runtime: Runtime, opts: &RunOptions) {
+    enter(&mut runtime, opts);
+    if opts.strace {
+        runtime.observe(Strace::new(std::io::stderr()));
+    }
+    if opts.profile.is_some() {
+        runtime.observe(Profiler::default());
+    }
+    if opts.coverage.is_some() {
+        runtime.observe(Coverage::default());
+    }
+    let result = run_to_end(&mut runtime);
+    if let (Some(path), Some(profiler)) = (&opts.profile, runtime.observer::<Profiler>()) {
+        if let Err(e) = profiler.write(path) {
+            error!("failed to write the profile to {path:?}: {e}");
+        }
+        // the table goes to stderr, out of the way of the program's output
+        let _ = profiler.write_table(&mut std::io::stderr().lock());
+    }
+    if let (Some(path), Some(coverage)) = (&opts.coverage, runtime.observer::<Coverage>()) {
+        let written = std::fs::File::create(path).and_then(|file| {
+            let mut out = std::io::BufWriter::new(file);
+            if Coverage::has_lines(&runtime) {
+                coverage.write_lcov(&runtime, &mut out)?;
+            } else {
+                coverage.write_json(&runtime, &mut out)?;
+            }
+            out.flush()
+        });
+        if let Err(e) = written {
+            error!("failed to write the coverage to {path:?}: {e}");
+        }
+    }
+    finish(&runtime, opts, result)
+}
+
+/// Replaces the program's start with the call given by `--invoke`, if it
+/// was, for a runner that steps the runtime itself.
+fn enter(runtime: &mut Runtime, opts: &RunOptions) {
+    if let Some(name) = &opts.invoke {
+        // the program's own start is not what was asked for
+        runtime.stack.clear();
+        let args = parse_args(runtime, name, &opts.args);
+        if let Err(e) = runtime.enter_in(runtime::MAIN_MODULE, name, &args) {
+            exit_on(e)
+        }
+    }
+}
+
+/// Ends a run started by [`enter`]: prints the results of `--invoke`, saves
+/// the program for `--snapshot` if it ran out of fuel, or shows the error
+/// and where it happened.
+fn finish(runtime: &Runtime, opts: &RunOptions, result: Result<Vec<Value>, RuntimeError>) {
+    match (result, &opts.snapshot) {
+        (Ok(results), _) if opts.invoke.is_some() => {
+            let mut out = std::io::stdout().lock();
+            for v in results {
+                if writeln!(out, "{v}").is_err() {
+                    break;
+                }
+            }
+        }
+        (Ok(_), _) => {}
+        (Err(RuntimeError::Trap(TrapCode::OutOfFuel)), Some(path)) => {
+            let saved = runtime
+                .snapshot()
+                .map_err(|e| e.to_string())
+                .and_then(|snapshot| std::fs::write(path, snapshot).map_err(|e| e.to_string()));
+            match saved {
+                Ok(()) => info!("out of fuel, saved the program to {path:?}"),
+                Err(e) => {
+                    error!("failed to save the program to {path:?}: {e}");
+                    std::process::exit(1)
+                }
+            }
+        }
+        (Err(e), _) => exit_at(e, Some(&runtime.backtrace())),
+    }
+}
+
+/// Debugs the start of the program, or the call of the export given with
+/// `--invoke`.
+fn debug(opts: RunOptions) {
+    let mut runtime = build(&opts).unwrap_or_else(|e| {
+        error!("failed to load runtime: {e}");
+        std::process::exit(1)
+    });
+    enter(&mut runtime, &opts);
+    let debugged = match &opts.gdb {
+        Some(addr) => debug::gdb(runtime, addr),
+        None => debug::debug(
+            runtime,
+            std::io::stdin().lock(),
+            &mut std::io::stdout().lock(),
+        ),
+    };
+    if let Err(e) = debugged {
+        error!("{e}");
+        std::process::exit(1)
+    }
+}
+
+/// Runs the export `init` of the module and writes the module it leaves
+/// behind to `output`.
+fn preinit(opts: &RunOptions, init: &str, output: &std::path::Path) {
+    let module = Module::from_file(&opts.path).unwrap_or_else(|e| exit_on(e));
+    let mut runtime = build(opts).unwrap_or_else(|e| {
+        error!("failed to load runtime: {e}");
+        std::process::exit(1)
+    });
+    // the program itself is not what runs
+    runtime.stack.clear();
+    if let Err(e) = runtime.invoke(init, &[]) {
+        exit_at(e, runtime.trap_backtrace())
+    }
+    let module = preinit::initialized(&runtime, &module, init).unwrap_or_else(|e| {
+        error!("{e}");
+        std::process::exit(1)
+    });
+    let bytes = module.to_bytes().unwrap_or_else(|e| {
+        error!("failed to encode the initialized module: {e}");
+        std::process::exit(1)
+    });
+    if let Err(e) = std::fs::write(output, bytes) {
+        error!("failed to write {output:?}: {e}");
+        std::process::exit(1)
+    }
+}
+
+fn inspect(path: &std::path::Path) {
+    let buf = std::fs::read(path).unwrap_or_else(|e| {
+        error!("failed to read {path:?}: {e}");
+        std::process::exit(1)
+    });
+    let mut cursor = Cursor::new(&buf[..]);
+    match Module::parse(&mut cursor, &mut Vec::new()) {
+        Ok(module) => {
+            // a closed pipe, as with `wasp inspect x.wasm | head`, is fine
+            let _ = cli::inspect(&module, &mut std::io::stdout().lock());
+        }
+        Err(e) => {
+            error!("{e} (at byte {:#x})", cursor.position());
+            std::process::exit(1)
+        }
     }
 }
 
 fn main() {
-    pretty_env_logger::init();
-    let path = args()
-        .skip(1)
-        .find(|p| !p.starts_with("-"))
-        .unwrap_or("examples/c_addition.wasm".to_string());
-
-    if path.ends_with(".wast") {
-        testsuite::test(path);
-    } else {
-        let mut runtime = Runtime::build(path)
-            // .add_io(
-            //     "wasi_snapshot_preview1",
-            //     IO {
-            //         functions: {
-            //             let map: Vec<(&'static str, runtime::IOFunction)> = vec![
-            //                 (
-            //                     "args_sizes_get",
-            //                     IOFunction {
-            //                         ty: todo!(),
-            //                         func: &|_, _| Ok(vec![Value::I32(0)]),
-            //                     },
-            //                 ),
-            //                 #[allow(clippy::print_stdout)]
-            //                 ("fd_write", &|locals, memory| {
-            //                     let Some(Value::I32(fd)) = locals.get(&0) else {
-            //                         panic!()
-            //                     };
-            //                     let Some(Value::I32(iovs_base)) = locals.get(&1) else {
-            //                         panic!()
-            //                     };
-            //                     let Some(Value::I32(iovs_length)) = locals.get(&2) else {
-            //                         panic!()
-            //                     };
-            //                     let Some(Value::I32(bytes_written)) = locals.get(&2) else {
-            //                         panic!()
-            //                     };
-            //                     // println!("fd: {fd}, base: {iovs_base}, len: {iovs_length}");
-            //                     let bytes_written = *bytes_written as usize;
-            //                     let mut written = 0i32;
-            //                     for i in 0..*iovs_length as usize {
-            //                         let ptr = *iovs_base as usize + i * 8;
-            //                         let buf = memory.get::<u32>(ptr, MemArg::default())? as usize;
-            //                         let buf_len =
-            //                             memory.get::<u32>(ptr + 4, MemArg::default())? as usize;
-            //                         let mut b = Vec::new();
-            //                         for i in 0..buf_len {
-            //                             b.push(memory.get::<u8>(buf + i, MemArg::default())?);
-            //                             written += 1;
-            //                         }
-            //                         // print!("{b:?}");
-            //                         match fd {
-            //                             1 => print!("{}", String::from_utf8_lossy(&b)),
-            //                             _ => panic!(),
-            //                         }
-            //                     }
-            //                     memory.set(bytes_written, MemArg::default(), written)?;
-            //                     Ok(vec![Value::I32(0)])
-            //                 }),
-            //             ];
-            //             let mut res = HashMap::new();
-            //             for (k, v) in map {
-            //                 res.insert(k, v);
-            //             }
-            //             res
-            //         },
-            //         globals: HashMap::new(),
-            //         memory: runtime::Memory::new(0, 0).into(),
-            //     },
-            // )
-            .build()
-            .expect("Failed to load runtime");
-        // let memory = unsafe { runtime.modules["_$_main_$_"].as_ws() }
-        // .memory
-        // .clone();
-        loop {
-            if let Err(e) = runtime.step() {
-                match e {
-                    RuntimeError::Exit(x) => std::process::exit(x),
-                    _ => {
-                        error!("{e:?}");
+    let cli = cli::parse(args().skip(1));
+    let mut logger = pretty_env_logger::formatted_builder();
+    match &cli {
+        Ok(Cli {
+            log: Some(filter), ..
+        }) => logger.parse_filters(filter),
+        _ => logger.parse_filters(&std::env::var("RUST_LOG").unwrap_or_default()),
+    };
+    logger.init();
+
+    let command = cli.map(|c| c.command).unwrap_or_else(|e| {
+        error!("{e}\n\n{}", cli::USAGE);
+        std::process::exit(2)
+    });
+    match command {
+        Command::Run(opts) => run(opts),
+        Command::Debug(opts) => debug(opts),
+        Command::Validate(opts) => match build(&opts) {
+            Ok(_) => info!("{:?} is valid", opts.path),
+            Err(e) => {
+                error!("{:?} is invalid: {e}", opts.path);
+                std::process::exit(1)
+            }
+        },
+        Command::Preinit { opts, init, output } => preinit(&opts, &init, &output),
+        Command::Inspect(path) => inspect(&path),
+        Command::Test(opts) => testsuite::test(opts),
+        Command::Fuzz {
+            targets,
+            iterations,
+            seed,
+        } => {
+            for target in targets {
+                match fuzz::fuzz(target, iterations, seed) {
+                    Ok(()) => info!("{}: no failures in {iterations} iterations", target.name()),
+                    Err(fuzz::Failure { path, reason }) => {
+                        error!("{}: {reason}, input saved to {path:?}", target.name());
                         std::process::exit(1)
                     }
                 }
             }
         }
+        Command::Help => {
+            #[allow(clippy::print_stdout)]
+            {
+                println!("{}", cli::USAGE);
+            }
+        }
     }
 }
@@ -1,6 +1,6 @@
 use super::{Func, Parsable};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct Code {
     pub size: u32,
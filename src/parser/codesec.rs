@@ -1,13 +1,19 @@
 use super::{error::ParseError, Code, Parsable};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct CodeSection {
     pub size: u32,
     pub code: Vec<Code>,
+    /// Where the section's contents start in the module, which DWARF gives
+    /// code addresses relative to.
+    pub start: u32,
 }
 impl CodeSection {
     pub fn concat(&mut self, mut other: Self) {
+        if self.code.is_empty() {
+            self.start = other.start;
+        }
         self.size += other.size;
         self.code.append(&mut other.code);
     }
@@ -22,6 +28,7 @@ impl Parsable for CodeSection {
         Self: std::marker::Sized,
     {
         let size = u32::parse(data, stack)?;
+        let start = data.position() as u32;
         let expected = data.position() + size as u64;
         let code = Vec::parse(data, stack)?;
 
@@ -29,6 +36,6 @@ impl Parsable for CodeSection {
             return Err(ParseError::SectionSizeMismatch(expected, data.position()));
         }
 
-        Ok(Self { size, code })
+        Ok(Self { size, code, start })
     }
 }
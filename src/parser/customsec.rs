@@ -1,6 +1,6 @@
 use super::{error::ParseError, Name, Parsable};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct CustomSection {
     pub size: u32,
@@ -1,7 +1,7 @@
 use super::{Expr, MemIdX, Parsable};
 use crate::{hex::Hex, parser::error::ParseError};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub enum Data {
     Active(Expr, Vec<u8>),
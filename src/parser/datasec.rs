@@ -1,6 +1,6 @@
 use super::{error::ParseError, Data, Parsable};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct DataSection {
     pub size: u32,
@@ -2,7 +2,7 @@ use super::{error::ParseError, Expr, FuncIdx, Parsable, RefTyp, TableIdX};
 use Elem::*;
 pub type ElemKind = RefTyp;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub enum Elem {
     E0(Expr, Vec<FuncIdx>),
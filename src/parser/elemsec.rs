@@ -1,6 +1,6 @@
 use super::{error::ParseError, Elem, Parsable};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ElementSection {
     pub size: u32,
     pub elems: Vec<Elem>,
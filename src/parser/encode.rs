@@ -0,0 +1,912 @@
+//! Writing a [`Module`] back out in the binary format, the inverse of
+//! [`Parsable`]. What the parser does not keep comes out canonical: every
+//! LEB128 as short as it can be, sections in the order the spec lists them,
+//! exports sorted, and custom sections last.
+//!
+//! [`Parsable`]: super::Parsable
+
+use super::{
+    BlockType, Code, CustomSection, Data, DataIdx, Elem, ElemIdx, ExportDesc, Expr, Func, FuncIdx,
+    FuncType, Global, GlobalIdX, GlobalType, Import, ImportDesc, Instr, LabelIdX, Limits, LocalIdX,
+    Locals, Mem, MemArg, MemIdX, MemType, Module, Mutable, Name, NumType, RefTyp, ResultType,
+    Table, TableIdX, TableType, TypeIdX, ValType,
+};
+use std::fmt::Display;
+
+/// Something of a module the binary format has no way to write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodeError {
+    pub message: String,
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+type Result<T> = std::result::Result<T, EncodeError>;
+
+fn error<T>(message: impl Into<String>) -> Result<T> {
+    Err(EncodeError {
+        message: message.into(),
+    })
+}
+
+/// Writes a value the way the binary format has it.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()>;
+}
+
+fn unsigned(out: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut v: i64) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        // done once the rest is just the sign the last byte carries
+        if (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// A `0xfc` prefixed instruction.
+fn ext(out: &mut Vec<u8>, op: u32) {
+    out.push(0xfc);
+    unsigned(out, op.into());
+}
+
+/// Section `id` holding `body`, left out when there is nothing in it.
+fn section(out: &mut Vec<u8>, id: u8, count: usize, body: Vec<u8>) -> Result<()> {
+    if count == 0 {
+        return Ok(());
+    }
+    out.push(id);
+    body.len().encode(out)?;
+    out.extend(body);
+    Ok(())
+}
+
+/// A section holding the vector `items`.
+fn vec_section<T: Encode>(out: &mut Vec<u8>, id: u8, items: &[T]) -> Result<()> {
+    section(out, id, items.len(), encoded(items)?)
+}
+
+fn encoded<T: Encode + ?Sized>(v: &T) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    v.encode(&mut out)?;
+    Ok(out)
+}
+
+fn refers_to_data(instrs: &[Instr]) -> bool {
+    instrs.iter().any(|i| match i {
+        Instr::xfc_8_memory_init(..) | Instr::xfc_9_data_drop(_) => true,
+        Instr::x02_block(_, body) | Instr::x03_loop(_, body) => refers_to_data(body),
+        Instr::x04_if_else(_, then, otherwise) => {
+            refers_to_data(then) || otherwise.as_deref().is_some_and(refers_to_data)
+        }
+        _ => false,
+    })
+}
+
+impl Module {
+    /// Whether code refers to data segments by index, with `memory.init` or
+    /// `data.drop`, which takes the data count section.
+    pub fn refers_to_data(&self) -> bool {
+        self.code
+            .code
+            .iter()
+            .any(|c| refers_to_data(&c.code.e.instrs))
+    }
+
+    /// The module in the binary format, which parses back to the same
+    /// module.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend(*self.magic);
+        out.extend(*self.version);
+        vec_section(&mut out, 1, &self.types.function_types)?;
+        vec_section(&mut out, 2, &self.imports.imports)?;
+        vec_section(&mut out, 3, &self.funcs.functions)?;
+        vec_section(&mut out, 4, &self.tables.tables)?;
+        vec_section(&mut out, 5, &self.mems.mems)?;
+        vec_section(&mut out, 6, &self.globals.globals)?;
+
+        let mut exports: Vec<_> = self.exports.exports.iter().collect();
+        exports.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0)));
+        let mut body = Vec::new();
+        exports.len().encode(&mut body)?;
+        for (name, desc) in &exports {
+            name.as_bytes().encode(&mut body)?;
+            desc.encode(&mut body)?;
+        }
+        section(&mut out, 7, exports.len(), body)?;
+
+        if let Some(start) = self.start {
+            section(&mut out, 8, 1, encoded(&start)?)?;
+        }
+        vec_section(&mut out, 9, &self.elems.elems)?;
+        if self.refers_to_data() {
+            section(&mut out, 12, 1, encoded(&self.datas.data.len())?)?;
+        }
+        vec_section(&mut out, 10, &self.code.code)?;
+        vec_section(&mut out, 11, &self.datas.data)?;
+        for custom in &self.customs {
+            custom.encode(&mut out)?;
+        }
+        Ok(out)
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.len().encode(out)?;
+        for item in self {
+            item.encode(out)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self[..].encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.push(*self);
+        Ok(())
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        unsigned(out, *self as u64);
+        Ok(())
+    }
+}
+
+/// Lengths and counts, which the format has as `u32`s.
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        unsigned(out, *self as u64);
+        Ok(())
+    }
+}
+
+impl Encode for i32 {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        signed(out, *self as i64);
+        Ok(())
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        signed(out, *self);
+        Ok(())
+    }
+}
+
+impl Encode for f32 {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.extend(self.to_le_bytes());
+        Ok(())
+    }
+}
+
+impl Encode for f64 {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.extend(self.to_le_bytes());
+        Ok(())
+    }
+}
+
+macro_rules! index {
+    ($($t:ty),*) => {
+        $(impl Encode for $t {
+            fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+                self.0.encode(out)?;
+                Ok(())
+            }
+        })*
+    };
+}
+index!(DataIdx, ElemIdx, FuncIdx, GlobalIdX, LabelIdX, LocalIdX, MemIdX, TableIdX, TypeIdX);
+
+impl Encode for Name {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.as_bytes().encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for RefTyp {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.push(match self {
+            RefTyp::FuncRef => 0x70,
+            RefTyp::ExternRef => 0x6f,
+        });
+        Ok(())
+    }
+}
+
+impl Encode for ValType {
+    /// Fails on [`ValType::Poly`], which only exists while type checking.
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.push(match self {
+            ValType::Num(NumType::I32) => 0x7f,
+            ValType::Num(NumType::I64) => 0x7e,
+            ValType::Num(NumType::F32) => 0x7d,
+            ValType::Num(NumType::F64) => 0x7c,
+            ValType::Vec128 => 0x7b,
+            ValType::Ref(RefTyp::FuncRef) => 0x70,
+            ValType::Ref(RefTyp::ExternRef) => 0x6f,
+            ValType::Poly => return error("the polymorphic type has no encoding"),
+        });
+        Ok(())
+    }
+}
+
+impl Encode for ResultType {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.types.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for FuncType {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.push(0x60);
+        self.input.encode(out)?;
+        self.output.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for Limits {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Limits::Min(min) => {
+                out.push(0x00);
+                min.encode(out)?;
+            }
+            Limits::MinMax(min, max) => {
+                out.push(0x01);
+                min.encode(out)?;
+                max.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Encode for MemType {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.0.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for Mem {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.limits.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for TableType {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.et.encode(out)?;
+        self.lim.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for Table {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.et.encode(out)?;
+        self.lim.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for Mutable {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.push(match self {
+            Mutable::Const => 0x00,
+            Mutable::Var => 0x01,
+        });
+        Ok(())
+    }
+}
+
+impl Encode for GlobalType {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.t.encode(out)?;
+        self.mutable.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for Global {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.gt.encode(out)?;
+        self.e.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for ImportDesc {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            ImportDesc::Func(t) => {
+                out.push(0x00);
+                t.encode(out)?;
+            }
+            ImportDesc::Table(t) => {
+                out.push(0x01);
+                t.encode(out)?;
+            }
+            ImportDesc::Mem(m) => {
+                out.push(0x02);
+                m.encode(out)?;
+            }
+            ImportDesc::Global(g) => {
+                out.push(0x03);
+                g.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Encode for Import {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.module.encode(out)?;
+        self.name.encode(out)?;
+        self.desc.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for ExportDesc {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        let (kind, index) = match self {
+            ExportDesc::Func(i) => (0x00, i.0),
+            ExportDesc::Table(i) => (0x01, i.0),
+            ExportDesc::Mem(i) => (0x02, i.0),
+            ExportDesc::Global(i) => (0x03, i.0),
+        };
+        out.push(kind);
+        index.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for Locals {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.n.encode(out)?;
+        self.t.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for Func {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.t.encode(out)?;
+        self.e.encode(out)?;
+        Ok(())
+    }
+}
+
+impl Encode for Code {
+    /// Sized anew, `size` is what the function took up where it was parsed.
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        encoded(&self.code)?.encode(out)
+    }
+}
+
+impl Encode for Expr {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        for instr in &self.instrs {
+            instr.encode(out)?;
+        }
+        out.push(0x0b);
+        Ok(())
+    }
+}
+
+impl Encode for BlockType {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            BlockType::Eps => {
+                out.push(0x40);
+                Ok(())
+            }
+            BlockType::T(t) => t.encode(out),
+            BlockType::TypIdx(i) => i.encode(out),
+        }
+    }
+}
+
+impl Encode for MemArg {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        memarg(out, self);
+        Ok(())
+    }
+}
+
+impl Encode for Data {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Data::Active(e, bytes) => {
+                0u32.encode(out)?;
+                e.encode(out)?;
+                bytes.encode(out)?;
+            }
+            Data::Passive(bytes) => {
+                1u32.encode(out)?;
+                bytes.encode(out)?;
+            }
+            Data::ActiveX(m, e, bytes) => {
+                2u32.encode(out)?;
+                m.encode(out)?;
+                e.encode(out)?;
+                bytes.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Encode for Elem {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        // the element kind of the function index forms, funcref is all
+        // there is
+        const FUNCREF: u8 = 0x00;
+        match self {
+            Elem::E0(e, funcs) => {
+                0u32.encode(out)?;
+                e.encode(out)?;
+                funcs.encode(out)?;
+            }
+            Elem::E1(_, funcs) | Elem::E3(_, funcs) => {
+                (if let Elem::E1(..) = self { 1u32 } else { 3 }).encode(out)?;
+                out.push(FUNCREF);
+                funcs.encode(out)?;
+            }
+            Elem::E2(t, e, _, funcs) => {
+                2u32.encode(out)?;
+                t.encode(out)?;
+                e.encode(out)?;
+                out.push(FUNCREF);
+                funcs.encode(out)?;
+            }
+            Elem::E4(e, exprs) => {
+                4u32.encode(out)?;
+                e.encode(out)?;
+                exprs.encode(out)?;
+            }
+            Elem::E5(t, exprs) | Elem::E7(t, exprs) => {
+                (if let Elem::E5(..) = self { 5u32 } else { 7 }).encode(out)?;
+                t.encode(out)?;
+                exprs.encode(out)?;
+            }
+            Elem::E6(table, e, t, exprs) => {
+                6u32.encode(out)?;
+                table.encode(out)?;
+                e.encode(out)?;
+                t.encode(out)?;
+                exprs.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Encode for CustomSection {
+    /// Each of the custom sections this was parsed from, which
+    /// [`CustomSection::concat`] may have merged, under its name.
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        for contents in &self.sections {
+            let mut body = encoded(&self.name)?;
+            body.extend(contents);
+            section(out, 0, 1, body)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for Instr {
+    /// Fails on the instructions the runtime flattens blocks into and on
+    /// reserved opcodes, neither of which the parser makes.
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        use Instr::*;
+        match self {
+            x02_block(bt, body) | x03_loop(bt, body) => {
+                out.push(if let x02_block(..) = self { 0x02 } else { 0x03 });
+                bt.encode(out)?;
+                for instr in body {
+                    instr.encode(out)?;
+                }
+                out.push(0x0b);
+            }
+            x04_if_else(bt, then, otherwise) => {
+                out.push(0x04);
+                bt.encode(out)?;
+                for instr in then {
+                    instr.encode(out)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    out.push(0x05);
+                    for instr in otherwise {
+                        instr.encode(out)?;
+                    }
+                }
+                out.push(0x0b);
+            }
+            x0c_br(l) => {
+                out.push(0x0c);
+                l.encode(out)?;
+            }
+            x0d_br_if(l) => {
+                out.push(0x0d);
+                l.encode(out)?;
+            }
+            x0e_br_table(ls, l) => {
+                out.push(0x0e);
+                ls.encode(out)?;
+                l.encode(out)?;
+            }
+            x10_call(f) => {
+                out.push(0x10);
+                f.encode(out)?;
+            }
+            x11_call_indirect(ty, table) => {
+                out.push(0x11);
+                ty.encode(out)?;
+                table.encode(out)?;
+            }
+            x20_local_get(i) | x21_local_set(i) | x22_local_tee(i) => {
+                out.push(match self {
+                    x20_local_get(_) => 0x20,
+                    x21_local_set(_) => 0x21,
+                    _ => 0x22,
+                });
+                i.encode(out)?;
+            }
+            x23_global_get(i) | x24_global_set(i) => {
+                out.push(if let x23_global_get(_) = self {
+                    0x23
+                } else {
+                    0x24
+                });
+                i.encode(out)?;
+            }
+            x26_table_set(t) => {
+                out.push(0x26);
+                t.encode(out)?;
+            }
+            x28_i32_load(m) => memory(out, 0x28, m),
+            x29_i64_load(m) => memory(out, 0x29, m),
+            x2a_f32_load(m) => memory(out, 0x2a, m),
+            x2b_f64_load(m) => memory(out, 0x2b, m),
+            x2c_i32_load8_s(m) => memory(out, 0x2c, m),
+            x2d_i32_load8_u(m) => memory(out, 0x2d, m),
+            x2e_i32_load16_s(m) => memory(out, 0x2e, m),
+            x2f_i32_load16_u(m) => memory(out, 0x2f, m),
+            x30_i64_load8_s(m) => memory(out, 0x30, m),
+            x31_i64_load8_u(m) => memory(out, 0x31, m),
+            x32_i64_load16_s(m) => memory(out, 0x32, m),
+            x33_i64_load16_u(m) => memory(out, 0x33, m),
+            x34_i64_load32_s(m) => memory(out, 0x34, m),
+            x35_i64_load32_u(m) => memory(out, 0x35, m),
+            x36_i32_store(m) => memory(out, 0x36, m),
+            x37_i64_store(m) => memory(out, 0x37, m),
+            x38_f32_store(m) => memory(out, 0x38, m),
+            x39_f64_store(m) => memory(out, 0x39, m),
+            x3a_i32_store8(m) => memory(out, 0x3a, m),
+            x3b_i32_store16(m) => memory(out, 0x3b, m),
+            x3c_i64_store8(m) => memory(out, 0x3c, m),
+            x3d_i64_store16(m) => memory(out, 0x3d, m),
+            x3e_i64_store32(m) => memory(out, 0x3e, m),
+            x3f_memory_size(_) => out.extend([0x3f, 0x00]),
+            x40_memory_grow => out.extend([0x40, 0x00]),
+            x41_i32_const(v) => {
+                out.push(0x41);
+                v.encode(out)?;
+            }
+            x42_i64_const(v) => {
+                out.push(0x42);
+                v.encode(out)?;
+            }
+            x43_f32_const(v) => {
+                out.push(0x43);
+                v.encode(out)?;
+            }
+            x44_f64_const(v) => {
+                out.push(0x44);
+                v.encode(out)?;
+            }
+            xd0_ref_null(t) => {
+                out.push(0xd0);
+                t.encode(out)?;
+            }
+            xd2_ref_func(f) => {
+                out.push(0xd2);
+                f.encode(out)?;
+            }
+            xfc_8_memory_init(d, m) => {
+                ext(out, 8);
+                d.encode(out)?;
+                m.encode(out)?;
+            }
+            xfc_9_data_drop(d) => {
+                ext(out, 9);
+                d.encode(out)?;
+            }
+            xfc_10_memory_copy(to, from) => {
+                ext(out, 10);
+                to.encode(out)?;
+                from.encode(out)?;
+            }
+            xfc_11_memory_fill(m) => {
+                ext(out, 11);
+                m.encode(out)?;
+            }
+            xfc_12_table_init(e, t) => {
+                ext(out, 12);
+                e.encode(out)?;
+                t.encode(out)?;
+            }
+            xfc_13_elem_drop(e) => {
+                ext(out, 13);
+                e.encode(out)?;
+            }
+            xfc_14_table_copy(to, from) => {
+                ext(out, 14);
+                to.encode(out)?;
+                from.encode(out)?;
+            }
+            xfc_15_table_grow(t) => {
+                ext(out, 15);
+                t.encode(out)?;
+            }
+            xfc_16_table_size(t) => {
+                ext(out, 16);
+                t.encode(out)?;
+            }
+            xfc_17_table_fill(t) => {
+                ext(out, 17);
+                t.encode(out)?;
+            }
+            comment(_, instr) => instr.encode(out)?,
+            x00_unreachable => out.push(0x00),
+            x01_nop => out.push(0x01),
+            x0f_return => out.push(0x0f),
+            x1a_drop => out.push(0x1a),
+            x1b_select => out.push(0x1b),
+            x45_i32_eqz => out.push(0x45),
+            x46_i32_eq => out.push(0x46),
+            x47_i32_ne => out.push(0x47),
+            x48_i32_lt_s => out.push(0x48),
+            x49_i32_lt_u => out.push(0x49),
+            x4a_i32_gt_s => out.push(0x4a),
+            x4b_i32_gt_u => out.push(0x4b),
+            x4c_i32_le_s => out.push(0x4c),
+            x4d_i32_le_u => out.push(0x4d),
+            x4e_i32_ge_s => out.push(0x4e),
+            x4f_i32_ge_u => out.push(0x4f),
+            x50_i64_eqz => out.push(0x50),
+            x51_i64_eq => out.push(0x51),
+            x52_i64_ne => out.push(0x52),
+            x53_i64_lt_s => out.push(0x53),
+            x54_i64_lt_u => out.push(0x54),
+            x55_i64_gt_s => out.push(0x55),
+            x56_i64_gt_u => out.push(0x56),
+            x57_i64_le_s => out.push(0x57),
+            x58_i64_le_u => out.push(0x58),
+            x59_i64_ge_s => out.push(0x59),
+            x5a_i64_ge_u => out.push(0x5a),
+            x5b_f32_eq => out.push(0x5b),
+            x5c_f32_ne => out.push(0x5c),
+            x5d_f32_lt => out.push(0x5d),
+            x5e_f32_gt => out.push(0x5e),
+            x5f_f32_le => out.push(0x5f),
+            x60_f32_ge => out.push(0x60),
+            x61_f64_eq => out.push(0x61),
+            x62_f64_ne => out.push(0x62),
+            x63_f64_lt => out.push(0x63),
+            x64_f64_gt => out.push(0x64),
+            x65_f64_le => out.push(0x65),
+            x66_f64_ge => out.push(0x66),
+            x67_i32_clz => out.push(0x67),
+            x68_i32_ctz => out.push(0x68),
+            x69_i32_popcnt => out.push(0x69),
+            x6a_i32_add => out.push(0x6a),
+            x6b_i32_sub => out.push(0x6b),
+            x6c_i32_mul => out.push(0x6c),
+            x6d_i32_div_s => out.push(0x6d),
+            x6e_i32_div_u => out.push(0x6e),
+            x6f_i32_rem_s => out.push(0x6f),
+            x70_i32_rem_u => out.push(0x70),
+            x71_i32_and => out.push(0x71),
+            x72_i32_or => out.push(0x72),
+            x73_i32_xor => out.push(0x73),
+            x74_i32_shl => out.push(0x74),
+            x75_i32_shr_s => out.push(0x75),
+            x76_i32_shr_u => out.push(0x76),
+            x77_i32_rotl => out.push(0x77),
+            x78_i32_rotr => out.push(0x78),
+            x79_i64_clz => out.push(0x79),
+            x7a_i64_ctz => out.push(0x7a),
+            x7b_i64_popcnt => out.push(0x7b),
+            x7c_i64_add => out.push(0x7c),
+            x7d_i64_sub => out.push(0x7d),
+            x7e_i64_mul => out.push(0x7e),
+            x7f_i64_div_s => out.push(0x7f),
+            x80_i64_div_u => out.push(0x80),
+            x81_i64_rem_s => out.push(0x81),
+            x82_i64_rem_u => out.push(0x82),
+            x83_i64_and => out.push(0x83),
+            x84_i64_or => out.push(0x84),
+            x85_i64_xor => out.push(0x85),
+            x86_i64_shl => out.push(0x86),
+            x87_i64_shr_s => out.push(0x87),
+            x88_i64_shr_u => out.push(0x88),
+            x89_i64_rotl => out.push(0x89),
+            x8a_i64_rotr => out.push(0x8a),
+            x8b_f32_abs => out.push(0x8b),
+            x8c_f32_neg => out.push(0x8c),
+            x8d_f32_ceil => out.push(0x8d),
+            x8e_f32_floor => out.push(0x8e),
+            x8f_f32_trunc => out.push(0x8f),
+            x90_f32_nearest => out.push(0x90),
+            x91_f32_sqrt => out.push(0x91),
+            x92_f32_add => out.push(0x92),
+            x93_f32_sub => out.push(0x93),
+            x94_f32_mul => out.push(0x94),
+            x95_f32_div => out.push(0x95),
+            x96_f32_min => out.push(0x96),
+            x97_f32_max => out.push(0x97),
+            x98_f32_copysign => out.push(0x98),
+            x99_f64_abs => out.push(0x99),
+            x9a_f64_neg => out.push(0x9a),
+            x9b_f64_ceil => out.push(0x9b),
+            x9c_f64_floor => out.push(0x9c),
+            x9d_f64_trunc => out.push(0x9d),
+            x9e_f64_nearest => out.push(0x9e),
+            x9f_f64_sqrt => out.push(0x9f),
+            xa0_f64_add => out.push(0xa0),
+            xa1_f64_sub => out.push(0xa1),
+            xa2_f64_mul => out.push(0xa2),
+            xa3_f64_div => out.push(0xa3),
+            xa4_f64_min => out.push(0xa4),
+            xa5_f64_max => out.push(0xa5),
+            xa6_f64_copysign => out.push(0xa6),
+            xa7_i32_wrap_i64 => out.push(0xa7),
+            xa8_i32_trunc_f32_s => out.push(0xa8),
+            xa9_i32_trunc_f32_u => out.push(0xa9),
+            xaa_i32_trunc_f64_s => out.push(0xaa),
+            xab_i32_trunc_f64_u => out.push(0xab),
+            xac_i64_extend_i32_s => out.push(0xac),
+            xad_i64_extend_i32_u => out.push(0xad),
+            xae_i64_trunc_f32_s => out.push(0xae),
+            xaf_i64_trunc_f32_u => out.push(0xaf),
+            xb0_i64_trunc_f64_s => out.push(0xb0),
+            xb1_i64_trunc_f64_u => out.push(0xb1),
+            xb2_f32_convert_i32_s => out.push(0xb2),
+            xb3_f32_convert_i32_u => out.push(0xb3),
+            xb4_f32_convert_i64_s => out.push(0xb4),
+            xb5_f32_convert_i64_u => out.push(0xb5),
+            xb6_f32_demote_f64 => out.push(0xb6),
+            xb7_f64_convert_i32_s => out.push(0xb7),
+            xb8_f64_convert_i32_u => out.push(0xb8),
+            xb9_f64_convert_i64_s => out.push(0xb9),
+            xba_f64_convert_i64_u => out.push(0xba),
+            xbb_f64_promote_f32 => out.push(0xbb),
+            xbc_i32_reinterpret_f32 => out.push(0xbc),
+            xbd_i64_reinterpret_f64 => out.push(0xbd),
+            xbe_f32_reinterpret_i32 => out.push(0xbe),
+            xbf_f64_reinterpret_i64 => out.push(0xbf),
+            xc0_i32_extend8_s => out.push(0xc0),
+            xc1_i32_extend16_s => out.push(0xc1),
+            xc2_i64_extend8_s => out.push(0xc2),
+            xc3_i64_extend16_s => out.push(0xc3),
+            xc4_i64_extend32_s => out.push(0xc4),
+            xfc_0_i32_trunc_sat_f32_s => ext(out, 0),
+            xfc_1_i32_trunc_sat_f32_u => ext(out, 1),
+            xfc_2_i32_trunc_sat_f64_s => ext(out, 2),
+            xfc_3_i32_trunc_sat_f64_u => ext(out, 3),
+            xfc_4_i64_trunc_sat_f32_s => ext(out, 4),
+            xfc_5_i64_trunc_sat_f32_u => ext(out, 5),
+            xfc_6_i64_trunc_sat_f64_s => ext(out, 6),
+            xfc_7_i64_trunc_sat_f64_u => ext(out, 7),
+            instr => {
+                return error(format!(
+                    "{instr:?} is not an instruction of the binary format"
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn memarg(out: &mut Vec<u8>, m: &MemArg) {
+    // kept as the alignment itself, the format has its exponent
+    unsigned(out, m.align.trailing_zeros().into());
+    unsigned(out, m.offset.into());
+}
+
+fn memory(out: &mut Vec<u8>, op: u8, m: &MemArg) {
+    out.push(op);
+    memarg(out, m);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reencode(bytes: &[u8]) -> Vec<u8> {
+        Module::from_bytes(bytes)
+            .expect("the module parses")
+            .to_bytes()
+            .expect("the module encodes")
+    }
+
+    #[test]
+    fn examples_come_back_the_same() {
+        for name in ["c_addition", "hello_world", "rust_addition"] {
+            let bytes = std::fs::read(format!("examples/{name}.wasm")).expect("the example exists");
+            let once = reencode(&bytes);
+            assert_eq!(reencode(&once), once, "{name}");
+        }
+    }
+
+    #[test]
+    fn canonical_modules_are_written_byte_for_byte() {
+        // a memory, an exported function with a local, a data segment and
+        // the data count section `memory.init` needs
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // type
+            0x03, 0x02, 0x01, 0x00, // function
+            0x05, 0x03, 0x01, 0x00, 0x01, // memory
+            0x07, 0x08, 0x01, 0x04, b'm', b'a', b'i', b'n', 0x00, 0x00, // export
+            0x0c, 0x01, 0x01, // data count
+            0x0a, 0x12, 0x01, 0x10, 0x01, 0x01, 0x7f, 0x41, 0x00, 0x41, 0x00, 0x41, 0x02, 0xfc,
+            0x08, 0x00, 0x00, 0x20, 0x00, 0x0b, // code
+            0x0b, 0x05, 0x01, 0x01, 0x02, b'h', b'i', // data
+        ];
+        assert_eq!(reencode(&bytes), bytes);
+    }
+
+    #[test]
+    fn what_the_format_cannot_hold_is_an_error() {
+        let mut out = Vec::new();
+        assert!(ValType::Poly.encode(&mut out).is_err());
+        assert!(Instr::else_jump(0).encode(&mut out).is_err());
+        let nop = Instr::comment("kept out".to_string(), Box::new(Instr::x01_nop));
+        assert_eq!(nop.encode(&mut out), Ok(()));
+        assert_eq!(out, [0x01]);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::hex::Hex;
-use std::io;
+use std::{fmt::Display, io};
 
 #[derive(Debug)]
 #[allow(unused)]
@@ -18,6 +18,7 @@ pub enum ParseError {
     InvalidExportDesc(Hex<1>),
     InvalidLimit(Hex<1>),
     UnknownInstruction(Hex<1>),
+    UnknownExtendedInstruction(u8, u32),
     Unknown0x40(Hex<1>),
     InvalidData(Hex<1>),
     InvalidRefType(Hex<1>),
@@ -34,6 +35,7 @@ pub enum ParseError {
     OutOfOrderSection,
     TypeMismatch,
     ExpectedZero,
+    NestingTooDeep(usize),
 }
 impl From<io::Error> for ParseError {
     fn from(value: io::Error) -> Self {
@@ -68,3 +70,82 @@ pub enum ModuleError {
 pub enum SectionError {
     UnknownHeader(Hex<1>),
 }
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ExponentTooLarge(e) => write!(f, "malformed memop flags (alignment 2^{e})"),
+            Self::InvalidModule(e) => write!(f, "{e}"),
+            Self::InvalidSection(e) => write!(f, "{e}"),
+            Self::NotImplemented(what) => write!(f, "not implemented: {what}"),
+            Self::IOError(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                write!(f, "unexpected end")
+            }
+            Self::IOError(e) => write!(f, "{e}"),
+            Self::Leb128(e) => write!(f, "integer representation too long ({e})"),
+            Self::SignedIntegerTooLarge(i) => write!(f, "integer too large ({i})"),
+            Self::UnsignedIntegerTooLarge(i) => write!(f, "integer too large ({i})"),
+            Self::UnknownType(b) => write!(f, "malformed value type {b:?}"),
+            Self::InvalidFuncType(b) => write!(f, "malformed function type {b:?}"),
+            Self::InvalidImportDesc(b) => write!(f, "malformed import kind {b:?}"),
+            Self::InvalidExportDesc(b) => write!(f, "malformed export kind {b:?}"),
+            Self::InvalidLimit(b) => write!(f, "integer too large (limits flag {b:?})"),
+            Self::UnknownInstruction(b) => write!(f, "illegal opcode {b:?}"),
+            Self::UnknownExtendedInstruction(prefix, ind) => {
+                write!(f, "illegal opcode {prefix:#04x} {ind}")
+            }
+            Self::Unknown0x40(b) => write!(f, "zero byte expected (got {b:?})"),
+            Self::InvalidData(b) => write!(f, "malformed data segment {b:?}"),
+            Self::InvalidRefType(b) => write!(f, "malformed reference type {b:?}"),
+            Self::InvalidElem(e) => write!(f, "malformed elements segment kind {e}"),
+            Self::EndOfInstructions => write!(f, "unexpected end of instructions"),
+            Self::ElseHit => write!(f, "unexpected else"),
+            Self::AlignmentError => write!(f, "alignment must not be larger than natural"),
+            Self::SectionSizeMismatch(exp, got) => {
+                write!(f, "section size mismatch (expected end {exp}, got {got})")
+            }
+            Self::TooManyLocals(n) => write!(f, "too many locals ({n})"),
+            Self::InconsistentFunctionAndCodeSectionLength => {
+                write!(f, "function and code section have inconsistent lengths")
+            }
+            Self::InvalidDataCount => {
+                write!(f, "data count and data section have inconsistent lengths")
+            }
+            Self::NoDataCountSection => write!(f, "data count section required"),
+            Self::DuplicateSection(id) => write!(f, "unexpected content after last section ({id})"),
+            Self::OutOfOrderSection => write!(f, "unexpected content after last section"),
+            Self::TypeMismatch => write!(f, "type mismatch"),
+            Self::ExpectedZero => write!(f, "zero byte expected"),
+            Self::NestingTooDeep(depth) => write!(f, "nesting too deep ({depth} levels)"),
+        }
+    }
+}
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::IOError(e) => Some(e),
+            Self::InvalidModule(e) => Some(e),
+            Self::InvalidSection(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ModuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHeader(h) => write!(f, "magic header not detected {h:?}"),
+            Self::InvalidVersion(v) => write!(f, "unknown binary version {v:?}"),
+        }
+    }
+}
+impl std::error::Error for ModuleError {}
+
+impl Display for SectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownHeader(h) => write!(f, "malformed section id {h:?}"),
+        }
+    }
+}
+impl std::error::Error for SectionError {}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use super::{error::ParseError, Export, ExportDesc, Parsable};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct ExportSection {
     pub size: u32,
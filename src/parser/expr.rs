@@ -4,6 +4,11 @@ use super::{error::ParseError, Instr, Parsable};
 #[allow(unused)]
 pub struct Expr {
     pub instrs: Vec<Instr>,
+    /// Where each instruction starts in the module, in the order they come
+    /// in the binary: a block's own offset, then those of the instructions
+    /// in it, then that of its `else` if it has one and those after it, then
+    /// that of its `end`. The offset of the expression's own `end` is last.
+    pub offsets: Vec<u32>,
 }
 impl Parsable for Expr {
     fn parse_inner(
@@ -14,16 +19,18 @@ impl Parsable for Expr {
         Self: std::marker::Sized,
     {
         let mut instrs = Vec::new();
+        let mut offsets = Vec::new();
         loop {
-            match Instr::parse(data, stack) {
+            match Instr::parse_located(data, stack, &mut offsets) {
                 Ok(i) => instrs.push(i),
                 Err(ParseError::EndOfInstructions) => {
                     stack.pop();
+                    offsets.push(data.position() as u32 - 1);
                     break;
                 }
                 Err(e) => Err(e)?,
             }
         }
-        Ok(Self { instrs })
+        Ok(Self { instrs, offsets })
     }
 }
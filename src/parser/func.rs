@@ -1,6 +1,6 @@
 use super::{Expr, Locals, Parsable};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct Func {
     pub t: Vec<Locals>,
@@ -1,6 +1,6 @@
 use super::{Parsable, TypeIdX};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct FunctionSection {
     pub size: u32,
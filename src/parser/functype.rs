@@ -9,6 +9,23 @@ pub struct FuncType {
     #[allow(unused)]
     pub output: ResultType,
 }
+impl std::fmt::Display for FuncType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |types: &[super::ValType]| {
+            types
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(
+            f,
+            "({}) -> ({})",
+            list(&self.input.types),
+            list(&self.output.types)
+        )
+    }
+}
 impl Parsable for FuncType {
     fn parse_inner(
         data: &mut std::io::Cursor<&[u8]>,
@@ -1,6 +1,6 @@
 use super::{Expr, GlobalType, Parsable};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct Global {
     pub gt: GlobalType,
@@ -1,6 +1,6 @@
 use super::{error::ParseError, Global, Parsable};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct GlobalSection {
     pub size: u32,
     pub globals: Vec<Global>,
@@ -1,6 +1,6 @@
 use super::{Mutable, Parsable, ValType};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct GlobalType {
     pub t: ValType,
     pub mutable: Mutable,
@@ -2,7 +2,7 @@ use crate::parser::Name;
 
 use super::{ImportDesc, Parsable};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct Import {
     pub module: Name,
@@ -4,7 +4,7 @@ use crate::hex::Hex;
 
 use super::{error::ParseError, GlobalType, MemType, Parsable, TableType, TypeIdX};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub enum ImportDesc {
     Func(TypeIdX),
@@ -1,6 +1,6 @@
 use super::{error::ParseError, Import, Parsable};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct ImportSection {
     pub size: u32,
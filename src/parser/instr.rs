@@ -1,6 +1,6 @@
 use super::{
     error::ParseError, BlockType, DataIdx as DataIdX, ElemIdx, FuncIdx, GlobalIdX, LabelIdX,
-    LocalIdX, MemArg, MemIdX, Parsable, RefTyp, TableIdX, TypeIdX,
+    LocalIdX, MemArg, MemIdX, Parsable, RefTyp, TableIdX, TypeIdX, MAX_PARSE_DEPTH,
 };
 use crate::hex::Hex;
 use std::io::Read;
@@ -304,88 +304,131 @@ impl Parsable for Instr {
     where
         Self: std::marker::Sized,
     {
+        Instr::parse_block(data, stack, &mut Vec::new())
+    }
+}
+
+impl Instr {
+    /// [`Parsable::parse`] that also pushes where in the module this
+    /// instruction starts to `offsets`, in the order [`Expr::offsets`]
+    /// describes.
+    ///
+    /// [`Expr::offsets`]: super::Expr::offsets
+    pub fn parse_located(
+        data: &mut std::io::Cursor<&[u8]>,
+        stack: super::DebugStack,
+        offsets: &mut Vec<u32>,
+    ) -> Result<Self, ParseError> {
+        if stack.len() >= MAX_PARSE_DEPTH {
+            return Err(ParseError::NestingTooDeep(stack.len()));
+        }
+        stack.push(Self::STACK_NAME);
+        let len = offsets.len();
+        offsets.push(data.position() as u32);
+        match Instr::parse_block(data, stack, offsets) {
+            Ok(instr) => {
+                stack.pop();
+                Ok(instr)
+            }
+            Err(e) => {
+                // `end` and `else` are not instructions of their own, whoever
+                // parses the block they close records them
+                offsets.truncate(len);
+                Err(e)
+            }
+        }
+    }
+
+    /// Parses instructions up to the `end` or `else` that stops them, which
+    /// is returned with where it is.
+    fn parse_until(
+        data: &mut std::io::Cursor<&[u8]>,
+        stack: super::DebugStack,
+        offsets: &mut Vec<u32>,
+    ) -> Result<(Vec<Instr>, ParseError), ParseError> {
+        let mut v = Vec::new();
+        loop {
+            match Instr::parse_located(data, stack, offsets) {
+                Ok(i) => v.push(i),
+                Err(e @ (ParseError::EndOfInstructions | ParseError::ElseHit)) => {
+                    stack.pop();
+                    offsets.push(data.position() as u32 - 1);
+                    return Ok((v, e));
+                }
+                Err(e) => Err(e)?,
+            }
+        }
+    }
+
+    fn parse_block(
+        data: &mut std::io::Cursor<&[u8]>,
+        stack: super::DebugStack,
+        offsets: &mut Vec<u32>,
+    ) -> Result<Self, ParseError> {
         macro_rules! p {
             () => {
                 Parsable::parse(data, stack)?
             };
         }
-        macro_rules! val {
-            ($cons:expr, $size:expr) => {{
-                let p = MemArg::parse(data, stack)?;
-                if p.align > $size {
-                    return Err(ParseError::AlignmentError);
+        macro_rules! body {
+            () => {
+                match Instr::parse_until(data, stack, offsets)? {
+                    (v, ParseError::EndOfInstructions) => v,
+                    (_, e) => Err(e)?,
                 }
-                $cons(p)
-            }};
+            };
         }
         let mut typ = [0];
         data.read_exact(&mut typ)?;
+        // Blocks recurse, so they are kept out of `parse_op` whose frame is
+        // large enough to overflow the stack on deeply nested code.
         Ok(match typ[0] {
-            0x00 => x00_unreachable,
-            0x01 => x01_nop,
             0x02 => {
                 let block_type = p!();
-                let mut v = Vec::new();
-                loop {
-                    match Instr::parse(data, stack) {
-                        Ok(i) => v.push(i),
-                        Err(ParseError::EndOfInstructions) => {
-                            stack.pop();
-                            break;
-                        }
-                        Err(e) => Err(e)?,
-                    }
-                }
-                x02_block(block_type, v)
+                x02_block(block_type, body!())
             }
             0x03 => {
                 let block_type = p!();
-                let mut v = Vec::new();
-                loop {
-                    match Instr::parse(data, stack) {
-                        Ok(i) => v.push(i),
-                        Err(ParseError::EndOfInstructions) => {
-                            stack.pop();
-                            break;
-                        }
-                        Err(e) => Err(e)?,
-                    }
-                }
-                x03_loop(block_type, v)
+                x03_loop(block_type, body!())
             }
             0x04 => {
                 let block_type = p!();
-                let mut v = Vec::new();
-                loop {
-                    match Instr::parse(data, stack) {
-                        Ok(i) => v.push(i),
-                        Err(ParseError::ElseHit) => {
-                            stack.pop();
-                            break;
-                        }
-                        Err(ParseError::EndOfInstructions) => {
-                            stack.pop();
-                            return Ok(x04_if_else(block_type, v, None));
-                        }
-                        Err(e) => Err(e)?,
-                    }
+                match Instr::parse_until(data, stack, offsets)? {
+                    (v, ParseError::ElseHit) => x04_if_else(block_type, v, Some(body!())),
+                    (v, _) => x04_if_else(block_type, v, None),
                 }
-                let els = {
-                    let mut v = Vec::new();
-                    loop {
-                        match Instr::parse(data, stack) {
-                            Ok(i) => v.push(i),
-                            Err(ParseError::EndOfInstructions) => {
-                                stack.pop();
-                                break;
-                            }
-                            Err(e) => Err(e)?,
-                        }
-                    }
-                    v
-                };
-                x04_if_else(block_type, v, Some(els))
             }
+            _ => Instr::parse_op(typ, data, stack)?,
+        })
+    }
+}
+
+impl Instr {
+    #[inline(never)]
+    fn parse_op(
+        typ: [u8; 1],
+        data: &mut std::io::Cursor<&[u8]>,
+        stack: super::DebugStack,
+    ) -> Result<Self, ParseError> {
+        macro_rules! p {
+            () => {
+                Parsable::parse(data, stack)?
+            };
+        }
+        macro_rules! val {
+            ($cons:expr, $size:expr) => {{
+                let p = MemArg::parse(data, stack)?;
+                if p.align > $size {
+                    return Err(ParseError::AlignmentError);
+                }
+                $cons(p)
+            }};
+        }
+        Ok(match typ[0] {
+            0x00 => x00_unreachable,
+            0x01 => x01_nop,
+            // parsed by `parse_inner`
+            0x02..=0x04 => Err(ParseError::UnknownInstruction(Hex(typ)))?,
             0x05 => Err(ParseError::ElseHit)?,
             0x06 => Err(ParseError::UnknownInstruction(Hex(typ)))?,
             0x07 => Err(ParseError::UnknownInstruction(Hex(typ)))?,
@@ -664,7 +707,7 @@ impl Parsable for Instr {
                 15 => xfc_15_table_grow(p!()),
                 16 => xfc_16_table_size(p!()),
                 17 => xfc_17_table_fill(p!()),
-                ind => todo!("0xfc {ind}"),
+                ind => Err(ParseError::UnknownExtendedInstruction(0xfc, ind))?,
             },
             0xfd => Err(ParseError::UnknownInstruction(Hex(typ)))?,
             0xfe => Err(ParseError::UnknownInstruction(Hex(typ)))?,
@@ -4,12 +4,20 @@ use crate::hex::Hex;
 
 use super::Parsable;
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[allow(unused)]
 pub enum Limits {
     Min(u32),
     MinMax(u32, u32),
 }
+impl std::fmt::Display for Limits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limits::Min(min) => write!(f, "{min}.."),
+            Limits::MinMax(min, max) => write!(f, "{min}..={max}"),
+        }
+    }
+}
 impl Parsable for Limits {
     fn parse_inner(
         data: &mut std::io::Cursor<&[u8]>,
@@ -1,6 +1,6 @@
 use super::{Limits, Parsable};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct Mem {
     pub limits: Limits,
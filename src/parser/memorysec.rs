@@ -1,6 +1,6 @@
 use super::{error::ParseError, Mem, Parsable};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct MemorySection {
     pub size: u32,
     pub mems: Vec<Mem>,
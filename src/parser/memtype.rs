@@ -2,7 +2,7 @@ use std::ops::{Deref, DerefMut};
 
 use super::{Limits, Parsable};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MemType(pub Limits);
 impl Deref for MemType {
     type Target = Limits;
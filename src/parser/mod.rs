@@ -89,7 +89,12 @@ mod labelidx;
 pub use labelidx::*;
 mod customsec;
 pub use customsec::*;
+mod namesec;
+pub use namesec::*;
 mod dataidx;
 pub use dataidx::*;
 mod elemidx;
 pub use elemidx::*;
+mod encode;
+#[allow(unused)]
+pub use encode::*;
@@ -14,7 +14,7 @@ use std::{
 };
 
 /// https://webassembly.github.io/spec/core/binary/modules.html#binary-module
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct Module {
     pub magic: Hex<4>,
@@ -30,7 +30,7 @@ pub struct Module {
     pub elems: ElementSection,  //elemsec
     pub code: CodeSection,
     pub datas: DataSection,
-    pub customs: CustomSection,
+    pub customs: Vec<CustomSection>,
 }
 impl Parsable for Module {
     fn parse_inner(
@@ -61,7 +61,7 @@ impl Parsable for Module {
         let mut mems = MemorySection::default();
         let mut globals = GlobalSection::default();
         let mut elements = ElementSection::default();
-        let customs = CustomSection::default();
+        let mut customs = Vec::new();
 
         let mut section_header = [0];
 
@@ -90,7 +90,7 @@ impl Parsable for Module {
             }
             last_section = section_header[0];
             match section_header[0] {
-                0 => drop(CustomSection::parse(data, stack)?), // customs.concat(CustomSection::parse(data, stack)?),
+                0 => customs.push(CustomSection::parse(data, stack)?),
                 1 => types.concat(TypeSection::parse(data, stack)?),
                 2 => import.concat(ImportSection::parse(data, stack)?),
                 3 => functions.concat(FunctionSection::parse(data, stack)?),
@@ -4,7 +4,7 @@ use crate::hex::Hex;
 
 use super::{error::ParseError, Parsable};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Mutable {
     Const,
     Var,
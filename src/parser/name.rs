@@ -2,7 +2,7 @@ use std::ops::{Deref, DerefMut};
 
 use super::Parsable;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Name(pub String);
 impl std::fmt::Debug for Name {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
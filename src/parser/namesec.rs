@@ -0,0 +1,34 @@
+use super::{error::ParseError, CustomSection, Name, Parsable};
+use std::{collections::HashMap, io::Cursor};
+
+/// The function names of a `name` custom section, the only part of it used.
+/// https://webassembly.github.io/spec/core/appendix/custom.html#name-section
+#[derive(Debug, Clone, Default)]
+pub struct NameSection {
+    pub functions: HashMap<u32, String>,
+}
+
+impl NameSection {
+    pub fn from_custom(custom: &CustomSection) -> Result<Self, ParseError> {
+        let bytes = custom.sections.concat();
+        let mut data = Cursor::new(&bytes[..]);
+        let stack = &mut Vec::new();
+        let mut functions = HashMap::new();
+        while (data.position() as usize) < bytes.len() {
+            let id = u8::parse(&mut data, stack)?;
+            let size = u32::parse(&mut data, stack)?;
+            let end = data.position() + size as u64;
+            // module (0) and local (2) names, and whatever subsections later
+            // proposals add, are skipped
+            if id == 1 {
+                let count = u32::parse(&mut data, stack)?;
+                for _ in 0..count {
+                    let index = u32::parse(&mut data, stack)?;
+                    functions.insert(index, Name::parse(&mut data, stack)?.0);
+                }
+            }
+            data.set_position(end);
+        }
+        Ok(Self { functions })
+    }
+}
@@ -10,12 +10,19 @@ use std::{
 
 pub type DebugStack<'t> = &'t mut Vec<&'static str>;
 
+/// How deep the parse stack may grow before a module is rejected. Deeply
+/// nested blocks would otherwise overflow the native stack.
+pub const MAX_PARSE_DEPTH: usize = 512;
+
 pub trait Parsable: Debug {
     const STACK_NAME: &'static str = std::any::type_name::<Self>();
     fn parse(data: &mut Cursor<&[u8]>, stack: DebugStack) -> Result<Self, ParseError>
     where
         Self: std::marker::Sized,
     {
+        if stack.len() >= MAX_PARSE_DEPTH {
+            return Err(ParseError::NestingTooDeep(stack.len()));
+        }
         stack.push(Self::STACK_NAME);
         #[cfg(target_os = "windows")] // ugly fix to disable this on all important platforms
         {
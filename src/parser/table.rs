@@ -4,7 +4,7 @@ use crate::hex::Hex;
 
 use super::{error::ParseError, Limits, Parsable, RefTyp};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct Table {
     pub et: RefTyp,
@@ -1,6 +1,6 @@
 use super::{error::ParseError, Parsable, Table};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct TableSection {
     pub size: u32,
@@ -1,6 +1,6 @@
 use super::{Limits, Parsable, RefTyp};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TableType {
     pub et: RefTyp,
     pub lim: Limits,
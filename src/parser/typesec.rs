@@ -2,7 +2,7 @@ use super::{error::ParseError, Parsable};
 use crate::parser::FuncType;
 use std::io::Cursor;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct TypeSection {
     pub size: u32,
@@ -24,6 +24,20 @@ pub enum RefTyp {
     FuncRef,
     ExternRef,
 }
+impl std::fmt::Display for ValType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValType::Poly => write!(f, "any"),
+            ValType::Num(NumType::I32) => write!(f, "i32"),
+            ValType::Num(NumType::I64) => write!(f, "i64"),
+            ValType::Num(NumType::F32) => write!(f, "f32"),
+            ValType::Num(NumType::F64) => write!(f, "f64"),
+            ValType::Vec128 => write!(f, "v128"),
+            ValType::Ref(RefTyp::FuncRef) => write!(f, "funcref"),
+            ValType::Ref(RefTyp::ExternRef) => write!(f, "externref"),
+        }
+    }
+}
 impl Parsable for RefTyp {
     fn parse_inner(
         data: &mut std::io::Cursor<&[u8]>,
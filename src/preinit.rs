@@ -0,0 +1,165 @@
+//! `wasp preinit`: runs the initialization of a module ahead of time and
+//! writes a module that starts out where it left off, like wizer does.
+//!
+//! The memory the initialization leaves becomes the data segments, and the
+//! mutable globals start with the values it left them. The start function
+//! and the init export are gone, both already ran. Tables are taken as the
+//! element segments make them, and nothing of the host, like files opened
+//! through WASI, survives. Code offsets change as the module is written
+//! anew, so DWARF sections are left out.
+
+use crate::{
+    parser::{Data, ExportDesc, Expr, FuncIdx, ImportDesc, Instr, Limits, Module, Mutable, RefTyp},
+    runtime::{Instance, Runtime, Value, MAIN_MODULE},
+};
+use std::fmt::Display;
+
+/// The name wizer calls the init export, the default of `--init`.
+pub const DEFAULT_INIT: &str = "wizer.initialize";
+
+const PAGE_SIZE: usize = 65536;
+
+/// Runs of zeros shorter than this are taken into the data segment around
+/// them, which costs fewer bytes than another segment.
+const MAX_GAP: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreinitError {
+    pub message: String,
+}
+
+impl Display for PreinitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for PreinitError {}
+
+fn error(message: impl Into<String>) -> PreinitError {
+    PreinitError {
+        message: message.into(),
+    }
+}
+
+/// `module` as the main instance of `runtime` is after running `init`,
+/// which [`Runtime::invoke`] must have done.
+pub fn initialized(runtime: &Runtime, module: &Module, init: &str) -> Result<Module, PreinitError> {
+    let instance = runtime
+        .instance(MAIN_MODULE)
+        .map_err(|e| error(e.to_string()))?;
+    let mut out = module.clone();
+    let imported = |kind: fn(&ImportDesc) -> bool| {
+        module
+            .imports
+            .imports
+            .iter()
+            .filter(|i| kind(&i.desc))
+            .count()
+    };
+
+    let imported_globals = imported(|d| matches!(d, ImportDesc::Global(_)));
+    for (i, global) in out.globals.globals.iter_mut().enumerate() {
+        if global.gt.mutable != Mutable::Var {
+            continue;
+        }
+        let index = (imported_globals + i) as u32;
+        let Some(value) = instance.global_at(index).map(|g| g.get()) else {
+            return Err(error(format!(
+                "global {index} is missing from the instance"
+            )));
+        };
+        global.e = Expr {
+            instrs: vec![constant(value, index)?],
+            offsets: Vec::new(),
+        };
+    }
+
+    // active segments were dropped by instantiating, but code may still
+    // refer to them all by index
+    out.datas.data = if module.refers_to_data() {
+        (module.datas.data.iter().enumerate())
+            .map(|(index, data)| match data {
+                Data::Passive(_) => Data::Passive(instance.data(index as u32).unwrap_or_default()),
+                Data::Active(..) | Data::ActiveX(..) => Data::Passive(Vec::new()),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if !module.mems.mems.is_empty() {
+        let pages = instance.own_memory().size();
+        for mem in &mut out.mems.mems {
+            mem.limits = match mem.limits {
+                Limits::Min(_) => Limits::Min(pages as u32),
+                Limits::MinMax(_, max) => Limits::MinMax(pages as u32, max),
+            };
+        }
+        out.datas.data.extend(memory_segments(&instance)?);
+    } else if imported(|d| matches!(d, ImportDesc::Mem(_))) > 0 {
+        return Err(error(
+            "the module imports its memory, which can't be part of it",
+        ));
+    }
+
+    out.start = None;
+    match out.exports.exports.remove(init) {
+        Some(ExportDesc::Func(_)) => {}
+        _ => return Err(error(format!("no function is exported as {init:?}"))),
+    }
+    out.customs.retain(|c| !c.name.starts_with(".debug_"));
+    Ok(out)
+}
+
+/// The constant expression making `value`, the value of global `index`.
+fn constant(value: Value, index: u32) -> Result<Instr, PreinitError> {
+    Ok(match value {
+        Value::I32(v) => Instr::x41_i32_const(v),
+        Value::I64(v) => Instr::x42_i64_const(v),
+        Value::F32(v) => Instr::x43_f32_const(v),
+        Value::F64(v) => Instr::x44_f64_const(v),
+        Value::FuncRef(u32::MAX) => Instr::xd0_ref_null(RefTyp::FuncRef),
+        Value::FuncRef(f) => Instr::xd2_ref_func(FuncIdx(f)),
+        Value::Externref(u32::MAX) => Instr::xd0_ref_null(RefTyp::ExternRef),
+        Value::Externref(_) | Value::BlockLock => {
+            return Err(error(format!(
+                "global {index} holds a host reference, which no module can"
+            )))
+        }
+    })
+}
+
+/// Active data segments for everything but the zeros in the memory of
+/// `instance`.
+fn memory_segments(instance: &Instance) -> Result<Vec<Data>, PreinitError> {
+    let memory = instance.own_memory();
+    let mut page = vec![0; PAGE_SIZE];
+    // start and end of each run of bytes to keep
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for index in 0..memory.size() {
+        let base = index * PAGE_SIZE;
+        memory
+            .read(base, &mut page)
+            .map_err(|e| error(e.to_string()))?;
+        for (i, _) in page.iter().enumerate().filter(|(_, b)| **b != 0) {
+            let at = base + i;
+            match runs.last_mut() {
+                Some((_, end)) if at - *end < MAX_GAP => *end = at + 1,
+                _ => runs.push((at, at + 1)),
+            }
+        }
+    }
+    runs.into_iter()
+        .map(|(start, end)| {
+            let mut bytes = vec![0; end - start];
+            memory
+                .read(start, &mut bytes)
+                .map_err(|e| error(e.to_string()))?;
+            let offset = Expr {
+                instrs: vec![Instr::x41_i32_const(start as u32 as i32)],
+                offsets: Vec::new(),
+            };
+            Ok(Data::Active(offset, bytes))
+        })
+        .collect()
+}
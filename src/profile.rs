@@ -0,0 +1,356 @@
+//! `wasp run --profile`: counts every instruction a program runs, and the
+//! time it spends in host functions, against the call stack it ran under.
+//!
+//! The stacks are written in the folded format flamegraph tools read, one
+//! `outer;inner count` line per stack, and summed up per function in a
+//! table.
+
+use crate::{
+    parser::Instr,
+    runtime::{
+        clean_model::Function, function_label, instance_name, Observer, Runtime, RuntimeError,
+        Site, Value,
+    },
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// The node every call tree starts from, standing for no frame at all.
+const ROOT: usize = 0;
+
+/// A function that was called, under whichever call stacks.
+struct Func {
+    instance: String,
+    index: u32,
+    name: Option<String>,
+    host: bool,
+}
+
+/// A function called from one particular stack.
+struct Node {
+    func: usize,
+    parent: usize,
+    children: HashMap<usize, usize>,
+    calls: u64,
+    instrs: u64,
+    host: Duration,
+}
+
+impl Node {
+    fn new(func: usize, parent: usize) -> Self {
+        Self {
+            func,
+            parent,
+            children: HashMap::new(),
+            calls: 0,
+            instrs: 0,
+            host: Duration::ZERO,
+        }
+    }
+}
+
+/// A call tree built by watching a [`Runtime`] call and run.
+pub struct Profiler {
+    funcs: Vec<Func>,
+    func_ids: HashMap<(String, u32), usize>,
+    /// Children always come after their parent.
+    nodes: Vec<Node>,
+    /// The node of each frame on the stack, outermost first.
+    path: Vec<usize>,
+    /// When the host function being called was.
+    host_call: Option<Instant>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            funcs: Vec::new(),
+            func_ids: HashMap::new(),
+            nodes: vec![Node::new(usize::MAX, ROOT)],
+            path: Vec::new(),
+            host_call: None,
+        }
+    }
+}
+
+impl Observer for Profiler {
+    fn before_instruction(&mut self, _: &Runtime, _: Site, _: &Instr) {
+        let node = self.top();
+        self.nodes[node].instrs += 1;
+    }
+
+    fn on_call(&mut self, rt: &Runtime, site: Site) {
+        let parent = self.top();
+        let func = self.func(rt, site);
+        let node = match self.nodes[parent].children.get(&func) {
+            Some(&node) => node,
+            None => {
+                self.nodes.push(Node::new(func, parent));
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.insert(func, node);
+                node
+            }
+        };
+        self.nodes[node].calls += 1;
+        self.path.push(node);
+    }
+
+    fn on_return(&mut self, _: &Runtime, _: Site, _: &[Value]) {
+        self.path.pop();
+    }
+
+    fn before_host_call(&mut self, _: &Runtime, _: Site, _: &[Value]) {
+        self.host_call = Some(Instant::now());
+    }
+
+    fn after_host_call(
+        &mut self,
+        _: &Runtime,
+        _: Site,
+        _: &[Value],
+        _: Result<&[Value], &RuntimeError>,
+    ) {
+        if let Some(start) = self.host_call.take() {
+            let node = self.top();
+            self.nodes[node].host += start.elapsed();
+        }
+    }
+}
+
+impl Profiler {
+    fn top(&self) -> usize {
+        self.path.last().copied().unwrap_or(ROOT)
+    }
+
+    fn func(&mut self, rt: &Runtime, site: Site) -> usize {
+        if let Some(&id) = self.func_ids.get(&(site.instance.to_string(), site.func)) {
+            return id;
+        }
+        let inst = rt.instance(site.instance).ok();
+        self.funcs.push(Func {
+            instance: site.instance.to_string(),
+            index: site.func,
+            name: inst
+                .as_ref()
+                .and_then(|i| i.function_name(site.func))
+                .map(str::to_string),
+            host: matches!(
+                inst.and_then(|i| i.function(site.func)),
+                Some(Function::IO { .. })
+            ),
+        });
+        let id = self.funcs.len() - 1;
+        self.func_ids
+            .insert((site.instance.to_string(), site.func), id);
+        id
+    }
+
+    /// How `func` appears in a folded stack, which has no room for `;`.
+    fn frame_name(&self, func: usize) -> String {
+        let func = &self.funcs[func];
+        function_label(&func.instance, func.index, func.name.as_deref()).replace(';', ":")
+    }
+
+    /// The frames of `node`, outermost first.
+    fn stack_of(&self, mut node: usize) -> Vec<usize> {
+        let mut funcs = Vec::new();
+        while node != ROOT {
+            funcs.push(self.nodes[node].func);
+            node = self.nodes[node].parent;
+        }
+        funcs.reverse();
+        funcs
+    }
+
+    /// Writes one line per stack with a non-zero `weight`.
+    fn write_folded(&self, out: &mut impl Write, weight: impl Fn(&Node) -> u64) -> io::Result<()> {
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            let weight = weight(node);
+            if weight == 0 {
+                continue;
+            }
+            let names: Vec<_> = self
+                .stack_of(i)
+                .into_iter()
+                .map(|f| self.frame_name(f))
+                .collect();
+            writeln!(out, "{} {weight}", names.join(";"))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the instructions run by each stack to `path`, and the
+    /// microseconds spent in host functions, if any were called, to
+    /// `path.host`.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_folded(&mut out, |n| n.instrs)?;
+        out.flush()?;
+        if self.nodes.iter().any(|n| !n.host.is_zero()) {
+            let mut host = path.as_os_str().to_owned();
+            host.push(".host");
+            let mut out = BufWriter::new(File::create(PathBuf::from(host))?);
+            self.write_folded(&mut out, |n| n.host.as_micros() as u64)?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the calls, instructions and host time of each function,
+    /// those that ran the most instructions themselves first. Inclusive
+    /// counts take in everything called, counting recursive calls once.
+    pub fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        struct Row {
+            calls: u64,
+            instrs: u64,
+            inclusive: u64,
+            host: Duration,
+        }
+        let mut inclusive: Vec<u64> = self.nodes.iter().map(|n| n.instrs).collect();
+        for i in (1..self.nodes.len()).rev() {
+            let parent = self.nodes[i].parent;
+            inclusive[parent] += inclusive[i];
+        }
+        let mut rows: Vec<_> = self
+            .funcs
+            .iter()
+            .map(|_| Row {
+                calls: 0,
+                instrs: 0,
+                inclusive: 0,
+                host: Duration::ZERO,
+            })
+            .collect();
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            let row = &mut rows[node.func];
+            row.calls += node.calls;
+            row.instrs += node.instrs;
+            row.host += node.host;
+            let mut up = node.parent;
+            while up != ROOT && self.nodes[up].func != node.func {
+                up = self.nodes[up].parent;
+            }
+            if up == ROOT {
+                row.inclusive += inclusive[i];
+            }
+        }
+        let total = inclusive[ROOT].max(1);
+        let mut order: Vec<_> = (0..rows.len()).collect();
+        order.sort_by_key(|&f| {
+            (
+                std::cmp::Reverse(rows[f].instrs),
+                std::cmp::Reverse(rows[f].inclusive),
+            )
+        });
+
+        writeln!(
+            out,
+            "{:>10} {:>12} {:>7} {:>12} {:>10}  function",
+            "calls", "self", "self%", "inclusive", "host"
+        )?;
+        for f in order {
+            let row = &rows[f];
+            let func = &self.funcs[f];
+            let host = if func.host {
+                format!("{:.3}ms", row.host.as_secs_f64() * 1000.0)
+            } else {
+                "-".to_string()
+            };
+            write!(
+                out,
+                "{:>10} {:>12} {:>6.2}% {:>12} {host:>10}  {}:${}",
+                row.calls,
+                row.instrs,
+                row.instrs as f64 * 100.0 / total as f64,
+                row.inclusive,
+                instance_name(&func.instance),
+                func.index
+            )?;
+            match &func.name {
+                Some(name) => writeln!(out, " {name:?}")?,
+                None => writeln!(out)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{Import, MAIN_MODULE},
+        wast::text_module,
+    };
+
+    const CALLS: &str = r#"(module
+        (import "spectest" "print" (func $print))
+        (func $leaf (export "leaf") (param i32) (result i32)
+            (i32.add (local.get 0) (i32.const 1)))
+        (func (export "main") (result i32)
+            (call $print)
+            (call $leaf (call $leaf (i32.const 1)))))"#;
+
+    fn profiled() -> Profiler {
+        let mut rt = Runtime::builder()
+            .add_io("spectest", Import::spectest())
+            .add_module(MAIN_MODULE, &text_module(CALLS))
+            .build()
+            .expect("the runtime builds");
+        // the program's own `main`, not yet run, is not what is measured
+        rt.stack.clear();
+        rt.observe(Profiler::default());
+        assert!(rt.invoke("main", &[]).expect("main returns") == [Value::I32(3)]);
+        rt.take_observers()
+            .pop()
+            .and_then(|o| (o as Box<dyn std::any::Any>).downcast().ok())
+            .map(|p: Box<Profiler>| *p)
+            .expect("the profiler is still there")
+    }
+
+    #[test]
+    fn stacks_are_folded() {
+        let profiler = profiled();
+        let folded = |weight: fn(&Node) -> u64| {
+            let mut out = Vec::new();
+            profiler
+                .write_folded(&mut out, weight)
+                .expect("writing to memory works");
+            String::from_utf8(out).expect("the stacks are text")
+        };
+        // the host function runs no instructions
+        assert_eq!(folded(|n| n.instrs), "main 4\nmain;leaf 6\n");
+        assert_eq!(
+            folded(|n| n.calls),
+            "main 1\nmain;spectest.print 1\nmain;leaf 2\n"
+        );
+    }
+
+    #[test]
+    fn functions_are_summed_up() {
+        let mut out = Vec::new();
+        profiled()
+            .write_table(&mut out)
+            .expect("writing to memory works");
+        let table = String::from_utf8(out).expect("the table is text");
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(
+            lines[..3],
+            [
+                "     calls         self   self%    inclusive       host  function",
+                "         2            6  60.00%            6          -  main:$1 \"leaf\"",
+                "         1            4  40.00%           10          -  main:$2 \"main\"",
+            ]
+        );
+        // only the time in the host function depends on the machine
+        let (counts, host) = lines[3].split_at(44);
+        assert_eq!(counts, "         1            0   0.00%            0");
+        assert!(host.ends_with("ms  main:$0 \"spectest.print\""), "{host}");
+        assert_eq!(lines.len(), 4);
+    }
+}
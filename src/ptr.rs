@@ -20,6 +20,11 @@ impl<T> RwLock<T> {
     pub fn read(&self) -> std::cell::Ref<'_, T> {
         self.lock.borrow()
     }
+    /// Like [`RwLock::write`], but `None` instead of a panic while the value
+    /// is already borrowed.
+    pub fn try_write(&self) -> Option<std::cell::RefMut<'_, T>> {
+        self.lock.try_borrow_mut().ok()
+    }
     // pub fn write(&self) -> std::sync::RwLockWriteGuard<'_, T> {
     //     self.lock.write().expect("poison!")
     // }
@@ -0,0 +1,101 @@
+//! Where execution is, or was when it trapped, one frame at a time, with
+//! source positions where the module has DWARF line info.
+
+use super::{FuncId, Runtime, MAIN_MODULE};
+use std::fmt::Display;
+
+/// How an instance is shown to users, `main` for the one
+/// [`Runtime::build`] was given.
+pub fn instance_name(name: &str) -> &str {
+    if name == MAIN_MODULE {
+        "main"
+    } else {
+        name
+    }
+}
+
+/// A function as tools list it: its name, prefixed by the instance for
+/// those outside the main one, or `instance:$index` without a name.
+pub fn function_label(instance: &str, index: u32, name: Option<&str>) -> String {
+    match (name, instance) {
+        (Some(name), MAIN_MODULE) => name.to_string(),
+        (Some(name), instance) => format!("{instance}:{name}"),
+        (None, instance) => format!("{}:${index}", instance_name(instance)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BacktraceFrame {
+    pub instance: String,
+    pub func: u32,
+    /// The name of the function, see [`super::Instance::function_name`].
+    pub name: Option<String>,
+    pub pc: usize,
+    /// `file:line` of the instruction at `pc`.
+    pub location: Option<String>,
+}
+
+/// The frames of a [`Runtime`], innermost first.
+#[derive(Debug, Clone, Default)]
+pub struct Backtrace {
+    pub frames: Vec<BacktraceFrame>,
+}
+
+impl Display for BacktraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:${}", instance_name(&self.instance), self.func)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name:?}")?;
+        }
+        match &self.location {
+            Some(location) => write!(f, " at {location}"),
+            None => write!(f, " @{}", self.pc),
+        }
+    }
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, frame) in self.frames.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "#{i} {frame}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Runtime {
+    /// Where each frame on the stack is.
+    pub fn backtrace(&self) -> Backtrace {
+        let frames = self.stack.iter().rev().map(|frame| {
+            let (instance, func) = match &frame.func_id {
+                FuncId::Id(id) => (&frame.module, *id),
+                FuncId::Foreign { module, id } => (module, *id),
+            };
+            let inst = self.instance(instance).ok();
+            BacktraceFrame {
+                instance: instance.clone(),
+                func,
+                name: inst
+                    .as_ref()
+                    .and_then(|i| i.function_name(func))
+                    .map(str::to_string),
+                pc: frame.pc,
+                location: inst
+                    .and_then(|i| i.location(func, frame.pc))
+                    .map(|l| l.to_string()),
+            }
+        });
+        Backtrace {
+            frames: frames.collect(),
+        }
+    }
+
+    /// Where the frames were when the last call through
+    /// [`Runtime::invoke`] failed, which unwinds them.
+    pub fn trap_backtrace(&self) -> Option<&Backtrace> {
+        self.trap_backtrace.as_ref()
+    }
+}
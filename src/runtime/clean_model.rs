@@ -1,15 +1,13 @@
 use super::{
-    memory::Memory,
-    typecheck::TypeCheckError,
-    IOFunction, Import,
-    RuntimeError::{self, *},
-    Value, IO,
+    memory::Memory, typecheck::TypeCheckError, IOFunction, Import, InternalErrorKind::*, LinkError,
+    RuntimeError, TrapCode, ValidationError, Value, IO,
 };
 use crate::{
+    dwarf::LineTable,
     parser::{
-        self, Code, Data, Elem, ExportDesc, Expr, FuncIdx, FuncType, Global as PGlobal, GlobalIdX,
-        ImportDesc, Instr, LabelIdX, Limits, Locals, MemArg, MemIdX, Module, Mutable, RefTyp,
-        TableIdX, TypeIdX, BT,
+        self, Code, CustomSection, Data, Elem, ExportDesc, Expr, FuncIdx, FuncType,
+        Global as PGlobal, GlobalIdX, ImportDesc, Instr, LabelIdX, Limits, Locals, MemArg, MemIdX,
+        Module, Mutable, NameSection, RefTyp, TableIdX, TypeIdX, BT,
     },
     ptr::{Ptr, PtrRW},
 };
@@ -18,8 +16,12 @@ use std::collections::HashMap;
 pub enum Function {
     WS {
         ty: FuncType,
-        _locals: Vec<Locals>,
+        locals: Vec<Locals>,
         code: Vec<Instr>,
+        /// Where each instruction of `code`, and the function's final `end`
+        /// after them, starts, from the start of the code section's
+        /// contents, which is how DWARF addresses wasm code.
+        offsets: Vec<u32>,
         _labels: HashMap<Vec<u32>, u32>,
     },
     IO {
@@ -33,14 +35,16 @@ impl std::fmt::Debug for Function {
         match self {
             Self::WS {
                 ty,
-                _locals,
+                locals,
                 code,
+                offsets,
                 _labels,
             } => f
                 .debug_struct("WS")
                 .field("ty", ty)
-                .field("_locals", _locals)
+                .field("locals", locals)
                 .field("code", code)
+                .field("offsets", offsets)
                 .field("_labels", _labels)
                 .finish(),
             Self::IO { .. } => write!(f, "IO"),
@@ -48,6 +52,46 @@ impl std::fmt::Debug for Function {
     }
 }
 
+impl Function {
+    /// Zero-initialises the declared locals, which are numbered after the
+    /// parameters.
+    pub fn zero_locals(&self, locals: &mut HashMap<u32, Value>) {
+        let Function::WS {
+            ty,
+            locals: declared,
+            ..
+        } = self
+        else {
+            return;
+        };
+        let mut index = ty.input.types.len() as u32;
+        for Locals { n, t } in declared {
+            for _ in 0..*n {
+                if let Some(v) = Value::zero(t) {
+                    locals.insert(index, v);
+                }
+                index += 1;
+            }
+        }
+    }
+}
+
+fn unknown_import(import: &parser::Import) -> RuntimeError {
+    LinkError::UnknownImport {
+        module: import.module.0.clone(),
+        name: import.name.0.clone(),
+    }
+    .into()
+}
+
+fn incompatible_import(import: &parser::Import) -> RuntimeError {
+    LinkError::IncompatibleImportType {
+        module: import.module.0.clone(),
+        name: import.name.0.clone(),
+    }
+    .into()
+}
+
 #[derive(Debug)]
 pub struct Table {
     pub table: HashMap<u32, FuncIdx>,
@@ -65,10 +109,12 @@ fn setup_imports(
         Vec<PtrRW<(Mutable, Value)>>,
         Vec<PtrRW<Table>>,
         Option<PtrRW<Memory<65536>>>,
+        HashMap<u32, (String, u32)>,
     ),
     RuntimeError,
 > {
     let mut functions = Vec::new();
+    let mut foreign = HashMap::new();
     let mut globals = Vec::new();
     let mut tables = Vec::new();
     let mut memory = None;
@@ -80,38 +126,41 @@ fn setup_imports(
                     .types
                     .function_types
                     .get(*tid as usize)
-                    .ok_or(MissingType(file!(), line!(), column!()))?
+                    .ok_or(MissingType.at(file!(), line!(), column!()))?
                     .clone();
 
-                match other.get(&import.module.0).expect("impossible!") {
+                match other
+                    .get(&import.module.0)
+                    .ok_or_else(|| unknown_import(import))?
+                {
                     Import::WS(other) => {
                         let exp = other
                             .exports
                             .get(&import.name.0)
-                            .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                            .ok_or_else(|| unknown_import(import))?;
                         let ExportDesc::Func(FuncIdx(id)) = exp else {
-                            return Err(RuntimeError::IncompatibleImportType(
-                                file!(),
-                                line!(),
-                                column!(),
-                            ));
+                            return Err(incompatible_import(import));
                         };
                         let c = other
                             .functions
                             .get(*id as usize)
-                            .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                            .ok_or_else(|| unknown_import(import))?;
                         let ty2 = match c.as_ref() {
                             Function::WS { ty, .. } | Function::IO { ty, .. } => ty,
                         };
 
                         if &ty != ty2 {
-                            return Err(RuntimeError::IncompatibleImportType(
-                                file!(),
-                                line!(),
-                                column!(),
-                            ));
+                            return Err(incompatible_import(import));
                         }
 
+                        // run it where it was defined, following the other
+                        // module's own imports
+                        let home = other
+                            .foreign
+                            .get(id)
+                            .cloned()
+                            .unwrap_or_else(|| (import.module.0.clone(), *id));
+                        foreign.insert(functions.len() as u32, home);
                         functions.push(c.clone());
                     }
                     Import::IO(IO {
@@ -119,36 +168,40 @@ fn setup_imports(
                     }) => {
                         let func = funcs
                             .get(&*import.name.0)
-                            .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                            .ok_or_else(|| unknown_import(import))?;
+                        if func.ty().is_some_and(|t| t != &ty) {
+                            return Err(incompatible_import(import));
+                        }
 
-                        functions.push(Function::IO { func: *func, ty }.into())
+                        functions.push(
+                            Function::IO {
+                                func: func.clone(),
+                                ty,
+                            }
+                            .into(),
+                        )
                     }
                 }
             }
-            ImportDesc::Global(gt) => match other.get(&import.module.0).expect("impossible!") {
+            ImportDesc::Global(gt) => match other
+                .get(&import.module.0)
+                .ok_or_else(|| unknown_import(import))?
+            {
                 Import::WS(other) => {
                     let exp = other
                         .exports
                         .get(&import.name.0)
-                        .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                        .ok_or_else(|| unknown_import(import))?;
                     let ExportDesc::Global(GlobalIdX(id)) = exp else {
-                        return Err(RuntimeError::IncompatibleImportType(
-                            file!(),
-                            line!(),
-                            column!(),
-                        ));
+                        return Err(incompatible_import(import));
                     };
                     let g = other
                         .globals
                         .get(*id as usize)
-                        .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                        .ok_or_else(|| unknown_import(import))?;
 
                     if !g.read().1.is_type(&gt.t) || g.read().0 != gt.mutable {
-                        return Err(RuntimeError::IncompatibleImportType(
-                            file!(),
-                            line!(),
-                            column!(),
-                        ));
+                        return Err(incompatible_import(import));
                     }
 
                     globals.push(g.clone());
@@ -156,37 +209,32 @@ fn setup_imports(
                 Import::IO(IO { globals: globs, .. }) => {
                     let g = globs
                         .get(&*import.name.0)
-                        .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                        .ok_or_else(|| unknown_import(import))?;
 
                     if !g.read().1.is_type(&gt.t) || Mutable::Const != gt.mutable {
-                        return Err(RuntimeError::IncompatibleImportType(
-                            file!(),
-                            line!(),
-                            column!(),
-                        ));
+                        return Err(incompatible_import(import));
                     }
 
                     globals.push(g.clone())
                 }
             },
             ImportDesc::Table(t) => {
-                let (g, tt, l) = match other.get(&import.module.0).expect("impossible!") {
+                let (g, tt, l) = match other
+                    .get(&import.module.0)
+                    .ok_or_else(|| unknown_import(import))?
+                {
                     Import::WS(other) => {
                         let exp = other
                             .exports
                             .get(&import.name.0)
-                            .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                            .ok_or_else(|| unknown_import(import))?;
                         let ExportDesc::Table(TableIdX(id)) = exp else {
-                            return Err(RuntimeError::IncompatibleImportType(
-                                file!(),
-                                line!(),
-                                column!(),
-                            ));
+                            return Err(incompatible_import(import));
                         };
                         let g = other
                             .tables
                             .get(*id as usize)
-                            .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                            .ok_or_else(|| unknown_import(import))?;
 
                         let tt = g.read().typ;
                         let l = g.read().table_length;
@@ -196,7 +244,7 @@ fn setup_imports(
                     Import::IO(IO { tables: tabs, .. }) => {
                         let g = tabs
                             .get(&*import.name.0)
-                            .ok_or(RuntimeError::UnknownImport(file!(), line!(), column!()))?;
+                            .ok_or_else(|| unknown_import(import))?;
                         let tt = g.read().typ;
                         let l = g.read().table_length;
                         (g.clone(), tt, l)
@@ -204,67 +252,33 @@ fn setup_imports(
                 };
 
                 if t.et != tt {
-                    return Err(RuntimeError::IncompatibleImportType(
-                        file!(),
-                        line!(),
-                        column!(),
-                    ));
+                    return Err(incompatible_import(import));
                 }
 
-                if l.0 == l.1 {
-                    match t.lim {
-                        Limits::Min(i) => {
-                            if i > l.0 as u32 {
-                                return Err(RuntimeError::IncompatibleImportType(
-                                    file!(),
-                                    line!(),
-                                    column!(),
-                                ));
-                            }
-                        }
-                        Limits::MinMax(_, _) => {
-                            return Err(RuntimeError::IncompatibleImportType(
-                                file!(),
-                                line!(),
-                                column!(),
-                            ));
-                        }
-                    }
-                } else {
-                    match t.lim {
-                        Limits::Min(l1) => {
-                            if l1 > (l.1 - l.0) as u32 {
-                                return Err(RuntimeError::IncompatibleImportType(
-                                    file!(),
-                                    line!(),
-                                    column!(),
-                                ));
-                            }
-                        }
-                        Limits::MinMax(l1, l2) => {
-                            let tl_size = l2 - l1;
-                            if tl_size < l.0 as u32 {
-                                return Err(RuntimeError::IncompatibleImportType(
-                                    file!(),
-                                    line!(),
-                                    column!(),
-                                ));
-                            }
-                        }
-                    }
+                // an unbounded table has `u32::MAX` as its max
+                let (size, max) = l;
+                let (min, declared_max) = match t.lim {
+                    Limits::Min(min) => (min as usize, u32::MAX as usize),
+                    Limits::MinMax(min, max) => (min as usize, max as usize),
+                };
+                if size < min || max > declared_max {
+                    return Err(incompatible_import(import));
                 }
 
                 tables.push(g.clone())
             }
             ImportDesc::Mem(mt) => {
-                let mtt = match other.get(&import.module.0).expect("impossible!") {
+                let mtt = match other
+                    .get(&import.module.0)
+                    .ok_or_else(|| unknown_import(import))?
+                {
                     Import::WS(model) => match model.exports.get(&import.name.0) {
                         Some(ExportDesc::Mem(_)) => {
                             let mem = model.memory.clone();
                             memory = Some(mem);
                             model.memory.read().pages()
                         }
-                        _ => return Err(UnknownImport(file!(), line!(), column!())),
+                        _ => return Err(unknown_import(import)),
                     },
                     Import::IO(IO {
                         memory_name,
@@ -272,7 +286,7 @@ fn setup_imports(
                         ..
                     }) => {
                         if memory_name != &import.name.0 {
-                            return Err(UnknownImport(file!(), line!(), column!()));
+                            return Err(unknown_import(import));
                         }
                         memory = Some(mem.clone());
                         mem.read().pages()
@@ -281,20 +295,20 @@ fn setup_imports(
                 match mt.0 {
                     Limits::Min(m) if mtt.1 == usize::MAX => {
                         if mtt.0 < m as usize {
-                            return Err(IncompatibleImportType(file!(), line!(), column!()));
+                            return Err(incompatible_import(import));
                         }
                     }
                     Limits::Min(m) => {
                         if mtt.0 < m as usize {
-                            return Err(IncompatibleImportType(file!(), line!(), column!()));
+                            return Err(incompatible_import(import));
                         }
                     }
                     Limits::MinMax(_, _) if mtt.1 == usize::MAX => {
-                        return Err(IncompatibleImportType(file!(), line!(), column!()))
+                        return Err(incompatible_import(import))
                     }
                     Limits::MinMax(m, n) => {
                         if mtt.0 < m as usize || mtt.1 > n as usize {
-                            return Err(IncompatibleImportType(file!(), line!(), column!()));
+                            return Err(incompatible_import(import));
                         }
                     }
                 }
@@ -302,30 +316,102 @@ fn setup_imports(
         }
     }
 
-    Ok((functions, globals, tables, memory))
+    Ok((functions, globals, tables, memory, foreign))
 }
 
 fn get_tables(value: &Module, tables: &mut Vec<PtrRW<Table>>) -> Result<(), RuntimeError> {
-    tables.extend(value.tables.tables.iter().map(|t| {
+    for t in &value.tables.tables {
         let table_length = match t.lim {
-            Limits::Min(m) => (m as usize, m as usize),
+            Limits::Min(m) => (m as usize, u32::MAX as usize),
+            Limits::MinMax(n, m) if n > m => return Err(ValidationError::MemMinLargerMemMax.into()),
             Limits::MinMax(n, m) => (n as usize, m as usize),
         };
-        let table = (table_length.0..table_length.1)
-            .map(|a| (a as u32, FuncIdx(u32::MAX)))
-            .collect();
-        Table {
-            table,
-            table_length,
-            typ: t.et,
-        }
-        .into()
-    }));
+        // entries are only stored once written, a missing index is a null reference
+        tables.push(
+            Table {
+                table: HashMap::new(),
+                table_length,
+                typ: t.et,
+            }
+            .into(),
+        );
+    }
     Ok(())
 }
 
+/// Locals are zeroed on every call, so a function may not declare more than this.
+const MAX_LOCALS: u64 = 50_000;
+
+fn next_offset(offsets: &mut std::slice::Iter<u32>) -> u32 {
+    offsets.next().copied().unwrap_or_default()
+}
+
+/// Flattens nested blocks into `block_start`/`block_end` pairs and `if`s
+/// into jumps between them, pushing each resulting instruction to `code`
+/// and where it came from, taken from `offsets` as [`Expr::offsets`] lists
+/// them, to `at`.
+fn flatten(
+    instrs: Vec<Instr>,
+    offsets: &mut std::slice::Iter<u32>,
+    code: &mut Vec<Instr>,
+    at: &mut Vec<u32>,
+) {
+    for instr in instrs {
+        let start = next_offset(offsets);
+        match instr {
+            Instr::x02_block(bt, ins) => {
+                code.push(Instr::block_start(BT::Block, 0, bt));
+                at.push(start);
+                flatten(ins, offsets, code, at);
+                code.push(Instr::block_end(BT::Block, 0, bt));
+                at.push(next_offset(offsets));
+            }
+            Instr::x03_loop(bt, ins) => {
+                code.push(Instr::block_start(BT::Loop, 0, bt));
+                at.push(start);
+                flatten(ins, offsets, code, at);
+                code.push(Instr::block_end(BT::Loop, 0, bt));
+                at.push(next_offset(offsets));
+            }
+            Instr::x04_if_else(bt, then, els) => {
+                // the else branch comes first, jumped over when the
+                // condition holds, though its offsets come after the then
+                // branch's
+                let (mut then_code, mut then_at) = (Vec::new(), Vec::new());
+                flatten(then, offsets, &mut then_code, &mut then_at);
+                code.push(Instr::if_then_else(els.is_some() as usize));
+                at.push(start);
+                let end = match els {
+                    Some(els) => {
+                        code.push(Instr::block_start(BT::Block, 0, bt));
+                        at.push(next_offset(offsets));
+                        flatten(els, offsets, code, at);
+                        let end = next_offset(offsets);
+                        code.push(Instr::block_end(BT::Block, 0, bt));
+                        code.push(Instr::else_jump(0));
+                        at.extend([end, end]);
+                        end
+                    }
+                    None => next_offset(offsets),
+                };
+                code.push(Instr::block_start(BT::Block, 0, bt));
+                at.push(start);
+                code.append(&mut then_code);
+                at.append(&mut then_at);
+                code.push(Instr::block_end(BT::Block, 0, bt));
+                at.push(end);
+            }
+            instr => {
+                code.push(instr);
+                at.push(start);
+            }
+        }
+    }
+}
+
 fn get_functions(
     code: Vec<Code>,
+    code_start: u32,
     function_types: &[FuncType],
     function_idx: &[TypeIdX],
     functions: &mut Vec<Ptr<Function>>,
@@ -333,68 +419,26 @@ fn get_functions(
     for (k, code) in code.into_iter().enumerate() {
         let ty = code.code.t;
         let locals = ty.to_vec();
-
-        let ty = function_types[function_idx[k].0 as usize].clone();
-        let mut code = code.code.e.instrs;
-
-        let mut pc = 0;
-        while pc < code.len() {
-            match &code[pc] {
-                Instr::x02_block(bt, ins) => {
-                    let c = ins.clone();
-                    let bt = *bt;
-
-                    code.remove(pc);
-                    code.insert(pc, Instr::block_end(BT::Block, 0, bt));
-                    for (_, i) in c.into_iter().enumerate().rev() {
-                        code.insert(pc, i);
-                    }
-                    code.insert(pc, Instr::block_start(BT::Block, 0, bt));
-                }
-                Instr::x03_loop(bt, ins) => {
-                    let c = ins.clone();
-                    let bt = *bt;
-
-                    code.remove(pc);
-                    code.insert(pc, Instr::block_end(BT::Loop, 0, bt));
-                    for (_, i) in c.into_iter().enumerate().rev() {
-                        code.insert(pc, i);
-                    }
-                    code.insert(pc, Instr::block_start(BT::Loop, 0, bt));
-                }
-                Instr::x04_if_else(bt, then, els) => {
-                    let bt = *bt;
-
-                    let then = then.clone();
-                    // increment_labels(&mut then, 1);
-                    let els = els.clone();
-                    code.remove(pc);
-
-                    code.insert(pc, Instr::block_end(BT::Block, 0, bt)); // then block end
-                    for i in then.into_iter().rev() {
-                        code.insert(pc, i);
-                    }
-                    code.insert(pc, Instr::block_start(BT::Block, 0, bt)); // then block end
-                    let offset = els.is_some() as usize;
-
-                    if let Some(els) = els {
-                        // increment_labels(&mut els, 1);
-                        code.insert(pc, Instr::else_jump(0));
-                        code.insert(pc, Instr::block_end(BT::Block, 0, bt));
-                        for i in els.into_iter().rev() {
-                            code.insert(pc, i);
-                        }
-                        code.insert(pc, Instr::block_start(BT::Block, 0, bt));
-                        // els block end
-                    }
-                    code.insert(pc, Instr::if_then_else(offset));
-                    // then block end
-                }
-                _ => {}
-            }
-            pc += 1;
+        if locals.iter().map(|l| l.n as u64).sum::<u64>() > MAX_LOCALS {
+            return Err(ValidationError::TooManyLocals.into());
         }
 
+        let ty = function_idx
+            .get(k)
+            .and_then(|TypeIdX(t)| function_types.get(*t as usize))
+            .ok_or_else(|| RuntimeError::from(TypeCheckError::UnknownType))?
+            .clone();
+        let mut offsets = code.code.e.offsets.iter();
+        let (mut flat, mut at) = (Vec::new(), Vec::new());
+        flatten(code.code.e.instrs, &mut offsets, &mut flat, &mut at);
+        // the function's own `end`, where it returns from
+        at.push(next_offset(&mut offsets));
+        let offsets = at
+            .into_iter()
+            .map(|o| o.saturating_sub(code_start))
+            .collect();
+        let mut code = flat;
+
         let mut pc = 0;
         while pc < code.len() {
             let (sp, ep) = if let Instr::block_start(_, _, _bt) = &mut code[pc] {
@@ -421,11 +465,11 @@ fn get_functions(
             };
 
             let Instr::block_start(_, ins, _bt) = &mut code[sp] else {
-                panic!()
+                return Err(Impossible.at(file!(), line!(), column!()));
             };
             *ins = ep;
             let Instr::block_end(_, ins, _bt) = &mut code[ep] else {
-                panic!()
+                return Err(Impossible.at(file!(), line!(), column!()));
             };
             *ins = sp;
             pc += 1;
@@ -449,7 +493,7 @@ fn get_functions(
                     in_pc += 1;
                 }
                 let Instr::if_then_else(offset) = &mut code[pc] else {
-                    unreachable!()
+                    return Err(Impossible.at(file!(), line!(), column!()));
                 };
                 *offset += in_pc + 1;
             } else if let Instr::else_jump(_) = &code[pc] {
@@ -468,7 +512,7 @@ fn get_functions(
                     in_pc += 1;
                 }
                 let Instr::else_jump(offset) = &mut code[pc] else {
-                    unreachable!()
+                    return Err(Impossible.at(file!(), line!(), column!()));
                 };
                 *offset += in_pc + 1;
             }
@@ -478,9 +522,10 @@ fn get_functions(
         functions.push(
             Function::WS {
                 ty,
-                _locals: locals,
+                locals,
                 _labels: HashMap::new(),
                 code,
+                offsets,
             }
             .into(),
         );
@@ -586,60 +631,61 @@ fn validate_instrs(
         ) {
             Ok(_) => {}
             Err(Unknown::Function) => {
-                return Err(RuntimeError::TypeError(TypeCheckError::UnknownFunction));
-            }
-            Err(Unknown::Table) => {
-                return Err(RuntimeError::TypeError(TypeCheckError::UnknownTable))
+                return Err(RuntimeError::from(TypeCheckError::UnknownFunction));
             }
-            Err(Unknown::Memory) => return Err(RuntimeError::UnknownMemory),
-            Err(Unknown::Type) => return Err(RuntimeError::TypeError(TypeCheckError::UnknownType)),
+            Err(Unknown::Table) => return Err(RuntimeError::from(TypeCheckError::UnknownTable)),
+            Err(Unknown::Memory) => return Err(ValidationError::UnknownMemory.into()),
+            Err(Unknown::Type) => return Err(RuntimeError::from(TypeCheckError::UnknownType)),
         }
     }
     Ok(())
 }
 
+/// Evaluates the offset of an active element or data segment.
+fn const_offset(expr: &Expr, globals: &[PtrRW<(Mutable, Value)>]) -> Result<u32, RuntimeError> {
+    match &expr.instrs[..] {
+        [Instr::x41_i32_const(p)] => Ok(*p as u32),
+        [Instr::x23_global_get(GlobalIdX(i))] => {
+            let Some(global) = globals.get(*i as usize) else {
+                return Err(ValidationError::UnknownGlobal.into());
+            };
+            let global = global.read();
+            if global.0 != Mutable::Const {
+                return Err(ValidationError::ConstantExpressionRequired.into());
+            }
+            match global.1 {
+                Value::I32(p) => Ok(p as u32),
+                _ => Err(RuntimeError::from(TypeCheckError::WrongTypeOnStack)),
+            }
+        }
+        _ => Err(ValidationError::ConstantExpressionRequired.into()),
+    }
+}
+
+/// Flattens the initializers of an element segment into function indices,
+/// `u32::MAX` standing in for a null reference.
+fn elem_funcs(elem: &Elem) -> Result<Vec<FuncIdx>, RuntimeError> {
+    match elem {
+        Elem::E0(_, vec) | Elem::E1(_, vec) | Elem::E2(_, _, _, vec) | Elem::E3(_, vec) => {
+            Ok(vec.clone())
+        }
+        Elem::E4(_, vec) | Elem::E5(_, vec) | Elem::E6(_, _, _, vec) | Elem::E7(_, vec) => vec
+            .iter()
+            .map(|e| match &e.instrs[..] {
+                [Instr::x41_i32_const(f)] => Ok(FuncIdx(*f as u32)),
+                [Instr::xd2_ref_func(f)] => Ok(*f),
+                [Instr::xd0_ref_null(_)] => Ok(FuncIdx(u32::MAX)),
+                _ => Err(ValidationError::ConstantExpressionRequired.into()),
+            })
+            .collect(),
+    }
+}
+
 fn validate_elems(elems: &[Elem], functions: &[Ptr<Function>]) -> Result<(), RuntimeError> {
     for e in elems {
-        let (_, vec) = match e {
-            Elem::E0(expr, vec) | Elem::E2(_, expr, _, vec) => (
-                match &expr.instrs[..] {
-                    [Instr::x41_i32_const(offset)] => *offset as u32,
-                    _ => todo!(),
-                },
-                vec.clone(),
-            ),
-            Elem::E4(expr, vec) | Elem::E6(_, expr, _, vec) => (
-                match &expr.instrs[..] {
-                    [Instr::x41_i32_const(offset)] => *offset as u32,
-                    e => todo!("{e:?}"),
-                },
-                vec.iter()
-                    .flat_map(|e| {
-                        e.instrs.iter().map(|e| match e {
-                            Instr::x41_i32_const(offset) => FuncIdx(*offset as u32),
-                            e => todo!("{e:?}"),
-                        })
-                    })
-                    .collect(),
-            ),
-            Elem::E1(_, vec) | Elem::E3(_, vec) => (0, vec.clone()),
-            Elem::E5(_, vec) | Elem::E7(_, vec) => (
-                0,
-                vec.iter()
-                    .flat_map(|e| {
-                        e.instrs.iter().map(|e| match e {
-                            Instr::x41_i32_const(offset) => FuncIdx(*offset as u32),
-                            Instr::xd2_ref_func(f) => *f,
-                            Instr::xd0_ref_null(_) => FuncIdx(0),
-                            e => todo!("{e:?}"),
-                        })
-                    })
-                    .collect(),
-            ),
-        };
-        for FuncIdx(f) in vec {
-            if f >= functions.len() as u32 {
-                return Err(RuntimeError::TypeError(TypeCheckError::UnknownFunction));
+        for FuncIdx(f) in elem_funcs(e)? {
+            if f != u32::MAX && f >= functions.len() as u32 {
+                return Err(RuntimeError::from(TypeCheckError::UnknownFunction));
             }
         }
     }
@@ -649,61 +695,51 @@ fn validate_elems(elems: &[Elem], functions: &[Ptr<Function>]) -> Result<(), Run
 fn setup_elems(
     elems: Vec<Elem>,
     tables: &mut [PtrRW<Table>],
+    globals: &[PtrRW<(Mutable, Value)>],
 ) -> Result<Vec<PtrRW<Expr>>, RuntimeError> {
     let mut result = Vec::new();
     for elem in elems.into_iter() {
-        match elem {
-            Elem::E0(expr, vec) => match &expr.instrs[..] {
-                [Instr::x41_i32_const(off)] => {
-                    for (i, v) in vec.into_iter().enumerate() {
-                        let mut table = tables[0].write();
-                        let Table { table, .. } = &mut *table;
-                        table.insert(*off as u32 + i as u32, v);
-                    }
-                    result.push(expr.clone().into());
-                }
-                _ => panic!(),
-            },
-            Elem::E1(_fr, funcs) => {
-                result.push(
-                    Expr {
-                        instrs: funcs
-                            .into_iter()
-                            .map(|FuncIdx(i)| Instr::x41_i32_const(i as i32))
-                            .collect(),
-                    }
-                    .into(),
-                );
+        let funcs = elem_funcs(&elem)?;
+        let active = match &elem {
+            Elem::E0(expr, _) | Elem::E4(expr, _) => Some((0, expr)),
+            Elem::E2(TableIdX(t), expr, _, _) | Elem::E6(TableIdX(t), expr, _, _) => {
+                Some((*t, expr))
             }
-            Elem::E2(TableIdX(t), expr, _rt, vec) => match &expr.instrs[..] {
-                [Instr::x41_i32_const(off)] => {
-                    result.push(
-                        Expr {
-                            instrs: vec
-                                .iter()
-                                .map(|FuncIdx(i)| Instr::x41_i32_const(*i as i32))
-                                .collect(),
-                        }
-                        .into(),
-                    );
-                    for (i, v) in vec.iter().enumerate() {
-                        let mut table = tables[t as usize].write();
-                        table.table.insert(*off as u32 + i as u32, *v);
-                    }
-                }
-                _ => panic!(),
-            },
-            Elem::E3(_, _) => todo!(),
-            Elem::E4(_, _) => todo!(),
-            Elem::E5(_rt, vec) => {
-                // might be wrong
-                for expr in vec {
-                    result.push(expr.into());
-                }
+            Elem::E1(..) | Elem::E3(..) | Elem::E5(..) | Elem::E7(..) => None,
+        };
+        if let Some((t, expr)) = active {
+            let off = const_offset(expr, globals)?;
+            let table = tables
+                .get(t as usize)
+                .ok_or_else(|| RuntimeError::from(TypeCheckError::UnknownTable))?;
+            let mut table = table.write();
+            let Table {
+                table,
+                table_length,
+                ..
+            } = &mut *table;
+            if off as usize + funcs.len() > table_length.0 {
+                return Err(TrapCode::OutOfBoundsTableAccess.into());
+            }
+            for (i, v) in funcs.iter().enumerate() {
+                table.insert(off + i as u32, *v);
             }
-            Elem::E6(_, _, _, _) => todo!(),
-            Elem::E7(_, _) => todo!(),
         }
+        // active and declarative segments are dropped once instantiated
+        let instrs = match elem {
+            Elem::E1(..) | Elem::E5(..) => funcs
+                .into_iter()
+                .map(|FuncIdx(i)| Instr::x41_i32_const(i as i32))
+                .collect(),
+            _ => Vec::new(),
+        };
+        result.push(
+            Expr {
+                instrs,
+                offsets: Vec::new(),
+            }
+            .into(),
+        );
     }
     Ok(result)
 }
@@ -713,12 +749,12 @@ fn get_globals(
     p_globals: Vec<parser::Global>,
 ) -> Result<(), RuntimeError> {
     for PGlobal { e, gt, .. } in p_globals {
-        let val = match e.instrs[0] {
-            Instr::x41_i32_const(x) => Value::I32(x),
-            Instr::x42_i64_const(x) => Value::I64(x),
-            Instr::x43_f32_const(x) => Value::F32(x),
-            Instr::x44_f64_const(x) => Value::F64(x),
-            _ => return Err(GlobalWithoutValue),
+        let val = match e.instrs.first() {
+            Some(Instr::x41_i32_const(x)) => Value::I32(*x),
+            Some(Instr::x42_i64_const(x)) => Value::I64(*x),
+            Some(Instr::x43_f32_const(x)) => Value::F32(*x),
+            Some(Instr::x44_f64_const(x)) => Value::F64(*x),
+            _ => return Err(ValidationError::ConstantExpressionRequired.into()),
         };
         globals.push((gt.mutable, val).into());
     }
@@ -727,21 +763,23 @@ fn get_globals(
 
 fn setup_memory<const N: usize>(mems: Vec<parser::Mem>) -> Result<(Memory<N>, bool), RuntimeError> {
     if mems.len() > 1 {
-        return Err(MultipleMemories(file!(), line!(), column!()));
+        return Err(ValidationError::MultipleMemories.into());
     }
     let (mem_cur, mem_max) = mems
         .first()
         .map(|m| match m.limits {
-            Limits::Min(i) => (i as usize, u16::MAX as usize),
-            Limits::MinMax(i, m) => (i as usize, m as usize),
+            Limits::Min(i) => (i as usize, None),
+            Limits::MinMax(i, m) => (i as usize, Some(m as usize)),
         })
-        .unwrap_or((0, 0));
-    if mem_cur > 1 + u16::MAX as usize || mem_max > 1 + u16::MAX as usize {
-        return Err(MemorySizeLargerThanMax);
+        .unwrap_or((0, Some(0)));
+    if mem_cur > 1 + u16::MAX as usize || mem_max.is_some_and(|m| m > 1 + u16::MAX as usize) {
+        return Err(ValidationError::MemorySizeLargerThanMax.into());
     }
-    if mem_cur > mem_max {
-        return Err(MemMinLargerMemMax);
+    if mem_max.is_some_and(|m| mem_cur > m) {
+        return Err(ValidationError::MemMinLargerMemMax.into());
     }
+    // without a declared max only the runtime's resource limits apply
+    let mem_max = mem_max.unwrap_or(usize::MAX);
     Ok((Memory::new(mem_cur, mem_max), !mems.is_empty()))
 }
 
@@ -761,29 +799,35 @@ fn setup_data<const N: usize>(
                         if let Some(ko) = globals.get(*i as usize) {
                             let k = ko.read();
                             if k.0 != Mutable::Const || !PtrRW::is_weak(ko) {
-                                return Err(UnknownGlobal);
+                                return Err(ValidationError::UnknownGlobal.into());
                             }
                             match k.1 {
                                 Value::I32(p) => p,
-                                _ => todo!(),
+                                _ => {
+                                    return Err(RuntimeError::from(
+                                        TypeCheckError::WrongTypeOnStack,
+                                    ))
+                                }
                             }
                         } else {
-                            return Err(UnknownGlobal);
+                            return Err(ValidationError::UnknownGlobal.into());
                         }
                     }
                     _ => {
-                        return Err(ActiveDataWithoutOffset);
+                        return Err(ValidationError::ActiveDataWithoutOffset.into());
                     }
                 };
-                if vec.is_empty() && p == 1 {
-                    memory.get::<u8>(p as usize, MemArg::default())?;
-                }
                 if !mem_exists {
-                    return Err(UnknownMemory);
+                    return Err(ValidationError::UnknownMemory.into());
+                }
+                // a segment is written whole or not at all
+                let end = (p as u32 as usize).checked_add(vec.len());
+                if end.is_none_or(|end| end > memory.pages().0 * N) {
+                    return Err(TrapCode::OutOfBoundsMemoryAccess.into());
                 }
                 for (i, v) in vec.iter().enumerate() {
                     memory.set(
-                        p as usize + i,
+                        p as u32 as usize + i,
                         MemArg {
                             align: 0,
                             offset: 0,
@@ -794,13 +838,7 @@ fn setup_data<const N: usize>(
                 datas.push(vec.clone().into());
             }
             Data::Passive(v) => datas.push(v.clone().into()),
-            Data::ActiveX(MemIdX(m), _, _) => {
-                if m != 0 {
-                    return Err(RuntimeError::UnknownMemory);
-                } else {
-                    todo!()
-                }
-            }
+            Data::ActiveX(MemIdX(_), _, _) => return Err(ValidationError::UnknownMemory.into()),
         }
     }
     Ok(datas)
@@ -816,17 +854,17 @@ fn validate_label_depth(functions: &[Ptr<Function>]) -> Result<(), RuntimeError>
             match c {
                 Instr::x0c_br(LabelIdX(i)) | Instr::x0d_br_if(LabelIdX(i)) => {
                     if *i > depth {
-                        return Err(RuntimeError::UnknownLabel);
+                        return Err(ValidationError::UnknownLabel.into());
                     }
                 }
                 Instr::x0e_br_table(ls, LabelIdX(i)) => {
                     for LabelIdX(i) in ls {
                         if *i > depth {
-                            return Err(RuntimeError::UnknownLabel);
+                            return Err(ValidationError::UnknownLabel.into());
                         }
                     }
                     if *i > depth {
-                        return Err(RuntimeError::UnknownLabel);
+                        return Err(ValidationError::UnknownLabel.into());
                     }
                 }
                 Instr::block_start(_, _, _) => depth += 1,
@@ -848,35 +886,106 @@ pub struct Model {
     pub exports: HashMap<String, ExportDesc>,
     pub datas: Vec<PtrRW<Vec<u8>>>,
     pub memory: PtrRW<Memory<65536>>,
+    /// Functions imported from other wasm instances, by index, with the
+    /// instance and index they run under.
+    pub foreign: HashMap<u32, (String, u32)>,
+    /// The function to run once the instance is set up.
+    pub start: Option<u32>,
+    /// Where code came from in the source, if the module has DWARF line
+    /// info.
+    pub lines: Option<LineTable>,
+    /// Function names from the module's `name` section, and `module.name`
+    /// for imported functions it leaves out.
+    pub names: HashMap<u32, String>,
+    /// The module and name of each imported function, which come first.
+    pub function_imports: Vec<(String, String)>,
+}
+
+/// The line table of the module's `.debug_line` section. Broken debug info
+/// only loses the source positions, the module still loads.
+fn line_table(customs: &[CustomSection]) -> Option<LineTable> {
+    let section = |name: &str| {
+        customs
+            .iter()
+            .find(|c| &*c.name == name)
+            .map(|c| c.sections.concat())
+    };
+    let debug_line = section(".debug_line")?;
+    let debug_str = section(".debug_str").unwrap_or_default();
+    let debug_line_str = section(".debug_line_str").unwrap_or_default();
+    LineTable::parse(&debug_line, &debug_str, &debug_line_str)
+        .inspect_err(|e| warn!("ignoring DWARF line info: {e}"))
+        .ok()
+}
+
+/// The function names of the module's `name` section, falling back to what
+/// imports are imported as. Like debug info, a broken section is ignored.
+fn function_names(value: &Module) -> HashMap<u32, String> {
+    let mut names = value
+        .customs
+        .iter()
+        .find(|c| &*c.name == "name")
+        .and_then(|custom| {
+            NameSection::from_custom(custom)
+                .inspect_err(|e| warn!("ignoring name section: {e}"))
+                .ok()
+        })
+        .map(|n| n.functions)
+        .unwrap_or_default();
+    for (index, (module, name)) in function_imports(value).into_iter().enumerate() {
+        names
+            .entry(index as u32)
+            .or_insert_with(|| format!("{module}.{name}"));
+    }
+    names
+}
+
+fn function_imports(value: &Module) -> Vec<(String, String)> {
+    value
+        .imports
+        .imports
+        .iter()
+        .filter(|i| matches!(i.desc, ImportDesc::Func(_)))
+        .map(|i| (i.module.0.clone(), i.name.0.clone()))
+        .collect()
 }
 impl TryFrom<(&HashMap<String, Import>, Module)> for Model {
     type Error = RuntimeError;
     fn try_from((other, value): (&HashMap<String, Import>, Module)) -> Result<Self, Self::Error> {
         let type_len = value.types.function_types.len() as u32;
+        let names = function_names(&value);
+        let function_imports = function_imports(&value);
 
-        let (mut functions, mut globals, mut tables, memory) = setup_imports(other, &value)?;
+        let (mut functions, mut globals, mut tables, memory, foreign) =
+            setup_imports(other, &value)?;
         get_tables(&value, &mut tables)?;
         get_functions(
             value.code.code,
+            value.code.start,
             &value.types.function_types,
             &value.funcs.functions,
             &mut functions,
         )?;
         validate_elems(&value.elems.elems, &functions)?;
-        let elems = setup_elems(value.elems.elems, &mut tables)?;
         get_globals(&mut globals, value.globals.globals)?;
         let (memory, mem_exists) = if let Some(mem) = memory {
             if !value.mems.mems.is_empty() {
-                return Err(RuntimeError::UnknownMemory);
+                return Err(ValidationError::UnknownMemory.into());
             }
             (mem, true)
         } else {
             let (mem, mem_exists) = setup_memory(value.mems.mems)?;
             (mem.into(), mem_exists)
         };
-        let datas = setup_data(value.datas.data, &mut memory.write(), &globals, mem_exists)?;
         validate_label_depth(&functions)?;
         validate_instrs(&functions, &tables, type_len, mem_exists)?;
+        if value.start.is_some_and(|f| f as usize >= functions.len()) {
+            return Err(RuntimeError::from(TypeCheckError::UnknownFunction));
+        }
+        // segments go into tables and memories other instances may share, so
+        // nothing may be written before the module is known to be valid
+        let elems = setup_elems(value.elems.elems, &mut tables, &globals)?;
+        let datas = setup_data(value.datas.data, &mut memory.write(), &globals, mem_exists)?;
 
         Ok(Self {
             functions,
@@ -892,6 +1001,11 @@ impl TryFrom<(&HashMap<String, Import>, Module)> for Model {
             exports: value.exports.exports.into_iter().collect::<HashMap<_, _>>(),
             datas,
             memory,
+            foreign,
+            start: value.start,
+            lines: line_table(&value.customs),
+            names,
+            function_imports,
         })
     }
 }
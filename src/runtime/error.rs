@@ -1,160 +1,363 @@
 use super::{typecheck::TypeCheckError, Value};
+use crate::parser::error::ParseError;
+use std::{fmt::Display, path::PathBuf};
 
+/// Everything that can go wrong while loading or running a module.
+///
+/// The variants separate faults caused by the guest ([`TrapCode`]) from
+/// problems with linking, validation, parsing, reading modules from disk and
+/// the interpreter itself.
 #[allow(unused)]
 pub enum RuntimeError {
-    ParseError(String),
+    Trap(TrapCode),
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Link(LinkError),
+    Validation(ValidationError),
+    Parse {
+        offset: u64,
+        error: ParseError,
+    },
+    Internal(InternalError),
     Exit(i32),
-    GlobalWithoutValue,
+    ReturnedToNoFrame(Vec<Value>),
+}
+
+/// A fault raised by guest code, with the message the spec test-suite expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(unused)]
+pub enum TrapCode {
+    Unreachable,
+    IntegerOverflow,
+    IntegerDivideByZero,
+    InvalidConversionToInteger,
+    OutOfBoundsMemoryAccess,
+    OutOfBoundsTableAccess,
+    UndefinedElement,
+    UninitializedElement,
+    IndirectCallTypeMismatch,
+    StackExhaustion,
+    OutOfFuel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(unused)]
+pub enum LinkError {
+    UnknownImport {
+        module: String,
+        name: String,
+    },
+    IncompatibleImportType {
+        module: String,
+        name: String,
+    },
+    UnknownExport(String),
+    ArgumentMismatch {
+        export: String,
+        expected: String,
+        got: String,
+    },
+    ResourceLimitExceeded(&'static str),
+    DuplicateInstance(String),
+    ExportKindMismatch {
+        export: String,
+        expected: &'static str,
+    },
+    ImmutableGlobal(String),
+    /// A reference to a function the instance of the table doesn't have.
+    UnknownFunction {
+        export: String,
+        index: u32,
+    },
+}
+
+#[derive(Debug)]
+#[allow(unused)]
+pub enum ValidationError {
+    Type(TypeCheckError),
+    ConstantExpressionRequired,
     ActiveDataWithoutOffset,
     MemMinLargerMemMax,
     MemorySizeLargerThanMax,
-    UnknownFunction(String, String),
-    ReturnedToNoFrame(Vec<Value>, &'static str, u32, u32),
-    UnknownImport(&'static str, u32, u32),
-    IncompatibleImportType(&'static str, u32, u32),
-    NoFrame(&'static str, u32, u32),
-    NoModule(String, &'static str, u32, u32),
-    MissingGlobal(&'static str, u32, u32),
-    WrongType(&'static str, &'static str, &'static str, u32, u32),
-    EmptyStack(&'static str, u32, u32),
-    Unreachable(&'static str, u32, u32),
-    Impossible(&'static str, u32, u32),
-    MissingLocal(&'static str, u32, u32),
-    MissingFunction(&'static str, u32, u32),
-    MissingFunctionImport(String, &'static str, u32, u32),
-    MissingJumpLabel(&'static str, u32, u32),
-    MissingTableIndex(&'static str, u32, u32),
-    OutOfBoundsTableAccess(&'static str, u32, u32),
-    MissingData(&'static str, u32, u32),
-    DataInitOutOfRange(&'static str, u32, u32),
-    MissingElementIndex(&'static str, u32, u32),
-    MissingType(&'static str, u32, u32),
-    TypeError(TypeCheckError),
-    UninitializedElement(&'static str, u32, u32),
-    UndefinedElement(&'static str, u32, u32),
-    IndirectCallTypeMismatch(&'static str, u32, u32),
-    IntegerOverflow(&'static str, u32, u32),
-    InvalidConversionToInteger(&'static str, u32, u32),
-    IntegerDivideByZero(&'static str, u32, u32),
-    MultipleMemories(&'static str, u32, u32),
+    MultipleMemories,
     UnknownLabel,
     UnknownGlobal,
     UnknownMemory,
-    OutOfBoundsMemoryAccess,
-    StackExhaustion(usize, usize),
+    TooManyLocals,
+}
+
+/// A broken invariant inside the interpreter, tagged with where it was noticed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternalError {
+    pub kind: InternalErrorKind,
+    pub file: &'static str,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(unused)]
+pub enum InternalErrorKind {
+    NoFrame,
+    NoModule(String),
+    MissingGlobal,
+    WrongType(&'static str, &'static str),
+    EmptyStack,
+    Impossible,
+    MissingLocal,
+    MissingFunction,
+    MissingJumpLabel,
+    MissingTableIndex,
+    MissingData,
+    MissingElementIndex,
+    MissingType,
+    ReentrantHostCall,
+}
+
+// `at` is what the `unwrap!`/`throw!` macros call, so every error kind can be
+// raised the same way whether or not it cares about the interpreter location.
+impl TrapCode {
+    pub fn at(self, _file: &'static str, _line: u32, _column: u32) -> RuntimeError {
+        RuntimeError::Trap(self)
+    }
+}
+impl LinkError {
+    #[allow(unused)]
+    pub fn at(self, _file: &'static str, _line: u32, _column: u32) -> RuntimeError {
+        RuntimeError::Link(self)
+    }
+}
+impl ValidationError {
+    #[allow(unused)]
+    pub fn at(self, _file: &'static str, _line: u32, _column: u32) -> RuntimeError {
+        RuntimeError::Validation(self)
+    }
+}
+impl InternalErrorKind {
+    pub fn at(self, file: &'static str, line: u32, column: u32) -> RuntimeError {
+        RuntimeError::Internal(InternalError {
+            kind: self,
+            file,
+            line,
+            column,
+        })
+    }
+}
+
+impl RuntimeError {
+    #[allow(unused)]
+    pub fn trap_code(&self) -> Option<TrapCode> {
+        match self {
+            Self::Trap(code) => Some(*code),
+            _ => None,
+        }
+    }
 }
 
+impl From<TrapCode> for RuntimeError {
+    fn from(value: TrapCode) -> Self {
+        Self::Trap(value)
+    }
+}
+impl From<LinkError> for RuntimeError {
+    fn from(value: LinkError) -> Self {
+        Self::Link(value)
+    }
+}
+impl From<ValidationError> for RuntimeError {
+    fn from(value: ValidationError) -> Self {
+        Self::Validation(value)
+    }
+}
+impl From<InternalError> for RuntimeError {
+    fn from(value: InternalError) -> Self {
+        Self::Internal(value)
+    }
+}
 impl From<TypeCheckError> for RuntimeError {
     fn from(value: TypeCheckError) -> Self {
-        Self::TypeError(value)
+        Self::Validation(ValidationError::Type(value))
     }
 }
 
-impl std::fmt::Debug for RuntimeError {
+impl Display for TrapCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::MemorySizeLargerThanMax => {
-                write!(f, "memory size must be at most 65536 pages (4GiB)")
-            }
-            Self::MemMinLargerMemMax => write!(f, "size minimum must not be greater than maximum"),
-            Self::UnknownGlobal => write!(f, "unknown global"),
-            Self::UnknownMemory => write!(f, "unknown memory"),
-            Self::StackExhaustion(cur, max) => write!(f, "stack overflow ({cur}/{max})"),
-            Self::IndirectCallTypeMismatch(..) => write!(f, "indirect call type mismatch"),
-            Self::NoModule(module, arg0, arg1, arg2) => {
-                write!(
-                    f,
-                    "tried to use unloaded module \"{module}\": {arg0}:{arg1}:{arg2}"
-                )
-            }
-            Self::IntegerOverflow(arg0, arg1, arg2) => {
-                write!(f, "integer overflow: {arg0}:{arg1}:{arg2}")
-            }
-            Self::IntegerDivideByZero(arg0, arg1, arg2) => {
-                write!(f, "integer divide by zero: {arg0}:{arg1}:{arg2}")
-            }
-            Self::MultipleMemories(arg0, arg1, arg2) => {
-                write!(f, "multiple memories: {arg0}:{arg1}:{arg2}")
+            Self::Unreachable => write!(f, "unreachable"),
+            Self::IntegerOverflow => write!(f, "integer overflow"),
+            Self::IntegerDivideByZero => write!(f, "integer divide by zero"),
+            Self::InvalidConversionToInteger => write!(f, "invalid conversion to integer"),
+            Self::OutOfBoundsMemoryAccess => write!(f, "out of bounds memory access"),
+            Self::OutOfBoundsTableAccess => write!(f, "out of bounds table access"),
+            Self::UndefinedElement => write!(f, "undefined element"),
+            Self::UninitializedElement => write!(f, "uninitialized element"),
+            Self::IndirectCallTypeMismatch => write!(f, "indirect call type mismatch"),
+            Self::StackExhaustion => write!(f, "call stack exhausted"),
+            Self::OutOfFuel => write!(f, "all fuel consumed"),
+        }
+    }
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownImport { module, name } => {
+                write!(f, "unknown import: {module:?} {name:?}")
             }
-            Self::IncompatibleImportType(arg0, arg1, arg2) => {
-                write!(f, "incompatible import type: {arg0}:{arg1}:{arg2}")
+            Self::IncompatibleImportType { module, name } => {
+                write!(f, "incompatible import type: {module:?} {name:?}")
             }
-            Self::UnknownImport(arg0, arg1, arg2) => {
-                write!(f, "unknown import: {arg0}:{arg1}:{arg2}")
+            Self::UnknownExport(name) => write!(f, "unknown export: {name:?}"),
+            Self::ArgumentMismatch {
+                export,
+                expected,
+                got,
+            } => write!(
+                f,
+                "wrong arguments for {export:?}: expected {expected}, got ({got})"
+            ),
+            Self::ResourceLimitExceeded(resource) => {
+                write!(f, "resource limit exceeded: {resource}")
             }
-            Self::MissingData(arg0, arg1, arg2) => {
-                write!(f, "tried to get non-existent data: {arg0}:{arg1}:{arg2}")
+            Self::DuplicateInstance(name) => write!(f, "instance {name:?} already exists"),
+            Self::ExportKindMismatch { export, expected } => {
+                write!(f, "export {export:?} is not a {expected}")
             }
-            Self::TypeError(t) => write!(f, "type error: {t:?}"),
-            Self::OutOfBoundsMemoryAccess => write!(f, "out of bounds memory access"),
-            Self::ParseError(string) => write!(f, "ParseError({string})"),
-            Self::Exit(arg0) => f.debug_tuple("Exit").field(arg0).finish(),
-            Self::GlobalWithoutValue => write!(f, "GlobalWithoutOffset"),
-            Self::ActiveDataWithoutOffset => write!(f, "ActiveDataWithoutOffset"),
-            Self::UnknownFunction(arg0, arg1) => write!(f, "unknown function: {arg0}::{arg1}"),
-            Self::ReturnedToNoFrame(stack, arg0, arg1, arg2) => {
+            Self::ImmutableGlobal(name) => write!(f, "global {name:?} is immutable"),
+            Self::UnknownFunction { export, index } => {
                 write!(
                     f,
-                    "returned, but no more frames ({stack:?}): {arg0}:{arg1}:{arg2}"
+                    "table {export:?} can't hold function {index}, there is none"
                 )
             }
-            Self::MissingType(arg0, arg1, arg2) => {
-                write!(f, "missing type: {arg0}:{arg1}:{arg2}")
-            }
-            Self::MissingGlobal(arg0, arg1, arg2) => {
-                write!(f, "missing global: {arg0}:{arg1}:{arg2}")
-            }
-            Self::NoFrame(arg0, arg1, arg2) => {
-                write!(f, "ran out of stack frames: {arg0}:{arg1}:{arg2}")
-            }
-            Self::WrongType(arg0, exp, got, arg1, arg2) => {
-                write!(f, "wrong type popped from stack (got {got}, expected {exp}): {arg0}:{arg1}:{arg2}")
-            }
-            Self::EmptyStack(arg0, arg1, arg2) => {
-                write!(f, "empty stack: {arg0}:{arg1}:{arg2}")
-            }
-            Self::Unreachable(arg0, arg1, arg2) => {
-                write!(f, "hit an unreachable code segment: {arg0}:{arg1}:{arg2}")
-            }
-            Self::Impossible(arg0, arg1, arg2) => {
-                write!(f, "an impossible case happened: {arg0}:{arg1}:{arg2}")
-            }
-            Self::MissingLocal(arg0, arg1, arg2) => {
-                write!(f, "a local is missing: {arg0}:{arg1}:{arg2}")
-            }
-            Self::MissingFunction(arg0, arg1, arg2) => {
-                write!(f, "missing function index: {arg0}:{arg1}:{arg2}")
-            }
-            Self::MissingFunctionImport(name, arg0, arg1, arg2) => {
-                write!(f, "missing function {name:?}: {arg0}:{arg1}:{arg2}")
-            }
-            Self::MissingJumpLabel(arg0, arg1, arg2) => {
-                write!(f, "missing jump label: {arg0}:{arg1}:{arg2}")
-            }
-            Self::MissingTableIndex(arg0, arg1, arg2) => {
-                write!(f, "missing table index: {arg0}:{arg1}:{arg2}")
-            }
-            Self::MissingElementIndex(arg0, arg1, arg2) => {
-                write!(f, "missing element vector index: {arg0}:{arg1}:{arg2}")
-            }
-            Self::DataInitOutOfRange(_, _, _) => {
-                write!(f, "out of bounds memory access")
-            }
-            Self::OutOfBoundsTableAccess(_, _, _) => {
-                write!(f, "out of bounds table access")
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Type(t) => write!(f, "{t}"),
+            Self::ConstantExpressionRequired => write!(f, "constant expression required"),
+            Self::ActiveDataWithoutOffset => write!(f, "active data segment without offset"),
+            Self::MemMinLargerMemMax => write!(f, "size minimum must not be greater than maximum"),
+            Self::MemorySizeLargerThanMax => {
+                write!(f, "memory size must be at most 65536 pages (4GiB)")
             }
-            Self::UnknownLabel => {
-                write!(f, "unknown label")
+            Self::MultipleMemories => write!(f, "multiple memories"),
+            Self::UnknownLabel => write!(f, "unknown label"),
+            Self::UnknownGlobal => write!(f, "unknown global"),
+            Self::UnknownMemory => write!(f, "unknown memory"),
+            Self::TooManyLocals => write!(f, "too many locals"),
+        }
+    }
+}
+
+impl Display for InternalErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoFrame => write!(f, "ran out of stack frames"),
+            Self::NoModule(module) => write!(f, "tried to use unloaded module {module:?}"),
+            Self::MissingGlobal => write!(f, "missing global"),
+            Self::WrongType(exp, got) => {
+                write!(
+                    f,
+                    "wrong type popped from stack (got {got}, expected {exp})"
+                )
             }
-            Self::UndefinedElement(_arg0, _arg1, _arg2) => {
-                write!(f, "undefined element")
+            Self::EmptyStack => write!(f, "empty stack"),
+            Self::Impossible => write!(f, "an impossible case happened"),
+            Self::MissingLocal => write!(f, "a local is missing"),
+            Self::MissingFunction => write!(f, "missing function index"),
+            Self::MissingJumpLabel => write!(f, "missing jump label"),
+            Self::MissingTableIndex => write!(f, "missing table index"),
+            Self::MissingData => write!(f, "tried to get non-existent data"),
+            Self::MissingElementIndex => write!(f, "missing element vector index"),
+            Self::MissingType => write!(f, "missing type"),
+            Self::ReentrantHostCall => {
+                write!(f, "host function called again while it is still running")
             }
-            Self::UninitializedElement(_arg0, _arg1, _arg2) => {
-                write!(f, "uninitialized element")
+        }
+    }
+}
+
+impl Display for InternalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            kind,
+            file,
+            line,
+            column,
+        } = self;
+        write!(f, "{kind}: {file}:{line}:{column}")
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Trap(code) => write!(f, "{code}"),
+            Self::Io { path, error } => write!(f, "failed to read {path:?}: {error}"),
+            Self::Link(e) => write!(f, "{e}"),
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::Parse { offset, error } => write!(f, "{error} (at byte {offset:#x})"),
+            Self::Internal(e) => write!(f, "internal error: {e}"),
+            Self::Exit(code) => write!(f, "exited with code {code}"),
+            Self::ReturnedToNoFrame(stack) => {
+                write!(f, "returned, but no more frames ({stack:?})")
             }
-            Self::InvalidConversionToInteger(_arg0, _arg1, _arg2) => {
-                write!(f, "invalid conversion to integer")
+        }
+    }
+}
+
+impl std::fmt::Debug for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Trap(code) => f.debug_tuple("Trap").field(code).finish(),
+            Self::Io { path, error } => f
+                .debug_struct("Io")
+                .field("path", path)
+                .field("error", error)
+                .finish(),
+            Self::Link(e) => f.debug_tuple("Link").field(e).finish(),
+            Self::Validation(e) => f.debug_tuple("Validation").field(e).finish(),
+            Self::Parse { offset, error } => f
+                .debug_struct("Parse")
+                .field("offset", offset)
+                .field("error", error)
+                .finish(),
+            Self::Internal(e) => f.debug_tuple("Internal").field(e).finish(),
+            Self::Exit(code) => f.debug_tuple("Exit").field(code).finish(),
+            Self::ReturnedToNoFrame(stack) => {
+                f.debug_tuple("ReturnedToNoFrame").field(stack).finish()
             }
         }
     }
 }
+
+impl std::error::Error for TrapCode {}
+impl std::error::Error for LinkError {}
+impl std::error::Error for ValidationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Type(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+impl std::error::Error for InternalError {}
+impl std::error::Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Trap(e) => Some(e),
+            Self::Io { error, .. } => Some(error),
+            Self::Link(e) => Some(e),
+            Self::Validation(e) => Some(e),
+            Self::Parse { error, .. } => Some(error),
+            Self::Internal(e) => Some(e),
+            Self::Exit(_) | Self::ReturnedToNoFrame(_) => None,
+        }
+    }
+}
@@ -1,6 +1,7 @@
 #[allow(clippy::wrong_self_convention)]
 pub trait FloatExp {
     type Int;
+    /// The quiet NaN with a positive sign and no payload.
     const NAN_CANONICAL: Self;
     const NAN_ARITHMETIC: Self;
 
@@ -10,16 +11,15 @@ pub trait FloatExp {
 }
 impl FloatExp for f32 {
     type Int = u32;
-    const NAN_CANONICAL: Self = f32::from_bits(0b01000000000000000000000000000000);
-    const NAN_ARITHMETIC: Self = f32::from_bits(0b01000000000000000000000000000000);
+    const NAN_CANONICAL: Self = f32::from_bits(0b01111111110000000000000000000000);
+    const NAN_ARITHMETIC: Self = f32::from_bits(0b01111111110000000000000000000000);
 
     fn is_nan_canonical(self) -> bool {
-        (0b01000000000000000000000000000000 & self.to_bits()) != 0
-            && (0b0011111110000000000000000000000 & self.to_bits()) == 0
+        self.is_nan() && (0b00000000011111111111111111111111 & self.to_bits()) == 1 << 22
     }
 
     fn is_nan_arithmetic(self) -> bool {
-        (0b01000000000000000000000000000000 & self.to_bits()) != 0
+        self.is_nan() && (0b00000000010000000000000000000000 & self.to_bits()) != 0
     }
 
     fn nan_sign(self) -> Self::Int {
@@ -30,18 +30,20 @@ impl FloatExp for f32 {
 impl FloatExp for f64 {
     type Int = u64;
     const NAN_CANONICAL: Self =
-        f64::from_bits(0b0100000000000000000000000000000000000000000000000000000000000000);
+        f64::from_bits(0b0111111111111000000000000000000000000000000000000000000000000000);
     const NAN_ARITHMETIC: Self =
-        f64::from_bits(0b0100000000000000000000000000000000000000000000000000000000000000);
+        f64::from_bits(0b0111111111111000000000000000000000000000000000000000000000000000);
 
     fn is_nan_canonical(self) -> bool {
-        (0b0100000000000000000000000000000000000000000000000000000000000000 & self.to_bits()) != 0
-            && (0b0011111111110000000000000000000000000000000000000000000000000000 & self.to_bits())
-                == 0
+        self.is_nan()
+            && (0b0000000000001111111111111111111111111111111111111111111111111111 & self.to_bits())
+                == 1 << 51
     }
 
     fn is_nan_arithmetic(self) -> bool {
-        (0b0100000000000000000000000000000000000000000000000000000000000000 & self.to_bits()) != 0
+        self.is_nan()
+            && (0b0000000000001000000000000000000000000000000000000000000000000000 & self.to_bits())
+                != 0
     }
 
     fn nan_sign(self) -> Self::Int {
@@ -0,0 +1,214 @@
+use super::super::{
+    clean_model::{Model, Table},
+    memory::Memory,
+    Import,
+    InternalErrorKind::*,
+    Runtime, RuntimeError, Value,
+};
+use crate::{parser::Mutable, ptr::PtrRW};
+use std::any::Any;
+
+/// What a host function sees of the runtime while it runs: the instance that
+/// called it, the embedder's data and the way back into the guest.
+pub struct Caller<'r> {
+    runtime: &'r mut Runtime,
+    module: String,
+}
+
+// wasp itself only reads the memory, the rest is there for embedders, which
+// the tests stand in for
+#[cfg_attr(not(test), allow(unused))]
+impl<'r> Caller<'r> {
+    pub(crate) fn new(runtime: &'r mut Runtime, module: String) -> Self {
+        Self { runtime, module }
+    }
+
+    fn model(&self) -> Result<&Model, RuntimeError> {
+        match self.runtime.modules.get(&self.module) {
+            Some(Import::WS(model)) => Ok(model),
+            _ => Err(NoModule(self.module.clone()).at(file!(), line!(), column!())),
+        }
+    }
+
+    /// The name the calling instance was loaded under.
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// See [`Runtime::data`].
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.runtime.data()
+    }
+
+    /// See [`Runtime::data_mut`].
+    pub fn data_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.runtime.data_mut()
+    }
+
+    /// The memory of the calling instance.
+    pub fn memory(&self) -> Result<PtrRW<Memory<65536>>, RuntimeError> {
+        Ok(self.model()?.memory.clone())
+    }
+
+    /// Global `index` of the calling instance, imported ones first.
+    pub fn global(&self, index: u32) -> Option<PtrRW<(Mutable, Value)>> {
+        self.model().ok()?.globals.get(index as usize).cloned()
+    }
+
+    /// Table `index` of the calling instance, imported ones first.
+    pub fn table(&self, index: u32) -> Option<PtrRW<Table>> {
+        self.model().ok()?.tables.get(index as usize).cloned()
+    }
+
+    /// Calls an export of the calling instance, see [`Runtime::invoke`].
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, RuntimeError> {
+        let module = self.module.clone();
+        self.runtime.invoke_in(&module, name, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{
+            import::IOFunction, InternalError, InternalErrorKind, TrapCode, IO, MAIN_MODULE,
+        },
+        wast::text_module,
+    };
+    use std::collections::HashMap;
+
+    const MODULE: &str = r#"(module
+        (import "host" "count" (func $count (result i32)))
+        (import "host" "peek" (func $peek (param i32) (result i32)))
+        (import "host" "twice" (func $twice (param i32) (result i32)))
+        (import "host" "again" (func $again (result i32)))
+        (memory 1)
+        (data (i32.const 8) "\2a")
+        (global $g (mut i64) (i64.const 5))
+        (table 3 funcref)
+        (func $double (export "double") (param i32) (result i32)
+            (i32.mul (local.get 0) (i32.const 2)))
+        (func (export "count") (result i32) (call $count))
+        (func (export "peek") (param i32) (result i32) (call $peek (local.get 0)))
+        (func (export "twice") (param i32) (result i32) (call $twice (local.get 0)))
+        (func (export "again") (result i32) (call $again)))"#;
+
+    fn runtime() -> Runtime {
+        let functions = [
+            (
+                "count",
+                IOFunction::wrap(|caller: &mut Caller| -> Result<i32, RuntimeError> {
+                    assert!(caller.data::<String>().is_none());
+                    let count = caller.data_mut::<u32>().ok_or(TrapCode::Unreachable)?;
+                    *count += 1;
+                    Ok(*count as i32)
+                }),
+            ),
+            (
+                "peek",
+                IOFunction::wrap(
+                    |caller: &mut Caller, addr: u32| -> Result<i32, RuntimeError> {
+                        assert_eq!(caller.module(), MAIN_MODULE);
+                        let byte = caller
+                            .memory()?
+                            .read()
+                            .get::<u8>(addr as usize, Default::default())?;
+                        let global = caller.global(0).ok_or(TrapCode::Unreachable)?;
+                        let Value::I64(g) = global.read().1 else {
+                            return Err(TrapCode::Unreachable.into());
+                        };
+                        caller.memory()?.write().slice_write(0, &[byte + 1])?;
+                        let table = caller.table(0).ok_or(TrapCode::Unreachable)?;
+                        assert_eq!(table.read().table_length.0, 3);
+                        assert!(caller.global(1).is_none() && caller.table(1).is_none());
+                        Ok(i32::from(byte) + g as i32)
+                    },
+                ),
+            ),
+            (
+                "twice",
+                IOFunction::wrap(|caller: &mut Caller, x: i32| {
+                    match caller.invoke("double", &[Value::I32(x)])?[..] {
+                        [Value::I32(doubled)] => Ok(doubled + 1),
+                        _ => Err(RuntimeError::from(TrapCode::Unreachable)),
+                    }
+                }),
+            ),
+            (
+                "again",
+                IOFunction::wrap(|caller: &mut Caller| -> Result<i32, RuntimeError> {
+                    caller.invoke("again", &[])?;
+                    Ok(0)
+                }),
+            ),
+        ];
+        let io = IO {
+            functions: functions.into_iter().collect(),
+            globals: HashMap::new(),
+            tables: HashMap::new(),
+            memory: Memory::new(0, 0).into(),
+            memory_name: "memory".to_string(),
+            state: None,
+        };
+        Runtime::builder()
+            .add_io("host", io)
+            .add_module(MAIN_MODULE, &text_module(MODULE))
+            .data(0u32)
+            .build()
+            .expect("the runtime builds")
+    }
+
+    #[test]
+    fn host_functions_keep_data_across_calls() {
+        let mut rt = runtime();
+        for n in 1..=3 {
+            assert!(rt.invoke("count", &[]).expect("count returns") == [Value::I32(n)]);
+        }
+        assert_eq!(rt.data::<u32>(), Some(&3));
+    }
+
+    #[test]
+    fn host_functions_see_the_callers_memory_and_globals() {
+        let mut rt = runtime();
+        let peeked = rt.invoke("peek", &[Value::I32(8)]).expect("peek returns");
+        assert!(peeked == [Value::I32(42 + 5)]);
+        let memory = rt
+            .instance(MAIN_MODULE)
+            .expect("main is loaded")
+            .own_memory();
+        let mut byte = [0];
+        memory
+            .read(0, &mut byte)
+            .expect("address 0 is in the memory");
+        assert_eq!(byte, [43]);
+        // out of the memory, the host's error is the guest's trap
+        assert!(matches!(
+            rt.invoke("peek", &[Value::I32(0x10000)]),
+            Err(RuntimeError::Trap(TrapCode::OutOfBoundsMemoryAccess))
+        ));
+    }
+
+    #[test]
+    fn host_functions_can_call_back_into_the_guest() {
+        let mut rt = runtime();
+        let results = rt
+            .invoke("twice", &[Value::I32(20)])
+            .expect("twice returns");
+        assert!(results == [Value::I32(41)]);
+    }
+
+    #[test]
+    fn reentering_a_host_function_is_an_error() {
+        let mut rt = runtime();
+        assert!(matches!(
+            rt.invoke("again", &[]),
+            Err(RuntimeError::Internal(InternalError {
+                kind: InternalErrorKind::ReentrantHostCall,
+                ..
+            }))
+        ));
+        // and the function can still be called afterwards
+        assert!(rt.invoke("count", &[]).expect("count returns") == [Value::I32(1)]);
+    }
+}
@@ -1,26 +1,24 @@
 use std::collections::HashMap;
 
 use crate::{
-    parser::{Mutable, RefTyp},
+    parser::{FuncType, Mutable, RefTyp},
     ptr::PtrRW,
 };
 
 use super::{
     clean_model::{Model, Table},
     memory::Memory,
-    RuntimeError::{self, *},
-    Value,
+    InternalErrorKind::*,
+    RuntimeError, Value,
 };
 
 pub type Locals<'t> = &'t HashMap<u32, Value>;
 pub type Mem<'t> = &'t mut Memory<65536>;
 pub type Stack = Vec<Value>;
 
-pub type IOFunction = &'static dyn Fn(Locals, Mem) -> Result<Stack, RuntimeError>;
-
 macro_rules! unwrap {
     ($expr:expr, $err:expr) => {
-        $expr.ok_or($err(file!(), line!(), column!()))?
+        $expr.ok_or_else(|| $err.at(file!(), line!(), column!()))?
     };
 }
 
@@ -38,53 +36,106 @@ macro_rules! get {
     (i32, $index:expr, $locals:expr) => {{
         let val = match unwrap!($locals.get($index), MissingLocal) {
             Value::I32(val) => val,
-            x => throw!(|a, b, c| WrongType(a, "i32", x.as_str(), b, c)),
+            x => throw!(WrongType("i32", x.as_str())),
         };
         val
     }};
     (i64, $index:expr, $locals:expr) => {{
         let val = match unwrap!($locals.get($index), MissingLocal) {
             Value::I64(val) => val,
-            x => throw!(|a, b, c| WrongType(a, "i64", x.as_str(), b, c)),
+            x => throw!(WrongType("i64", x.as_str())),
         };
         val
     }};
     (u32, $index:expr, $locals:expr) => {{
         let val = match unwrap!($locals.get($index), MissingLocal) {
             Value::I32(val) => val,
-            x => throw!(|a, b, c| WrongType(a, "u32", x.as_str(), b, c)),
+            x => throw!(WrongType("u32", x.as_str())),
         };
         unsafe { std::mem::transmute::<i32, u32>(val) }
     }};
     (u64, $index:expr, $locals:expr) => {{
         let val = match unwrap!($locals.get($index), MissingLocal) {
             Value::I64(val) => val,
-            x => throw!(|a, b, c| WrongType(a, "u64", x.as_str(), b, c)),
+            x => throw!(WrongType("u64", x.as_str())),
         };
         unsafe { std::mem::transmute::<i64, u64>(val) }
     }};
     (f32, $index:expr, $locals:expr) => {{
         let val = match unwrap!($locals.get($index), MissingLocal) {
             Value::F32(val) => val,
-            x => throw!(|a, b, c| WrongType(a, "f32", x.as_str(), b, c)),
+            x => throw!(WrongType("f32", x.as_str())),
         };
         val
     }};
     (f64, $index:expr, $locals:expr) => {{
         let val = match unwrap!($locals.get($index), MissingLocal) {
             Value::F64(val) => val,
-            x => throw!(|a, b, c| WrongType(a, "f64", x.as_str(), b, c)),
+            x => throw!(WrongType("f64", x.as_str())),
         };
         val
     }};
 }
 
+type HostFn = dyn FnMut(&mut Caller, Locals) -> Result<Stack, RuntimeError>;
+
+/// A function provided by the host. The [`IO`] defining it owns the closure,
+/// the modules importing it share it.
+#[derive(Clone)]
+pub struct IOFunction {
+    func: PtrRW<Box<HostFn>>,
+    ty: Option<FuncType>,
+}
+impl IOFunction {
+    /// An untyped host function, taking whatever the importing module
+    /// declares. See [`IOFunction::wrap`] for one with a checked signature.
+    pub fn new(
+        f: impl FnMut(&mut Caller, Locals) -> Result<Stack, RuntimeError> + 'static,
+    ) -> Self {
+        Self {
+            func: PtrRW::from(Box::new(f) as Box<HostFn>),
+            ty: None,
+        }
+    }
+
+    /// The signature the function was registered with, if it has one.
+    pub fn ty(&self) -> Option<&FuncType> {
+        self.ty.as_ref()
+    }
+
+    pub fn call(&self, caller: &mut Caller, locals: Locals) -> Result<Stack, RuntimeError> {
+        let mut f = unwrap!(self.func.try_write(), ReentrantHostCall);
+        f(caller, locals)
+    }
+}
+
+mod caller;
+mod typed;
+mod wasi;
+pub use caller::Caller;
+#[allow(unused)]
+pub use typed::{IntoHostFunc, WasmResults, WasmTy};
+pub use wasi::{describe_call, errno_name, WasiConfig, WASI_MODULE};
+
+/// What host functions keep between calls that a snapshot should keep too,
+/// so that a restored program sees the host as it was.
+pub trait HostState {
+    fn save(&self) -> Vec<u8>;
+    /// Puts back what [`HostState::save`] gave, changing nothing when it
+    /// doesn't fit.
+    fn restore(&self, saved: &[u8]) -> Result<(), String>;
+}
+
 pub struct IO {
     pub functions: HashMap<&'static str, IOFunction>,
     pub globals: HashMap<&'static str, PtrRW<(Mutable, Value)>>,
     pub tables: HashMap<&'static str, PtrRW<Table>>,
     pub memory: PtrRW<Memory<65536>>,
     pub memory_name: String,
+    /// Saved and restored with the instances, see [`Runtime::snapshot`].
+    ///
+    /// [`Runtime::snapshot`]: super::Runtime::snapshot
+    pub state: Option<Box<dyn HostState>>,
 }
 pub enum Import {
     WS(Model),
@@ -101,49 +152,23 @@ impl Import {
     #[allow(clippy::print_stdout)]
     pub fn spectest() -> IO {
         let map: Vec<(&'static str, IOFunction)> = vec![
-            ("print_i32", &|locals, _| {
-                let a = *get!(i32, &0, locals);
-                println!("{a}");
-                Ok(Vec::new())
-            }),
-            ("print_i64", &|locals, _| {
-                let a = *get!(i64, &0, locals);
-                println!("{a}");
-                Ok(Vec::new())
-            }),
-            #[allow(clippy::print_stdout)]
-            ("print_i32_f32", &|locals, _| {
-                let b = *get!(f32, &1, locals);
-                let a = *get!(i32, &0, locals);
-                println!("{a} {b}");
-                Ok(Vec::new())
-            }),
-            #[allow(clippy::print_stdout)]
-            ("print_i64_f64", &|locals, _| {
-                let b = *get!(f64, &1, locals);
-                let a = *get!(i64, &0, locals);
-                println!("{a} {b}");
-                Ok(Vec::new())
-            }),
-            #[allow(clippy::print_stdout)]
-            ("print_f64_f64", &|locals, _| {
-                let b = *get!(f64, &1, locals);
-                let a = *get!(f64, &0, locals);
-                println!("{a} {b}");
-                Ok(Vec::new())
-            }),
-            #[allow(clippy::print_stdout)]
-            ("print_f32", &|locals, _| {
-                let a = *get!(f32, &0, locals);
-                println!("{a}");
-                Ok(Vec::new())
-            }),
-            #[allow(clippy::print_stdout)]
-            ("print_f64", &|locals, _| {
-                let a = *get!(f64, &0, locals);
-                println!("{a}");
-                Ok(Vec::new())
-            }),
+            ("print", IOFunction::wrap(|| {})),
+            ("print_i32", IOFunction::wrap(|a: i32| println!("{a}"))),
+            ("print_i64", IOFunction::wrap(|a: i64| println!("{a}"))),
+            ("print_f32", IOFunction::wrap(|a: f32| println!("{a}"))),
+            ("print_f64", IOFunction::wrap(|a: f64| println!("{a}"))),
+            (
+                "print_i32_f32",
+                IOFunction::wrap(|a: i32, b: f32| println!("{a} {b}")),
+            ),
+            (
+                "print_f64_f64",
+                IOFunction::wrap(|a: f64, b: f64| println!("{a} {b}")),
+            ),
+            (
+                "print_i64_f64",
+                IOFunction::wrap(|a: i64, b: f64| println!("{a} {b}")),
+            ),
         ];
         let mut res = HashMap::new();
         for (k, v) in map {
@@ -192,6 +217,7 @@ impl Import {
             memory: Memory::new(1, 2).into(),
             tables,
             memory_name: "memory".to_string(),
+            state: None,
         }
     }
 }
@@ -0,0 +1,274 @@
+//! Host functions as plain Rust functions: `fn(i32, f64) -> (i64,)` and
+//! closures alike, with their wasm signature derived from the Rust one.
+
+use super::{super::InternalErrorKind::*, Caller, IOFunction, Locals, RuntimeError, Stack, Value};
+use crate::parser::{FuncType, NumType, ResultType, ValType};
+
+/// A Rust type that maps onto a single wasm value.
+pub trait WasmTy: Sized + 'static {
+    const NAME: &'static str;
+    fn valtype() -> ValType;
+    fn from_value(value: Value) -> Option<Self>;
+    fn into_value(self) -> Value;
+}
+
+macro_rules! wasm_ty {
+    ($t:ty, $name:literal, $num:ident, $variant:ident, $raw:ty) => {
+        impl WasmTy for $t {
+            const NAME: &'static str = $name;
+            fn valtype() -> ValType {
+                ValType::Num(NumType::$num)
+            }
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::$variant(v) => Some(v as $t),
+                    _ => None,
+                }
+            }
+            fn into_value(self) -> Value {
+                Value::$variant(self as $raw)
+            }
+        }
+    };
+}
+
+wasm_ty!(i32, "i32", I32, I32, i32);
+wasm_ty!(u32, "u32", I32, I32, i32);
+wasm_ty!(i64, "i64", I64, I64, i64);
+wasm_ty!(u64, "u64", I64, I64, i64);
+wasm_ty!(f32, "f32", F32, F32, f32);
+wasm_ty!(f64, "f64", F64, F64, f64);
+
+/// What a host function may return: nothing, one value, a tuple of values,
+/// or any of those wrapped in a `Result` to trap.
+pub trait WasmResults {
+    fn types() -> Vec<ValType>;
+    fn into_stack(self) -> Result<Stack, RuntimeError>;
+}
+
+impl<T: WasmTy> WasmResults for T {
+    fn types() -> Vec<ValType> {
+        vec![T::valtype()]
+    }
+    fn into_stack(self) -> Result<Stack, RuntimeError> {
+        Ok(vec![self.into_value()])
+    }
+}
+
+impl<R: WasmResults> WasmResults for Result<R, RuntimeError> {
+    fn types() -> Vec<ValType> {
+        R::types()
+    }
+    fn into_stack(self) -> Result<Stack, RuntimeError> {
+        self?.into_stack()
+    }
+}
+
+macro_rules! wasm_results {
+    ($($t:ident),*) => {
+        impl<$($t: WasmTy),*> WasmResults for ($($t,)*) {
+            fn types() -> Vec<ValType> {
+                vec![$($t::valtype()),*]
+            }
+            #[allow(non_snake_case)]
+            fn into_stack(self) -> Result<Stack, RuntimeError> {
+                let ($($t,)*) = self;
+                Ok(vec![$($t.into_value()),*])
+            }
+        }
+    };
+}
+
+wasm_results!();
+wasm_results!(A);
+wasm_results!(A, B);
+wasm_results!(A, B, C);
+wasm_results!(A, B, C, D);
+
+/// Argument `index` of a host call, as a `T`.
+fn arg<T: WasmTy>(locals: Locals, index: u32) -> Result<T, RuntimeError> {
+    let value = *unwrap!(locals.get(&index), MissingLocal);
+    match T::from_value(value) {
+        Some(v) => Ok(v),
+        None => Err(WrongType(T::NAME, value.as_str()).at(file!(), line!(), column!())),
+    }
+}
+
+/// Marks [`IntoHostFunc`] impls for functions whose first argument is the
+/// [`Caller`].
+pub struct WithCaller;
+
+/// Something [`IOFunction::wrap`] can turn into a host function. `Params` only
+/// tells the impls for the different arities apart.
+pub trait IntoHostFunc<Params, Results> {
+    fn into_host_func(self) -> IOFunction;
+}
+
+macro_rules! into_host_func {
+    ($($t:ident),*) => {
+        impl<F, R, $($t: WasmTy),*> IntoHostFunc<($($t,)*), R> for F
+        where
+            F: FnMut($($t),*) -> R + 'static,
+            R: WasmResults,
+        {
+            #[allow(non_snake_case, unused_assignments, unused_mut, unused_variables)]
+            fn into_host_func(mut self) -> IOFunction {
+                let ty = signature(vec![$($t::valtype()),*], R::types());
+                let mut f = IOFunction::new(move |_, locals| {
+                    let mut i = 0;
+                    $(let $t = arg::<$t>(locals, i)?; i += 1;)*
+                    self($($t),*).into_stack()
+                });
+                f.ty = Some(ty);
+                f
+            }
+        }
+
+        impl<F, R, $($t: WasmTy),*> IntoHostFunc<(WithCaller, $($t,)*), R> for F
+        where
+            F: FnMut(&mut Caller, $($t),*) -> R + 'static,
+            R: WasmResults,
+        {
+            #[allow(non_snake_case, unused_assignments, unused_mut, unused_variables)]
+            fn into_host_func(mut self) -> IOFunction {
+                let ty = signature(vec![$($t::valtype()),*], R::types());
+                let mut f = IOFunction::new(move |caller, locals| {
+                    let mut i = 0;
+                    $(let $t = arg::<$t>(locals, i)?; i += 1;)*
+                    self(caller, $($t),*).into_stack()
+                });
+                f.ty = Some(ty);
+                f
+            }
+        }
+    };
+}
+
+into_host_func!();
+into_host_func!(A);
+into_host_func!(A, B);
+into_host_func!(A, B, C);
+into_host_func!(A, B, C, D);
+into_host_func!(A, B, C, D, E);
+into_host_func!(A, B, C, D, E, G);
+
+fn signature(input: Vec<ValType>, output: Vec<ValType>) -> FuncType {
+    FuncType {
+        input: ResultType { types: input },
+        output: ResultType { types: output },
+    }
+}
+
+impl IOFunction {
+    /// A host function from a Rust function or closure, optionally taking the
+    /// [`Caller`] first. Its signature is derived from the Rust one and checked
+    /// against the import when a module is instantiated:
+    ///
+    /// ```ignore
+    /// IOFunction::wrap(|a: i32, b: i32| a.wrapping_add(b));
+    /// IOFunction::wrap(|caller: &mut Caller, ptr: u32| -> Result<(), RuntimeError> { .. });
+    /// ```
+    pub fn wrap<Params, Results>(f: impl IntoHostFunc<Params, Results>) -> Self {
+        f.into_host_func()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{memory::Memory, LinkError, Runtime, TrapCode, IO, MAIN_MODULE},
+        wast::text_module,
+    };
+    use std::collections::HashMap;
+
+    fn host(functions: Vec<(&'static str, IOFunction)>) -> IO {
+        IO {
+            functions: functions.into_iter().collect(),
+            globals: HashMap::new(),
+            tables: HashMap::new(),
+            memory: Memory::new(0, 0).into(),
+            memory_name: "memory".to_string(),
+            state: None,
+        }
+    }
+
+    /// A runtime whose main module imports `import` from `host` under
+    /// `"host" "f"` and exports it as `f`.
+    fn runtime(f: IOFunction, import: &str) -> Result<Runtime, RuntimeError> {
+        let module = text_module(&format!(
+            r#"(module (import "host" "f" (func {import})) (export "f" (func 0)))"#
+        ));
+        Runtime::builder()
+            .add_io("host", host(vec![("f", f)]))
+            .add_module(MAIN_MODULE, &module)
+            .build()
+    }
+
+    #[test]
+    fn typed_imports_are_called_with_their_arguments() {
+        let add = IOFunction::wrap(|a: i32, b: i64| (a, b + 1));
+        let mut rt = runtime(add, "(param i32 i64) (result i32 i64)").expect("the types agree");
+        let results = rt
+            .invoke("f", &[Value::I32(-4), Value::I64(9)])
+            .expect("f returns");
+        assert!(results == [Value::I32(-4), Value::I64(10)]);
+    }
+
+    #[test]
+    fn mismatched_imports_are_refused() {
+        for import in [
+            "(param i64) (result i32)",
+            "(param i32) (result i64)",
+            "(param i32 i32) (result i32)",
+            "(param i32)",
+        ] {
+            let f = IOFunction::wrap(|a: i32| a);
+            assert!(
+                matches!(
+                    runtime(f, import),
+                    Err(RuntimeError::Link(LinkError::IncompatibleImportType { .. }))
+                ),
+                "{import}"
+            );
+        }
+    }
+
+    #[test]
+    fn host_errors_trap() {
+        let f = IOFunction::wrap(|a: i32| -> Result<i32, RuntimeError> {
+            match a {
+                0 => Err(TrapCode::Unreachable.into()),
+                a => Ok(a),
+            }
+        });
+        let mut rt = runtime(f, "(param i32) (result i32)").expect("the types agree");
+        assert!(matches!(
+            rt.invoke("f", &[Value::I32(0)]),
+            Err(RuntimeError::Trap(TrapCode::Unreachable))
+        ));
+    }
+
+    #[test]
+    fn unsigned_values_keep_their_bits() {
+        let f = IOFunction::wrap(|a: u32, b: u64| {
+            assert_eq!((a, b), (u32::MAX - 1, u64::MAX - 1));
+            (a + 1, b + 1)
+        });
+        let mut rt = runtime(f, "(param i32 i64) (result i32 i64)").expect("the types agree");
+        let results = rt
+            .invoke("f", &[Value::I32(-2), Value::I64(-2)])
+            .expect("f returns");
+        assert!(results == [Value::I32(-1), Value::I64(-1)]);
+        // and just past i32::MAX and i64::MAX, which only fit unsigned
+        let f = IOFunction::wrap(|a: u32, b: u64| {
+            assert_eq!((a, b), (1 << 31, 1 << 63));
+            (a, b)
+        });
+        let mut rt = runtime(f, "(param i32 i64) (result i32 i64)").expect("the types agree");
+        let results = rt
+            .invoke("f", &[Value::I32(i32::MIN), Value::I64(i64::MIN)])
+            .expect("f returns");
+        assert!(results == [Value::I32(i32::MIN), Value::I64(i64::MIN)]);
+    }
+}
@@ -0,0 +1,718 @@
+//! A subset of `wasi_snapshot_preview1`: arguments, environment, stdio,
+//! files below preopened directories, clocks, randomness and `proc_exit`.
+//!
+//! With [`WasiConfig::deterministic`] the clocks and randomness are made up:
+//! every clock starts at zero and moves a microsecond each time it is read,
+//! and the random bytes come from a fixed seed.
+
+use super::{Caller, HostState, IOFunction, Import, Locals, Mem, Stack, IO};
+use crate::{
+    parser::MemArg,
+    runtime::{
+        memory::Memory, ExportedMemory, InternalErrorKind::*, RuntimeError, TrapCode, Value,
+    },
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    sync::OnceLock,
+    time::{Instant, SystemTime},
+};
+
+pub const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+mod errno {
+    pub const SUCCESS: i32 = 0;
+    pub const ACCES: i32 = 2;
+    pub const BADF: i32 = 8;
+    pub const EXIST: i32 = 20;
+    pub const INVAL: i32 = 28;
+    pub const IO: i32 = 29;
+    pub const ISDIR: i32 = 31;
+    pub const NOENT: i32 = 44;
+    pub const NOTDIR: i32 = 54;
+    pub const SPIPE: i32 = 70;
+    pub const NOTCAPABLE: i32 = 76;
+}
+use errno::*;
+
+const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+const FILETYPE_DIRECTORY: u8 = 3;
+const FILETYPE_REGULAR_FILE: u8 = 4;
+
+const OFLAGS_CREAT: i32 = 1;
+const OFLAGS_DIRECTORY: i32 = 2;
+const OFLAGS_EXCL: i32 = 4;
+const OFLAGS_TRUNC: i32 = 8;
+const FDFLAGS_APPEND: i32 = 1;
+const RIGHTS_FD_WRITE: i64 = 1 << 6;
+
+/// How far the virtual clocks move on every read, in nanoseconds.
+const VIRTUAL_TICK: u64 = 1_000;
+/// Where the virtual randomness starts.
+const VIRTUAL_SEED: u64 = 0x5eed;
+
+/// What the guest gets to see of the host.
+#[derive(Debug, Default, Clone)]
+pub struct WasiConfig {
+    /// `argv`, including the program name.
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// Directories the guest may open files under, as `(guest, host)` paths.
+    pub dirs: Vec<(String, PathBuf)>,
+    /// Whether the clocks and randomness are virtual, the same on every run.
+    pub deterministic: bool,
+}
+
+enum Fd {
+    Stdin,
+    Stdout,
+    Stderr,
+    Dir { guest: String, host: PathBuf },
+    File(File),
+}
+
+/// Shared by all the functions of one [`Import::wasi`].
+struct State {
+    args: Vec<String>,
+    env: Vec<String>,
+    fds: Vec<Option<Fd>>,
+    /// The time of the virtual clocks.
+    clock: u64,
+    /// The state of the xorshift generator behind the virtual randomness.
+    random: u64,
+}
+
+/// The virtual clocks and randomness, which a snapshot of a deterministic
+/// run has to keep to go on the same.
+struct Virtual(Rc<RefCell<State>>);
+
+impl HostState for Virtual {
+    fn save(&self) -> Vec<u8> {
+        let state = self.0.borrow();
+        [state.clock, state.random]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect()
+    }
+
+    fn restore(&self, saved: &[u8]) -> Result<(), String> {
+        let [clock, random] = [0, 8].map(|at| {
+            saved
+                .get(at..at + 8)
+                .and_then(|b| b.try_into().ok())
+                .map(u64::from_le_bytes)
+        });
+        let (Some(clock), Some(random), 16) = (clock, random, saved.len()) else {
+            return Err(format!("{} bytes of WASI state, expected 16", saved.len()));
+        };
+        let mut state = self.0.borrow_mut();
+        state.clock = clock;
+        state.random = random;
+        Ok(())
+    }
+}
+
+fn errno(code: i32) -> Result<Stack, RuntimeError> {
+    Ok(vec![Value::I32(code)])
+}
+
+fn io_errno(e: std::io::Error) -> i32 {
+    match e.kind() {
+        ErrorKind::NotFound => NOENT,
+        ErrorKind::PermissionDenied => ACCES,
+        ErrorKind::AlreadyExists => EXIST,
+        _ => IO,
+    }
+}
+
+/// A guest pointer or length argument.
+fn ptr(locals: Locals, index: u32) -> Result<usize, RuntimeError> {
+    Ok(*get!(i32, &index, locals) as u32 as usize)
+}
+
+fn store<T>(mem: Mem, address: usize, val: T) -> Result<(), RuntimeError> {
+    mem.set(address, MemArg::default(), val)
+}
+
+/// Writes the element count and the total size of `list` as C strings.
+fn list_sizes(list: &[String], locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let size = list.iter().map(|s| s.len() + 1).sum::<usize>();
+    store(mem, ptr(locals, 0)?, list.len() as u32)?;
+    store(mem, ptr(locals, 1)?, size as u32)?;
+    errno(SUCCESS)
+}
+
+/// Copies `list` into the guest as an array of pointers into a buffer of
+/// nul terminated strings.
+fn list_get(list: &[String], locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let pointers = ptr(locals, 0)?;
+    let mut at = ptr(locals, 1)?;
+    for (i, s) in list.iter().enumerate() {
+        store(mem, pointers + i * 4, at as u32)?;
+        mem.slice_write(at, s.as_bytes())?;
+        store(mem, at + s.len(), 0u8)?;
+        at += s.len() + 1;
+    }
+    errno(SUCCESS)
+}
+
+/// The `(buf, buf_len)` pairs of an iovec array.
+fn iovecs(
+    mem: &Memory<65536>,
+    iovs: usize,
+    len: usize,
+) -> Result<Vec<(usize, usize)>, RuntimeError> {
+    (0..len)
+        .map(|i| {
+            let buf = mem.get::<u32>(iovs + i * 8, MemArg::default())? as usize;
+            let buf_len = mem.get::<u32>(iovs + i * 8 + 4, MemArg::default())? as usize;
+            Ok((buf, buf_len))
+        })
+        .collect()
+}
+
+fn fd_write(state: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let fd = ptr(locals, 0)?;
+    let mut total = 0;
+    for (buf, len) in iovecs(mem, ptr(locals, 1)?, ptr(locals, 2)?)? {
+        // one buffer at a time, each no larger than the memory it is in
+        if !mem.contains(buf, len) {
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
+        }
+        let data = mem.slice_read(buf, len)?;
+        let res = match state.fds.get_mut(fd) {
+            Some(Some(Fd::Stdout)) => {
+                let mut out = std::io::stdout().lock();
+                out.write_all(&data).and_then(|_| out.flush())
+            }
+            Some(Some(Fd::Stderr)) => std::io::stderr().write_all(&data),
+            Some(Some(Fd::File(file))) => file.write_all(&data),
+            Some(Some(Fd::Dir { .. })) => return errno(ISDIR),
+            _ => return errno(BADF),
+        };
+        if let Err(e) = res {
+            return errno(io_errno(e));
+        }
+        total += len;
+    }
+    store(mem, ptr(locals, 3)?, total as u32)?;
+    errno(SUCCESS)
+}
+
+fn fd_read(state: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let fd = ptr(locals, 0)?;
+    let mut total = 0;
+    for (buf, len) in iovecs(mem, ptr(locals, 1)?, ptr(locals, 2)?)? {
+        // the guest picks the length, don't allocate more than it has
+        if !mem.contains(buf, len) {
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
+        }
+        let mut data = vec![0; len];
+        let res = match state.fds.get_mut(fd) {
+            Some(Some(Fd::Stdin)) => std::io::stdin().read(&mut data),
+            Some(Some(Fd::File(file))) => file.read(&mut data),
+            Some(Some(Fd::Dir { .. })) => return errno(ISDIR),
+            _ => return errno(BADF),
+        };
+        let read = match res {
+            Ok(read) => read,
+            Err(e) => return errno(io_errno(e)),
+        };
+        mem.slice_write(buf, &data[..read])?;
+        total += read;
+        if read < len {
+            break;
+        }
+    }
+    store(mem, ptr(locals, 3)?, total as u32)?;
+    errno(SUCCESS)
+}
+
+fn fd_seek(state: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let fd = ptr(locals, 0)?;
+    let offset = *get!(i64, &1, locals);
+    let from = match *get!(i32, &2, locals) {
+        0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return errno(INVAL),
+    };
+    let res = match state.fds.get_mut(fd) {
+        Some(Some(Fd::File(file))) => file.seek(from),
+        Some(Some(Fd::Dir { .. })) => return errno(ISDIR),
+        Some(Some(_)) => return errno(SPIPE),
+        _ => return errno(BADF),
+    };
+    match res {
+        Ok(position) => {
+            store(mem, ptr(locals, 3)?, position)?;
+            errno(SUCCESS)
+        }
+        Err(e) => errno(io_errno(e)),
+    }
+}
+
+fn fd_fdstat_get(state: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let filetype = match state.fds.get(ptr(locals, 0)?) {
+        Some(Some(Fd::Stdin | Fd::Stdout | Fd::Stderr)) => FILETYPE_CHARACTER_DEVICE,
+        Some(Some(Fd::Dir { .. })) => FILETYPE_DIRECTORY,
+        Some(Some(Fd::File(_))) => FILETYPE_REGULAR_FILE,
+        _ => return errno(BADF),
+    };
+    let at = ptr(locals, 1)?;
+    store(mem, at, filetype)?;
+    store(mem, at + 2, 0u16)?;
+    store(mem, at + 8, u64::MAX)?;
+    store(mem, at + 16, u64::MAX)?;
+    errno(SUCCESS)
+}
+
+fn fd_prestat_get(state: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let len = match state.fds.get(ptr(locals, 0)?) {
+        Some(Some(Fd::Dir { guest, .. })) => guest.len(),
+        _ => return errno(BADF),
+    };
+    let at = ptr(locals, 1)?;
+    store(mem, at, 0u8)?;
+    store(mem, at + 4, len as u32)?;
+    errno(SUCCESS)
+}
+
+fn fd_prestat_dir_name(state: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let name = match state.fds.get(ptr(locals, 0)?) {
+        Some(Some(Fd::Dir { guest, .. })) => guest.clone(),
+        _ => return errno(BADF),
+    };
+    let len = ptr(locals, 2)?.min(name.len());
+    mem.slice_write(ptr(locals, 1)?, &name.as_bytes()[..len])?;
+    errno(SUCCESS)
+}
+
+/// Resolves `path` below `dir`, refusing anything that could step outside.
+fn sandboxed(dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then(|| dir.join(path))
+}
+
+fn path_open(state: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let path = mem.slice_read(ptr(locals, 2)?, ptr(locals, 3)?)?;
+    let Ok(path) = String::from_utf8(path) else {
+        return errno(INVAL);
+    };
+    let oflags = *get!(i32, &4, locals);
+    let rights = *get!(i64, &5, locals);
+    let fdflags = *get!(i32, &7, locals);
+
+    let host = match state.fds.get(ptr(locals, 0)?) {
+        Some(Some(Fd::Dir { host, .. })) => host.clone(),
+        Some(Some(_)) => return errno(NOTDIR),
+        _ => return errno(BADF),
+    };
+    let Some(host) = sandboxed(&host, &path) else {
+        return errno(NOTCAPABLE);
+    };
+
+    let fd = if host.is_dir() {
+        Fd::Dir { guest: path, host }
+    } else if oflags & OFLAGS_DIRECTORY != 0 {
+        return errno(if host.exists() { NOTDIR } else { NOENT });
+    } else {
+        let create = oflags & OFLAGS_CREAT != 0;
+        let truncate = oflags & OFLAGS_TRUNC != 0;
+        let append = fdflags & FDFLAGS_APPEND != 0;
+        let write = rights & RIGHTS_FD_WRITE != 0 || create || truncate || append;
+        let opened = OpenOptions::new()
+            .read(true)
+            .write(write && !append)
+            .append(append)
+            .create(create)
+            .create_new(create && oflags & OFLAGS_EXCL != 0)
+            .truncate(truncate)
+            .open(&host);
+        match opened {
+            Ok(file) => Fd::File(file),
+            Err(e) => return errno(io_errno(e)),
+        }
+    };
+
+    let index = match state.fds.iter().position(Option::is_none) {
+        Some(index) => {
+            state.fds[index] = Some(fd);
+            index
+        }
+        None => {
+            state.fds.push(Some(fd));
+            state.fds.len() - 1
+        }
+    };
+    store(mem, ptr(locals, 8)?, index as u32)?;
+    errno(SUCCESS)
+}
+
+fn clock_time_get(_: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let nanos = match *get!(i32, &0, locals) {
+        // realtime
+        0 => SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default(),
+        // monotonic and the cpu time clocks
+        1..=3 => START.get_or_init(Instant::now).elapsed().as_nanos(),
+        _ => return errno(INVAL),
+    };
+    store(mem, ptr(locals, 2)?, nanos as u64)?;
+    errno(SUCCESS)
+}
+
+fn virtual_clock_time_get(
+    state: &mut State,
+    locals: Locals,
+    mem: Mem,
+) -> Result<Stack, RuntimeError> {
+    if !(0..=3).contains(get!(i32, &0, locals)) {
+        return errno(INVAL);
+    }
+    store(mem, ptr(locals, 2)?, state.clock)?;
+    state.clock += VIRTUAL_TICK;
+    errno(SUCCESS)
+}
+
+fn random_get(caller: &mut Caller, buf: u32, len: u32) -> Result<i32, RuntimeError> {
+    if !caller.memory()?.read().contains(buf as usize, len as usize) {
+        return Err(TrapCode::OutOfBoundsMemoryAccess.into());
+    }
+    // every RandomState is seeded from the OS, which is all the entropy we
+    // need without pulling in a dependency
+    let bytes = (0..(len as usize).div_ceil(8))
+        .flat_map(|_| {
+            std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish()
+                .to_le_bytes()
+        })
+        .take(len as usize)
+        .collect::<Vec<_>>();
+    caller.memory()?.write().slice_write(buf as usize, &bytes)?;
+    Ok(SUCCESS)
+}
+
+fn virtual_random_get(state: &mut State, locals: Locals, mem: Mem) -> Result<Stack, RuntimeError> {
+    let (buf, len) = (ptr(locals, 0)?, ptr(locals, 1)?);
+    if !mem.contains(buf, len) {
+        return Err(TrapCode::OutOfBoundsMemoryAccess.into());
+    }
+    let bytes = (0..len.div_ceil(8))
+        .flat_map(|_| {
+            state.random ^= state.random << 13;
+            state.random ^= state.random >> 7;
+            state.random ^= state.random << 17;
+            state.random.to_le_bytes()
+        })
+        .take(len)
+        .collect::<Vec<_>>();
+    mem.slice_write(buf, &bytes)?;
+    errno(SUCCESS)
+}
+
+fn fd_close(state: &mut State, locals: Locals, _: Mem) -> Result<Stack, RuntimeError> {
+    match state.fds.get_mut(ptr(locals, 0)?) {
+        Some(fd @ Some(_)) => {
+            *fd = None;
+            errno(SUCCESS)
+        }
+        _ => errno(BADF),
+    }
+}
+
+/// The name of an errno `code`, for those this implementation returns.
+pub fn errno_name(code: i32) -> Option<&'static str> {
+    Some(match code {
+        SUCCESS => "SUCCESS",
+        ACCES => "ACCES",
+        BADF => "BADF",
+        EXIST => "EXIST",
+        INVAL => "INVAL",
+        IO => "IO",
+        ISDIR => "ISDIR",
+        NOENT => "NOENT",
+        NOTDIR => "NOTDIR",
+        SPIPE => "SPIPE",
+        NOTCAPABLE => "NOTCAPABLE",
+        _ => return None,
+    })
+}
+
+/// How much of a buffer [`describe_call`] shows.
+const SHOWN_BYTES: usize = 64;
+
+/// The arguments of a call of the WASI function `name` made with `args`,
+/// decoded the way strace would: strings and buffers are read from
+/// `memory`, and so are the values the call wrote back, so this is meant
+/// for after the call. `None` for functions it does not know.
+pub fn describe_call(name: &str, args: &[Value], memory: &ExportedMemory) -> Option<String> {
+    let arg = |i: usize| match args.get(i) {
+        Some(Value::I32(v)) => *v as u32 as u64,
+        Some(Value::I64(v)) => *v as u64,
+        _ => 0,
+    };
+    let bytes = |address: u64, len: u64| {
+        let mut buf = vec![0; len as usize];
+        memory.read(address as usize, &mut buf).ok().map(|()| buf)
+    };
+    let u32_at = |address: u64| {
+        let buf = bytes(address, 4)?;
+        Some(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as u64)
+    };
+    let u64_at = |address: u64| {
+        let buf = bytes(address, 8)?;
+        Some(u64::from_le_bytes(buf.try_into().ok()?))
+    };
+    let out = |value: Option<u64>| value.map_or("?".to_string(), |v| v.to_string());
+    let text = |buf: &[u8]| {
+        let shown = String::from_utf8_lossy(&buf[..buf.len().min(SHOWN_BYTES)]).into_owned();
+        let more = if buf.len() > SHOWN_BYTES { "..." } else { "" };
+        format!("{shown:?}{more}")
+    };
+    let string = |address: u64, len: u64| {
+        bytes(address, len.min(4096)).map_or("?".to_string(), |b| text(&b))
+    };
+    // the buffers of an iovec array, and the first `used` bytes in them
+    let iovecs = |iovs: u64, len: u64, used: Option<u64>| {
+        let bufs: Vec<_> = (0..len.min(1024))
+            .map_while(|i| Some((u32_at(iovs + i * 8)?, u32_at(iovs + i * 8 + 4)?)))
+            .collect();
+        let total: u64 = bufs.iter().map(|(_, len)| len).sum();
+        let mut data = Vec::new();
+        let mut left = used.unwrap_or(total).min(SHOWN_BYTES as u64 + 1);
+        for (buf, len) in bufs {
+            let take = len.min(left);
+            data.extend(bytes(buf, take).unwrap_or_default());
+            left -= take;
+        }
+        let s = if len == 1 { "" } else { "s" };
+        format!("[{len} iovec{s}, {total} bytes: {}]", text(&data))
+    };
+    let clock = |id: u64| match id {
+        0 => "REALTIME".to_string(),
+        1 => "MONOTONIC".to_string(),
+        2 => "PROCESS_CPUTIME".to_string(),
+        3 => "THREAD_CPUTIME".to_string(),
+        id => id.to_string(),
+    };
+
+    Some(match name {
+        "args_sizes_get" | "environ_sizes_get" => format!(
+            "count={}, size={}",
+            out(u32_at(arg(0))),
+            out(u32_at(arg(1)))
+        ),
+        "args_get" | "environ_get" => format!("{:#x}, {:#x}", arg(0), arg(1)),
+        "fd_write" => {
+            let written = u32_at(arg(3));
+            format!(
+                "fd={}, iovs={}, nwritten={}",
+                arg(0),
+                iovecs(arg(1), arg(2), written),
+                out(written)
+            )
+        }
+        "fd_read" => {
+            let read = u32_at(arg(3));
+            format!(
+                "fd={}, iovs={}, nread={}",
+                arg(0),
+                iovecs(arg(1), arg(2), Some(read.unwrap_or(0))),
+                out(read)
+            )
+        }
+        "fd_seek" => {
+            let whence = match arg(2) {
+                0 => "SET".to_string(),
+                1 => "CUR".to_string(),
+                2 => "END".to_string(),
+                w => w.to_string(),
+            };
+            format!(
+                "fd={}, offset={}, whence={whence}, newoffset={}",
+                arg(0),
+                arg(1) as i64,
+                out(u64_at(arg(3)))
+            )
+        }
+        "fd_close" => format!("fd={}", arg(0)),
+        "fd_fdstat_get" | "fd_prestat_get" => format!("fd={}, {:#x}", arg(0), arg(1)),
+        "fd_prestat_dir_name" => format!("fd={}, path={}", arg(0), string(arg(1), arg(2))),
+        "path_open" => format!(
+            "dirfd={}, dirflags={:#x}, path={}, oflags={:#x}, rights={:#x}, \
+             inheriting={:#x}, fdflags={:#x}, fd={}",
+            arg(0),
+            arg(1),
+            string(arg(2), arg(3)),
+            arg(4),
+            arg(5),
+            arg(6),
+            arg(7),
+            out(u32_at(arg(8)))
+        ),
+        "clock_time_get" => format!(
+            "id={}, precision={}, time={}",
+            clock(arg(0)),
+            arg(1),
+            out(u64_at(arg(2)))
+        ),
+        "random_get" => format!("{:#x}, len={}", arg(0), arg(1)),
+        "sched_yield" => String::new(),
+        "proc_exit" => format!("code={}", arg(0) as i32),
+        _ => return None,
+    })
+}
+
+impl Import {
+    /// The `wasi_snapshot_preview1` functions, see the module docs for what is
+    /// covered.
+    pub fn wasi(config: WasiConfig) -> IO {
+        let mut fds = vec![Some(Fd::Stdin), Some(Fd::Stdout), Some(Fd::Stderr)];
+        for (guest, host) in config.dirs {
+            fds.push(Some(Fd::Dir { guest, host }));
+        }
+        let state = Rc::new(RefCell::new(State {
+            args: config.args,
+            env: config
+                .env
+                .into_iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect(),
+            fds,
+            clock: 0,
+            random: VIRTUAL_SEED,
+        }));
+
+        type WasiFn = fn(&mut State, Locals, Mem) -> Result<Stack, RuntimeError>;
+        let with_state = |f: WasiFn| {
+            let state = state.clone();
+            IOFunction::new(move |caller: &mut Caller, locals| {
+                f(
+                    &mut state.borrow_mut(),
+                    locals,
+                    &mut caller.memory()?.write(),
+                )
+            })
+        };
+
+        let map: Vec<(&'static str, IOFunction)> = vec![
+            (
+                "args_sizes_get",
+                with_state(|s, l, m| list_sizes(&s.args, l, m)),
+            ),
+            ("args_get", with_state(|s, l, m| list_get(&s.args, l, m))),
+            (
+                "environ_sizes_get",
+                with_state(|s, l, m| list_sizes(&s.env, l, m)),
+            ),
+            ("environ_get", with_state(|s, l, m| list_get(&s.env, l, m))),
+            ("fd_write", with_state(fd_write)),
+            ("fd_read", with_state(fd_read)),
+            ("fd_seek", with_state(fd_seek)),
+            ("fd_close", with_state(fd_close)),
+            ("fd_fdstat_get", with_state(fd_fdstat_get)),
+            ("fd_prestat_get", with_state(fd_prestat_get)),
+            ("fd_prestat_dir_name", with_state(fd_prestat_dir_name)),
+            ("path_open", with_state(path_open)),
+            (
+                "clock_time_get",
+                if config.deterministic {
+                    with_state(virtual_clock_time_get)
+                } else {
+                    with_state(clock_time_get)
+                },
+            ),
+            (
+                "random_get",
+                if config.deterministic {
+                    with_state(virtual_random_get)
+                } else {
+                    IOFunction::wrap(random_get)
+                },
+            ),
+            ("sched_yield", IOFunction::wrap(|| SUCCESS)),
+            (
+                "proc_exit",
+                IOFunction::wrap(|code: i32| -> Result<(), _> { Err(RuntimeError::Exit(code)) }),
+            ),
+        ];
+
+        IO {
+            functions: map.into_iter().collect::<HashMap<_, _>>(),
+            globals: HashMap::new(),
+            tables: HashMap::new(),
+            memory: Memory::new(0, 0).into(),
+            memory_name: String::new(),
+            state: config
+                .deterministic
+                .then(|| Box::new(Virtual(state)) as Box<dyn HostState>),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{Runtime, MAIN_MODULE},
+        wast::text_module,
+    };
+
+    /// Reads two clocks and 12 random bytes into memory.
+    const CLOCKS: &str = r#"(module
+        (import "wasi_snapshot_preview1" "clock_time_get"
+            (func $clock (param i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "random_get"
+            (func $random (param i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (func (export "run")
+            (drop (call $clock (i32.const 0) (i64.const 1) (i32.const 0)))
+            (drop (call $clock (i32.const 1) (i64.const 1) (i32.const 8)))
+            (drop (call $random (i32.const 16) (i32.const 12)))))"#;
+
+    /// What a fresh runtime's `run` leaves in memory.
+    fn run(deterministic: bool) -> [u8; 28] {
+        let wasi = Import::wasi(WasiConfig {
+            deterministic,
+            ..WasiConfig::default()
+        });
+        let mut rt = Runtime::builder()
+            .add_io(WASI_MODULE, wasi)
+            .add_module(MAIN_MODULE, &text_module(CLOCKS))
+            .build()
+            .expect("the runtime builds");
+        rt.invoke("run", &[]).expect("run returns");
+        let instance = rt.instance(MAIN_MODULE).expect("main is loaded");
+        let mut out = [0; 28];
+        instance
+            .memory("memory")
+            .and_then(|m| m.read(0, &mut out))
+            .expect("memory is exported");
+        out
+    }
+
+    #[test]
+    fn virtual_clocks_and_randomness_repeat() {
+        let first = run(true);
+        assert_eq!(first, run(true));
+        let clock = |at: usize| u64::from_le_bytes(first[at..at + 8].try_into().expect("8 bytes"));
+        // each read moves the virtual time on
+        assert!(clock(8) > clock(0));
+        assert_ne!(first[16..], [0; 12]);
+        // while the real randomness doesn't repeat
+        assert_ne!(run(false)[16..], run(false)[16..]);
+    }
+}
@@ -0,0 +1,480 @@
+//! Safe host access to what an instance exports: its memory, globals and
+//! tables, looked up by export name.
+
+use super::{
+    clean_model::{Function, Model, Table},
+    memory::Memory,
+    Import,
+    InternalErrorKind::NoModule,
+    LinkError, ResourceLimiter, Runtime, RuntimeError, TrapCode, Value,
+};
+use crate::{
+    dwarf::Location,
+    parser::{ExportDesc, FuncIdx, GlobalIdX, Mutable, RefTyp, TableIdX},
+    ptr::PtrRW,
+};
+
+/// One instance of a [`Runtime`], see [`Runtime::instance`].
+pub struct Instance<'r> {
+    model: &'r Model,
+    limits: &'r ResourceLimiter,
+}
+
+impl Runtime {
+    /// The instance loaded under `name`, [`MAIN_MODULE`](super::MAIN_MODULE)
+    /// for the one [`Runtime::build`] was given.
+    #[allow(unused)]
+    pub fn instance(&self, name: &str) -> Result<Instance<'_>, RuntimeError> {
+        match self.modules.get(name) {
+            Some(Import::WS(model)) => Ok(Instance {
+                model,
+                limits: &self.limits,
+            }),
+            _ => Err(NoModule(name.to_string()).at(file!(), line!(), column!())),
+        }
+    }
+
+    /// The names of the wasm instances, in no particular order.
+    pub fn instances(&self) -> impl Iterator<Item = &str> {
+        self.modules
+            .iter()
+            .filter(|(_, i)| matches!(i, Import::WS(_)))
+            .map(|(name, _)| &**name)
+    }
+}
+
+fn kind_mismatch(export: &str, expected: &'static str) -> RuntimeError {
+    LinkError::ExportKindMismatch {
+        export: export.to_string(),
+        expected,
+    }
+    .into()
+}
+
+impl<'r> Instance<'r> {
+    fn export(&self, name: &str) -> Result<&'r ExportDesc, RuntimeError> {
+        self.model
+            .exports
+            .get(name)
+            .ok_or_else(|| LinkError::UnknownExport(name.to_string()).into())
+    }
+
+    /// The memory exported as `name`.
+    #[allow(unused)]
+    pub fn memory(&self, name: &str) -> Result<ExportedMemory<'r>, RuntimeError> {
+        match self.export(name)? {
+            ExportDesc::Mem(_) => Ok(ExportedMemory {
+                memory: &self.model.memory,
+                max_pages: self.limits.max_memory_pages.min(1 << 16),
+            }),
+            _ => Err(kind_mismatch(name, "memory")),
+        }
+    }
+
+    /// The global exported as `name`.
+    #[allow(unused)]
+    pub fn global(&self, name: &str) -> Result<ExportedGlobal<'r>, RuntimeError> {
+        let ExportDesc::Global(GlobalIdX(id)) = self.export(name)? else {
+            return Err(kind_mismatch(name, "global"));
+        };
+        let global = self
+            .model
+            .globals
+            .get(*id as usize)
+            .ok_or_else(|| LinkError::UnknownExport(name.to_string()))?;
+        Ok(ExportedGlobal {
+            name: name.to_string(),
+            global,
+        })
+    }
+
+    /// The table exported as `name`.
+    #[allow(unused)]
+    pub fn table(&self, name: &str) -> Result<ExportedTable<'r>, RuntimeError> {
+        let ExportDesc::Table(TableIdX(id)) = self.export(name)? else {
+            return Err(kind_mismatch(name, "table"));
+        };
+        let table = self
+            .model
+            .tables
+            .get(*id as usize)
+            .ok_or_else(|| LinkError::UnknownExport(name.to_string()))?;
+        Ok(ExportedTable {
+            name: name.to_string(),
+            table,
+            max_elements: self.limits.max_table_elements,
+            functions: self.model.functions.len(),
+        })
+    }
+}
+
+/// Everything an instance has, exported or not, by index, for tools like
+/// the debugger that look inside.
+#[allow(unused)]
+impl<'r> Instance<'r> {
+    /// The instance's linear memory.
+    pub fn own_memory(&self) -> ExportedMemory<'r> {
+        ExportedMemory {
+            memory: &self.model.memory,
+            max_pages: self.limits.max_memory_pages.min(1 << 16),
+        }
+    }
+
+    /// Global `index`, imported or defined here.
+    pub fn global_at(&self, index: u32) -> Option<ExportedGlobal<'r>> {
+        let global = self.model.globals.get(index as usize)?;
+        Some(ExportedGlobal {
+            name: format!("global {index}"),
+            global,
+        })
+    }
+
+    pub fn globals(&self) -> usize {
+        self.model.globals.len()
+    }
+
+    /// What is left of data segment `index`, nothing once `data.drop`
+    /// dropped it.
+    pub fn data(&self, index: u32) -> Option<Vec<u8>> {
+        Some(self.model.datas.get(index as usize)?.read().clone())
+    }
+
+    /// Function `index`, imported or defined here.
+    pub fn function(&self, index: u32) -> Option<&'r Function> {
+        self.model.functions.get(index as usize).map(|f| f.as_ref())
+    }
+
+    pub fn functions(&self) -> usize {
+        self.model.functions.len()
+    }
+
+    /// Whether function `index` is imported from another wasm instance,
+    /// where it runs under that instance's own index.
+    pub fn imports_function(&self, index: u32) -> bool {
+        self.model.foreign.contains_key(&index)
+    }
+
+    /// The module and name function `index` is imported as, if it is.
+    pub fn function_import(&self, index: u32) -> Option<(&'r str, &'r str)> {
+        let (module, name) = self.model.function_imports.get(index as usize)?;
+        Some((module, name))
+    }
+
+    /// Whether the module came with DWARF line info.
+    pub fn has_lines(&self) -> bool {
+        self.model.lines.is_some()
+    }
+
+    /// The name of the function `index`: the one the name section gives it,
+    /// or else the one it is exported as.
+    pub fn function_name(&self, index: u32) -> Option<&'r str> {
+        if let Some(name) = self.model.names.get(&index) {
+            return Some(name);
+        }
+        self.model
+            .exports
+            .iter()
+            .find(|(_, e)| matches!(e, ExportDesc::Func(FuncIdx(i)) if *i == index))
+            .map(|(name, _)| &**name)
+    }
+
+    /// Where in the source the instruction `pc` of the function `func` came
+    /// from.
+    pub fn location(&self, func: u32, pc: usize) -> Option<Location<'r>> {
+        let Function::WS { offsets, .. } = self.function(func)? else {
+            return None;
+        };
+        let lines = self.model.lines.as_ref()?;
+        lines.lookup((*offsets.get(pc)?).into())
+    }
+
+    /// The index of the function exported as `name`, or given it by the
+    /// name section.
+    pub fn function_index(&self, name: &str) -> Option<u32> {
+        match self.model.exports.get(name) {
+            Some(ExportDesc::Func(FuncIdx(i))) => Some(*i),
+            _ => self
+                .model
+                .names
+                .iter()
+                .find(|(_, n)| *n == name)
+                .map(|(i, _)| *i),
+        }
+    }
+
+    /// The index of the global exported as `name`.
+    pub fn global_index(&self, name: &str) -> Option<u32> {
+        match self.model.exports.get(name)? {
+            ExportDesc::Global(GlobalIdX(i)) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
+/// An exported linear memory, addressed in bytes.
+pub struct ExportedMemory<'r> {
+    memory: &'r PtrRW<Memory<65536>>,
+    max_pages: usize,
+}
+
+#[allow(unused)]
+impl ExportedMemory<'_> {
+    /// Fills `buf` from guest memory starting at `offset`.
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), RuntimeError> {
+        buf.copy_from_slice(&self.memory.read().slice_read(offset, buf.len())?);
+        Ok(())
+    }
+
+    /// Copies `buf` into guest memory starting at `offset`.
+    pub fn write(&self, offset: usize, buf: &[u8]) -> Result<(), RuntimeError> {
+        self.memory.write().slice_write(offset, buf)
+    }
+
+    /// Grows the memory by `pages` pages like `memory.grow` does, returning
+    /// the previous size or `None` when it may not grow that far.
+    pub fn grow(&self, pages: usize) -> Option<usize> {
+        let mut memory = self.memory.write();
+        if memory.pages().0.checked_add(pages)? > self.max_pages {
+            return None;
+        }
+        usize::try_from(memory.grow(pages)).ok()
+    }
+
+    /// The current size in pages.
+    pub fn size(&self) -> usize {
+        self.memory.read().pages().0
+    }
+
+    /// Whether the `len` bytes from `offset` on are all in the memory.
+    pub fn contains(&self, offset: usize, len: usize) -> bool {
+        self.memory.read().contains(offset, len)
+    }
+}
+
+/// An exported global.
+pub struct ExportedGlobal<'r> {
+    name: String,
+    global: &'r PtrRW<(Mutable, Value)>,
+}
+
+#[allow(unused)]
+impl ExportedGlobal<'_> {
+    pub fn get(&self) -> Value {
+        self.global.read().1
+    }
+
+    /// Sets the global, which must be mutable and keep its type.
+    pub fn set(&self, value: Value) -> Result<(), RuntimeError> {
+        let mut global = self.global.write();
+        if global.0 != Mutable::Var {
+            return Err(LinkError::ImmutableGlobal(self.name.clone()).into());
+        }
+        if global.1.as_str() != value.as_str() {
+            return Err(LinkError::ArgumentMismatch {
+                export: self.name.clone(),
+                expected: global.1.as_str().to_string(),
+                got: value.as_str().to_string(),
+            }
+            .into());
+        }
+        global.1 = value;
+        Ok(())
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        self.global.read().0 == Mutable::Var
+    }
+}
+
+/// An exported table of references.
+pub struct ExportedTable<'r> {
+    name: String,
+    table: &'r PtrRW<Table>,
+    max_elements: usize,
+    /// How many functions the instance has, which a funcref must be below.
+    functions: usize,
+}
+
+fn reference(typ: RefTyp, index: u32) -> Value {
+    match typ {
+        RefTyp::FuncRef => Value::FuncRef(index),
+        RefTyp::ExternRef => Value::Externref(index),
+    }
+}
+
+/// The index a reference of table type `typ` holds, `u32::MAX` for null.
+fn index_of(typ: RefTyp, value: Value, table: &ExportedTable) -> Result<u32, RuntimeError> {
+    let name = &table.name;
+    match (typ, value) {
+        (RefTyp::FuncRef, Value::FuncRef(i)) if i != u32::MAX && i as usize >= table.functions => {
+            Err(LinkError::UnknownFunction {
+                export: name.to_string(),
+                index: i,
+            }
+            .into())
+        }
+        (RefTyp::FuncRef, Value::FuncRef(i)) | (RefTyp::ExternRef, Value::Externref(i)) => Ok(i),
+        _ => Err(LinkError::ArgumentMismatch {
+            export: name.to_string(),
+            expected: reference(typ, 0).as_str().to_string(),
+            got: value.as_str().to_string(),
+        }
+        .into()),
+    }
+}
+
+#[allow(unused)]
+impl ExportedTable<'_> {
+    /// The reference at `index`, a null one where nothing was stored.
+    pub fn get(&self, index: usize) -> Result<Value, RuntimeError> {
+        let table = self.table.read();
+        if index >= table.table_length.0 {
+            return Err(TrapCode::OutOfBoundsTableAccess.into());
+        }
+        let entry = table.table.get(&(index as u32)).map_or(u32::MAX, |f| f.0);
+        Ok(reference(table.typ, entry))
+    }
+
+    /// Stores the reference `value` at `index`.
+    pub fn set(&self, index: usize, value: Value) -> Result<(), RuntimeError> {
+        let mut table = self.table.write();
+        let entry = index_of(table.typ, value, self)?;
+        if index >= table.table_length.0 {
+            return Err(TrapCode::OutOfBoundsTableAccess.into());
+        }
+        // null entries are simply left out
+        if entry == u32::MAX {
+            table.table.remove(&(index as u32));
+        } else {
+            table.table.insert(index as u32, FuncIdx(entry));
+        }
+        Ok(())
+    }
+
+    /// Grows the table by `amount` entries of `init` like `table.grow` does,
+    /// returning the previous size or `None` when it may not grow that far.
+    pub fn grow(&self, amount: usize, init: Value) -> Result<Option<usize>, RuntimeError> {
+        let mut table = self.table.write();
+        let init = index_of(table.typ, init, self)?;
+        let (size, max) = table.table_length;
+        match size.checked_add(amount) {
+            Some(new) if new <= max.min(self.max_elements) => {
+                if init != u32::MAX {
+                    for i in size..new {
+                        table.table.insert(i as u32, FuncIdx(init));
+                    }
+                }
+                table.table_length.0 = new;
+                Ok(Some(size))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The current number of entries.
+    pub fn size(&self) -> usize {
+        self.table.read().table_length.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::MAIN_MODULE, wast::text_module};
+
+    const EXPORTS: &str = r#"(module
+        (memory (export "memory") 1 2)
+        (global (export "const") i32 (i32.const 1))
+        (global (export "var") (mut i64) (i64.const 2))
+        (table (export "table") 2 3 funcref)
+        (func (export "f")))"#;
+
+    fn runtime() -> Runtime {
+        Runtime::builder()
+            .add_module(MAIN_MODULE, &text_module(EXPORTS))
+            .build()
+            .expect("the runtime builds")
+    }
+
+    #[test]
+    fn memory_accesses_stay_inside_the_memory() {
+        let rt = runtime();
+        let instance = rt.instance(MAIN_MODULE).expect("main is loaded");
+        let memory = instance.memory("memory").expect("memory is exported");
+        let mut buf = [0; 4];
+        memory
+            .write(0xfffc, &[1, 2, 3, 4])
+            .expect("the end is in the memory");
+        memory
+            .read(0xfffc, &mut buf)
+            .expect("the end is in the memory");
+        assert_eq!(buf, [1, 2, 3, 4]);
+        for offset in [0xfffd, 0x10000, usize::MAX - 1] {
+            assert!(!memory.contains(offset, buf.len()));
+            assert!(memory.read(offset, &mut buf).is_err(), "{offset:#x}");
+            assert!(memory.write(offset, &buf).is_err(), "{offset:#x}");
+        }
+        assert_eq!(memory.grow(2), None);
+        assert_eq!(memory.grow(1), Some(1));
+        memory.read(0xfffd, &mut buf).expect("the memory grew");
+        assert!(matches!(
+            instance.memory("f"),
+            Err(RuntimeError::Link(LinkError::ExportKindMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn only_mutable_globals_are_set() {
+        let rt = runtime();
+        let instance = rt.instance(MAIN_MODULE).expect("main is loaded");
+        let constant = instance.global("const").expect("const is exported");
+        assert!(!constant.is_mutable());
+        assert!(matches!(
+            constant.set(Value::I32(5)),
+            Err(RuntimeError::Link(LinkError::ImmutableGlobal(_)))
+        ));
+        assert!(constant.get() == Value::I32(1));
+        let var = instance.global("var").expect("var is exported");
+        assert!(matches!(
+            var.set(Value::I32(5)),
+            Err(RuntimeError::Link(LinkError::ArgumentMismatch { .. }))
+        ));
+        var.set(Value::I64(5)).expect("var is a mutable i64");
+        assert!(var.get() == Value::I64(5));
+    }
+
+    #[test]
+    fn tables_only_hold_known_functions() {
+        let rt = runtime();
+        let instance = rt.instance(MAIN_MODULE).expect("main is loaded");
+        let table = instance.table("table").expect("table is exported");
+        assert!(matches!(
+            table.set(0, Value::FuncRef(1)),
+            Err(RuntimeError::Link(LinkError::UnknownFunction {
+                index: 1,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            table.grow(1, Value::FuncRef(7)),
+            Err(RuntimeError::Link(LinkError::UnknownFunction {
+                index: 7,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            table.set(0, Value::Externref(0)),
+            Err(RuntimeError::Link(LinkError::ArgumentMismatch { .. }))
+        ));
+        assert!(matches!(
+            table.set(2, Value::FuncRef(0)),
+            Err(RuntimeError::Trap(TrapCode::OutOfBoundsTableAccess))
+        ));
+        table.set(1, Value::FuncRef(0)).expect("f is function 0");
+        assert!(matches!(table.get(1), Ok(Value::FuncRef(0))));
+        assert!(matches!(table.get(0), Ok(Value::FuncRef(u32::MAX))));
+        assert!(matches!(table.grow(2, Value::FuncRef(0)), Ok(None)));
+        assert!(matches!(table.grow(1, Value::FuncRef(0)), Ok(Some(2))));
+        assert_eq!(table.size(), 3);
+    }
+}
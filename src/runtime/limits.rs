@@ -0,0 +1,137 @@
+/// Caps on the resources a [`Runtime`](super::Runtime) may hand to guest code.
+///
+/// Hitting the call depth or operand stack limit traps with
+/// `StackExhaustion`, `memory.grow` and `table.grow` return `-1` when they
+/// would go over, and modules that need more than allowed up front fail to
+/// instantiate.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceLimiter {
+    /// Frames on the call stack.
+    pub max_call_depth: usize,
+    /// Values on the operand stack of a single frame.
+    pub max_stack_size: usize,
+    /// Pages of 64KiB in a single memory.
+    pub max_memory_pages: usize,
+    /// Elements in a single table.
+    pub max_table_elements: usize,
+    /// Modules instantiated by one runtime, including the main module.
+    pub max_instances: usize,
+}
+
+impl Default for ResourceLimiter {
+    fn default() -> Self {
+        Self {
+            max_call_depth: u16::MAX as usize,
+            max_stack_size: 1 << 20,
+            max_memory_pages: 1 << 16,
+            max_table_elements: 10_000_000,
+            max_instances: 1000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{LinkError, Runtime, RuntimeError, TrapCode, Value, MAIN_MODULE},
+        wast::text_module,
+    };
+
+    const GROWING: &str = r#"(module
+        (memory 1)
+        (table 1 funcref)
+        (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+        (func (export "grow_table") (param funcref i32) (result i32)
+            (table.grow (local.get 0) (local.get 1)))
+        (func $deep (export "deep") (param i32) (result i32)
+            (if (result i32) (local.get 0)
+                (then (i32.add (i32.const 1) (call $deep (i32.sub (local.get 0) (i32.const 1)))))
+                (else (i32.const 0)))))"#;
+
+    fn runtime(text: &str, limits: ResourceLimiter) -> Result<Runtime, RuntimeError> {
+        Runtime::builder()
+            .add_module(MAIN_MODULE, &text_module(text))
+            .limits(limits)
+            .build()
+    }
+
+    fn call(rt: &mut Runtime, name: &str, args: &[Value]) -> Result<i32, RuntimeError> {
+        match rt.invoke(name, args)?[..] {
+            [Value::I32(v)] => Ok(v),
+            ref v => panic!("{name} returned {v:?}"),
+        }
+    }
+
+    fn exceeded(result: Result<Runtime, RuntimeError>) -> Option<&'static str> {
+        match result {
+            Err(RuntimeError::Link(LinkError::ResourceLimitExceeded(what))) => Some(what),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn growing_past_the_limits_fails() {
+        let limits = ResourceLimiter {
+            max_memory_pages: 3,
+            max_table_elements: 4,
+            ..ResourceLimiter::default()
+        };
+        let mut rt = runtime(GROWING, limits).expect("the module fits");
+        let null = Value::FuncRef(u32::MAX);
+        assert_eq!(call(&mut rt, "grow", &[Value::I32(2)]).ok(), Some(1));
+        assert_eq!(call(&mut rt, "grow", &[Value::I32(1)]).ok(), Some(-1));
+        assert_eq!(call(&mut rt, "grow", &[Value::I32(0)]).ok(), Some(3));
+        assert_eq!(
+            call(&mut rt, "grow_table", &[null, Value::I32(3)]).ok(),
+            Some(1)
+        );
+        assert_eq!(
+            call(&mut rt, "grow_table", &[null, Value::I32(1)]).ok(),
+            Some(-1)
+        );
+    }
+
+    #[test]
+    fn deep_recursion_is_exhaustion() {
+        let limits = ResourceLimiter {
+            max_call_depth: 50,
+            ..ResourceLimiter::default()
+        };
+        let mut rt = runtime(GROWING, limits).expect("the module fits");
+        assert_eq!(call(&mut rt, "deep", &[Value::I32(40)]).ok(), Some(40));
+        assert!(matches!(
+            call(&mut rt, "deep", &[Value::I32(60)]),
+            Err(RuntimeError::Trap(TrapCode::StackExhaustion))
+        ));
+    }
+
+    #[test]
+    fn modules_that_need_too_much_do_not_instantiate() {
+        let small = ResourceLimiter {
+            max_memory_pages: 4,
+            max_table_elements: 5,
+            max_instances: 1,
+            ..ResourceLimiter::default()
+        };
+        let big_memory = runtime("(module (memory 5))", small);
+        assert_eq!(exceeded(big_memory), Some("memory pages"));
+        let big_table = runtime("(module (table 6 funcref))", small);
+        assert_eq!(exceeded(big_table), Some("table elements"));
+
+        let module = text_module("(module (memory 4) (table 5 funcref))");
+        let two = Runtime::builder()
+            .add_module(MAIN_MODULE, &module)
+            .add_module("other", &module)
+            .limits(small)
+            .build();
+        assert_eq!(exceeded(two), Some("instances"));
+        let mut one = runtime("(module (memory 4) (table 5 funcref))", small).expect("it fits");
+        assert!(matches!(
+            one.instantiate("other", &module),
+            Err(RuntimeError::Link(LinkError::ResourceLimitExceeded(
+                "instances"
+            )))
+        ));
+    }
+}
@@ -0,0 +1,316 @@
+//! Numbers in the notation of the text format: `-0x7fff_ffff`, `4_294_967_295`,
+//! `0x1.8p3`, `inf`, `nan:0x200000` and friends.
+
+use super::Value;
+use crate::parser::{NumType, RefTyp, ValType};
+
+fn split_sign(s: &str) -> (bool, &str) {
+    match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    }
+}
+
+/// The magnitude of an unsigned decimal or `0x` hex integer, `_` separators
+/// allowed between digits.
+fn magnitude(s: &str) -> Option<u64> {
+    let (digits, radix) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (s, 10),
+    };
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+    {
+        return None;
+    }
+    u64::from_str_radix(&digits.replace('_', ""), radix).ok()
+}
+
+/// Parses an integer of `bits` bits, accepting the whole signed and unsigned
+/// range and returning the two's complement bit pattern.
+fn int(s: &str, bits: u32) -> Option<u64> {
+    let (negative, rest) = split_sign(s);
+    let m = magnitude(rest)?;
+    let mask = u64::MAX >> (64 - bits);
+    if negative {
+        (m <= 1 << (bits - 1)).then(|| m.wrapping_neg() & mask)
+    } else {
+        (m <= mask).then_some(m)
+    }
+}
+
+pub fn parse_i32(s: &str) -> Option<i32> {
+    int(s, 32).map(|v| v as u32 as i32)
+}
+
+pub fn parse_i64(s: &str) -> Option<i64> {
+    int(s, 64).map(|v| v as i64)
+}
+
+/// Rounds the hex float `digits` (no sign, no `0x`) to nearest even, for a
+/// format with `mantissa` explicit mantissa bits and `exponent` exponent bits.
+/// Returns the bits without the sign.
+fn hex_float(digits: &str, mantissa: u32, exponent: u32) -> Option<u64> {
+    let (digits, exp) = match digits.split_once(['p', 'P']) {
+        Some((digits, exp)) => {
+            let (negative, exp) = split_sign(exp);
+            if exp.is_empty() || exp.starts_with('_') {
+                return None;
+            }
+            let exp = exp
+                .replace('_', "")
+                .parse::<u64>()
+                .unwrap_or(u64::MAX)
+                .min(100_000) as i64;
+            (digits, if negative { -exp } else { exp })
+        }
+        None => (digits, 0),
+    };
+    let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    if int.is_empty() || int.starts_with('_') || frac.starts_with('_') {
+        return None;
+    }
+
+    // m * 2^exp, with `sticky` set when nonzero digits did not fit in m
+    let mut m = 0u64;
+    let mut exp = exp;
+    let mut sticky = false;
+    for (c, after_dot) in int
+        .chars()
+        .map(|c| (c, false))
+        .chain(frac.chars().map(|c| (c, true)))
+    {
+        if c == '_' {
+            continue;
+        }
+        let d = c.to_digit(16)? as u64;
+        if m < 1 << 59 {
+            m = m * 16 + d;
+            exp -= after_dot as i64 * 4;
+        } else {
+            sticky |= d != 0;
+            exp += !after_dot as i64 * 4;
+        }
+    }
+    if m == 0 {
+        return Some(0);
+    }
+
+    let bias = (1i64 << (exponent - 1)) - 1;
+    let top = 63 - m.leading_zeros() as i64;
+    // exponent of the last mantissa bit we can keep, normal or subnormal
+    let mut lsb = (top + exp - mantissa as i64).max(1 - bias - mantissa as i64);
+    let shift = lsb - exp;
+    let mut q = if shift <= 0 {
+        m << -shift
+    } else if shift > 64 {
+        0
+    } else {
+        let m = m as u128;
+        let rem = m & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        let q = (m >> shift) as u64;
+        let up = rem > half || (rem == half && (sticky || q & 1 == 1));
+        q + up as u64
+    };
+    if q >> (mantissa + 1) != 0 {
+        q >>= 1;
+        lsb += 1;
+    }
+    let max_biased = (1u64 << exponent) - 1;
+    if q >> mantissa == 0 {
+        // subnormal
+        return Some(q);
+    }
+    let biased = lsb + mantissa as i64 + bias;
+    if biased >= max_biased as i64 {
+        return Some(max_biased << mantissa);
+    }
+    Some(((biased as u64) << mantissa) | (q & ((1 << mantissa) - 1)))
+}
+
+/// Bits of a float without its sign, for a format with `mantissa` mantissa
+/// bits and `exponent` exponent bits. Decimal numbers go through `decimal`.
+fn float(s: &str, mantissa: u32, exponent: u32, decimal: fn(&str) -> Option<u64>) -> Option<u64> {
+    let inf = ((1u64 << exponent) - 1) << mantissa;
+    if s == "inf" {
+        return Some(inf);
+    }
+    if s == "nan" {
+        return Some(inf | 1 << (mantissa - 1));
+    }
+    if let Some(payload) = s.strip_prefix("nan:") {
+        let payload = magnitude(payload).filter(|p| payload.starts_with("0x") && *p != 0)?;
+        return (payload >> mantissa == 0).then_some(inf | payload);
+    }
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return hex_float(hex, mantissa, exponent);
+    }
+    if !s.starts_with(|c: char| c.is_ascii_digit()) || s.contains("__") || s.ends_with('_') {
+        return None;
+    }
+    decimal(&s.replace('_', ""))
+}
+
+pub fn parse_f32(s: &str) -> Option<f32> {
+    let (negative, rest) = split_sign(s);
+    let bits = float(rest, 23, 8, |d| {
+        d.parse::<f32>().ok().map(|f| f.to_bits() as u64)
+    })?;
+    Some(f32::from_bits(bits as u32 | (negative as u32) << 31))
+}
+
+pub fn parse_f64(s: &str) -> Option<f64> {
+    let (negative, rest) = split_sign(s);
+    let bits = float(rest, 52, 11, |d| d.parse::<f64>().ok().map(f64::to_bits))?;
+    Some(f64::from_bits(bits | (negative as u64) << 63))
+}
+
+impl Value {
+    /// Parses `s` as a value of type `t`, `null` for references.
+    pub fn parse_as(s: &str, t: &ValType) -> Result<Self, String> {
+        let value = match t {
+            ValType::Num(NumType::I32) => parse_i32(s).map(Value::I32),
+            ValType::Num(NumType::I64) => parse_i64(s).map(Value::I64),
+            ValType::Num(NumType::F32) => parse_f32(s).map(Value::F32),
+            ValType::Num(NumType::F64) => parse_f64(s).map(Value::F64),
+            ValType::Ref(r) => {
+                let index = match s {
+                    "null" => Some(u32::MAX),
+                    _ => parse_i32(s).map(|i| i as u32),
+                };
+                index.map(|i| match r {
+                    RefTyp::FuncRef => Value::FuncRef(i),
+                    RefTyp::ExternRef => Value::Externref(i),
+                })
+            }
+            ValType::Poly | ValType::Vec128 => None,
+        };
+        value.ok_or_else(|| format!("invalid {t} {s:?}"))
+    }
+}
+
+fn nan_payload(payload: u64, canonical: u64) -> String {
+    if payload == canonical {
+        "nan".to_string()
+    } else {
+        format!("nan:{payload:#x}")
+    }
+}
+
+/// Prints values the way [`Value::parse_as`] reads them back.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::I32(v) => write!(f, "{v}"),
+            Value::I64(v) => write!(f, "{v}"),
+            Value::F32(v) if v.is_nan() => {
+                let sign = if v.is_sign_negative() { "-" } else { "" };
+                let payload = nan_payload((v.to_bits() & 0x7f_ffff) as u64, 1 << 22);
+                write!(f, "{sign}{payload}")
+            }
+            Value::F64(v) if v.is_nan() => {
+                let sign = if v.is_sign_negative() { "-" } else { "" };
+                let payload = nan_payload(v.to_bits() & 0xf_ffff_ffff_ffff, 1 << 51);
+                write!(f, "{sign}{payload}")
+            }
+            Value::F32(v) => write!(f, "{v:?}"),
+            Value::F64(v) => write!(f, "{v:?}"),
+            Value::FuncRef(u32::MAX) | Value::Externref(u32::MAX) => write!(f, "null"),
+            Value::FuncRef(i) | Value::Externref(i) => write!(f, "{i}"),
+            Value::BlockLock => write!(f, "--- BLOCK ---"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_cover_the_signed_and_unsigned_range() {
+        assert_eq!(parse_i32("0"), Some(0));
+        assert_eq!(parse_i32("-0x8000_0000"), Some(i32::MIN));
+        assert_eq!(parse_i32("4_294_967_295"), Some(-1));
+        assert_eq!(parse_i32("+0x7fffffff"), Some(i32::MAX));
+        assert_eq!(parse_i64("0xffff_ffff_ffff_ffff"), Some(-1));
+        assert_eq!(parse_i64("-9223372036854775808"), Some(i64::MIN));
+    }
+
+    #[test]
+    fn integers_out_of_range_or_misspelled_are_rejected() {
+        assert_eq!(parse_i32("4294967296"), None);
+        assert_eq!(parse_i32("-0x8000_0001"), None);
+        assert_eq!(parse_i64("18446744073709551616"), None);
+        for s in ["", "-", "0x", "_1", "1_", "1__0", "0x_1", "12a", "1.0"] {
+            assert_eq!(parse_i32(s), None, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn hex_floats_round_to_nearest_even() {
+        assert_eq!(parse_f32("0x1.8p3"), Some(12.0));
+        assert_eq!(parse_f64("-0x1p-1"), Some(-0.5));
+        // one bit past the f32 mantissa, exactly halfway: stays even
+        assert_eq!(
+            parse_f32("0x1.000001p0").map(f32::to_bits),
+            Some(0x3f80_0000)
+        );
+        // a little more than halfway rounds up
+        assert_eq!(
+            parse_f32("0x1.0000011p0").map(f32::to_bits),
+            Some(0x3f80_0001)
+        );
+        // the smallest subnormal and what rounds past the largest finite
+        assert_eq!(parse_f32("0x1p-149").map(f32::to_bits), Some(1));
+        assert_eq!(parse_f64("0x1p-1074").map(f64::to_bits), Some(1));
+        assert_eq!(parse_f32("0x1.ffffffp127"), Some(f32::INFINITY));
+        assert_eq!(parse_f64("0x1p100000"), Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn decimal_floats_and_specials() {
+        assert_eq!(parse_f32("1_000.5"), Some(1000.5));
+        assert_eq!(parse_f64("1e-3"), Some(0.001));
+        assert_eq!(parse_f64("-inf"), Some(f64::NEG_INFINITY));
+        assert_eq!(parse_f32("-0").map(f32::to_bits), Some(0x8000_0000));
+        assert_eq!(parse_f32("nan").map(f32::to_bits), Some(0x7fc0_0000));
+        assert_eq!(parse_f32("-nan:0x1").map(f32::to_bits), Some(0xff80_0001));
+        assert_eq!(
+            parse_f64("nan:0x8_0000_0000_0000").map(f64::to_bits),
+            Some(0x7ff8_0000_0000_0000)
+        );
+        for s in [
+            "nan:0x0",
+            "nan:0x800000",
+            "nan:1",
+            ".5",
+            "1__0",
+            "1_",
+            "infinity",
+        ] {
+            assert_eq!(parse_f32(s), None, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn values_print_the_way_they_are_read() {
+        let cases = [
+            (ValType::Num(NumType::I32), "-1"),
+            (ValType::Num(NumType::I64), "9223372036854775807"),
+            (ValType::Num(NumType::F32), "-nan:0x1"),
+            (ValType::Num(NumType::F64), "nan"),
+            (ValType::Num(NumType::F64), "0.1"),
+            (ValType::Ref(RefTyp::FuncRef), "null"),
+            (ValType::Ref(RefTyp::ExternRef), "7"),
+        ];
+        for (t, s) in cases {
+            let value = Value::parse_as(s, &t).expect("the literal parses");
+            assert_eq!(value.to_string(), s);
+        }
+        assert!(Value::parse_as("1.5", &ValType::Num(NumType::I32)).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::parser::MemArg;
 use std::{collections::HashMap, fmt::Debug, mem, num::Wrapping};
 
-use super::RuntimeError;
+use super::{RuntimeError, TrapCode};
 
 struct Page<const PAGE_SIZE: usize> {
     _zero: usize,
@@ -33,11 +33,41 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
         (self.current_pages, self.max_pages)
     }
 
+    /// Whether the `len` bytes from `address` on are all in the memory.
+    pub fn contains(&self, address: usize, len: usize) -> bool {
+        address
+            .checked_add(len)
+            .is_some_and(|end| end <= self.current_pages * PAGE_SIZE)
+    }
+
     #[allow(unused)]
     pub fn size(&self) -> usize {
         self.map.len() * PAGE_SIZE
     }
 
+    /// The pages written to so far, by index, the others are all zeros.
+    pub fn touched_pages(&self) -> impl Iterator<Item = (usize, &[u8])> {
+        self.map.iter().map(|(i, page)| (*i, &page.data[..]))
+    }
+
+    /// Empties the memory and resizes it, as a start for [`Memory::set_page`].
+    pub fn reset(&mut self, current_pages: usize, max_pages: usize) {
+        self.current_pages = current_pages;
+        self.max_pages = max_pages;
+        self.map.clear();
+    }
+
+    /// Overwrites page `index` with `data`, zero filled past its end.
+    pub fn set_page(&mut self, index: usize, data: &[u8]) {
+        let mut page = Page {
+            _zero: 1,
+            data: [0; PAGE_SIZE],
+        };
+        let len = data.len().min(PAGE_SIZE);
+        page.data[..len].copy_from_slice(&data[..len]);
+        self.map.insert(index, page);
+    }
+
     pub fn new(current_pages: usize, max_pages: usize) -> Self {
         Self {
             current_pages,
@@ -57,7 +87,7 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
         // println!("writing {byte} to {address}");
         let block = address / PAGE_SIZE;
         if block >= self.current_pages {
-            return Err(RuntimeError::OutOfBoundsMemoryAccess);
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
         }
         let index = address % PAGE_SIZE;
         let entry = self.map.entry(block).or_insert(Page {
@@ -70,7 +100,7 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
 
     pub fn set<T>(&mut self, address: usize, mem_arg: MemArg, val: T) -> Result<(), RuntimeError> {
         if self.current_pages == 0 || address >= 2usize.pow(32) {
-            return Err(RuntimeError::OutOfBoundsMemoryAccess);
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
         }
         // println!("setting {}", address + mem_arg.offset as usize);
         // let align = 2usize.pow(align);
@@ -84,7 +114,7 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
                 || end_block >= self.current_pages
                 || end_block > self.max_pages
             {
-                return Err(RuntimeError::OutOfBoundsMemoryAccess);
+                return Err(TrapCode::OutOfBoundsMemoryAccess.into());
             }
         }
 
@@ -116,7 +146,7 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
         mem_arg: MemArg,
     ) -> Result<T, RuntimeError> {
         if self.current_pages == 0 {
-            return Err(RuntimeError::OutOfBoundsMemoryAccess);
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
         }
         // println!("getting {}", address + mem_arg.offset as usize);
 
@@ -133,7 +163,7 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
                 || end_block >= self.current_pages
                 || end_block >= self.max_pages
             {
-                return Err(RuntimeError::OutOfBoundsMemoryAccess);
+                return Err(TrapCode::OutOfBoundsMemoryAccess.into());
             }
         }
 
@@ -170,7 +200,7 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
             || destination_block >= self.current_pages
             || destination_block_end >= self.current_pages
         {
-            return Err(RuntimeError::OutOfBoundsMemoryAccess);
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
         }
 
         let mut buf = Vec::new();
@@ -187,15 +217,17 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
 
     pub fn slice_write(&mut self, address: usize, slice: &[u8]) -> Result<(), RuntimeError> {
         if self.current_pages == 0 {
-            return Err(RuntimeError::OutOfBoundsMemoryAccess);
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
         }
         let start_address = address;
         let start_block = start_address.checked_sub(1).unwrap_or_default() / PAGE_SIZE;
-        let end_address = address + slice.len();
+        let end_address = address
+            .checked_add(slice.len())
+            .ok_or(TrapCode::OutOfBoundsMemoryAccess)?;
         let end_block = end_address.checked_sub(1).unwrap_or_default() / PAGE_SIZE;
 
         if start_block > self.current_pages || end_block >= self.current_pages {
-            return Err(RuntimeError::OutOfBoundsMemoryAccess);
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
         }
 
         for (i, b) in slice.iter().enumerate() {
@@ -205,17 +237,29 @@ impl<const PAGE_SIZE: usize> Memory<PAGE_SIZE> {
         Ok(())
     }
 
+    pub fn slice_read(&self, address: usize, len: usize) -> Result<Vec<u8>, RuntimeError> {
+        let end_address = address
+            .checked_add(len)
+            .ok_or(TrapCode::OutOfBoundsMemoryAccess)?;
+        if self.current_pages == 0 || end_address > self.current_pages * PAGE_SIZE {
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
+        }
+        Ok((address..end_address)
+            .map(|i| self.get_u8(i, MemArg::default()))
+            .collect())
+    }
+
     pub fn bulk_write(&mut self, address: usize, end: usize, val: u8) -> Result<(), RuntimeError> {
         if self.current_pages == 0 {
-            return Err(RuntimeError::OutOfBoundsMemoryAccess);
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
         }
         let start_address = address;
         let start_block = start_address / PAGE_SIZE;
-        let end_address = address + end - 1;
+        let end_address = (address + end).saturating_sub(1);
         let end_block = end_address / PAGE_SIZE;
 
         if start_block > self.current_pages || end_block >= self.current_pages {
-            return Err(RuntimeError::OutOfBoundsMemoryAccess);
+            return Err(TrapCode::OutOfBoundsMemoryAccess.into());
         }
 
         for i in address..address + end {
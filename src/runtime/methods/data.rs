@@ -0,0 +1,20 @@
+use super::super::Runtime;
+use std::any::Any;
+
+impl Runtime {
+    /// The data handed to [`RuntimeBuilder::data`](super::new::RuntimeBuilder::data),
+    /// if it is a `T`.
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.data.as_ref()?.downcast_ref()
+    }
+
+    pub fn data_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.data.as_mut()?.downcast_mut()
+    }
+
+    /// Replaces the data host functions see, returning the old one.
+    #[allow(unused)]
+    pub fn set_data<T: Any>(&mut self, data: T) -> Option<Box<dyn Any>> {
+        self.data.replace(Box::new(data))
+    }
+}
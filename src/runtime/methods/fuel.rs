@@ -0,0 +1,140 @@
+use super::super::{error::RuntimeError, Runtime, TrapCode};
+
+/// Takes `amount` from `fuel`, leaving it untouched if there is not enough so
+/// that the instruction can be retried once refueled.
+pub(super) fn burn(fuel: &mut Option<u64>, amount: u64) -> Result<(), RuntimeError> {
+    match fuel {
+        None => Ok(()),
+        Some(left) if *left >= amount => {
+            *left -= amount;
+            Ok(())
+        }
+        Some(_) => Err(TrapCode::OutOfFuel.into()),
+    }
+}
+
+impl Runtime {
+    /// Fuel left, `None` when execution is unmetered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Replaces the fuel left, `None` turns metering off.
+    #[allow(unused)]
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Adds fuel so that a runtime which ran out can be resumed with
+    /// [`Runtime::step`]. Does nothing when execution is unmetered.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(left) = &mut self.fuel {
+            *left = left.saturating_add(fuel);
+        }
+    }
+
+    /// Extra fuel charged for every call into a host function.
+    #[allow(unused)]
+    pub fn set_host_call_fuel(&mut self, fuel: u64) {
+        self.host_call_fuel = fuel;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{Import, Value, MAIN_MODULE},
+        wast::text_module,
+    };
+
+    /// Calls the host ten times in a loop.
+    const LOOP: &str = r#"(module
+        (import "spectest" "print" (func $print))
+        (global $n (mut i32) (i32.const 0))
+        (func (export "main")
+            (loop $l
+                (call $print)
+                (global.set $n (i32.add (global.get $n) (i32.const 1)))
+                (br_if $l (i32.lt_u (global.get $n) (i32.const 10)))))
+        (func (export "n") (result i32) (global.get $n)))"#;
+
+    fn runtime(fuel: u64, host_call_fuel: u64) -> Runtime {
+        Runtime::builder()
+            .add_io("spectest", Import::spectest())
+            .add_module(MAIN_MODULE, &text_module(LOOP))
+            .fuel(fuel)
+            .host_call_fuel(host_call_fuel)
+            .build()
+            .expect("the runtime builds")
+    }
+
+    /// Runs the program to the end, refueling `chunk` at a time, and returns
+    /// the fuel it used.
+    fn used(rt: &mut Runtime, chunk: u64) -> u64 {
+        let mut given = rt.fuel().unwrap_or_default();
+        loop {
+            match rt.step() {
+                Ok(()) => {}
+                Err(RuntimeError::Trap(TrapCode::OutOfFuel)) => {
+                    rt.add_fuel(chunk);
+                    given += chunk;
+                }
+                Err(_) => break,
+            }
+        }
+        let left = rt.fuel().unwrap_or_default();
+        // the getter would need fuel of its own
+        rt.set_fuel(None);
+        assert_eq!(rt.invoke("n", &[]).ok(), Some(vec![Value::I32(10)]));
+        given - left
+    }
+
+    #[test]
+    fn burning_more_than_is_left_takes_nothing() {
+        let mut fuel = Some(3);
+        assert!(burn(&mut fuel, 2).is_ok());
+        assert!(matches!(
+            burn(&mut fuel, 2),
+            Err(RuntimeError::Trap(TrapCode::OutOfFuel))
+        ));
+        assert_eq!(fuel, Some(1));
+        assert!(burn(&mut fuel, 1).is_ok());
+        assert_eq!(fuel, Some(0));
+        assert!(burn(&mut None, u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn refueling_along_the_way_uses_the_same_fuel() {
+        let whole = used(&mut runtime(1_000_000, 0), 0);
+        assert!(whole > 0);
+        for chunk in [1, 2, 7] {
+            assert_eq!(
+                used(&mut runtime(chunk, 3), chunk),
+                used(&mut runtime(1_000_000, 3), 0)
+            );
+            assert_eq!(
+                used(&mut runtime(chunk, 0), chunk),
+                whole,
+                "chunks of {chunk}"
+            );
+        }
+    }
+
+    #[test]
+    fn host_calls_cost_extra() {
+        let plain = used(&mut runtime(1_000_000, 0), 0);
+        assert_eq!(used(&mut runtime(1_000_000, 5), 0), plain + 10 * 5);
+    }
+
+    #[test]
+    fn unmetered_runtimes_stay_unmetered() {
+        let mut rt = runtime(10, 0);
+        rt.set_fuel(None);
+        rt.add_fuel(5);
+        assert_eq!(rt.fuel(), None);
+        rt.set_fuel(Some(4));
+        rt.add_fuel(u64::MAX);
+        assert_eq!(rt.fuel(), Some(u64::MAX));
+    }
+}
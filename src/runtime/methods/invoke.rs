@@ -0,0 +1,174 @@
+use super::super::{
+    clean_model::Function, error::RuntimeError, Frame, FuncId, Import, InternalErrorKind::*,
+    LinkError, Runtime, Value, MAIN_MODULE,
+};
+use crate::parser::{ExportDesc, FuncIdx, FuncType};
+use std::collections::HashMap;
+
+impl Runtime {
+    /// The function `module` exports as `name`, with the instance and index
+    /// it runs under.
+    fn exported_function(
+        &self,
+        module: &str,
+        name: &str,
+    ) -> Result<(String, u32, &Function), RuntimeError> {
+        let Some(Import::WS(main)) = self.modules.get(module) else {
+            return Err(NoModule(module.to_string()).at(file!(), line!(), column!()));
+        };
+        let Some(ExportDesc::Func(FuncIdx(id))) = main.exports.get(name) else {
+            return Err(LinkError::UnknownExport(name.to_string()).into());
+        };
+        let function = main
+            .functions
+            .get(*id as usize)
+            .ok_or_else(|| MissingFunction.at(file!(), line!(), column!()))?;
+        let (home, id) = main
+            .foreign
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| (module.to_string(), *id));
+        Ok((home, id, function.as_ref()))
+    }
+
+    /// The signature of the function the main module exports as `name`.
+    pub fn export_type(&self, name: &str) -> Result<FuncType, RuntimeError> {
+        self.export_type_in(MAIN_MODULE, name)
+    }
+
+    /// [`Runtime::export_type`] for an export of the instance `module`.
+    #[allow(unused)]
+    pub fn export_type_in(&self, module: &str, name: &str) -> Result<FuncType, RuntimeError> {
+        let (_, _, function) = self.exported_function(module, name)?;
+        Ok(match function {
+            Function::WS { ty, .. } | Function::IO { ty, .. } => ty.clone(),
+        })
+    }
+
+    /// Calls the function the main module exports as `name` and runs it to
+    /// completion, returning its results. Frames already on the stack are
+    /// left alone, and an error unwinds just the frames of this call.
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, RuntimeError> {
+        self.invoke_in(MAIN_MODULE, name, args)
+    }
+
+    /// [`Runtime::invoke`] on an export of the instance `module`.
+    pub fn invoke_in(
+        &mut self,
+        module: &str,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Vec<Value>, RuntimeError> {
+        let depth = self.stack.len();
+        let results = self.enter_in(module, name, args)?;
+        self.finish_call(depth, results)
+    }
+
+    /// Pushes the frame for a call of the export `name` of the instance
+    /// `module` without running any of it, for [`Runtime::step`] to take from
+    /// there. Returns how many results the call leaves.
+    pub fn enter_in(
+        &mut self,
+        module: &str,
+        name: &str,
+        args: &[Value],
+    ) -> Result<usize, RuntimeError> {
+        let ty = self.export_type_in(module, name)?;
+        let types = &ty.input.types;
+        if args.len() != types.len() || args.iter().zip(types).any(|(v, t)| !v.is_type(t)) {
+            return Err(LinkError::ArgumentMismatch {
+                export: name.to_string(),
+                expected: ty.to_string(),
+                got: args
+                    .iter()
+                    .map(|a| a.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            }
+            .into());
+        }
+
+        let (home, id, function) = self.exported_function(module, name)?;
+        let mut locals = args
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i as u32, *v))
+            .collect::<HashMap<_, _>>();
+        function.zero_locals(&mut locals);
+        self.push_frame(home, id, locals);
+        Ok(ty.output.types.len())
+    }
+
+    /// Runs the start function of the instance `name`, if it has one.
+    pub(super) fn start(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let Some(Import::WS(model)) = self.modules.get(name) else {
+            return Err(NoModule(name.to_string()).at(file!(), line!(), column!()));
+        };
+        let Some(id) = model.start else {
+            return Ok(());
+        };
+        let function = model
+            .functions
+            .get(id as usize)
+            .ok_or_else(|| MissingFunction.at(file!(), line!(), column!()))?;
+        let mut locals = HashMap::new();
+        function.zero_locals(&mut locals);
+        let (home, id) = model
+            .foreign
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| (name.to_string(), id));
+        self.call(home, id, locals, 0).map(drop)
+    }
+
+    fn push_frame(&mut self, module: String, id: u32, locals: HashMap<u32, Value>) {
+        self.stack.push(Frame {
+            func_id: FuncId::Id(id),
+            pc: 0,
+            module,
+            stack: Vec::new(),
+            locals,
+            depth_stack: Vec::new(),
+        });
+        self.entered();
+    }
+
+    /// Runs the function `id` of the instance `module` to completion, leaving
+    /// the frames below it alone, and returns its `results` results.
+    fn call(
+        &mut self,
+        module: String,
+        id: u32,
+        locals: HashMap<u32, Value>,
+        results: usize,
+    ) -> Result<Vec<Value>, RuntimeError> {
+        let depth = self.stack.len();
+        self.push_frame(module, id, locals);
+        self.finish_call(depth, results)
+    }
+
+    /// Steps until the stack is back to `depth` frames, returning the
+    /// `results` results of the call that was on top of it.
+    fn finish_call(&mut self, depth: usize, results: usize) -> Result<Vec<Value>, RuntimeError> {
+        loop {
+            match self.step() {
+                Ok(()) if self.stack.len() > depth => {}
+                Ok(()) => {
+                    // the results were handed to the frame below us
+                    let frame = self
+                        .stack
+                        .last_mut()
+                        .ok_or_else(|| NoFrame.at(file!(), line!(), column!()))?;
+                    let at = frame.stack.len().saturating_sub(results);
+                    return Ok(frame.stack.split_off(at));
+                }
+                Err(RuntimeError::ReturnedToNoFrame(results)) => return Ok(results),
+                Err(e) => {
+                    self.trap_backtrace = Some(self.backtrace());
+                    self.stack.truncate(depth);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
@@ -1,2 +1,6 @@
+mod data;
+mod fuel;
+mod invoke;
 mod new;
+mod observe;
 mod step;
@@ -1,18 +1,26 @@
-use super::super::{clean_model::Model, error::RuntimeError, Frame, Runtime};
+use super::super::{
+    clean_model::{Function, Model},
+    error::RuntimeError,
+    Frame, Runtime, Value,
+};
 use crate::{
     parser::{ExportDesc, FuncIdx, Module, Parsable},
-    runtime::{FuncId, Import, IO},
+    runtime::{
+        FuncId, Import, InternalErrorKind::NoModule, LinkError, ResourceLimiter, IO, MAIN_MODULE,
+    },
 };
 use std::{
+    any::Any,
     collections::{BTreeSet, HashMap},
-    fs::File,
-    io::{Cursor, Read},
+    io::Cursor,
     path::{Path, PathBuf},
 };
 
+#[allow(clippy::large_enum_variant)]
 pub enum ToImport {
     IO(IO),
     WS(PathBuf),
+    Module(Module),
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -34,17 +42,133 @@ impl Intermediate {
     }
 }
 
+/// Finds an import that could not be resolved while ordering `deps`, either
+/// because its module was never added or because of an import cycle.
+fn missing_dependency(
+    modules: &HashMap<String, Intermediate>,
+    deps: &HashMap<String, BTreeSet<String>>,
+) -> RuntimeError {
+    let import = deps
+        .keys()
+        .filter_map(|k| match modules.get(k) {
+            Some(Intermediate::WS(module)) => module
+                .imports
+                .imports
+                .iter()
+                .find(|i| deps.contains_key(&i.module.0) || !modules.contains_key(&i.module.0)),
+            _ => None,
+        })
+        .next();
+    LinkError::UnknownImport {
+        module: import.map(|i| i.module.0.clone()).unwrap_or_default(),
+        name: import.map(|i| i.name.0.clone()).unwrap_or_default(),
+    }
+    .into()
+}
+
+impl Module {
+    /// Parses a binary module. The result can be instantiated any number of
+    /// times, see [`RuntimeBuilder::add_module`] and [`Runtime::instantiate`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RuntimeError> {
+        let mut cursor = Cursor::new(bytes);
+        let mut stack = Vec::new();
+        Module::parse(&mut cursor, &mut stack).map_err(|error| {
+            stack.reverse();
+            debug!("failed to parse module, stack: {stack:#?}");
+            RuntimeError::Parse {
+                offset: cursor.position(),
+                error,
+            }
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, RuntimeError> {
+        let path = path.as_ref();
+        let buf = std::fs::read(path).map_err(|error| RuntimeError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::from_bytes(&buf).inspect_err(|_| debug!("in {path:?}"))
+    }
+}
+
+/// Rejects a freshly instantiated module that already needs more than the
+/// limiter allows.
+fn check_limits(model: &Model, limits: &ResourceLimiter) -> Result<(), RuntimeError> {
+    if model.memory.read().pages().0 > limits.max_memory_pages {
+        return Err(LinkError::ResourceLimitExceeded("memory pages").into());
+    }
+    if model
+        .tables
+        .iter()
+        .any(|t| t.read().table_length.0 > limits.max_table_elements)
+    {
+        return Err(LinkError::ResourceLimitExceeded("table elements").into());
+    }
+    Ok(())
+}
+
 pub struct RuntimeBuilder {
-    path: PathBuf,
+    path: Option<PathBuf>,
     modules: HashMap<String, ToImport>,
+    fuel: Option<u64>,
+    host_call_fuel: u64,
+    limits: ResourceLimiter,
+    data: Option<Box<dyn Any>>,
+    deterministic: bool,
 }
 impl RuntimeBuilder {
+    /// Stores `data` on the runtime for host functions, see [`Runtime::data`].
+    #[allow(unused)]
+    pub fn data<T: Any>(mut self, data: T) -> Self {
+        self.data = Some(Box::new(data));
+        self
+    }
+
+    /// Caps what the module may use, see [`ResourceLimiter`].
+    pub fn limits(mut self, limits: ResourceLimiter) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Meters execution, trapping with `OutOfFuel` once `fuel` instructions
+    /// have run.
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Charges `fuel` on top of the instruction itself for every host call.
+    #[allow(unused)]
+    pub fn host_call_fuel(mut self, fuel: u64) -> Self {
+        self.host_call_fuel = fuel;
+        self
+    }
+
+    /// Makes every float operation that results in a NaN give the canonical
+    /// one, so that the program computes the same bits on every host. The
+    /// host functions are the embedder's to pin down, see
+    /// [`WasiConfig::deterministic`](crate::runtime::WasiConfig::deterministic).
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
     pub fn add_ws<P: AsRef<Path>>(mut self, name: &str, p: P) -> Self {
         self.modules
             .insert(name.to_string(), ToImport::WS(p.as_ref().to_path_buf()));
         self
     }
 
+    /// Adds an instance of an already parsed module under `name`. The same
+    /// module can be added under several names, every instance gets its own
+    /// memory, tables and globals.
+    pub fn add_module(mut self, name: &str, module: &Module) -> Self {
+        self.modules
+            .insert(name.to_string(), ToImport::Module(module.clone()));
+        self
+    }
+
     pub fn add_io(mut self, name: &str, io: IO) -> Self {
         self.modules.insert(name.to_string(), ToImport::IO(io));
         self
@@ -52,34 +176,17 @@ impl RuntimeBuilder {
 
     pub fn build(mut self) -> Result<Runtime, RuntimeError> {
         let mut non_ordered = HashMap::new();
-        self.modules
-            .insert("_$_main_$_".to_string(), ToImport::WS(self.path.clone()));
+        if let Some(path) = self.path.take() {
+            self.modules
+                .insert(MAIN_MODULE.to_string(), ToImport::WS(path));
+        }
         for (k, v) in self.modules {
             non_ordered.insert(
                 k,
                 match v {
                     ToImport::IO(io) => Intermediate::IO(io),
-                    ToImport::WS(path) => {
-                        let mut buf = Vec::new();
-
-                        let mut f = File::open(&path).expect("Failed to open file");
-                        f.read_to_end(&mut buf).expect("Failed to read file");
-
-                        let mut cursor = Cursor::new(&buf[..]);
-                        let mut stack = Vec::new();
-                        let module = match Module::parse(&mut cursor, &mut stack) {
-                            Ok(o) => o,
-                            Err(e) => {
-                                stack.reverse();
-                                return Err(RuntimeError::ParseError(format!(
-                                    "File: {:?}\n{e:?}, bin pos: {}, stack: {stack:#?}",
-                                    path,
-                                    cursor.position()
-                                )));
-                            }
-                        };
-                        Intermediate::WS(module)
-                    }
+                    ToImport::WS(path) => Intermediate::WS(Module::from_file(path)?),
+                    ToImport::Module(module) => Intermediate::WS(module),
                 },
             );
         }
@@ -100,7 +207,7 @@ impl RuntimeBuilder {
                 deps.remove(k);
             }
             if deps == old {
-                return Err(RuntimeError::UnknownImport(file!(), line!(), column!()));
+                return Err(missing_dependency(&non_ordered, &deps));
                 // panic!(
                 //     "missing dependency or cycle:\n\tfixed: {ordered:?}\n\tdependencies left: {deps:?}"
                 // )
@@ -112,44 +219,143 @@ impl RuntimeBuilder {
         // println!("get_dependencies: {deps:?}");
         // do a topological sort here
 
+        let instances = non_ordered
+            .values()
+            .filter(|m| matches!(m, Intermediate::WS(_)))
+            .count();
+        if instances > self.limits.max_instances {
+            return Err(LinkError::ResourceLimitExceeded("instances").into());
+        }
+
         let mut modules = HashMap::new();
-        for k in ordered {
+        for k in ordered.clone() {
             let r = match non_ordered.remove(&k) {
                 Some(Intermediate::IO(io)) => Import::IO(io),
-                Some(Intermediate::WS(module)) => Import::WS(Model::try_from((&modules, module))?),
-                _ => panic!(),
+                Some(Intermediate::WS(module)) => {
+                    let model = Model::try_from((&modules, module))?;
+                    check_limits(&model, &self.limits)?;
+                    Import::WS(model)
+                }
+                None => return Err(NoModule(k).at(file!(), line!(), column!())),
             };
             modules.insert(k, r);
         }
 
-        Runtime::new(modules)
+        let mut runtime = Runtime::new(modules)?;
+        runtime.fuel = self.fuel;
+        runtime.host_call_fuel = self.host_call_fuel;
+        runtime.limits = self.limits;
+        runtime.data = self.data;
+        runtime.deterministic = self.deterministic;
+        for name in ordered {
+            if let Some(Import::WS(_)) = runtime.modules.get(&name) {
+                runtime.start(&name)?;
+            }
+        }
+        Ok(runtime)
     }
 }
 
 impl Runtime {
+    /// A runtime around the module at `path`, which [`Runtime::invoke`] calls
+    /// into and whose `main` or `_start` the first step runs.
     pub fn build<P: AsRef<Path>>(path: P) -> RuntimeBuilder {
         RuntimeBuilder {
-            path: path.as_ref().to_path_buf(),
+            path: Some(path.as_ref().to_path_buf()),
+            ..Self::builder()
+        }
+    }
+
+    /// A runtime without a main module, holding just the instances added to
+    /// the builder and those [`Runtime::instantiate`] adds later.
+    pub fn builder() -> RuntimeBuilder {
+        RuntimeBuilder {
+            path: None,
             modules: HashMap::new(),
+            fuel: None,
+            host_call_fuel: 0,
+            limits: ResourceLimiter::default(),
+            data: None,
+            deterministic: false,
         }
     }
 
-    fn new(modules: HashMap<String, Import>) -> Result<Self, RuntimeError> {
-        let stack = if let Some(ExportDesc::Func(FuncIdx(main_id))) = match &modules["_$_main_$_"] {
-            Import::WS(module) => module,
-            Import::IO(_) => unreachable!(),
+    /// Links another instance of `module` into the runtime under `name`,
+    /// resolving its imports against the instances already there.
+    #[allow(unused)]
+    pub fn instantiate(&mut self, name: &str, module: &Module) -> Result<(), RuntimeError> {
+        if self.modules.contains_key(name) {
+            return Err(LinkError::DuplicateInstance(name.to_string()).into());
         }
-        .exports
-        .iter()
-        .find(|s| matches!(&**s.0, "main" | "_start"))
-        .map(|f| f.1)
-        {
+        let instances = self
+            .modules
+            .values()
+            .filter(|m| matches!(m, Import::WS(_)))
+            .count();
+        if instances >= self.limits.max_instances {
+            return Err(LinkError::ResourceLimitExceeded("instances").into());
+        }
+        let model = Model::try_from((&self.modules, module.clone()))?;
+        check_limits(&model, &self.limits)?;
+        self.modules.insert(name.to_string(), Import::WS(model));
+        // an instance whose start function traps never becomes available,
+        // though what it wrote to shared memories and tables stays
+        if let Err(e) = self.start(name) {
+            self.modules.remove(name);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Makes the instance `name` importable as `alias` as well, the way the
+    /// spec tests `register` a module. Both names share one instance.
+    #[allow(unused)]
+    pub fn register(&mut self, alias: &str, name: &str) -> Result<(), RuntimeError> {
+        if self.modules.contains_key(alias) {
+            return Err(LinkError::DuplicateInstance(alias.to_string()).into());
+        }
+        let Some(Import::WS(model)) = self.modules.get(name) else {
+            return Err(NoModule(name.to_string()).at(file!(), line!(), column!()));
+        };
+        let model = model.clone();
+        self.modules.insert(alias.to_string(), Import::WS(model));
+        Ok(())
+    }
+
+    fn new(modules: HashMap<String, Import>) -> Result<Self, RuntimeError> {
+        let main = match modules.get(MAIN_MODULE) {
+            Some(Import::WS(main)) => Some(main),
+            Some(Import::IO(_)) => {
+                return Err(NoModule(MAIN_MODULE.to_string()).at(file!(), line!(), column!()))
+            }
+            None => None,
+        };
+        let entry = main.and_then(|main| {
+            main.exports
+                .iter()
+                .find(|s| matches!(&**s.0, "main" | "_start"))
+                .map(|f| (main, f.1))
+        });
+        let stack = if let Some((main, ExportDesc::Func(FuncIdx(main_id)))) = entry {
+            // the entry point gets no arguments, so a C style `main(argc, argv)`
+            // sees zeroes just like its declared locals
+            let mut locals = HashMap::new();
+            if let Some(f) = main.functions.get(*main_id as usize) {
+                if let Function::WS { ty, .. } = f.as_ref() {
+                    for (i, t) in ty.input.types.iter().enumerate() {
+                        if let Some(v) = Value::zero(t) {
+                            locals.insert(i as u32, v);
+                        }
+                    }
+                }
+                f.zero_locals(&mut locals);
+            }
             vec![Frame {
                 func_id: FuncId::Id(*main_id),
                 pc: 0,
-                module: "_$_main_$_".to_string(),
+                module: MAIN_MODULE.to_string(),
                 stack: Vec::new(),
-                locals: HashMap::new(),
+                locals,
                 // labels: HashMap::new(),
                 depth_stack: Vec::new(),
             }]
@@ -157,6 +363,16 @@ impl Runtime {
             Vec::new()
         };
 
-        Ok(Self { modules, stack })
+        Ok(Self {
+            modules,
+            stack,
+            fuel: None,
+            host_call_fuel: 0,
+            trap_backtrace: None,
+            observers: Vec::new(),
+            limits: ResourceLimiter::default(),
+            data: None,
+            deterministic: false,
+        })
     }
 }
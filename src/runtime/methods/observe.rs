@@ -0,0 +1,238 @@
+use super::super::{
+    clean_model::Function,
+    error::RuntimeError,
+    observer::{Observer, Site},
+    FuncId, Runtime, Value,
+};
+use crate::parser::{Instr, MemArg};
+use std::any::Any;
+
+/// What a load or store touches: whether it writes, how many bytes, and its
+/// static offset.
+fn memory_access(instr: &Instr) -> Option<(bool, u32, &MemArg)> {
+    use Instr::*;
+    Some(match instr {
+        x28_i32_load(m) | x2a_f32_load(m) | x34_i64_load32_s(m) | x35_i64_load32_u(m) => {
+            (false, 4, m)
+        }
+        x29_i64_load(m) | x2b_f64_load(m) => (false, 8, m),
+        x2c_i32_load8_s(m) | x2d_i32_load8_u(m) | x30_i64_load8_s(m) | x31_i64_load8_u(m) => {
+            (false, 1, m)
+        }
+        x2e_i32_load16_s(m) | x2f_i32_load16_u(m) | x32_i64_load16_s(m) | x33_i64_load16_u(m) => {
+            (false, 2, m)
+        }
+        x36_i32_store(m) | x38_f32_store(m) | x3e_i64_store32(m) => (true, 4, m),
+        x37_i64_store(m) | x39_f64_store(m) => (true, 8, m),
+        x3a_i32_store8(m) | x3c_i64_store8(m) => (true, 1, m),
+        x3b_i32_store16(m) | x3d_i64_store16(m) => (true, 2, m),
+        _ => return None,
+    })
+}
+
+fn address(base: Option<&Value>, memarg: &MemArg) -> u64 {
+    match base {
+        Some(Value::I32(base)) => *base as u32 as u64 + memarg.offset as u64,
+        _ => memarg.offset as u64,
+    }
+}
+
+impl Runtime {
+    /// Runs one instruction, or one call of a host function, telling the
+    /// observers installed with [`Runtime::observe`] about it.
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        if self.observers.is_empty() {
+            return self.execute();
+        }
+        let mut observers = std::mem::take(&mut self.observers);
+        let result = self.observed_step(&mut observers);
+        self.observers = observers;
+        result
+    }
+
+    /// Has `observer` told about every step from now on, after those
+    /// installed before it, starting with a call for each frame already on
+    /// the stack.
+    #[allow(unused)]
+    pub fn observe(&mut self, mut observer: impl Observer) {
+        for frame in &self.stack {
+            let FuncId::Id(func) = frame.func_id else {
+                continue;
+            };
+            let site = Site {
+                instance: &frame.module,
+                func,
+                pc: frame.pc,
+            };
+            observer.on_call(self, site);
+        }
+        self.observers.push(Box::new(observer));
+    }
+
+    /// The first observer of type `T`.
+    #[allow(unused)]
+    pub fn observer<T: Observer>(&self) -> Option<&T> {
+        self.observers
+            .iter()
+            .find_map(|o| (&**o as &dyn Any).downcast_ref())
+    }
+
+    #[allow(unused)]
+    pub fn observer_mut<T: Observer>(&mut self) -> Option<&mut T> {
+        self.observers
+            .iter_mut()
+            .find_map(|o| (&mut **o as &mut dyn Any).downcast_mut())
+    }
+
+    /// Removes every observer, returning them.
+    #[allow(unused)]
+    pub fn take_observers(&mut self) -> Vec<Box<dyn Observer>> {
+        std::mem::take(&mut self.observers)
+    }
+
+    /// What a step that popped a frame of a function with `outputs`
+    /// results returned: the top of the caller's stack, or what went back
+    /// to the embedder.
+    fn returned<'r>(&'r self, result: &'r Result<(), RuntimeError>, outputs: usize) -> &'r [Value] {
+        match (result, self.stack.last()) {
+            (Err(RuntimeError::ReturnedToNoFrame(results)), _) => results,
+            (_, Some(caller)) => &caller.stack[caller.stack.len().saturating_sub(outputs)..],
+            (_, None) => &[],
+        }
+    }
+
+    /// Tells `observers` about the call of the frame on top of the stack.
+    fn called(&self, observers: &mut [Box<dyn Observer>]) {
+        let Some(callee) = self.stack.last() else {
+            return;
+        };
+        let FuncId::Id(func) = callee.func_id else {
+            return;
+        };
+        let site = Site {
+            instance: &callee.module,
+            func,
+            pc: callee.pc,
+        };
+        for o in observers.iter_mut() {
+            o.on_call(self, site);
+        }
+    }
+
+    /// Tells the observers about a frame the embedder pushed, which they
+    /// see return like any other.
+    pub(super) fn entered(&mut self) {
+        let mut observers = std::mem::take(&mut self.observers);
+        self.called(&mut observers);
+        self.observers = observers;
+    }
+
+    fn observed_step(&mut self, observers: &mut [Box<dyn Observer>]) -> Result<(), RuntimeError> {
+        let Some(frame) = self.stack.last() else {
+            return self.execute();
+        };
+        let FuncId::Id(func) = frame.func_id else {
+            return self.execute();
+        };
+        let instance = frame.module.clone();
+        let site = Site {
+            instance: &instance,
+            func,
+            pc: frame.pc,
+        };
+        let depth = self.stack.len();
+        // taken before the step, which can pop the frame
+        let function = self.instance(&instance).ok().and_then(|i| i.function(func));
+        let (instr, inputs, outputs) = match function {
+            Some(Function::WS { ty, code, .. }) => {
+                let instr = code.get(frame.pc).map(|instr| match instr {
+                    Instr::comment(_, instr) => (**instr).clone(),
+                    instr => instr.clone(),
+                });
+                (instr, ty.input.types.len(), ty.output.types.len())
+            }
+            Some(Function::IO { ty, .. }) => (None, ty.input.types.len(), ty.output.types.len()),
+            None => return self.execute(),
+        };
+
+        if let Some(Function::IO { .. }) = function {
+            let args: Vec<_> = (0..inputs as u32)
+                .filter_map(|i| frame.locals.get(&i).copied())
+                .collect();
+            for o in observers.iter_mut() {
+                o.before_host_call(self, site, &args);
+            }
+            let result = self.execute();
+            let results = match &result {
+                Ok(()) | Err(RuntimeError::ReturnedToNoFrame(_)) => {
+                    Ok(self.returned(&result, outputs))
+                }
+                Err(e) => Err(e),
+            };
+            for o in observers.iter_mut() {
+                o.after_host_call(self, site, &args, results);
+            }
+            if let Ok(results) = results {
+                for o in observers.iter_mut() {
+                    o.on_return(self, site, results);
+                }
+            }
+            return result;
+        }
+
+        // operands the instruction is about to pop
+        let top = frame.stack.last().copied();
+        let below = frame.stack.len().checked_sub(2).map(|i| frame.stack[i]);
+        if let Some(instr) = &instr {
+            for o in observers.iter_mut() {
+                o.before_instruction(self, site, instr);
+            }
+        }
+        let result = self.execute();
+        if result.is_err() && !matches!(result, Err(RuntimeError::ReturnedToNoFrame(_))) {
+            return result;
+        }
+
+        if let Some(instr) = &instr {
+            match (memory_access(instr), instr) {
+                (Some((false, size, memarg)), _) => {
+                    let address = address(top.as_ref(), memarg);
+                    if let Some(&value) = self.stack.last().and_then(|f| f.stack.last()) {
+                        for o in observers.iter_mut() {
+                            o.on_memory_read(site, address, size, value);
+                        }
+                    }
+                }
+                (Some((true, size, memarg)), _) => {
+                    let address = address(below.as_ref(), memarg);
+                    if let Some(value) = top {
+                        for o in observers.iter_mut() {
+                            o.on_memory_write(site, address, size, value);
+                        }
+                    }
+                }
+                (None, Instr::x24_global_set(index)) => {
+                    if let Some(value) = top {
+                        for o in observers.iter_mut() {
+                            o.on_global_set(site, index.0, value);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            for o in observers.iter_mut() {
+                o.after_instruction(self, site, instr);
+            }
+        }
+
+        if self.stack.len() > depth {
+            self.called(observers);
+        } else if self.stack.len() < depth {
+            let results = self.returned(&result, outputs);
+            for o in observers.iter_mut() {
+                o.on_return(self, site, results);
+            }
+        }
+        result
+    }
+}
@@ -1,11 +1,14 @@
-use super::super::{
-    clean_model::Function,
-    error::{RuntimeError, RuntimeError::*},
-    DepthValue, Frame, Runtime, Value,
+use super::{
+    super::{
+        clean_model::Function,
+        error::{InternalErrorKind::*, RuntimeError, TrapCode::*},
+        Caller, DepthValue, Frame, Runtime, Value,
+    },
+    fuel::burn,
 };
 use crate::{
     parser::{
-        BlockType, DataIdx, ElemIdx, Expr, FuncIdx, GlobalIdX, Instr::*, LabelIdX, LocalIdX,
+        BlockType, DataIdx, ElemIdx, Expr, FuncIdx, GlobalIdX, Instr, Instr::*, LabelIdX, LocalIdX,
         RefTyp, TableIdX, TypeIdX, BT,
     },
     runtime::{clean_model::Table, FloatExp, FuncId},
@@ -29,42 +32,42 @@ macro_rules! gen_macros {
             (i32, $index:expr) => {{
                 let val = match unwrap!(f.locals.get($index), MissingLocal) {
                     Value::I32(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "i32", x.as_str(), b, c)),
+                    x => throw!(WrongType("i32", x.as_str())),
                 };
                 val
             }};
             (i64, $index:expr) => {{
                 let val = match unwrap!(f.locals.get($index), MissingLocal) {
                     Value::I64(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "i64", x.as_str(), b, c)),
+                    x => throw!(WrongType("i64", x.as_str())),
                 };
                 val
             }};
             (u32, $index:expr) => {{
                 let val = match unwrap!(f.locals.get($index), MissingLocal) {
                     Value::I32(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "u32", x.as_str(), b, c)),
+                    x => throw!(WrongType("u32", x.as_str())),
                 };
                 unsafe { std::mem::transmute::<i32, u32>(val) }
             }};
             (u64, $index:expr) => {{
                 let val = match unwrap!(f.locals.get($index), MissingLocal) {
                     Value::I64(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "u64", x.as_str(), b, c)),
+                    x => throw!(WrongType("u64", x.as_str())),
                 };
                 unsafe { std::mem::transmute::<i64, u64>(val) }
             }};
             (f32, $index:expr) => {{
                 let val = match unwrap!(f.locals.get($index), MissingLocal) {
                     Value::F32(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "f32", x.as_str(), b, c)),
+                    x => throw!(WrongType("f32", x.as_str())),
                 };
                 val
             }};
             (f64, $index:expr) => {{
                 let val = match unwrap!(f.locals.get($index), MissingLocal) {
                     Value::F64(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "f64", x.as_str(), b, c)),
+                    x => throw!(WrongType("f64", x.as_str())),
                 };
                 val
             }};
@@ -80,42 +83,42 @@ macro_rules! gen_macros {
             (i32) => {{
                 let val = match unwrap!(f.stack.pop(), EmptyStack) {
                     Value::I32(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "i32", x.as_str(), b, c)),
+                    x => throw!(WrongType("i32", x.as_str())),
                 };
                 val
             }};
             (i64) => {{
                 let val = match unwrap!(f.stack.pop(), EmptyStack) {
                     Value::I64(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "i64", x.as_str(), b, c)),
+                    x => throw!(WrongType("i64", x.as_str())),
                 };
                 val
             }};
             (u32) => {{
                 let val = match unwrap!(f.stack.pop(), EmptyStack) {
                     Value::I32(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "u32", x.as_str(), b, c)),
+                    x => throw!(WrongType("u32", x.as_str())),
                 };
                 unsafe { std::mem::transmute::<i32, u32>(val) }
             }};
             (u64) => {{
                 let val = match unwrap!(f.stack.pop(), EmptyStack) {
                     Value::I64(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "u64", x.as_str(), b, c)),
+                    x => throw!(WrongType("u64", x.as_str())),
                 };
                 unsafe { std::mem::transmute::<i64, u64>(val) }
             }};
             (f32) => {{
                 let val = match unwrap!(f.stack.pop(), EmptyStack) {
                     Value::F32(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "f32", x.as_str(), b, c)),
+                    x => throw!(WrongType("f32", x.as_str())),
                 };
                 val
             }};
             (f64) => {{
                 let val = match unwrap!(f.stack.pop(), EmptyStack) {
                     Value::F64(val) => val,
-                    x => throw!(|a, b, c| WrongType(a, "f64", x.as_str(), b, c)),
+                    x => throw!(WrongType("f64", x.as_str())),
                 };
                 val
             }};
@@ -209,34 +212,66 @@ macro_rules! gen_macros {
     };
 }
 
+/// Whether `instr` computes a float, rather than moving one around bit for
+/// bit like `abs`, `neg`, `copysign`, loads and reinterpretations do. The NaN
+/// these give is up to the host, see [`RuntimeBuilder::deterministic`].
+///
+/// [`RuntimeBuilder::deterministic`]: super::new::RuntimeBuilder::deterministic
+fn computes_float(instr: &Instr) -> bool {
+    matches!(
+        instr,
+        x8d_f32_ceil
+            | x8e_f32_floor
+            | x8f_f32_trunc
+            | x90_f32_nearest
+            | x91_f32_sqrt
+            | x92_f32_add
+            | x93_f32_sub
+            | x94_f32_mul
+            | x95_f32_div
+            | x96_f32_min
+            | x97_f32_max
+            | x9b_f64_ceil
+            | x9c_f64_floor
+            | x9d_f64_trunc
+            | x9e_f64_nearest
+            | x9f_f64_sqrt
+            | xa0_f64_add
+            | xa1_f64_sub
+            | xa2_f64_mul
+            | xa3_f64_div
+            | xa4_f64_min
+            | xa5_f64_max
+            | xb6_f32_demote_f64
+            | xbb_f64_promote_f32
+    )
+}
+
 impl Runtime {
-    pub fn step(&mut self) -> Result<(), RuntimeError> {
-        if self.stack.len() > u16::MAX as usize {
-            return Err(StackExhaustion(self.stack.len(), u16::MAX as usize));
+    /// [`Runtime::step`] without the observers.
+    pub(super) fn execute(&mut self) -> Result<(), RuntimeError> {
+        if self.stack.len() > self.limits.max_call_depth {
+            return Err(StackExhaustion.into());
         }
 
-        // print!("{} ", self.stack.len());
         macro_rules! unwrap {
             ($expr:expr, $err:expr) => {
-                $expr.ok_or($err(file!(), line!(), column!()))?
+                $expr.ok_or_else(|| $err.at(file!(), line!(), column!()))?
             };
         }
         gen_macros!(unwrap!(self.stack.last_mut(), NoFrame));
-        // println!("{:?}", get!(stack));
-        // println!("{:?}", get!(depth_stack));
-        // println!();
-
+        if get!(stack).len() > self.limits.max_stack_size {
+            throw!(StackExhaustion)
+        }
         let (code, ty, module) = {
             let func_id = get!(func_id).clone();
             let (module, function) = match func_id {
                 FuncId::Id(id) => (
                     unsafe {
-                        unwrap!(self.modules.get(get!(module)), |a, b, c| NoModule(
-                            get!(module).clone(),
-                            a,
-                            b,
-                            c
-                        ))
+                        unwrap!(
+                            self.modules.get(get!(module)),
+                            NoModule(get!(module).clone())
+                        )
                         .as_ws()
                     },
                     id,
@@ -247,33 +282,38 @@ impl Runtime {
             match ptr.as_ref() {
                 Function::WS { ty, code, .. } => (code, ty, module),
                 Function::IO { func, .. } => {
-                    let Frame { locals, .. } = unwrap!(self.stack.pop(), NoFrame);
-                    let res = func(&locals, &mut module.memory.write())?;
-                    let frame = unwrap!(self.stack.last_mut(), NoFrame);
-                    for v in res {
-                        frame.stack.push(v);
+                    let func = func.clone();
+                    burn(&mut self.fuel, 1 + self.host_call_fuel)?;
+                    let Frame { locals, module, .. } = unwrap!(self.stack.pop(), NoFrame);
+                    let mut res = func.call(&mut Caller::new(self, module), &locals)?;
+                    match self.stack.last_mut() {
+                        Some(frame) => frame.stack.append(&mut res),
+                        None => return Err(RuntimeError::ReturnedToNoFrame(res)),
                     }
                     return Ok(());
                 }
             }
         };
 
+        burn(&mut self.fuel, 1)?;
+
         // Execute
         if *get!(pc) >= code.len() {
             let mut frame = unwrap!(self.stack.pop(), NoFrame);
-            let last = unwrap!(self.stack.last_mut(), NoFrame);
             let mut col = Vec::new();
             for _ in 0..ty.output.types.len() {
                 col.push(unwrap!(frame.stack.pop(), EmptyStack));
             }
             col.reverse();
-            last.stack.append(&mut col);
+            match self.stack.last_mut() {
+                Some(last) => last.stack.append(&mut col),
+                None => return Err(RuntimeError::ReturnedToNoFrame(col)),
+            }
             return Ok(());
         }
         let mut instr = &code[*get!(pc)];
         instr = if let comment(_, r) = instr { r } else { instr };
         let instr = instr;
-        // println!("{instr:?}");
         set!(pc) += 1;
         match instr {
             x00_unreachable => {
@@ -339,9 +379,7 @@ impl Runtime {
                         res.reverse();
                         match self.stack.last_mut() {
                             Some(s) => s.stack.append(&mut res),
-                            None => {
-                                throw!(|a, b, c| ReturnedToNoFrame(res, a, b, c))
-                            }
+                            None => return Err(RuntimeError::ReturnedToNoFrame(res)),
                         }
                     } else {
                         for _ in 0..=*label {
@@ -396,7 +434,7 @@ impl Runtime {
                     res.reverse();
                     match self.stack.last_mut() {
                         Some(s) => s.stack.append(&mut res),
-                        None => throw!(|a, b, c| ReturnedToNoFrame(res, a, b, c)),
+                        None => return Err(RuntimeError::ReturnedToNoFrame(res)),
                     }
                 } else {
                     let mut last = None;
@@ -433,7 +471,6 @@ impl Runtime {
                 }
             }
             x0f_return => {
-                println!("return!");
                 let mut last_f = unwrap!(self.stack.pop(), NoFrame);
                 let func_id = match &last_f.func_id {
                     FuncId::Id(id) => id,
@@ -451,9 +488,7 @@ impl Runtime {
                 res.reverse();
                 match self.stack.last_mut() {
                     Some(s) => s.stack.append(&mut res),
-                    None => {
-                        throw!(|a, b, c| ReturnedToNoFrame(res, a, b, c))
-                    }
+                    None => return Err(RuntimeError::ReturnedToNoFrame(res)),
                 }
             }
             x10_call(FuncIdx(id)) => {
@@ -462,12 +497,16 @@ impl Runtime {
                     Function::WS { ty, .. } => ty,
                     Function::IO { ty, .. } => ty,
                 };
-                let (ty, module, func_id) = (ty, get!(module).clone(), FuncId::Id(*id));
+                let (module, func_id) = match module.foreign.get(id) {
+                    Some((home, id)) => (home.clone(), FuncId::Id(*id)),
+                    None => (get!(module).clone(), FuncId::Id(*id)),
+                };
 
                 let mut locals = HashMap::new();
                 for (i, _) in ty.input.types.iter().enumerate().rev() {
                     locals.insert(i as u32, pop!());
                 }
+                fun.zero_locals(&mut locals);
 
                 self.stack.push(Frame {
                     func_id,
@@ -496,8 +535,18 @@ impl Runtime {
                 //     "Call info ({}): \n\tinputs: {locals:?}\n\tfunction_index: {function_index}",
                 //     f.func_id
                 // );
-                let Table { table, .. } = &*table;
-                let FuncIdx(id) = *unwrap!(table.get(&(function_index as u32)), UndefinedElement);
+                let Table {
+                    table,
+                    table_length,
+                    ..
+                } = &*table;
+                let FuncIdx(id) = match table.get(&(function_index as u32)) {
+                    Some(f) => *f,
+                    None if (function_index as u32 as usize) < table_length.0 => {
+                        throw!(UninitializedElement)
+                    }
+                    None => throw!(UndefinedElement),
+                };
                 if id == u32::MAX {
                     throw!(UninitializedElement)
                 }
@@ -510,9 +559,13 @@ impl Runtime {
                     if ty.as_ref() != ty2 {
                         throw!(IndirectCallTypeMismatch)
                     }
+                    func.zero_locals(&mut locals);
                 }
 
-                let module = get!(module).clone();
+                let (module, id) = match module.foreign.get(&id) {
+                    Some((home, id)) => (home.clone(), *id),
+                    None => (get!(module).clone(), id),
+                };
                 self.stack.push(Frame {
                     func_id: FuncId::Id(id),
                     pc: 0,
@@ -686,10 +739,16 @@ impl Runtime {
                 let addr = pop!(u32);
                 module.memory.write().set(addr as usize, *mem, v as i32)?;
             }
-            x3f_memory_size(_) => push!(i32, module.memory.read().pages().1 as i32),
+            x3f_memory_size(_) => push!(i32, module.memory.read().pages().0 as i32),
             x40_memory_grow => {
-                let amount = pop!(i32);
-                push!(i32, module.memory.write().grow(amount as usize))
+                let amount = pop!(i32) as u32 as usize;
+                let mut memory = module.memory.write();
+                let limit = self.limits.max_memory_pages.min(1 << 16);
+                if memory.pages().0 + amount > limit {
+                    push!(i32, -1)
+                } else {
+                    push!(i32, memory.grow(amount))
+                }
             }
             x41_i32_const(val) => push!(i32, *val),
             x42_i64_const(val) => push!(i64, *val),
@@ -1573,12 +1632,12 @@ impl Runtime {
                 }
             }
             xfc_8_memory_init(DataIdx(i), _) => {
-                let amount = pop!(i32) as usize;
-                let source = pop!(i32) as usize;
-                let destination = pop!(i32) as usize;
+                let amount = pop!(i32) as u32 as usize;
+                let source = pop!(i32) as u32 as usize;
+                let destination = pop!(i32) as u32 as usize;
                 let val = unwrap!(module.datas.get(*i as usize), MissingData).read();
                 if source + amount > val.len() {
-                    throw!(DataInitOutOfRange)
+                    throw!(OutOfBoundsMemoryAccess)
                 }
                 module
                     .memory
@@ -1591,16 +1650,16 @@ impl Runtime {
                 }
             }
             xfc_10_memory_copy(_, _) => {
-                let amount = pop!(i32) as usize;
-                let source = pop!(i32) as usize;
-                let destination = pop!(i32) as usize;
+                let amount = pop!(i32) as u32 as usize;
+                let source = pop!(i32) as u32 as usize;
+                let destination = pop!(i32) as u32 as usize;
                 // println!("amount: {amount}, source: {source}, dest: {destination}");
                 module.memory.write().copy(source, amount, destination)?;
             }
             xfc_11_memory_fill(_) => {
-                let amount = pop!(i32) as usize;
+                let amount = pop!(i32) as u32 as usize;
                 let val = pop!(i32) as u8;
-                let ptr = pop!(i32) as usize;
+                let ptr = pop!(i32) as u32 as usize;
                 module.memory.write().bulk_write(ptr, amount, val)?;
             }
             xfc_12_table_init(ElemIdx(e), TableIdX(t)) => {
@@ -1615,10 +1674,9 @@ impl Runtime {
                     ..
                 } = &mut *table;
 
-                let check_1 = source + amount > elems.read().instrs.len() as u32;
-                // let check_2 = destination < table_length.0 as u32;
-                let check_3 = destination + amount > table_length.1 as u32;
-                if check_1 /*|| dbg!(check_2)*/ || check_3 {
+                let check_1 = source as u64 + amount as u64 > elems.read().instrs.len() as u64;
+                let check_3 = destination as u64 + amount as u64 > table_length.0 as u64;
+                if check_1 || check_3 {
                     throw!(OutOfBoundsTableAccess)
                 }
 
@@ -1635,7 +1693,10 @@ impl Runtime {
                 let Some(r) = module.elems.get(*i as usize) else {
                     unreachable!()
                 };
-                *r.write() = Expr { instrs: Vec::new() };
+                *r.write() = Expr {
+                    instrs: Vec::new(),
+                    offsets: Vec::new(),
+                };
             }
             xfc_14_table_copy(TableIdX(i_a), TableIdX(i_b)) => {
                 let amount = pop!(i32) as u32;
@@ -1647,7 +1708,10 @@ impl Runtime {
                 let a_len = a_source.table_length;
                 let a = &mut a_source.table;
 
-                let check_1 = source + amount > a_len.0 as u32;
+                let check_1 = source as u64 + amount as u64 > a_len.0 as u64;
+                if check_1 {
+                    throw!(OutOfBoundsTableAccess)
+                }
                 let mut clones = Vec::new();
                 for i in 0..amount {
                     let index = i + source;
@@ -1656,10 +1720,8 @@ impl Runtime {
                 }
                 drop(a_source);
                 let mut b = unwrap!(module.tables.get(*i_b as usize), MissingTableIndex).write();
-                let check_2 = destination > b.table.len() as u32;
-                let check_3 = destination + amount > b.table_length.1 as u32;
-
-                if check_1 || check_2 || check_3 {
+                let check_3 = destination as u64 + amount as u64 > b.table_length.0 as u64;
+                if check_3 {
                     throw!(OutOfBoundsTableAccess)
                 }
 
@@ -1668,6 +1730,31 @@ impl Runtime {
                     b.table.insert(index, v);
                 }
             }
+            xfc_15_table_grow(TableIdX(t)) => {
+                let amount = pop!(i32) as u32 as usize;
+                let init = match pop!() {
+                    Value::FuncRef(i) | Value::Externref(i) => FuncIdx(i),
+                    x => throw!(WrongType("ref", x.as_str())),
+                };
+                let mut table = unwrap!(module.tables.get(*t as usize), MissingTableIndex).write();
+                let (size, max) = table.table_length;
+                if size + amount > max.min(self.limits.max_table_elements) {
+                    push!(i32, -1)
+                } else {
+                    // null entries are simply left out
+                    if init.0 != u32::MAX {
+                        for i in size..size + amount {
+                            table.table.insert(i as u32, init);
+                        }
+                    }
+                    table.table_length.0 = size + amount;
+                    push!(i32, size as i32)
+                }
+            }
+            xfc_16_table_size(TableIdX(t)) => {
+                let table = unwrap!(module.tables.get(*t as usize), MissingTableIndex).read();
+                push!(i32, table.table_length.0 as i32)
+            }
             block_start(bt, be, vt) => {
                 // println!("block_start: {vt:?}");
                 let mut to_push = Vec::new();
@@ -1734,20 +1821,61 @@ impl Runtime {
                 if val != 0 {
                     set!(pc) = *jump_index;
                 }
-                // for (i, c) in code.iter().enumerate() {
-                //     println!("{i}:\t{c:?}");
-                // }
             }
             else_jump(jump_index) => {
-                // for (i, c) in code.iter().enumerate() {
-                //     println!("{i}:\t{c:?}");
-                // }
                 set!(pc) = *jump_index;
             }
             f => {
                 unimplemented!("instruction not supported : {f:?}")
             }
         };
+        if self.deterministic && computes_float(instr) {
+            let top = self.stack.last_mut().and_then(|f| f.stack.last_mut());
+            match top {
+                Some(Value::F32(x)) if x.is_nan() => *x = f32::NAN_CANONICAL,
+                Some(Value::F64(x)) if x.is_nan() => *x = f64::NAN_CANONICAL,
+                _ => {}
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::MAIN_MODULE, wast::text_module};
+
+    /// NaNs made in different ways: from nothing, from a negative square
+    /// root and from a NaN operand with a payload.
+    const NANS: &str = r#"(module
+        (func (export "f32_div") (result f32) (f32.div (f32.const 0) (f32.const 0)))
+        (func (export "f32_sqrt") (result f32) (f32.sqrt (f32.const -1)))
+        (func (export "f32_add") (result f32)
+            (f32.add (f32.reinterpret_i32 (i32.const 0xffa00001)) (f32.const 1)))
+        (func (export "f64_div") (result f64) (f64.div (f64.const 0) (f64.const 0)))
+        (func (export "f64_sqrt") (result f64) (f64.sqrt (f64.const -1)))
+        (func (export "f64_mul") (result f64)
+            (f64.mul (f64.reinterpret_i64 (i64.const 0xfff4000000000001)) (f64.const 2))))"#;
+
+    #[test]
+    fn deterministic_nans_are_canonical() {
+        let mut rt = Runtime::builder()
+            .add_module(MAIN_MODULE, &text_module(NANS))
+            .deterministic(true)
+            .build()
+            .expect("the runtime builds");
+        for name in ["f32_div", "f32_sqrt", "f32_add"] {
+            match rt.invoke(name, &[]).as_deref() {
+                Ok([Value::F32(x)]) => assert_eq!(x.to_bits(), 0x7fc0_0000, "{name}"),
+                _ => panic!("{name} returns an f32"),
+            }
+        }
+        for name in ["f64_div", "f64_sqrt", "f64_mul"] {
+            match rt.invoke(name, &[]).as_deref() {
+                Ok([Value::F64(x)]) => assert_eq!(x.to_bits(), 0x7ff8_0000_0000_0000, "{name}"),
+                _ => panic!("{name} returns an f64"),
+            }
+        }
+    }
+}
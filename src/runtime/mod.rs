@@ -5,14 +5,31 @@ pub mod clean_model;
 mod error;
 mod memory;
 use crate::parser::{BlockType, NumType, RefTyp, ValType, BT};
-pub use error::RuntimeError;
+pub use error::{
+    InternalError, InternalErrorKind, LinkError, RuntimeError, TrapCode, ValidationError,
+};
 mod float_exp;
 pub use float_exp::*;
 
+mod backtrace;
 mod import;
+mod instance;
+mod limits;
+pub mod literal;
 mod methods;
+mod observer;
+mod snapshot;
 mod typecheck;
+#[allow(unused)]
+pub use backtrace::{function_label, instance_name, Backtrace, BacktraceFrame};
 pub use import::*;
+#[allow(unused)]
+pub use instance::{ExportedGlobal, ExportedMemory, ExportedTable, Instance};
+pub use limits::ResourceLimiter;
+#[allow(unused)]
+pub use observer::{Observer, Site};
+#[allow(unused)]
+pub use snapshot::{SnapshotError, SNAPSHOT_VERSION};
 
 #[derive(Clone, Copy, PartialEq)]
 #[allow(unused)]
@@ -38,6 +55,19 @@ impl Value {
                 | (Value::FuncRef(_), ValType::Ref(RefTyp::FuncRef))
         )
     }
+    /// The value a declared local starts out with, `None` for types that
+    /// have no runtime representation.
+    pub fn zero(t: &ValType) -> Option<Self> {
+        Some(match t {
+            ValType::Num(NumType::I32) => Value::I32(0),
+            ValType::Num(NumType::I64) => Value::I64(0),
+            ValType::Num(NumType::F32) => Value::F32(0.0),
+            ValType::Num(NumType::F64) => Value::F64(0.0),
+            ValType::Ref(RefTyp::FuncRef) => Value::FuncRef(u32::MAX),
+            ValType::Ref(RefTyp::ExternRef) => Value::Externref(u32::MAX),
+            ValType::Poly | ValType::Vec128 => return None,
+        })
+    }
     pub fn as_str(&self) -> &'static str {
         match self {
             Value::I32(_) => "i32",
@@ -98,7 +128,24 @@ pub struct Frame {
     pub depth_stack: Vec<DepthValue>,
 }
 
+/// The instance name [`Runtime::build`] gives its module.
+pub const MAIN_MODULE: &str = "_$_main_$_";
+
 pub struct Runtime {
     pub(super) modules: HashMap<String, Import>,
     pub stack: Vec<Frame>,
+    /// Fuel left, every instruction costs one. `None` runs unmetered.
+    pub(super) fuel: Option<u64>,
+    /// Extra fuel charged for every call into a host function.
+    pub(super) host_call_fuel: u64,
+    pub(super) limits: ResourceLimiter,
+    /// Whatever the embedder wants host functions to reach through
+    /// [`Caller::data`].
+    pub(super) data: Option<Box<dyn std::any::Any>>,
+    /// See [`Runtime::trap_backtrace`].
+    pub(super) trap_backtrace: Option<Backtrace>,
+    /// See [`Runtime::observe`].
+    pub(super) observers: Vec<Box<dyn Observer>>,
+    /// Whether NaN results are canonicalized, see `RuntimeBuilder::deterministic`.
+    pub(super) deterministic: bool,
 }
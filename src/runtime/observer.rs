@@ -0,0 +1,174 @@
+//! Hooks for embedders to watch a [`Runtime`] run: tracers, profilers,
+//! coverage. Installed with [`Runtime::observe`], they are told about
+//! everything [`Runtime::step`] does. Without any installed, a step costs no
+//! more than the check that there are none.
+
+use super::{Runtime, RuntimeError, Value};
+use crate::parser::Instr;
+use std::any::Any;
+
+/// Where an event happened: the instruction `pc` of the function `func` of
+/// `instance`.
+#[derive(Debug, Clone, Copy)]
+pub struct Site<'a> {
+    pub instance: &'a str,
+    pub func: u32,
+    pub pc: usize,
+}
+
+/// Gets told what a [`Runtime`] does, one step at a time. Every method does
+/// nothing unless overridden. Those given the runtime see it as it is at
+/// that point, operands still on the stack before an instruction, results
+/// pushed after it.
+#[allow(unused_variables)]
+pub trait Observer: Any {
+    /// `instr` is about to run.
+    fn before_instruction(&mut self, rt: &Runtime, site: Site, instr: &Instr) {}
+
+    /// `instr` ran without trapping.
+    fn after_instruction(&mut self, rt: &Runtime, site: Site, instr: &Instr) {}
+
+    /// A frame was pushed for the function at `site`. Frames already on the
+    /// stack when the observer is installed come through here too,
+    /// outermost first.
+    fn on_call(&mut self, rt: &Runtime, site: Site) {}
+
+    /// The frame of the function at `site` was popped, leaving `results`
+    /// to its caller.
+    fn on_return(&mut self, rt: &Runtime, site: Site, results: &[Value]) {}
+
+    /// A load read `size` bytes at `address` of the memory of
+    /// `site.instance`, which made `value`.
+    fn on_memory_read(&mut self, site: Site, address: u64, size: u32, value: Value) {}
+
+    /// A store wrote the low `size` bytes of `value` at `address` of the
+    /// memory of `site.instance`.
+    fn on_memory_write(&mut self, site: Site, address: u64, size: u32, value: Value) {}
+
+    /// `global.set` gave the global `index` of `site.instance` `value`.
+    fn on_global_set(&mut self, site: Site, index: u32, value: Value) {}
+
+    /// The host function at `site` is about to be called with `args`.
+    fn before_host_call(&mut self, rt: &Runtime, site: Site, args: &[Value]) {}
+
+    /// The host function at `site` returned, or failed, which includes
+    /// the program exiting through it.
+    fn after_host_call(
+        &mut self,
+        rt: &Runtime,
+        site: Site,
+        args: &[Value],
+        results: Result<&[Value], &RuntimeError>,
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{Import, MAIN_MODULE},
+        wast::text_module,
+    };
+
+    const CALLS: &str = r#"(module
+        (import "spectest" "print" (func $print))
+        (memory 1)
+        (global $g (mut i32) (i32.const 0))
+        (func $store (param i32) (result i32)
+            (i32.store (i32.const 8) (local.get 0))
+            (i32.load (i32.const 8)))
+        (func (export "run") (result i32)
+            (call $print)
+            (global.set $g (i32.const 5))
+            (call $store (i32.const 7))))"#;
+
+    /// Writes down every event, by the function and pc it happened at.
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Observer for Log {
+        fn before_instruction(&mut self, _: &Runtime, site: Site, _: &Instr) {
+            self.0.push(format!("before {}@{}", site.func, site.pc));
+        }
+        fn after_instruction(&mut self, _: &Runtime, site: Site, _: &Instr) {
+            self.0.push(format!("after {}@{}", site.func, site.pc));
+        }
+        fn on_call(&mut self, _: &Runtime, site: Site) {
+            self.0.push(format!("call {}", site.func));
+        }
+        fn on_return(&mut self, _: &Runtime, site: Site, results: &[Value]) {
+            self.0.push(format!("return {} {results:?}", site.func));
+        }
+        fn on_memory_read(&mut self, _: Site, address: u64, size: u32, value: Value) {
+            self.0.push(format!("read {address} {size} {value:?}"));
+        }
+        fn on_memory_write(&mut self, _: Site, address: u64, size: u32, value: Value) {
+            self.0.push(format!("write {address} {size} {value:?}"));
+        }
+        fn on_global_set(&mut self, _: Site, index: u32, value: Value) {
+            self.0.push(format!("global {index} {value:?}"));
+        }
+        fn before_host_call(&mut self, _: &Runtime, site: Site, args: &[Value]) {
+            self.0.push(format!("host {} {args:?}", site.func));
+        }
+        fn after_host_call(
+            &mut self,
+            _: &Runtime,
+            site: Site,
+            _: &[Value],
+            results: Result<&[Value], &RuntimeError>,
+        ) {
+            self.0
+                .push(format!("host {} done {:?}", site.func, results.ok()));
+        }
+    }
+
+    #[test]
+    fn observers_hear_of_every_step_in_order() {
+        let mut rt = Runtime::builder()
+            .add_io("spectest", Import::spectest())
+            .add_module(MAIN_MODULE, &text_module(CALLS))
+            .build()
+            .expect("the runtime builds");
+        rt.observe(Log::default());
+        let results = rt.invoke("run", &[]).expect("run returns");
+        assert!(results == [Value::I32(7)]);
+        let log = rt.observer::<Log>().expect("the log is installed");
+        let expected = [
+            // `run` itself, which the embedder called
+            "call 2",
+            "before 2@0",
+            "after 2@0",
+            "call 0",
+            "host 0 []",
+            "host 0 done Some([])",
+            "return 0 []",
+            "before 2@1",
+            "after 2@1",
+            "before 2@2",
+            "global 0 i32(5)",
+            "after 2@2",
+            "before 2@3",
+            "after 2@3",
+            "before 2@4",
+            "after 2@4",
+            "call 1",
+            "before 1@0",
+            "after 1@0",
+            "before 1@1",
+            "after 1@1",
+            "before 1@2",
+            "write 8 4 i32(7)",
+            "after 1@2",
+            "before 1@3",
+            "after 1@3",
+            "before 1@4",
+            "read 8 4 i32(7)",
+            "after 1@4",
+            "return 1 [i32(7)]",
+            "return 2 [i32(7)]",
+        ];
+        assert_eq!(log.0, expected);
+    }
+}
@@ -0,0 +1,790 @@
+//! Checkpoints of a [`Runtime`]: everything a running program changes, so
+//! that it can be saved and resumed later, or elsewhere, by a runtime built
+//! from the same modules.
+//!
+//! A snapshot holds, for every instance, its memory, globals, tables and
+//! which data and element segments were dropped, then the [`HostState`] of
+//! the host modules that have one, then the whole frame stack. The code
+//! itself is not part of it, and neither is the rest of what host functions
+//! keep, like the files WASI has open.
+//!
+//! The format is little endian throughout: the magic `WASPSNAP`, a `u32`
+//! version, then the instances, the host states and the frames, every list
+//! prefixed with its `u32` length.
+//!
+//! [`HostState`]: super::HostState
+
+use super::{
+    clean_model::Function, DepthValue, Frame, FuncId, HostState, Import, Runtime, Value, IO,
+};
+use crate::parser::{BlockType, FuncIdx, Mutable, NumType, RefTyp, ValType, BT};
+use std::{collections::HashMap, fmt::Display};
+
+const MAGIC: &[u8; 8] = b"WASPSNAP";
+/// Bumped on every change to the format, older snapshots are refused.
+pub const SNAPSHOT_VERSION: u32 = 2;
+const PAGE_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotError {
+    /// Where in the snapshot it went wrong, 0 for problems with the runtime
+    /// it was taken from or restored into.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.offset {
+            0 => write!(f, "{}", self.message),
+            offset => write!(f, "{} at snapshot+{offset:#x}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+type Result<T> = std::result::Result<T, SnapshotError>;
+
+fn mismatch(message: impl Into<String>) -> SnapshotError {
+    SnapshotError {
+        offset: 0,
+        message: message.into(),
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.out.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.out.extend(v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.out.extend(v.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn bytes(&mut self, v: &[u8]) {
+        self.len(v.len());
+        self.out.extend(v);
+    }
+
+    fn value(&mut self, v: &Value) {
+        let (tag, bits) = match *v {
+            Value::I32(v) => (0x7f, v as u32 as u64),
+            Value::I64(v) => (0x7e, v as u64),
+            Value::F32(v) => (0x7d, v.to_bits() as u64),
+            Value::F64(v) => (0x7c, v.to_bits()),
+            Value::FuncRef(v) => (0x70, v as u64),
+            Value::Externref(v) => (0x6f, v as u64),
+            Value::BlockLock => (0x40, 0),
+        };
+        self.u8(tag);
+        self.u64(bits);
+    }
+
+    fn val_type(&mut self, t: &ValType) {
+        self.u8(match t {
+            ValType::Num(NumType::I32) => 0x7f,
+            ValType::Num(NumType::I64) => 0x7e,
+            ValType::Num(NumType::F32) => 0x7d,
+            ValType::Num(NumType::F64) => 0x7c,
+            ValType::Vec128 => 0x7b,
+            ValType::Ref(RefTyp::FuncRef) => 0x70,
+            ValType::Ref(RefTyp::ExternRef) => 0x6f,
+            ValType::Poly => 0x00,
+        });
+    }
+
+    fn depth(&mut self, d: &DepthValue) {
+        self.u8(match d.bt {
+            BT::Block => 0,
+            BT::Loop => 1,
+        });
+        self.u64(d.pos as u64);
+        match &d.vt {
+            BlockType::Eps => self.u8(0),
+            BlockType::T(t) => {
+                self.u8(1);
+                self.val_type(t);
+            }
+            BlockType::TypIdx(i) => {
+                self.u8(2);
+                self.u64(*i as u64);
+            }
+        }
+    }
+}
+
+struct Reader<'s> {
+    data: &'s [u8],
+    at: usize,
+}
+
+impl<'s> Reader<'s> {
+    fn error(&self, message: impl Into<String>) -> SnapshotError {
+        SnapshotError {
+            offset: self.at,
+            message: message.into(),
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'s [u8]> {
+        let bytes = self
+            .data
+            .get(self.at..self.at.saturating_add(n))
+            .ok_or_else(|| self.error("unexpected end of snapshot"))?;
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&'s [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error("malformed UTF-8"))
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let tag = self.u8()?;
+        let bits = self.u64()?;
+        Ok(match tag {
+            0x7f => Value::I32(bits as u32 as i32),
+            0x7e => Value::I64(bits as i64),
+            0x7d => Value::F32(f32::from_bits(bits as u32)),
+            0x7c => Value::F64(f64::from_bits(bits)),
+            0x70 => Value::FuncRef(bits as u32),
+            0x6f => Value::Externref(bits as u32),
+            0x40 => Value::BlockLock,
+            tag => return Err(self.error(format!("unknown value tag {tag:#x}"))),
+        })
+    }
+
+    fn val_type(&mut self) -> Result<ValType> {
+        Ok(match self.u8()? {
+            0x7f => ValType::Num(NumType::I32),
+            0x7e => ValType::Num(NumType::I64),
+            0x7d => ValType::Num(NumType::F32),
+            0x7c => ValType::Num(NumType::F64),
+            0x7b => ValType::Vec128,
+            0x70 => ValType::Ref(RefTyp::FuncRef),
+            0x6f => ValType::Ref(RefTyp::ExternRef),
+            0x00 => ValType::Poly,
+            tag => return Err(self.error(format!("unknown value type {tag:#x}"))),
+        })
+    }
+
+    fn depth(&mut self) -> Result<DepthValue> {
+        let bt = match self.u8()? {
+            0 => BT::Block,
+            1 => BT::Loop,
+            tag => return Err(self.error(format!("unknown block kind {tag}"))),
+        };
+        let pos = self.u64()? as usize;
+        let vt = match self.u8()? {
+            0 => BlockType::Eps,
+            1 => BlockType::T(self.val_type()?),
+            2 => BlockType::TypIdx(self.u64()? as i64),
+            tag => return Err(self.error(format!("unknown block type tag {tag}"))),
+        };
+        Ok(DepthValue { bt, pos, vt })
+    }
+}
+
+/// Whether `a` and `b` are the same value, telling NaNs and zeros apart.
+fn same_bits(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::F32(a), Value::F32(b)) => a.to_bits() == b.to_bits(),
+        (Value::F64(a), Value::F64(b)) => a.to_bits() == b.to_bits(),
+        (a, b) => a == b,
+    }
+}
+
+impl Runtime {
+    /// The state of every instance and the frame stack, see the module docs.
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        let mut w = Writer::default();
+        w.out.extend(MAGIC);
+        w.u32(SNAPSHOT_VERSION);
+
+        let mut names: Vec<_> = self.instances().collect();
+        names.sort();
+        w.len(names.len());
+        for name in names {
+            let Some(Import::WS(model)) = self.modules.get(name) else {
+                continue;
+            };
+            w.bytes(name.as_bytes());
+            w.len(model.functions.len());
+
+            let memory = model.memory.read();
+            let (current, max) = memory.pages();
+            w.u64(current as u64);
+            w.u64(max as u64);
+            let mut pages: Vec<_> = memory.touched_pages().collect();
+            pages.sort_by_key(|(i, _)| *i);
+            w.len(pages.len());
+            for (index, data) in pages {
+                w.u32(index as u32);
+                w.out.extend(data);
+            }
+
+            w.len(model.globals.len());
+            for global in &model.globals {
+                w.value(&global.read().1);
+            }
+
+            w.len(model.tables.len());
+            for table in &model.tables {
+                let table = table.read();
+                w.u64(table.table_length.0 as u64);
+                let mut entries: Vec<_> = table.table.iter().collect();
+                entries.sort_by_key(|(i, _)| **i);
+                w.len(entries.len());
+                for (index, FuncIdx(func)) in entries {
+                    w.u32(*index);
+                    w.u32(*func);
+                }
+            }
+
+            // dropped segments are emptied, which is all there is to save
+            w.len(model.datas.len());
+            for data in &model.datas {
+                w.u8(data.read().is_empty() as u8);
+            }
+            w.len(model.elems.len());
+            for elem in &model.elems {
+                w.u8(elem.read().instrs.is_empty() as u8);
+            }
+        }
+
+        let mut hosts: Vec<_> = (self.modules.iter())
+            .filter_map(|(name, import)| match import {
+                Import::IO(IO {
+                    state: Some(state), ..
+                }) => Some((name, state.save())),
+                _ => None,
+            })
+            .collect();
+        hosts.sort();
+        w.len(hosts.len());
+        for (name, state) in hosts {
+            w.bytes(name.as_bytes());
+            w.bytes(&state);
+        }
+
+        w.len(self.stack.len());
+        for frame in &self.stack {
+            let FuncId::Id(func) = frame.func_id else {
+                return Err(mismatch("frames of foreign functions can't be saved"));
+            };
+            w.bytes(frame.module.as_bytes());
+            w.u32(func);
+            w.u64(frame.pc as u64);
+            w.len(frame.stack.len());
+            for v in &frame.stack {
+                w.value(v);
+            }
+            let mut locals: Vec<_> = frame.locals.iter().collect();
+            locals.sort_by_key(|(i, _)| **i);
+            w.len(locals.len());
+            for (index, v) in locals {
+                w.u32(*index);
+                w.value(v);
+            }
+            w.len(frame.depth_stack.len());
+            for d in &frame.depth_stack {
+                w.depth(d);
+            }
+        }
+        Ok(w.out)
+    }
+
+    /// Puts back the state saved by [`Runtime::snapshot`] in a runtime built
+    /// from the same modules, replacing its frames, so that stepping it
+    /// resumes the program where it was. Nothing is changed when the
+    /// snapshot does not fit the runtime.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let mut r = Reader {
+            data: snapshot,
+            at: 0,
+        };
+        if r.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(mismatch("not a wasp snapshot"));
+        }
+        let version = r.u32()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(mismatch(format!(
+                "snapshot version {version}, only {SNAPSHOT_VERSION} is supported"
+            )));
+        }
+
+        // read and checked whole before anything is touched
+        let mut instances = Vec::new();
+        for _ in 0..r.len()? {
+            let name = r.string()?;
+            let Some(Import::WS(model)) = self.modules.get(&name) else {
+                return Err(mismatch(format!("no instance {name:?} to restore")));
+            };
+            let count = |what: &str, saved: usize, have: usize| {
+                if saved == have {
+                    Ok(())
+                } else {
+                    Err(mismatch(format!(
+                        "instance {name:?} has {have} {what}, the snapshot {saved}"
+                    )))
+                }
+            };
+            count("functions", r.len()?, model.functions.len())?;
+
+            let current = r.u64()? as usize;
+            let max = r.u64()? as usize;
+            let declared = model.memory.read().pages().1;
+            if max != declared {
+                return Err(mismatch(format!(
+                    "the memory of {name:?} has a maximum of {declared} pages, the snapshot {max}"
+                )));
+            }
+            let limit = self.limits.max_memory_pages.min(1 << 16).min(max);
+            if current > limit {
+                return Err(r.error(format!(
+                    "{current} pages of memory, {name:?} may have {limit}"
+                )));
+            }
+            let mut pages = Vec::new();
+            for _ in 0..r.len()? {
+                let index = r.u32()? as usize;
+                if index >= current {
+                    return Err(r.error(format!("page {index} past the memory's {current}")));
+                }
+                pages.push((index, r.take(PAGE_SIZE)?));
+            }
+
+            let globals = (0..r.len()?)
+                .map(|_| r.value())
+                .collect::<Result<Vec<_>>>()?;
+            count("globals", globals.len(), model.globals.len())?;
+            for (i, (global, value)) in model.globals.iter().zip(&globals).enumerate() {
+                let global = global.read();
+                let typ = global.1.as_str();
+                if value.as_str() != typ {
+                    return Err(mismatch(format!(
+                        "global {i} of {name:?} is {typ}, the snapshot has {}",
+                        value.as_str()
+                    )));
+                }
+                if global.0 == Mutable::Const && !same_bits(value, &global.1) {
+                    return Err(mismatch(format!(
+                        "global {i} of {name:?} is immutable, the snapshot changes it"
+                    )));
+                }
+            }
+
+            let mut tables = Vec::new();
+            for _ in 0..r.len()? {
+                // the entries are only known to be in range of a table
+                // that exists
+                let table = model.tables.get(tables.len()).map(|t| t.read());
+                let length = r.u64()? as usize;
+                if table.as_ref().is_some_and(|t| length > t.table_length.1) {
+                    return Err(r.error(format!("table {} past its maximum", tables.len())));
+                }
+                let funcs = table.is_some_and(|t| t.typ == RefTyp::FuncRef);
+                let mut entries = HashMap::new();
+                for _ in 0..r.len()? {
+                    let index = r.u32()?;
+                    let func = r.u32()?;
+                    if index as usize >= length {
+                        return Err(
+                            r.error(format!("table entry {index} past the table's {length}"))
+                        );
+                    }
+                    if funcs && func as usize >= model.functions.len() {
+                        return Err(r.error(format!("table entry {index} refers to no function")));
+                    }
+                    entries.insert(index, FuncIdx(func));
+                }
+                tables.push((length, entries));
+            }
+            count("tables", tables.len(), model.tables.len())?;
+
+            let datas = (0..r.len()?)
+                .map(|_| Ok(r.u8()? != 0))
+                .collect::<Result<Vec<_>>>()?;
+            count("data segments", datas.len(), model.datas.len())?;
+            let elems = (0..r.len()?)
+                .map(|_| Ok(r.u8()? != 0))
+                .collect::<Result<Vec<_>>>()?;
+            count("element segments", elems.len(), model.elems.len())?;
+
+            instances.push((model, (current, max, pages), globals, tables, datas, elems));
+        }
+
+        let mut hosts = Vec::new();
+        for _ in 0..r.len()? {
+            let name = r.string()?;
+            let Some(Import::IO(IO {
+                state: Some(state), ..
+            })) = self.modules.get(&name)
+            else {
+                return Err(mismatch(format!("host {name:?} keeps no state to restore")));
+            };
+            hosts.push((name, state, r.bytes()?));
+        }
+        let stateful = (self.modules.values())
+            .filter(|i| matches!(i, Import::IO(IO { state: Some(_), .. })))
+            .count();
+        if stateful != hosts.len() {
+            return Err(mismatch(
+                "the snapshot is missing the state of a host, was it taken in another mode?",
+            ));
+        }
+
+        let mut frames = Vec::new();
+        for _ in 0..r.len()? {
+            let module = r.string()?;
+            let func = r.u32()?;
+            let pc = r.u64()? as usize;
+            let code_len = match self.modules.get(&module) {
+                Some(Import::WS(model)) => match model.functions.get(func as usize) {
+                    Some(f) => match f.as_ref() {
+                        Function::WS { code, .. } => code.len(),
+                        Function::IO { .. } => 0,
+                    },
+                    None => return Err(mismatch(format!("no function {func} in {module:?}"))),
+                },
+                _ => return Err(mismatch(format!("no instance {module:?} for a frame"))),
+            };
+            if pc > code_len {
+                return Err(r.error(format!("pc {pc} past the end of function {func}")));
+            }
+            let stack = (0..r.len()?)
+                .map(|_| r.value())
+                .collect::<Result<Vec<_>>>()?;
+            let mut locals = HashMap::new();
+            for _ in 0..r.len()? {
+                let index = r.u32()?;
+                locals.insert(index, r.value()?);
+            }
+            let depth_stack = (0..r.len()?)
+                .map(|_| r.depth())
+                .collect::<Result<Vec<_>>>()?;
+            frames.push(Frame {
+                func_id: FuncId::Id(func),
+                module,
+                pc,
+                stack,
+                locals,
+                depth_stack,
+            });
+        }
+        if r.at != snapshot.len() {
+            return Err(r.error("trailing bytes after the frames"));
+        }
+
+        // the hosts go first, they are the only ones that can still refuse,
+        // and those restored before are put back when one does
+        let mut restored: Vec<(&dyn HostState, Vec<u8>)> = Vec::new();
+        for (name, state, saved) in hosts {
+            let before = state.save();
+            if let Err(e) = state.restore(saved) {
+                for (state, before) in restored {
+                    let _ = state.restore(&before);
+                }
+                return Err(mismatch(format!("host {name:?}: {e}")));
+            }
+            restored.push((state.as_ref(), before));
+        }
+
+        for (model, (current, max, pages), globals, tables, datas, elems) in instances {
+            let mut memory = model.memory.write();
+            memory.reset(current, max);
+            for (index, data) in pages {
+                memory.set_page(index, data);
+            }
+            drop(memory);
+            for (global, value) in model.globals.iter().zip(globals) {
+                global.write().1 = value;
+            }
+            for (table, (length, entries)) in model.tables.iter().zip(tables) {
+                let mut table = table.write();
+                table.table_length.0 = length;
+                table.table = entries;
+            }
+            for (data, dropped) in model.datas.iter().zip(datas) {
+                if dropped {
+                    *data.write() = Vec::new();
+                }
+            }
+            for (elem, dropped) in model.elems.iter().zip(elems) {
+                if dropped {
+                    let mut elem = elem.write();
+                    elem.instrs = Vec::new();
+                    elem.offsets = Vec::new();
+                }
+            }
+        }
+        self.stack = frames;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parser::Module,
+        runtime::{ResourceLimiter, RuntimeError, WasiConfig, MAIN_MODULE, WASI_MODULE},
+        wast::text_module as module,
+    };
+
+    /// Counts to 100 through a table, a float global and the virtual
+    /// randomness, so that every part of a snapshot has something in it.
+    const COUNTER: &str = r#"(module
+        (import "wasi_snapshot_preview1" "random_get" (func $random (param i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (global $n (mut i32) (i32.const 0))
+        (global $x (mut f64) (f64.const 0.5))
+        (table 2 funcref)
+        (elem (i32.const 1) $step)
+        (func $step
+            (global.set $n (i32.add (global.get $n) (i32.const 1)))
+            (global.set $x (f64.mul (global.get $x) (f64.const 1.5)))
+            (drop (call $random (i32.const 32) (i32.const 8))))
+        (func (export "main")
+            (loop $l
+                (call_indirect (i32.const 1))
+                (i32.store (i32.const 16) (global.get $n))
+                (br_if $l (i32.lt_u (global.get $n) (i32.const 100)))))
+        (func (export "n") (result i32) (global.get $n))
+        (func (export "random") (result i64) (i64.load (i32.const 32))))"#;
+
+    /// A memory with a maximum, and a constant and a mutable global of the
+    /// same value.
+    const SMALL: &str = r#"(module
+        (memory 1 4)
+        (global $k i32 (i32.const 7))
+        (global $m (mut i32) (i32.const 9)))"#;
+
+    fn runtime(module: &Module, deterministic: bool, fuel: u64) -> Runtime {
+        limited(module, deterministic, fuel, ResourceLimiter::default())
+    }
+
+    fn limited(
+        module: &Module,
+        deterministic: bool,
+        fuel: u64,
+        limits: ResourceLimiter,
+    ) -> Runtime {
+        let wasi = Import::wasi(WasiConfig {
+            deterministic,
+            ..WasiConfig::default()
+        });
+        Runtime::builder()
+            .add_io(WASI_MODULE, wasi)
+            .add_module(MAIN_MODULE, module)
+            .fuel(fuel)
+            .limits(limits)
+            .build()
+            .expect("the runtime builds")
+    }
+
+    /// `snapshot` with the main instance's memory resized to `current` pages
+    /// of at most `max`.
+    fn resized(snapshot: &[u8], current: u64, max: u64) -> Vec<u8> {
+        let name = MAIN_MODULE.as_bytes();
+        // the name, then the function count
+        let at = snapshot
+            .windows(name.len())
+            .position(|w| w == name)
+            .expect("the instance is in the snapshot")
+            + name.len()
+            + 4;
+        let mut snapshot = snapshot.to_vec();
+        snapshot[at..at + 8].copy_from_slice(&current.to_le_bytes());
+        snapshot[at + 8..at + 16].copy_from_slice(&max.to_le_bytes());
+        snapshot
+    }
+
+    /// Steps until the program is done or out of fuel, `true` when done.
+    fn run(rt: &mut Runtime) -> bool {
+        loop {
+            match rt.step() {
+                Ok(()) => {}
+                Err(RuntimeError::Trap(crate::runtime::TrapCode::OutOfFuel)) => return false,
+                Err(_) => return true,
+            }
+        }
+    }
+
+    fn results(rt: &mut Runtime) -> (Vec<Value>, Vec<Value>) {
+        (
+            rt.invoke("n", &[]).expect("n"),
+            rt.invoke("random", &[]).expect("random"),
+        )
+    }
+
+    #[test]
+    fn a_restored_program_finishes_like_an_uninterrupted_one() {
+        let module = module(COUNTER);
+        let mut whole = runtime(&module, true, u64::MAX);
+        assert!(run(&mut whole));
+
+        let mut first = runtime(&module, true, 500);
+        assert!(!run(&mut first));
+        let snapshot = first.snapshot().expect("the snapshot is taken");
+
+        let mut resumed = runtime(&module, true, u64::MAX);
+        resumed.restore(&snapshot).expect("the snapshot fits");
+        assert_eq!(resumed.snapshot().ok(), Some(snapshot));
+        assert!(run(&mut resumed));
+        assert!(results(&mut resumed) == results(&mut whole));
+    }
+
+    #[test]
+    fn snapshots_of_other_programs_are_refused_untouched() {
+        let mut rt = runtime(&module(COUNTER), true, 500);
+        assert!(!run(&mut rt));
+        let snapshot = rt.snapshot().expect("the snapshot is taken");
+
+        // the same shape with the globals the other way around
+        let swapped = COUNTER.replace(
+            "(global $n (mut i32) (i32.const 0))\n        (global $x (mut f64) (f64.const 0.5))",
+            "(global $x (mut f64) (f64.const 0.5))\n        (global $n (mut i32) (i32.const 0))",
+        );
+        assert_ne!(swapped, COUNTER);
+        let mut other = runtime(&module(&swapped), true, 500);
+        let before = other.snapshot().expect("the snapshot is taken");
+        let e = other.restore(&snapshot).expect_err("the globals differ");
+        assert!(e.message.contains("global 0"), "{e}");
+        assert_eq!(other.snapshot().ok(), Some(before));
+
+        // without the virtual clock there is no host state to put back
+        let mut real = runtime(&module(COUNTER), false, 500);
+        let e = real.restore(&snapshot).expect_err("wasi keeps no state");
+        assert!(e.message.contains("keeps no state"), "{e}");
+
+        assert!(rt.restore(b"WASPSNAP\x01\0\0\0").is_err());
+        assert!(rt.restore(b"not one").is_err());
+    }
+
+    #[test]
+    fn table_entries_must_name_a_function() {
+        let module = module(COUNTER);
+        let mut rt = runtime(&module, true, 500);
+        let mut snapshot = rt.snapshot().expect("the snapshot is taken");
+        // the table: length 2, one entry, index 1, function 1 as the
+        // import comes first
+        let entry = [
+            &2u64.to_le_bytes()[..],
+            &1u32.to_le_bytes(),
+            &1u32.to_le_bytes(),
+            &1u32.to_le_bytes(),
+        ]
+        .concat();
+        let at = snapshot
+            .windows(entry.len())
+            .position(|w| w == entry)
+            .expect("the table is in the snapshot");
+        snapshot[at + entry.len() - 4] = 9;
+        let e = rt.restore(&snapshot).expect_err("there is no function 9");
+        assert!(e.message.contains("refers to no function"), "{e}");
+    }
+
+    #[test]
+    fn memory_sizes_the_instance_cannot_have_are_refused() {
+        let small = module(SMALL);
+        let snapshot = runtime(&small, true, u64::MAX)
+            .snapshot()
+            .expect("the snapshot is taken");
+        let refused = [
+            (1 << 40, 1 << 41),
+            // more than the declared maximum, or a different one
+            (5, 4),
+            (3, 3),
+            (1, u64::MAX),
+        ];
+        for (current, max) in refused {
+            let mut rt = runtime(&small, true, u64::MAX);
+            let e = rt.restore(&resized(&snapshot, current, max));
+            assert!(e.is_err(), "{current} of {max} pages");
+            assert_eq!(rt.snapshot().ok().as_ref(), Some(&snapshot));
+        }
+        let grown = resized(&snapshot, 2, 4);
+        assert!(runtime(&small, true, u64::MAX).restore(&grown).is_ok());
+
+        // nor more than the runtime allows, or than wasm can address
+        let limits = ResourceLimiter {
+            max_memory_pages: 1,
+            ..ResourceLimiter::default()
+        };
+        assert!(limited(&small, true, u64::MAX, limits)
+            .restore(&grown)
+            .is_err());
+        let counter = module(COUNTER);
+        let snapshot = runtime(&counter, true, u64::MAX)
+            .snapshot()
+            .expect("the snapshot is taken");
+        let mut rt = runtime(&counter, true, u64::MAX);
+        assert!(rt.restore(&resized(&snapshot, 1 << 16, u64::MAX)).is_ok());
+        assert!(rt
+            .restore(&resized(&snapshot, (1 << 16) + 1, u64::MAX))
+            .is_err());
+    }
+
+    #[test]
+    fn immutable_globals_keep_their_value() {
+        let small = module(SMALL);
+        let mut rt = runtime(&small, true, u64::MAX);
+        let snapshot = rt.snapshot().expect("the snapshot is taken");
+        let global = |value: u64| [&[0x7f][..], &value.to_le_bytes()].concat();
+        let patched = |from: u64, to: u64| {
+            let at = snapshot
+                .windows(9)
+                .position(|w| w == global(from))
+                .expect("the global is in the snapshot");
+            let mut patched = snapshot.clone();
+            patched[at + 1] = to as u8;
+            patched
+        };
+        assert!(rt.restore(&patched(9, 10)).is_ok());
+        let e = rt
+            .restore(&patched(7, 8))
+            .expect_err("the constant changes");
+        assert!(e.message.contains("immutable"), "{e}");
+    }
+}
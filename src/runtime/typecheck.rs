@@ -83,6 +83,20 @@ pub enum TypeCheckError {
     UnknownTable,
     UnknownType,
 }
+impl std::fmt::Display for TypeCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WrongTypeOnStack | Self::EmptyStack => write!(f, "type mismatch"),
+            Self::MissingFunction | Self::UnknownFunction => write!(f, "unknown function"),
+            Self::MissingType | Self::UnknownType => write!(f, "unknown type"),
+            Self::IfElseTypeMismatch(a, b) => write!(f, "type mismatch ({a:?} vs {b:?})"),
+            Self::ReturnTypeMismatch(a, b) => write!(f, "type mismatch ({a:?} vs {b:?})"),
+            Self::MissingLocal => write!(f, "unknown local"),
+            Self::UnknownTable => write!(f, "unknown table"),
+        }
+    }
+}
+impl std::error::Error for TypeCheckError {}
 
 #[allow(unused)]
 pub fn check(
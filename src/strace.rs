@@ -0,0 +1,78 @@
+//! `wasp run --strace`: logs every call the guest makes into the host, the
+//! way strace logs system calls, with the arguments of WASI calls decoded.
+
+use crate::runtime::{
+    describe_call, errno_name, Observer, Runtime, RuntimeError, Site, Value, WASI_MODULE,
+};
+use std::{
+    io::{self, Write},
+    time::Instant,
+};
+
+/// Writes a line per host call to `out` once it returns.
+pub struct Strace<W> {
+    out: W,
+    started: Option<Instant>,
+}
+
+impl<W: Write> Strace<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, started: None }
+    }
+}
+
+impl<W: Write + 'static> Observer for Strace<W> {
+    fn before_host_call(&mut self, _: &Runtime, _: Site, _: &[Value]) {
+        self.started = Some(Instant::now());
+    }
+
+    fn after_host_call(
+        &mut self,
+        rt: &Runtime,
+        site: Site,
+        args: &[Value],
+        results: Result<&[Value], &RuntimeError>,
+    ) {
+        let took = self.started.take().map(|s| s.elapsed()).unwrap_or_default();
+        let instance = rt.instance(site.instance).ok();
+        let (module, name) = instance
+            .as_ref()
+            .and_then(|i| i.function_import(site.func))
+            .unwrap_or(("?", "?"));
+        let wasi = module == WASI_MODULE;
+        let described = match &instance {
+            Some(instance) if wasi => describe_call(name, args, &instance.own_memory()),
+            _ => None,
+        };
+        let args = described.unwrap_or_else(|| {
+            args.iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+        let results = match results {
+            Ok(&[Value::I32(errno)]) if wasi => match errno_name(errno) {
+                Some(name) => format!("{errno} ({name})"),
+                None => errno.to_string(),
+            },
+            Ok([]) => "()".to_string(),
+            Ok([result]) => result.to_string(),
+            Ok(results) => format!(
+                "({})",
+                results
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Err(RuntimeError::Exit(code)) => format!("? exit {code}"),
+            Err(e) => format!("? error: {e}"),
+        };
+        let line = format!(
+            "{module}.{name}({args}) = {results} <{:.6}>",
+            took.as_secs_f64()
+        );
+        // a closed pipe must not stop the program being traced
+        let _: io::Result<()> = writeln!(self.out, "{line}");
+    }
+}
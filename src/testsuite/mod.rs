@@ -0,0 +1,361 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    io::Write,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::TestOptions,
+    parser,
+    runtime::{
+        Import, InternalErrorKind::NoModule, ResourceLimiter, Runtime, RuntimeError, TrapCode,
+        Value,
+    },
+    wast::{parse_script, Action, Command, ScriptModule, WastError},
+};
+mod report;
+pub use report::{CaseReport, FileReport, Outcome};
+
+/// Instances by the `$name` their module command gave them, and the one
+/// instantiated last, which unqualified actions go to.
+#[derive(Default)]
+struct Instances {
+    named: HashMap<String, String>,
+    current: Option<String>,
+}
+impl Instances {
+    fn resolve(&self, module: Option<&String>) -> Result<String, RuntimeError> {
+        match module {
+            Some(name) => self.named.get(name),
+            None => self.current.as_ref(),
+        }
+        .cloned()
+        .ok_or_else(|| {
+            let name = module.map_or("current module", |m| m);
+            NoModule(name.to_string()).at(file!(), line!(), column!())
+        })
+    }
+}
+
+/// Whether an error with the message `actual` is the one a script expects
+/// with `text`, which like in the reference interpreter only has to be a
+/// prefix. Ours leave out indices, as in "uninitialized element 2".
+fn message_matches(text: &str, actual: &str) -> bool {
+    actual.starts_with(text)
+        || text.strip_prefix(actual).is_some_and(|rest| {
+            rest.strip_prefix(' ')
+                .is_some_and(|i| !i.is_empty() && i.bytes().all(|c| c.is_ascii_digit()))
+        })
+}
+
+/// Assembles `module` if it is in text form and instantiates it as `name`.
+fn instantiate(rt: &mut Runtime, module: &ScriptModule, name: &str) -> Result<(), Box<dyn Error>> {
+    let bytes = module.bytes.as_ref().map_err(Clone::clone)?;
+    rt.instantiate(name, &parser::Module::from_bytes(bytes)?)?;
+    Ok(())
+}
+
+fn handle_action(
+    rt: &mut Runtime,
+    instances: &Instances,
+    action: Action,
+) -> Result<Vec<Value>, RuntimeError> {
+    match action {
+        Action::Invoke {
+            module,
+            field,
+            args,
+        } => rt.invoke_in(&instances.resolve(module.as_ref())?, &field, &args),
+        Action::Get { module, field } => {
+            let instance = instances.resolve(module.as_ref())?;
+            Ok(vec![rt.instance(&instance)?.global(&field)?.get()])
+        }
+    }
+}
+
+/// The state a script builds up from one command to the next.
+struct Script {
+    rt: Runtime,
+    instances: Instances,
+    module_index: i32,
+}
+
+impl Script {
+    fn new() -> Result<Self, RuntimeError> {
+        // every module lives on in one runtime, so later ones can import from
+        // those registered before them
+        let rt = Runtime::builder()
+            .add_io("spectest", Import::spectest())
+            .limits(ResourceLimiter {
+                max_instances: usize::MAX,
+                ..ResourceLimiter::default()
+            })
+            .build()?;
+        Ok(Self {
+            rt,
+            instances: Instances::default(),
+            module_index: -1,
+        })
+    }
+
+    fn run(&mut self, test_i: usize, command: Command) -> Outcome {
+        let Self {
+            rt,
+            instances,
+            module_index,
+        } = self;
+        rt.stack = Vec::new();
+        // modules that are expected to fail get a name of their own
+        let scratch = format!("{module_index}/{test_i}");
+        match command {
+            Command::Module(module) => {
+                *module_index += 1;
+                let instance = module_index.to_string();
+                if let Err(e) = instantiate(rt, &module, &instance) {
+                    // later commands must not go to the module before it
+                    instances.current = None;
+                    return Outcome::Failed(format!("failed to load module: {e}"));
+                }
+                if let Some(name) = module.name {
+                    instances.named.insert(name, instance.clone());
+                }
+                instances.current = Some(instance);
+                Outcome::Passed
+            }
+            Command::AssertReturn { action, expected } => {
+                match handle_action(rt, instances, action) {
+                    Ok(results)
+                        if results.len() == expected.len()
+                            && expected.iter().zip(&results).all(|(e, r)| e.matches(r)) =>
+                    {
+                        Outcome::Passed
+                    }
+                    Ok(results) => {
+                        let expected = expected.iter().map(|e| format!("{e}")).collect::<Vec<_>>();
+                        Outcome::Failed(format!("got {results:?}, but expected {expected:?}"))
+                    }
+                    Err(e) => Outcome::Failed(format!("error: {e}")),
+                }
+            }
+            Command::Action(action) => match handle_action(rt, instances, action) {
+                Ok(_) => Outcome::Passed,
+                Err(e) => Outcome::Failed(format!("error: {e}")),
+            },
+            Command::AssertExhaustion { action, text } => {
+                match handle_action(rt, instances, action) {
+                    Err(RuntimeError::Trap(code @ TrapCode::StackExhaustion))
+                        if message_matches(&text, &code.to_string()) =>
+                    {
+                        Outcome::Passed
+                    }
+                    Err(e) => Outcome::Failed(format!("got \"{e}\", expected error: {text:?}")),
+                    Ok(_) => Outcome::Failed("did not exhaust the stack".to_string()),
+                }
+            }
+            Command::AssertTrap { action, text } => match handle_action(rt, instances, action) {
+                Ok(_) => Outcome::Failed(format!("did not fail, expected error: {text:?}")),
+                Err(RuntimeError::Trap(code)) if message_matches(&text, &code.to_string()) => {
+                    Outcome::Passed
+                }
+                Err(e) => Outcome::Failed(format!("got error \"{e}\", expected error: {text:?}")),
+            },
+            Command::AssertInvalid { module, text } => {
+                assert_rejected(rt, &module, &scratch, &text, false)
+            }
+            Command::AssertMalformed { module, text } => {
+                assert_rejected(rt, &module, &scratch, &text, true)
+            }
+            Command::AssertUninstantiable { module, text } => {
+                match instantiate(rt, &module, &scratch) {
+                    Ok(_) => Outcome::Failed(format!("did not trap, expected error: {text:?}")),
+                    Err(e) => match e.downcast_ref::<RuntimeError>() {
+                        Some(RuntimeError::Trap(code))
+                            if message_matches(&text, &code.to_string()) =>
+                        {
+                            Outcome::Passed
+                        }
+                        _ => Outcome::Failed(format!("got error \"{e}\", expected trap: {text:?}")),
+                    },
+                }
+            }
+            Command::AssertUnlinkable { module, text } => {
+                match instantiate(rt, &module, &scratch) {
+                    Ok(_) => {
+                        Outcome::Failed(format!("did not fail linking, expected error: {text:?}"))
+                    }
+                    Err(e) => match e.downcast_ref::<RuntimeError>() {
+                        Some(RuntimeError::Link(link))
+                            if message_matches(&text, &link.to_string()) =>
+                        {
+                            Outcome::Passed
+                        }
+                        _ => Outcome::Failed(format!(
+                            "got error \"{e}\", expected link error: {text:?}"
+                        )),
+                    },
+                }
+            }
+            Command::Register { name, module } => {
+                match instances
+                    .resolve(module.as_ref())
+                    .and_then(|instance| rt.register(&name, &instance))
+                {
+                    Ok(()) => Outcome::Passed,
+                    Err(e) => Outcome::Failed(format!("failed to register module: {e}")),
+                }
+            }
+        }
+    }
+}
+
+/// Checks that `module` fails to load with the kind of error an
+/// `assert_malformed`, or else an `assert_invalid`, expects: one parsing or
+/// assembling it, or one validating it.
+fn assert_rejected(
+    rt: &mut Runtime,
+    module: &ScriptModule,
+    name: &str,
+    text: &str,
+    malformed: bool,
+) -> Outcome {
+    // Currently skipping type checking
+    if text == "type mismatch" {
+        return Outcome::Skipped("type checking".to_string());
+    }
+    let expected = if malformed { "malformed" } else { "invalid" };
+    let e = match instantiate(rt, module, name) {
+        Ok(_) => {
+            return Outcome::Failed(format!(
+                "did not fail, expected it to be {expected}: {text:?}"
+            ))
+        }
+        Err(e) => e,
+    };
+    let category = match e.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::Parse { .. }) => "malformed",
+        Some(RuntimeError::Validation(_)) => "invalid",
+        None if e.is::<WastError>() => "malformed",
+        _ => "other",
+    };
+    if category == expected {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(format!(
+            "got error \"{e}\", expected it to be {expected}: {text:?}"
+        ))
+    }
+}
+
+thread_local! {
+    /// What the last panic said, taken by [`run_command`].
+    static PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs one command, turning a panic in the runtime into a failure of just
+/// that command.
+fn run_command(script: &mut Script, test_i: usize, command: Command) -> Outcome {
+    catch_unwind(AssertUnwindSafe(|| script.run(test_i, command))).unwrap_or_else(|_| {
+        let reason = PANIC.with(|p| p.borrow_mut().take()).unwrap_or_default();
+        Outcome::Failed(format!("panicked: {reason}"))
+    })
+}
+
+/// Runs every command of the script at `path`, continuing past failures.
+pub fn run_file(path: &Path) -> FileReport {
+    let mut report = FileReport::new(path);
+    let src = match std::fs::read(path) {
+        Ok(src) => src,
+        Err(e) => {
+            report.error = Some(format!("failed to read: {e}"));
+            return report;
+        }
+    };
+    let commands = match parse_script(&src) {
+        Ok(commands) => commands,
+        Err(e) => {
+            report.error = Some(format!("failed to parse: {e}"));
+            return report;
+        }
+    };
+    let mut script = match Script::new() {
+        Ok(script) => script,
+        Err(e) => {
+            report.error = Some(format!("failed to set up runtime: {e}"));
+            return report;
+        }
+    };
+    for (test_i, (line, command)) in commands.into_iter().enumerate() {
+        let name = command.name();
+        let outcome = run_command(&mut script, test_i + 1, command);
+        if let Outcome::Failed(reason) = &outcome {
+            error!("{}:{line}: {name} failed: {reason}", path.display());
+        }
+        report.cases.push(CaseReport {
+            line,
+            command: name,
+            outcome,
+        });
+    }
+    report
+}
+
+/// The `.wast` scripts under `path`, sorted, or `path` itself if it is a file.
+fn scripts(path: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !std::fs::metadata(path)?.is_dir() {
+        out.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            scripts(&entry, out)?;
+        } else if entry.extension().is_some_and(|e| e == "wast") {
+            out.push(entry);
+        }
+    }
+    Ok(())
+}
+
+pub fn test(opts: TestOptions) {
+    let mut files = Vec::new();
+    for path in &opts.paths {
+        if let Err(e) = scripts(path, &mut files) {
+            error!("failed to list {path:?}: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|info| {
+        PANIC.with(|p| *p.borrow_mut() = Some(info.to_string()));
+    }));
+    let reports = files.iter().map(|f| run_file(f)).collect::<Vec<_>>();
+    std::panic::set_hook(hook);
+
+    let mut out = std::io::stdout().lock();
+    // a closed pipe only loses the summary, the exit code still tells
+    let _ = report::summary(&reports, &mut out);
+    for (path, write) in [
+        (
+            &opts.json,
+            report::json as fn(&[FileReport], &mut dyn Write) -> _,
+        ),
+        (&opts.junit, report::junit),
+    ] {
+        let Some(path) = path else { continue };
+        let written = std::fs::File::create(path).and_then(|mut file| write(&reports, &mut file));
+        if let Err(e) = written {
+            error!("failed to write report {path:?}: {e}");
+            std::process::exit(1);
+        }
+    }
+    if reports.iter().any(|r| !r.ok()) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,218 @@
+//! What came of running spec scripts: per command, per file, and as a
+//! summary, JSON or JUnit XML.
+
+use serde_json::json;
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    /// Left out on purpose, for the reason given.
+    Skipped(String),
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Failed(_) => "failed",
+            Outcome::Skipped(_) => "skipped",
+        }
+    }
+
+    fn message(&self) -> Option<&str> {
+        match self {
+            Outcome::Passed => None,
+            Outcome::Failed(m) | Outcome::Skipped(m) => Some(m),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseReport {
+    pub line: usize,
+    pub command: &'static str,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub cases: Vec<CaseReport>,
+    /// Why the script could not be run at all.
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            cases: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn count(&self, outcome: &str) -> usize {
+        self.cases
+            .iter()
+            .filter(|c| c.outcome.name() == outcome)
+            .count()
+    }
+
+    pub fn passed(&self) -> usize {
+        self.count("passed")
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count("failed")
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count("skipped")
+    }
+
+    pub fn ok(&self) -> bool {
+        self.error.is_none() && self.failed() == 0
+    }
+}
+
+/// The cases of all files that passed, failed and were skipped, and how many
+/// files could not be run.
+fn totals(reports: &[FileReport]) -> [usize; 4] {
+    let broken = reports.iter().filter(|r| r.error.is_some()).count();
+    reports.iter().fold([0, 0, 0, broken], |[p, f, s, b], r| {
+        [p + r.passed(), f + r.failed(), s + r.skipped(), b]
+    })
+}
+
+/// One line per file and the totals.
+pub fn summary(reports: &[FileReport], out: &mut dyn Write) -> io::Result<()> {
+    for r in reports {
+        match &r.error {
+            Some(e) => writeln!(out, "{}: {e}", r.path.display())?,
+            None => writeln!(
+                out,
+                "{}: {} passed, {} failed, {} skipped",
+                r.path.display(),
+                r.passed(),
+                r.failed(),
+                r.skipped()
+            )?,
+        }
+    }
+    let [passed, failed, skipped, broken] = totals(reports);
+    writeln!(
+        out,
+        "total: {passed} passed, {failed} failed, {skipped} skipped in {} files ({broken} not run)",
+        reports.len()
+    )
+}
+
+pub fn json(reports: &[FileReport], out: &mut dyn Write) -> io::Result<()> {
+    let files = reports
+        .iter()
+        .map(|r| {
+            let cases = r
+                .cases
+                .iter()
+                .map(|c| {
+                    json!({
+                        "line": c.line,
+                        "command": c.command,
+                        "outcome": c.outcome.name(),
+                        "message": c.outcome.message(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({
+                "file": r.path.display().to_string(),
+                "error": r.error,
+                "passed": r.passed(),
+                "failed": r.failed(),
+                "skipped": r.skipped(),
+                "cases": cases,
+            })
+        })
+        .collect::<Vec<_>>();
+    let [passed, failed, skipped, broken] = totals(reports);
+    let summary = json!({
+        "files": reports.len(),
+        "not_run": broken,
+        "passed": passed,
+        "failed": failed,
+        "skipped": skipped,
+    });
+    serde_json::to_writer_pretty(&mut *out, &json!({ "summary": summary, "files": files }))?;
+    writeln!(out)
+}
+
+fn escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            // not allowed in XML 1.0 at all
+            c if c < ' ' && !matches!(c, '\t' | '\n' | '\r') => format!("\\x{:02x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// One `<testsuite>` per file and one `<testcase>` per command, named after
+/// the command and its line.
+pub fn junit(reports: &[FileReport], out: &mut dyn Write) -> io::Result<()> {
+    let total = |f: fn(&FileReport) -> usize| reports.iter().map(f).sum::<usize>();
+    let errors = reports.iter().filter(|r| r.error.is_some()).count();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuites tests="{}" failures="{}" errors="{errors}" skipped="{}">"#,
+        total(|r| r.cases.len()),
+        total(FileReport::failed),
+        total(FileReport::skipped),
+    )?;
+    for r in reports {
+        let name = escape(&r.path.display().to_string());
+        writeln!(
+            out,
+            r#"  <testsuite name="{name}" tests="{}" failures="{}" errors="{}" skipped="{}">"#,
+            r.cases.len(),
+            r.failed(),
+            usize::from(r.error.is_some()),
+            r.skipped(),
+        )?;
+        if let Some(e) = &r.error {
+            writeln!(out, r#"    <testcase name="script" classname="{name}">"#)?;
+            writeln!(out, r#"      <error message="{}"/>"#, escape(e))?;
+            writeln!(out, "    </testcase>")?;
+        }
+        for c in &r.cases {
+            let case = format!(
+                r#"    <testcase name="{} at line {}" classname="{name}""#,
+                c.command, c.line
+            );
+            match &c.outcome {
+                Outcome::Passed => writeln!(out, "{case}/>")?,
+                Outcome::Failed(m) => {
+                    writeln!(out, "{case}>")?;
+                    writeln!(out, r#"      <failure message="{}"/>"#, escape(m))?;
+                    writeln!(out, "    </testcase>")?;
+                }
+                Outcome::Skipped(m) => {
+                    writeln!(out, "{case}>")?;
+                    writeln!(out, r#"      <skipped message="{}"/>"#, escape(m))?;
+                    writeln!(out, "    </testcase>")?;
+                }
+            }
+        }
+        writeln!(out, "  </testsuite>")?;
+    }
+    writeln!(out, "</testsuites>")
+}
@@ -0,0 +1,271 @@
+//! Tokens and S-expressions of the text format, comments and whitespace
+//! already gone.
+
+use super::WastError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SExpr {
+    List(Vec<SExpr>, usize),
+    /// A keyword, number or `$id`, see [`SExpr::atom`].
+    Atom(String, usize),
+    Str(Vec<u8>, usize),
+}
+
+impl SExpr {
+    pub fn line(&self) -> usize {
+        match self {
+            SExpr::List(_, line) | SExpr::Atom(_, line) | SExpr::Str(_, line) => *line,
+        }
+    }
+
+    pub fn atom(&self) -> Option<&str> {
+        match self {
+            SExpr::Atom(a, _) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// The `$id` without its dollar sign.
+    pub fn id(&self) -> Option<&str> {
+        self.atom()?.strip_prefix('$')
+    }
+
+    pub fn list(&self) -> Option<&[SExpr]> {
+        match self {
+            SExpr::List(items, _) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// The items of a list starting with the keyword `head`, after it.
+    pub fn form(&self, head: &str) -> Option<&[SExpr]> {
+        match self.list()? {
+            [first, rest @ ..] if first.atom() == Some(head) => Some(rest),
+            _ => None,
+        }
+    }
+
+    /// The keyword a list starts with.
+    pub fn head(&self) -> Option<&str> {
+        self.list()?.first()?.atom()
+    }
+
+    pub fn string(&self) -> Option<&[u8]> {
+        match self {
+            SExpr::Str(s, _) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+struct Lexer<'s> {
+    src: &'s [u8],
+    pos: usize,
+    line: usize,
+}
+
+fn is_idchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-./:<=>?@\\^_`|~".contains(&c)
+}
+
+impl Lexer<'_> {
+    fn error(&self, message: impl Into<String>) -> WastError {
+        WastError::new(self.line, message)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == b'\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    /// Skips whitespace, line comments and nested block comments.
+    fn skip(&mut self) -> Result<(), WastError> {
+        loop {
+            match (self.peek(), self.src.get(self.pos + 1)) {
+                (Some(b' ' | b'\t' | b'\n' | b'\r'), _) => {
+                    self.bump();
+                }
+                (Some(b';'), Some(b';')) => while !matches!(self.bump(), Some(b'\n') | None) {},
+                (Some(b'('), Some(b';')) => {
+                    let mut depth = 0;
+                    loop {
+                        match (self.bump(), self.peek()) {
+                            (Some(b'('), Some(b';')) => {
+                                self.bump();
+                                depth += 1;
+                            }
+                            (Some(b';'), Some(b')')) => {
+                                self.bump();
+                                depth -= 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            (None, _) => return Err(self.error("unclosed block comment")),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, WastError> {
+        let mut out = Vec::new();
+        loop {
+            match self.bump() {
+                None | Some(b'\n') => return Err(self.error("unclosed string")),
+                Some(b'"') => return Ok(out),
+                Some(b'\\') => match self.bump() {
+                    Some(b't') => out.push(b'\t'),
+                    Some(b'n') => out.push(b'\n'),
+                    Some(b'r') => out.push(b'\r'),
+                    Some(b'"') => out.push(b'"'),
+                    Some(b'\'') => out.push(b'\''),
+                    Some(b'\\') => out.push(b'\\'),
+                    Some(b'u') => {
+                        if self.bump() != Some(b'{') {
+                            return Err(self.error("malformed unicode escape"));
+                        }
+                        let start = self.pos;
+                        while self.peek().is_some_and(|c| c != b'}') {
+                            self.bump();
+                        }
+                        let digits = String::from_utf8_lossy(&self.src[start..self.pos]);
+                        self.bump();
+                        let c = u32::from_str_radix(&digits.replace('_', ""), 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error("malformed unicode escape"))?;
+                        out.extend(c.to_string().bytes());
+                    }
+                    Some(h) => {
+                        let l = self.bump().unwrap_or_default();
+                        let hex = |c: u8| (c as char).to_digit(16);
+                        match (hex(h), hex(l)) {
+                            (Some(h), Some(l)) => out.push((h * 16 + l) as u8),
+                            _ => return Err(self.error("malformed escape")),
+                        }
+                    }
+                    None => return Err(self.error("unclosed string")),
+                },
+                Some(c) if c < 0x20 || c == 0x7f => {
+                    return Err(self.error("control character in string"))
+                }
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Result<Option<SExpr>, WastError> {
+        self.skip()?;
+        let line = self.line;
+        match self.peek() {
+            None | Some(b')') => Ok(None),
+            Some(b'(') => {
+                self.bump();
+                let mut items = Vec::new();
+                while let Some(item) = self.expr()? {
+                    items.push(item);
+                }
+                if self.bump() != Some(b')') {
+                    return Err(WastError::new(line, "unclosed list"));
+                }
+                Ok(Some(SExpr::List(items, line)))
+            }
+            Some(b'"') => {
+                self.bump();
+                Ok(Some(SExpr::Str(self.string()?, line)))
+            }
+            Some(c) if is_idchar(c) => {
+                let start = self.pos;
+                while self.peek().is_some_and(is_idchar) {
+                    self.bump();
+                }
+                match self.peek() {
+                    None | Some(b' ' | b'\t' | b'\n' | b'\r' | b'(' | b')' | b';') => {}
+                    Some(_) => return Err(self.error("unknown operator")),
+                }
+                let atom = String::from_utf8_lossy(&self.src[start..self.pos]).into_owned();
+                Ok(Some(SExpr::Atom(atom, line)))
+            }
+            Some(c) => Err(self.error(format!("unexpected character {:?}", c as char))),
+        }
+    }
+}
+
+/// Splits `src` into its top level S-expressions.
+pub fn parse(src: &[u8]) -> Result<Vec<SExpr>, WastError> {
+    let mut lexer = Lexer {
+        src,
+        pos: 0,
+        line: 1,
+    };
+    let mut exprs = Vec::new();
+    while let Some(expr) = lexer.expr()? {
+        exprs.push(expr);
+    }
+    if lexer.peek().is_some() {
+        return Err(lexer.error("unexpected )"));
+    }
+    Ok(exprs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(s: &str, line: usize) -> SExpr {
+        SExpr::Atom(s.to_string(), line)
+    }
+
+    #[test]
+    fn comments_and_whitespace_are_skipped() {
+        let src = b";; a line comment\n(a (; nested (; block ;) comment ;) $b)\n  c";
+        assert_eq!(
+            parse(src),
+            Ok(vec![
+                SExpr::List(vec![atom("a", 2), atom("$b", 2)], 2),
+                atom("c", 3),
+            ])
+        );
+    }
+
+    #[test]
+    fn strings_unescape() {
+        let exprs = parse(br#""\t\n\"\\\00\ff\u{1F600}x""#).expect("the string lexes");
+        assert_eq!(
+            exprs[0].string(),
+            Some(&b"\t\n\"\\\0\xff\xf0\x9f\x98\x80x"[..])
+        );
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let cases: [(&[u8], usize, &str); 6] = [
+            (b"(a\n(b)", 1, "unclosed list"),
+            (b"a)", 1, "unexpected )"),
+            (b"\n(; never closed", 2, "unclosed block comment"),
+            (b"\"abc", 1, "unclosed string"),
+            (b"\"\\g\"", 1, "malformed escape"),
+            (b"\n\nabc\"def\"", 3, "unknown operator"),
+        ];
+        for (src, line, message) in cases {
+            assert_eq!(
+                parse(src),
+                Err(WastError::new(line, message)),
+                "{}",
+                String::from_utf8_lossy(src)
+            );
+        }
+    }
+}
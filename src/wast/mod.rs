@@ -0,0 +1,461 @@
+//! `.wast` scripts as the spec test-suite writes them: modules in binary,
+//! quote and text form, actions, and the assertions about them.
+
+mod lexer;
+mod wat;
+
+use crate::runtime::{
+    literal::{parse_f32, parse_f64, parse_i32, parse_i64},
+    Value,
+};
+use lexer::SExpr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WastError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl WastError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for WastError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for WastError {}
+
+#[derive(Debug, Clone)]
+pub struct ScriptModule {
+    pub name: Option<String>,
+    /// The binary module, or why the text could not be assembled into one.
+    pub bytes: std::result::Result<Vec<u8>, WastError>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Invoke {
+        module: Option<String>,
+        field: String,
+        args: Vec<Value>,
+    },
+    Get {
+        module: Option<String>,
+        field: String,
+    },
+}
+
+/// A result an `assert_return` accepts.
+#[derive(Debug, Clone)]
+pub enum Expected {
+    Value(Value),
+    /// `nan:canonical`, for an f32 when `true`.
+    CanonicalNan(bool),
+    /// `nan:arithmetic`, for an f32 when `true`.
+    ArithmeticNan(bool),
+    /// `(ref.func)`, any non-null function reference.
+    AnyFuncRef,
+    /// `(ref.extern)`, any non-null external reference.
+    AnyExternRef,
+    /// `(ref.null)`, a null reference of either type.
+    AnyNull,
+    Either(Vec<Expected>),
+}
+
+impl Expected {
+    pub fn matches(&self, value: &Value) -> bool {
+        match (self, value) {
+            // floats compare by bits, so NaNs and signed zeros are told apart
+            (Expected::Value(Value::F32(e)), Value::F32(v)) => e.to_bits() == v.to_bits(),
+            (Expected::Value(Value::F64(e)), Value::F64(v)) => e.to_bits() == v.to_bits(),
+            (Expected::Value(e), v) => e == v,
+            (Expected::CanonicalNan(true), Value::F32(v)) => {
+                v.to_bits() & 0x7fff_ffff == 0x7fc0_0000
+            }
+            (Expected::CanonicalNan(false), Value::F64(v)) => {
+                v.to_bits() & 0x7fff_ffff_ffff_ffff == 0x7ff8_0000_0000_0000
+            }
+            (Expected::ArithmeticNan(true), Value::F32(v)) => {
+                v.is_nan() && v.to_bits() & 0x40_0000 != 0
+            }
+            (Expected::ArithmeticNan(false), Value::F64(v)) => {
+                v.is_nan() && v.to_bits() & 0x8_0000_0000_0000 != 0
+            }
+            (Expected::AnyFuncRef, Value::FuncRef(i))
+            | (Expected::AnyExternRef, Value::Externref(i)) => *i != u32::MAX,
+            (Expected::AnyNull, Value::FuncRef(i) | Value::Externref(i)) => *i == u32::MAX,
+            (Expected::Either(options), v) => options.iter().any(|e| e.matches(v)),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let float = |f32: &bool| if *f32 { "f32" } else { "f64" };
+        match self {
+            Expected::Value(v) => write!(f, "{} {v}", v.as_str()),
+            Expected::CanonicalNan(t) => write!(f, "{} nan:canonical", float(t)),
+            Expected::ArithmeticNan(t) => write!(f, "{} nan:arithmetic", float(t)),
+            Expected::AnyFuncRef => write!(f, "ref.func"),
+            Expected::AnyExternRef => write!(f, "ref.extern"),
+            Expected::AnyNull => write!(f, "ref.null"),
+            Expected::Either(options) => {
+                write!(f, "either(")?;
+                for (i, e) in options.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{e}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Module(ScriptModule),
+    Register {
+        name: String,
+        module: Option<String>,
+    },
+    Action(Action),
+    AssertReturn {
+        action: Action,
+        expected: Vec<Expected>,
+    },
+    AssertTrap {
+        action: Action,
+        text: String,
+    },
+    AssertExhaustion {
+        action: Action,
+        text: String,
+    },
+    AssertInvalid {
+        module: ScriptModule,
+        text: String,
+    },
+    AssertMalformed {
+        module: ScriptModule,
+        text: String,
+    },
+    AssertUnlinkable {
+        module: ScriptModule,
+        text: String,
+    },
+    /// `assert_trap` on a module, whose start function has to trap.
+    AssertUninstantiable {
+        module: ScriptModule,
+        text: String,
+    },
+}
+
+impl Command {
+    /// The keyword the command is written with.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Module(_) => "module",
+            Command::Register { .. } => "register",
+            Command::Action(Action::Invoke { .. }) => "invoke",
+            Command::Action(Action::Get { .. }) => "get",
+            Command::AssertReturn { .. } => "assert_return",
+            Command::AssertTrap { .. } | Command::AssertUninstantiable { .. } => "assert_trap",
+            Command::AssertExhaustion { .. } => "assert_exhaustion",
+            Command::AssertInvalid { .. } => "assert_invalid",
+            Command::AssertMalformed { .. } => "assert_malformed",
+            Command::AssertUnlinkable { .. } => "assert_unlinkable",
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, WastError>;
+
+fn error<T>(at: &SExpr, message: impl Into<String>) -> Result<T> {
+    Err(WastError::new(at.line(), message))
+}
+
+fn text(s: Option<&SExpr>, at: &SExpr) -> Result<String> {
+    match s.and_then(SExpr::string) {
+        Some(s) => Ok(String::from_utf8_lossy(s).into_owned()),
+        None => error(at, "expected a string"),
+    }
+}
+
+fn module(expr: &SExpr) -> Result<ScriptModule> {
+    let Some(items) = expr.form("module") else {
+        return error(expr, "expected a module");
+    };
+    let name = items.first().and_then(SExpr::id).map(str::to_string);
+    let items = &items[usize::from(name.is_some())..];
+    let strings = || {
+        items[1..]
+            .iter()
+            .map(|s| s.string().map_or_else(|| error(s, "expected a string"), Ok))
+            .collect::<Result<Vec<_>>>()
+            .map(|s| s.concat())
+    };
+    let bytes = match items.first().and_then(SExpr::atom) {
+        Some("binary") => Ok(strings()?),
+        Some("quote") => {
+            let src = strings()?;
+            // the text may or may not be wrapped in `(module ...)` itself
+            lexer::parse(&src).and_then(|exprs| match &exprs[..] {
+                [m] if m.head() == Some("module") => {
+                    let fields = m.form("module").unwrap_or_default();
+                    let named = fields.first().and_then(SExpr::id).is_some();
+                    wat::assemble(&fields[usize::from(named)..])
+                }
+                fields => wat::assemble(fields),
+            })
+        }
+        _ => wat::assemble(items),
+    };
+    Ok(ScriptModule { name, bytes })
+}
+
+/// A constant of an action, or one of the patterns `assert_return` accepts
+/// as a result.
+fn constant(expr: &SExpr) -> Result<Expected> {
+    let (Some(head), Some(items)) = (expr.head(), expr.list()) else {
+        return error(expr, "expected a constant");
+    };
+    let arg = items.get(1).and_then(SExpr::atom);
+    let parsed = match (head, arg) {
+        ("i32.const", Some(a)) => parse_i32(a).map(|v| Expected::Value(Value::I32(v))),
+        ("i64.const", Some(a)) => parse_i64(a).map(|v| Expected::Value(Value::I64(v))),
+        ("f32.const", Some("nan:canonical")) => Some(Expected::CanonicalNan(true)),
+        ("f32.const", Some("nan:arithmetic")) => Some(Expected::ArithmeticNan(true)),
+        ("f64.const", Some("nan:canonical")) => Some(Expected::CanonicalNan(false)),
+        ("f64.const", Some("nan:arithmetic")) => Some(Expected::ArithmeticNan(false)),
+        ("f32.const", Some(a)) => parse_f32(a).map(|v| Expected::Value(Value::F32(v))),
+        ("f64.const", Some(a)) => parse_f64(a).map(|v| Expected::Value(Value::F64(v))),
+        ("ref.null", Some("func")) => Some(Expected::Value(Value::FuncRef(u32::MAX))),
+        ("ref.null", Some("extern")) => Some(Expected::Value(Value::Externref(u32::MAX))),
+        ("ref.null", None) => Some(Expected::AnyNull),
+        ("ref.extern", Some(a)) => {
+            parse_i32(a).map(|v| Expected::Value(Value::Externref(v as u32)))
+        }
+        ("ref.extern", None) => Some(Expected::AnyExternRef),
+        ("ref.func", None) => Some(Expected::AnyFuncRef),
+        ("either", _) => Some(Expected::Either(
+            items[1..].iter().map(constant).collect::<Result<_>>()?,
+        )),
+        _ => None,
+    };
+    parsed.map_or_else(|| error(expr, format!("unsupported constant {head}")), Ok)
+}
+
+fn action(expr: &SExpr) -> Result<Action> {
+    let Some([head, items @ ..]) = expr.list() else {
+        return error(expr, "expected an action");
+    };
+    let module = items.first().and_then(SExpr::id).map(str::to_string);
+    let items = &items[usize::from(module.is_some())..];
+    let field = text(items.first(), expr)?;
+    match head.atom() {
+        Some("invoke") => {
+            let args = items[1..]
+                .iter()
+                .map(|arg| match constant(arg)? {
+                    Expected::Value(v) => Ok(v),
+                    _ => error(arg, "expected a value"),
+                })
+                .collect::<Result<_>>()?;
+            Ok(Action::Invoke {
+                module,
+                field,
+                args,
+            })
+        }
+        Some("get") => Ok(Action::Get { module, field }),
+        _ => error(expr, "expected an action"),
+    }
+}
+
+fn command(expr: &SExpr) -> Result<Command> {
+    let Some([head, items @ ..]) = expr.list() else {
+        return error(expr, "expected a command");
+    };
+    let first = || {
+        items
+            .first()
+            .map_or_else(|| error(expr, "missing argument"), Ok)
+    };
+    let message = || text(items.get(1), expr);
+    Ok(match head.atom() {
+        Some("module") => Command::Module(module(expr)?),
+        Some("register") => Command::Register {
+            name: text(items.first(), expr)?,
+            module: items.get(1).and_then(SExpr::id).map(str::to_string),
+        },
+        Some("invoke" | "get") => Command::Action(action(expr)?),
+        Some("assert_return") => Command::AssertReturn {
+            action: action(first()?)?,
+            expected: items[1..].iter().map(constant).collect::<Result<_>>()?,
+        },
+        Some("assert_trap") if first()?.head() == Some("module") => Command::AssertUninstantiable {
+            module: module(first()?)?,
+            text: message()?,
+        },
+        Some("assert_trap") => Command::AssertTrap {
+            action: action(first()?)?,
+            text: message()?,
+        },
+        Some("assert_exhaustion") => Command::AssertExhaustion {
+            action: action(first()?)?,
+            text: message()?,
+        },
+        Some("assert_invalid") => Command::AssertInvalid {
+            module: module(first()?)?,
+            text: message()?,
+        },
+        Some("assert_malformed") => Command::AssertMalformed {
+            module: module(first()?)?,
+            text: message()?,
+        },
+        Some("assert_unlinkable") => Command::AssertUnlinkable {
+            module: module(first()?)?,
+            text: message()?,
+        },
+        _ => return error(head, "unknown command"),
+    })
+}
+
+/// Parses a whole script into its commands, each with the line it starts on.
+pub fn parse_script(src: &[u8]) -> Result<Vec<(usize, Command)>> {
+    lexer::parse(src)?
+        .iter()
+        .map(|expr| Ok((expr.line(), command(expr)?)))
+        .collect()
+}
+
+/// Parses the text of a single `(module ...)`, for tests that would rather
+/// not spell out the binary.
+#[cfg(test)]
+pub fn text_module(src: &str) -> crate::parser::Module {
+    let bytes = match parse_script(src.as_bytes()).as_deref() {
+        Ok([(_, Command::Module(m))]) => m.bytes.clone().expect("the module assembles"),
+        _ => panic!("expected a single module"),
+    };
+    crate::parser::Module::from_bytes(&bytes).expect("the module parses")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(src: &str) -> Vec<Command> {
+        parse_script(src.as_bytes())
+            .expect("the script parses")
+            .into_iter()
+            .map(|(_, c)| c)
+            .collect()
+    }
+
+    #[test]
+    fn text_binary_and_quoted_modules_assemble_alike() {
+        let script = r#"
+            (module $m (func (export "f") (result i32) (i32.const 7)))
+            (module binary "\00asm" "\01\00\00\00")
+            (module quote "(func (export \"f\") (result i32) (i32.const 7))")
+        "#;
+        let modules = commands(script)
+            .into_iter()
+            .map(|c| match c {
+                Command::Module(m) => m,
+                c => panic!("expected a module, got {c:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(modules[0].name.as_deref(), Some("m"));
+        assert_eq!(modules[1].bytes, Ok(b"\0asm\x01\0\0\0".to_vec()));
+        assert!(modules[0].bytes.is_ok());
+        assert_eq!(modules[0].bytes, modules[2].bytes);
+    }
+
+    #[test]
+    fn quoted_text_that_does_not_assemble_is_kept_as_the_error() {
+        let script = r#"(assert_malformed (module quote "(func (i32.const))") "error")"#;
+        match &commands(script)[..] {
+            [Command::AssertMalformed { module, text }] => {
+                assert!(module.bytes.is_err());
+                assert_eq!(text, "error");
+            }
+            c => panic!("expected assert_malformed, got {c:?}"),
+        }
+    }
+
+    #[test]
+    fn assertions_and_actions() {
+        let script = r#"
+            (register "M" $m)
+            (assert_return (invoke $m "f" (i32.const -1) (f64.const 0x1p-1))
+                (either (f32.const nan:canonical) (ref.null)))
+            (assert_trap (invoke "g") "unreachable")
+            (assert_exhaustion (get "h") "call stack exhausted")
+        "#;
+        let names = commands(script)
+            .iter()
+            .map(Command::name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "register",
+                "assert_return",
+                "assert_trap",
+                "assert_exhaustion"
+            ]
+        );
+        let Some(Command::AssertReturn { action, expected }) = commands(script).into_iter().nth(1)
+        else {
+            panic!("expected assert_return");
+        };
+        match action {
+            Action::Invoke {
+                module,
+                field,
+                args,
+            } => {
+                assert_eq!(module.as_deref(), Some("m"));
+                assert_eq!(field, "f");
+                assert!(matches!(args[..], [Value::I32(-1), Value::F64(v)] if v == 0.5));
+            }
+            a => panic!("expected invoke, got {a:?}"),
+        }
+        assert!(expected[0].matches(&Value::F32(f32::from_bits(0xffc0_0000))));
+        assert!(expected[0].matches(&Value::FuncRef(u32::MAX)));
+        assert!(!expected[0].matches(&Value::F32(f32::from_bits(0x7fa0_0000))));
+    }
+
+    #[test]
+    fn mistakes_are_reported_at_their_line() {
+        let cases = [
+            ("\n(frobnicate)", 2, "unknown command"),
+            (
+                "(invoke \"f\" (i32.const 1.5))",
+                1,
+                "unsupported constant i32.const",
+            ),
+            ("(assert_trap (invoke \"f\"))", 1, "expected a string"),
+            ("(register)", 1, "expected a string"),
+        ];
+        for (src, line, message) in cases {
+            assert_eq!(
+                parse_script(src.as_bytes()).map(|_| ()),
+                Err(WastError::new(line, message)),
+                "{src}"
+            );
+        }
+    }
+}
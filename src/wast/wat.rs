@@ -0,0 +1,1505 @@
+//! Assembles modules written in the text format into the binary format, the
+//! parts of it this interpreter runs: everything up to bulk memory and
+//! reference types, with names, inline imports and exports and folded
+//! instructions.
+
+use super::{lexer::SExpr, WastError};
+use crate::runtime::literal::{parse_f32, parse_f64, parse_i32, parse_i64};
+use std::collections::HashMap;
+
+type Result<T> = std::result::Result<T, WastError>;
+
+/// What follows an opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Imm {
+    None,
+    Label,
+    BrTable,
+    Func,
+    CallIndirect,
+    Local,
+    Global,
+    Table,
+    /// Natural alignment as a power of two.
+    MemArg(u32),
+    Mem,
+    I32,
+    I64,
+    F32,
+    F64,
+    RefNull,
+    Select,
+    MemoryInit,
+    Data,
+    MemoryCopy,
+    TableInit,
+    Elem,
+    TableCopy,
+}
+
+/// Every plain instruction, opcodes after `0xfc` as `0xfcNN`.
+const INSTRS: &[(&str, u16, Imm)] = &[
+    ("unreachable", 0x00, Imm::None),
+    ("nop", 0x01, Imm::None),
+    ("br", 0x0c, Imm::Label),
+    ("br_if", 0x0d, Imm::Label),
+    ("br_table", 0x0e, Imm::BrTable),
+    ("return", 0x0f, Imm::None),
+    ("call", 0x10, Imm::Func),
+    ("call_indirect", 0x11, Imm::CallIndirect),
+    ("drop", 0x1a, Imm::None),
+    ("select", 0x1b, Imm::Select),
+    ("local.get", 0x20, Imm::Local),
+    ("local.set", 0x21, Imm::Local),
+    ("local.tee", 0x22, Imm::Local),
+    ("global.get", 0x23, Imm::Global),
+    ("global.set", 0x24, Imm::Global),
+    ("table.get", 0x25, Imm::Table),
+    ("table.set", 0x26, Imm::Table),
+    ("i32.load", 0x28, Imm::MemArg(2)),
+    ("i64.load", 0x29, Imm::MemArg(3)),
+    ("f32.load", 0x2a, Imm::MemArg(2)),
+    ("f64.load", 0x2b, Imm::MemArg(3)),
+    ("i32.load8_s", 0x2c, Imm::MemArg(0)),
+    ("i32.load8_u", 0x2d, Imm::MemArg(0)),
+    ("i32.load16_s", 0x2e, Imm::MemArg(1)),
+    ("i32.load16_u", 0x2f, Imm::MemArg(1)),
+    ("i64.load8_s", 0x30, Imm::MemArg(0)),
+    ("i64.load8_u", 0x31, Imm::MemArg(0)),
+    ("i64.load16_s", 0x32, Imm::MemArg(1)),
+    ("i64.load16_u", 0x33, Imm::MemArg(1)),
+    ("i64.load32_s", 0x34, Imm::MemArg(2)),
+    ("i64.load32_u", 0x35, Imm::MemArg(2)),
+    ("i32.store", 0x36, Imm::MemArg(2)),
+    ("i64.store", 0x37, Imm::MemArg(3)),
+    ("f32.store", 0x38, Imm::MemArg(2)),
+    ("f64.store", 0x39, Imm::MemArg(3)),
+    ("i32.store8", 0x3a, Imm::MemArg(0)),
+    ("i32.store16", 0x3b, Imm::MemArg(1)),
+    ("i64.store8", 0x3c, Imm::MemArg(0)),
+    ("i64.store16", 0x3d, Imm::MemArg(1)),
+    ("i64.store32", 0x3e, Imm::MemArg(2)),
+    ("memory.size", 0x3f, Imm::Mem),
+    ("memory.grow", 0x40, Imm::Mem),
+    ("i32.const", 0x41, Imm::I32),
+    ("i64.const", 0x42, Imm::I64),
+    ("f32.const", 0x43, Imm::F32),
+    ("f64.const", 0x44, Imm::F64),
+    ("i32.eqz", 0x45, Imm::None),
+    ("i32.eq", 0x46, Imm::None),
+    ("i32.ne", 0x47, Imm::None),
+    ("i32.lt_s", 0x48, Imm::None),
+    ("i32.lt_u", 0x49, Imm::None),
+    ("i32.gt_s", 0x4a, Imm::None),
+    ("i32.gt_u", 0x4b, Imm::None),
+    ("i32.le_s", 0x4c, Imm::None),
+    ("i32.le_u", 0x4d, Imm::None),
+    ("i32.ge_s", 0x4e, Imm::None),
+    ("i32.ge_u", 0x4f, Imm::None),
+    ("i64.eqz", 0x50, Imm::None),
+    ("i64.eq", 0x51, Imm::None),
+    ("i64.ne", 0x52, Imm::None),
+    ("i64.lt_s", 0x53, Imm::None),
+    ("i64.lt_u", 0x54, Imm::None),
+    ("i64.gt_s", 0x55, Imm::None),
+    ("i64.gt_u", 0x56, Imm::None),
+    ("i64.le_s", 0x57, Imm::None),
+    ("i64.le_u", 0x58, Imm::None),
+    ("i64.ge_s", 0x59, Imm::None),
+    ("i64.ge_u", 0x5a, Imm::None),
+    ("f32.eq", 0x5b, Imm::None),
+    ("f32.ne", 0x5c, Imm::None),
+    ("f32.lt", 0x5d, Imm::None),
+    ("f32.gt", 0x5e, Imm::None),
+    ("f32.le", 0x5f, Imm::None),
+    ("f32.ge", 0x60, Imm::None),
+    ("f64.eq", 0x61, Imm::None),
+    ("f64.ne", 0x62, Imm::None),
+    ("f64.lt", 0x63, Imm::None),
+    ("f64.gt", 0x64, Imm::None),
+    ("f64.le", 0x65, Imm::None),
+    ("f64.ge", 0x66, Imm::None),
+    ("i32.clz", 0x67, Imm::None),
+    ("i32.ctz", 0x68, Imm::None),
+    ("i32.popcnt", 0x69, Imm::None),
+    ("i32.add", 0x6a, Imm::None),
+    ("i32.sub", 0x6b, Imm::None),
+    ("i32.mul", 0x6c, Imm::None),
+    ("i32.div_s", 0x6d, Imm::None),
+    ("i32.div_u", 0x6e, Imm::None),
+    ("i32.rem_s", 0x6f, Imm::None),
+    ("i32.rem_u", 0x70, Imm::None),
+    ("i32.and", 0x71, Imm::None),
+    ("i32.or", 0x72, Imm::None),
+    ("i32.xor", 0x73, Imm::None),
+    ("i32.shl", 0x74, Imm::None),
+    ("i32.shr_s", 0x75, Imm::None),
+    ("i32.shr_u", 0x76, Imm::None),
+    ("i32.rotl", 0x77, Imm::None),
+    ("i32.rotr", 0x78, Imm::None),
+    ("i64.clz", 0x79, Imm::None),
+    ("i64.ctz", 0x7a, Imm::None),
+    ("i64.popcnt", 0x7b, Imm::None),
+    ("i64.add", 0x7c, Imm::None),
+    ("i64.sub", 0x7d, Imm::None),
+    ("i64.mul", 0x7e, Imm::None),
+    ("i64.div_s", 0x7f, Imm::None),
+    ("i64.div_u", 0x80, Imm::None),
+    ("i64.rem_s", 0x81, Imm::None),
+    ("i64.rem_u", 0x82, Imm::None),
+    ("i64.and", 0x83, Imm::None),
+    ("i64.or", 0x84, Imm::None),
+    ("i64.xor", 0x85, Imm::None),
+    ("i64.shl", 0x86, Imm::None),
+    ("i64.shr_s", 0x87, Imm::None),
+    ("i64.shr_u", 0x88, Imm::None),
+    ("i64.rotl", 0x89, Imm::None),
+    ("i64.rotr", 0x8a, Imm::None),
+    ("f32.abs", 0x8b, Imm::None),
+    ("f32.neg", 0x8c, Imm::None),
+    ("f32.ceil", 0x8d, Imm::None),
+    ("f32.floor", 0x8e, Imm::None),
+    ("f32.trunc", 0x8f, Imm::None),
+    ("f32.nearest", 0x90, Imm::None),
+    ("f32.sqrt", 0x91, Imm::None),
+    ("f32.add", 0x92, Imm::None),
+    ("f32.sub", 0x93, Imm::None),
+    ("f32.mul", 0x94, Imm::None),
+    ("f32.div", 0x95, Imm::None),
+    ("f32.min", 0x96, Imm::None),
+    ("f32.max", 0x97, Imm::None),
+    ("f32.copysign", 0x98, Imm::None),
+    ("f64.abs", 0x99, Imm::None),
+    ("f64.neg", 0x9a, Imm::None),
+    ("f64.ceil", 0x9b, Imm::None),
+    ("f64.floor", 0x9c, Imm::None),
+    ("f64.trunc", 0x9d, Imm::None),
+    ("f64.nearest", 0x9e, Imm::None),
+    ("f64.sqrt", 0x9f, Imm::None),
+    ("f64.add", 0xa0, Imm::None),
+    ("f64.sub", 0xa1, Imm::None),
+    ("f64.mul", 0xa2, Imm::None),
+    ("f64.div", 0xa3, Imm::None),
+    ("f64.min", 0xa4, Imm::None),
+    ("f64.max", 0xa5, Imm::None),
+    ("f64.copysign", 0xa6, Imm::None),
+    ("i32.wrap_i64", 0xa7, Imm::None),
+    ("i32.trunc_f32_s", 0xa8, Imm::None),
+    ("i32.trunc_f32_u", 0xa9, Imm::None),
+    ("i32.trunc_f64_s", 0xaa, Imm::None),
+    ("i32.trunc_f64_u", 0xab, Imm::None),
+    ("i64.extend_i32_s", 0xac, Imm::None),
+    ("i64.extend_i32_u", 0xad, Imm::None),
+    ("i64.trunc_f32_s", 0xae, Imm::None),
+    ("i64.trunc_f32_u", 0xaf, Imm::None),
+    ("i64.trunc_f64_s", 0xb0, Imm::None),
+    ("i64.trunc_f64_u", 0xb1, Imm::None),
+    ("f32.convert_i32_s", 0xb2, Imm::None),
+    ("f32.convert_i32_u", 0xb3, Imm::None),
+    ("f32.convert_i64_s", 0xb4, Imm::None),
+    ("f32.convert_i64_u", 0xb5, Imm::None),
+    ("f32.demote_f64", 0xb6, Imm::None),
+    ("f64.convert_i32_s", 0xb7, Imm::None),
+    ("f64.convert_i32_u", 0xb8, Imm::None),
+    ("f64.convert_i64_s", 0xb9, Imm::None),
+    ("f64.convert_i64_u", 0xba, Imm::None),
+    ("f64.promote_f32", 0xbb, Imm::None),
+    ("i32.reinterpret_f32", 0xbc, Imm::None),
+    ("i64.reinterpret_f64", 0xbd, Imm::None),
+    ("f32.reinterpret_i32", 0xbe, Imm::None),
+    ("f64.reinterpret_i64", 0xbf, Imm::None),
+    ("i32.extend8_s", 0xc0, Imm::None),
+    ("i32.extend16_s", 0xc1, Imm::None),
+    ("i64.extend8_s", 0xc2, Imm::None),
+    ("i64.extend16_s", 0xc3, Imm::None),
+    ("i64.extend32_s", 0xc4, Imm::None),
+    ("ref.null", 0xd0, Imm::RefNull),
+    ("ref.is_null", 0xd1, Imm::None),
+    ("ref.func", 0xd2, Imm::Func),
+    ("i32.trunc_sat_f32_s", 0xfc00, Imm::None),
+    ("i32.trunc_sat_f32_u", 0xfc01, Imm::None),
+    ("i32.trunc_sat_f64_s", 0xfc02, Imm::None),
+    ("i32.trunc_sat_f64_u", 0xfc03, Imm::None),
+    ("i64.trunc_sat_f32_s", 0xfc04, Imm::None),
+    ("i64.trunc_sat_f32_u", 0xfc05, Imm::None),
+    ("i64.trunc_sat_f64_s", 0xfc06, Imm::None),
+    ("i64.trunc_sat_f64_u", 0xfc07, Imm::None),
+    ("memory.init", 0xfc08, Imm::MemoryInit),
+    ("data.drop", 0xfc09, Imm::Data),
+    ("memory.copy", 0xfc0a, Imm::MemoryCopy),
+    ("memory.fill", 0xfc0b, Imm::Mem),
+    ("table.init", 0xfc0c, Imm::TableInit),
+    ("elem.drop", 0xfc0d, Imm::Elem),
+    ("table.copy", 0xfc0e, Imm::TableCopy),
+    ("table.grow", 0xfc0f, Imm::Table),
+    ("table.size", 0xfc10, Imm::Table),
+    ("table.fill", 0xfc11, Imm::Table),
+];
+
+fn uleb(out: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut v: i64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn bytes(out: &mut Vec<u8>, b: &[u8]) {
+    uleb(out, b.len() as u64);
+    out.extend(b);
+}
+
+fn vec(out: &mut Vec<u8>, items: &[Vec<u8>]) {
+    uleb(out, items.len() as u64);
+    items.iter().for_each(|i| out.extend(i));
+}
+
+fn error<T>(at: &SExpr, message: impl Into<String>) -> Result<T> {
+    Err(WastError::new(at.line(), message))
+}
+
+fn is_index(s: &SExpr) -> bool {
+    s.atom()
+        .is_some_and(|a| a.starts_with('$') || a.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+fn index(s: &SExpr) -> Result<u32> {
+    match s.atom() {
+        Some(a) if a.starts_with(|c: char| c.is_ascii_digit()) => parse_i64(a)
+            .and_then(|i| u32::try_from(i).ok())
+            .map_or_else(|| error(s, format!("malformed index {a}")), Ok),
+        _ => error(s, "expected an index"),
+    }
+}
+
+fn string<'s>(s: Option<&'s SExpr>, at: &SExpr) -> Result<&'s [u8]> {
+    s.and_then(SExpr::string)
+        .map_or_else(|| error(at, "expected a string"), Ok)
+}
+
+fn valtype(s: &SExpr) -> Result<u8> {
+    match (
+        s.atom(),
+        s.form("ref").map(|r| r.iter().map(SExpr::atom).collect()),
+    ) {
+        (Some("i32"), _) => Ok(0x7f),
+        (Some("i64"), _) => Ok(0x7e),
+        (Some("f32"), _) => Ok(0x7d),
+        (Some("f64"), _) => Ok(0x7c),
+        (Some("v128"), _) => Ok(0x7b),
+        (Some("funcref"), _) => Ok(0x70),
+        (Some("externref"), _) => Ok(0x6f),
+        (_, Some::<Vec<_>>(r)) if r == [Some("null"), Some("func")] => Ok(0x70),
+        (_, Some(r)) if r == [Some("null"), Some("extern")] => Ok(0x6f),
+        _ => error(s, "unknown value type"),
+    }
+}
+
+fn heaptype(s: &SExpr) -> Result<u8> {
+    match s.atom() {
+        Some("func") => Ok(0x70),
+        Some("extern") => Ok(0x6f),
+        _ => error(s, "unknown heap type"),
+    }
+}
+
+fn is_reftype(s: &SExpr) -> bool {
+    matches!(s.atom(), Some("funcref" | "externref")) || s.form("ref").is_some()
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Sig {
+    params: Vec<u8>,
+    results: Vec<u8>,
+}
+
+/// One index space with the `$names` in it.
+struct Space {
+    kind: &'static str,
+    names: HashMap<String, u32>,
+    len: u32,
+}
+
+impl Space {
+    fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            names: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    fn define(&mut self, id: Option<&str>, at: &SExpr) -> Result<u32> {
+        let i = self.len;
+        if let Some(id) = id {
+            if self.names.insert(id.to_string(), i).is_some() {
+                return error(at, format!("duplicate {} ${id}", self.kind));
+            }
+        }
+        self.len += 1;
+        Ok(i)
+    }
+
+    fn resolve(&self, s: &SExpr) -> Result<u32> {
+        match s.id() {
+            Some(id) => self
+                .names
+                .get(id)
+                .copied()
+                .map_or_else(|| error(s, format!("unknown {} ${id}", self.kind)), Ok),
+            None => index(s),
+        }
+    }
+}
+
+/// Names in scope while assembling one function body.
+#[derive(Default)]
+struct Body {
+    locals: HashMap<String, u32>,
+    labels: Vec<Option<String>>,
+}
+
+impl Body {
+    fn label(&self, s: &SExpr) -> Result<u32> {
+        match s.id() {
+            Some(id) => self
+                .labels
+                .iter()
+                .rev()
+                .position(|l| l.as_deref() == Some(id))
+                .map_or_else(
+                    || error(s, format!("unknown label ${id}")),
+                    |d| Ok(d as u32),
+                ),
+            None => index(s),
+        }
+    }
+
+    fn local(&self, s: &SExpr) -> Result<u32> {
+        match s.id() {
+            Some(id) => self
+                .locals
+                .get(id)
+                .copied()
+                .map_or_else(|| error(s, format!("unknown local ${id}")), Ok),
+            None => index(s),
+        }
+    }
+}
+
+/// Where the next definition of each kind goes, counted again while emitting.
+#[derive(Default)]
+struct Next {
+    func: u32,
+    table: u32,
+    memory: u32,
+    global: u32,
+    elem: u32,
+    data: u32,
+}
+
+struct Assembler {
+    types: Vec<Sig>,
+    type_names: Space,
+    funcs: Space,
+    tables: Space,
+    mems: Space,
+    globals: Space,
+    elems: Space,
+    datas: Space,
+    next: Next,
+    imports: Vec<Vec<u8>>,
+    functions: Vec<Vec<u8>>,
+    table_defs: Vec<Vec<u8>>,
+    memory_defs: Vec<Vec<u8>>,
+    global_defs: Vec<Vec<u8>>,
+    exports: Vec<Vec<u8>>,
+    start: Option<u32>,
+    elem_defs: Vec<Vec<u8>>,
+    data_defs: Vec<Vec<u8>>,
+    code: Vec<Vec<u8>>,
+    /// `memory.init` and `data.drop` need the data count section.
+    data_count: bool,
+}
+
+/// Assembles the fields of a `(module ...)` into a binary module.
+pub fn assemble(fields: &[SExpr]) -> std::result::Result<Vec<u8>, WastError> {
+    let mut asm = Assembler {
+        types: Vec::new(),
+        type_names: Space::new("type"),
+        funcs: Space::new("function"),
+        tables: Space::new("table"),
+        mems: Space::new("memory"),
+        globals: Space::new("global"),
+        elems: Space::new("elem segment"),
+        datas: Space::new("data segment"),
+        next: Next::default(),
+        imports: Vec::new(),
+        functions: Vec::new(),
+        table_defs: Vec::new(),
+        memory_defs: Vec::new(),
+        global_defs: Vec::new(),
+        exports: Vec::new(),
+        start: None,
+        elem_defs: Vec::new(),
+        data_defs: Vec::new(),
+        code: Vec::new(),
+        data_count: false,
+    };
+    // names can be used before their definition, so they are all collected
+    // before anything is emitted
+    for field in fields {
+        asm.declare(field)?;
+    }
+    for field in fields {
+        asm.field(field)?;
+    }
+    Ok(asm.finish())
+}
+
+impl Assembler {
+    fn declare(&mut self, field: &SExpr) -> Result<()> {
+        let Some([head, rest @ ..]) = field.list() else {
+            return error(field, "expected a module field");
+        };
+        let id = rest.first().and_then(SExpr::id);
+        match head.atom() {
+            Some("type") => {
+                let Some(func) = rest.iter().find_map(|r| r.form("func")) else {
+                    return error(field, "expected a function type");
+                };
+                let mut pos = usize::from(func.first().and_then(SExpr::id).is_some());
+                let (sig, _) = self.sig(func, &mut pos)?;
+                self.type_names.define(id, field)?;
+                self.types.push(sig);
+            }
+            Some("import") => {
+                let Some(desc) = rest.get(2) else {
+                    return error(field, "expected an import description");
+                };
+                let id = desc.list().and_then(|d| d.get(1)).and_then(SExpr::id);
+                match desc.head() {
+                    Some("func") => self.funcs.define(id, desc)?,
+                    Some("table") => self.tables.define(id, desc)?,
+                    Some("memory") => self.mems.define(id, desc)?,
+                    Some("global") => self.globals.define(id, desc)?,
+                    _ => return error(desc, "unknown import kind"),
+                };
+            }
+            Some("func") => {
+                self.funcs.define(id, field)?;
+            }
+            Some("table") => {
+                self.tables.define(id, field)?;
+                if rest.iter().any(|r| r.form("elem").is_some()) {
+                    self.elems.define(None, field)?;
+                }
+            }
+            Some("memory") => {
+                self.mems.define(id, field)?;
+                if rest.iter().any(|r| r.form("data").is_some()) {
+                    self.datas.define(None, field)?;
+                }
+            }
+            Some("global") => {
+                self.globals.define(id, field)?;
+            }
+            Some("elem") => {
+                self.elems.define(id, field)?;
+            }
+            Some("data") => {
+                self.datas.define(id, field)?;
+            }
+            Some("export" | "start") => {}
+            _ => return error(head, "unknown module field"),
+        }
+        Ok(())
+    }
+
+    /// Reads `(param ..)* (result ..)*`, returning the names of the params.
+    fn sig(&self, items: &[SExpr], pos: &mut usize) -> Result<(Sig, Vec<Option<String>>)> {
+        let mut sig = Sig::default();
+        let mut names = Vec::new();
+        while let Some(params) = items.get(*pos).and_then(|p| p.form("param")) {
+            match params {
+                [id, t] if id.id().is_some() => {
+                    names.push(id.id().map(str::to_string));
+                    sig.params.push(valtype(t)?);
+                }
+                _ => {
+                    for t in params {
+                        names.push(None);
+                        sig.params.push(valtype(t)?);
+                    }
+                }
+            }
+            *pos += 1;
+        }
+        while let Some(results) = items.get(*pos).and_then(|p| p.form("result")) {
+            for t in results {
+                sig.results.push(valtype(t)?);
+            }
+            *pos += 1;
+        }
+        Ok((sig, names))
+    }
+
+    fn type_of(&mut self, sig: Sig) -> u32 {
+        match self.types.iter().position(|t| *t == sig) {
+            Some(i) => i as u32,
+            None => {
+                self.types.push(sig);
+                self.types.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Reads `(type x)? (param ..)* (result ..)*`, returning the type index
+    /// and the names of the params.
+    fn typeuse(&mut self, items: &[SExpr], pos: &mut usize) -> Result<(u32, Vec<Option<String>>)> {
+        let explicit = match items.get(*pos).and_then(|t| t.form("type")) {
+            Some([t]) => {
+                *pos += 1;
+                Some((self.type_names.resolve(t)?, t))
+            }
+            _ => None,
+        };
+        let start = *pos;
+        let (sig, names) = self.sig(items, pos)?;
+        match explicit {
+            Some((index, at)) => {
+                let Some(declared) = self.types.get(index as usize) else {
+                    return error(at, format!("unknown type {index}"));
+                };
+                if *pos != start && *declared != sig {
+                    return error(at, "inline function type does not match");
+                }
+                let names = match *pos != start {
+                    true => names,
+                    false => vec![None; declared.params.len()],
+                };
+                Ok((index, names))
+            }
+            None => Ok((self.type_of(sig), names)),
+        }
+    }
+
+    /// Reads a block type: `(type x)` or params and results, inline when
+    /// there is at most one result.
+    fn blocktype(&mut self, items: &[SExpr], pos: &mut usize, out: &mut Vec<u8>) -> Result<()> {
+        if items.get(*pos).and_then(|t| t.form("type")).is_some() {
+            let (index, _) = self.typeuse(items, pos)?;
+            sleb(out, index as i64);
+            return Ok(());
+        }
+        let (sig, _) = self.sig(items, pos)?;
+        match (&*sig.params, &*sig.results) {
+            ([], []) => out.push(0x40),
+            ([], [t]) => out.push(*t),
+            _ => {
+                let index = self.type_of(sig);
+                sleb(out, index as i64);
+            }
+        }
+        Ok(())
+    }
+
+    fn limits(&self, items: &[SExpr], pos: &mut usize, out: &mut Vec<u8>) -> Result<()> {
+        let Some(min) = items.get(*pos) else {
+            return Err(WastError::new(0, "expected limits"));
+        };
+        let min = index(min)?;
+        *pos += 1;
+        match items.get(*pos).filter(|m| is_index(m)) {
+            Some(max) => {
+                out.push(0x01);
+                uleb(out, min as u64);
+                uleb(out, index(max)? as u64);
+                *pos += 1;
+            }
+            None => {
+                out.push(0x00);
+                uleb(out, min as u64);
+            }
+        }
+        Ok(())
+    }
+
+    fn globaltype(&self, s: &SExpr, out: &mut Vec<u8>) -> Result<()> {
+        match s.form("mut") {
+            Some([t]) => {
+                out.push(valtype(t)?);
+                out.push(0x01);
+            }
+            _ => {
+                out.push(valtype(s)?);
+                out.push(0x00);
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips the `$id` of a definition and reads its inline exports, giving
+    /// back the inline import if there is one.
+    fn header<'s>(
+        &mut self,
+        items: &'s [SExpr],
+        pos: &mut usize,
+        kind: u8,
+        index: u32,
+    ) -> Result<Option<(&'s [u8], &'s [u8])>> {
+        if items.get(*pos).and_then(SExpr::id).is_some() {
+            *pos += 1;
+        }
+        while let Some(export) = items.get(*pos).and_then(|e| e.form("export")) {
+            let mut out = Vec::new();
+            bytes(&mut out, string(export.first(), &items[*pos])?);
+            out.push(kind);
+            uleb(&mut out, index as u64);
+            self.exports.push(out);
+            *pos += 1;
+        }
+        match items.get(*pos).and_then(|i| i.form("import")) {
+            Some([module, name]) => {
+                let at = &items[*pos];
+                *pos += 1;
+                Ok(Some((string(Some(module), at)?, string(Some(name), at)?)))
+            }
+            Some(_) => error(&items[*pos], "malformed import"),
+            None => Ok(None),
+        }
+    }
+
+    fn import(&mut self, module: &[u8], name: &[u8], desc: Vec<u8>) {
+        let mut out = Vec::new();
+        bytes(&mut out, module);
+        bytes(&mut out, name);
+        out.extend(desc);
+        self.imports.push(out);
+    }
+
+    /// A constant expression: `(offset instr*)`, `(item instr*)` or a single
+    /// folded instruction.
+    fn const_expr(&mut self, s: &SExpr) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut body = Body::default();
+        match s.form("offset").or_else(|| s.form("item")) {
+            Some(instrs) => {
+                let mut pos = 0;
+                self.instrs(&mut body, instrs, &mut pos, &mut out)?;
+                if pos != instrs.len() {
+                    return error(s, "unexpected end");
+                }
+            }
+            None => self.folded(&mut body, s, &mut out)?,
+        }
+        out.push(0x0b);
+        Ok(out)
+    }
+
+    fn field(&mut self, field: &SExpr) -> Result<()> {
+        let Some([head, items @ ..]) = field.list() else {
+            return error(field, "expected a module field");
+        };
+        match head.atom() {
+            Some("import") => {
+                let module = string(items.first(), field)?;
+                let name = string(items.get(1), field)?;
+                let Some([kind, desc @ ..]) = items.get(2).and_then(SExpr::list) else {
+                    return error(field, "expected an import description");
+                };
+                let mut pos = usize::from(desc.first().and_then(SExpr::id).is_some());
+                let mut out = Vec::new();
+                match kind.atom() {
+                    Some("func") => {
+                        self.next.func += 1;
+                        out.push(0x00);
+                        let (index, _) = self.typeuse(desc, &mut pos)?;
+                        uleb(&mut out, index as u64);
+                    }
+                    Some("table") => {
+                        self.next.table += 1;
+                        out.push(0x01);
+                        self.tabletype(desc, &mut pos, &mut out, field)?;
+                    }
+                    Some("memory") => {
+                        self.next.memory += 1;
+                        out.push(0x02);
+                        self.limits(desc, &mut pos, &mut out)?;
+                    }
+                    Some("global") => {
+                        self.next.global += 1;
+                        out.push(0x03);
+                        let Some(t) = desc.get(pos) else {
+                            return error(field, "expected a global type");
+                        };
+                        self.globaltype(t, &mut out)?;
+                    }
+                    _ => return error(kind, "unknown import kind"),
+                }
+                self.import(module, name, out);
+            }
+            Some("func") => self.func(field, items)?,
+            Some("table") => self.table(field, items)?,
+            Some("memory") => self.memory(field, items)?,
+            Some("global") => {
+                let index = self.next.global;
+                self.next.global += 1;
+                let mut pos = 0;
+                let import = self.header(items, &mut pos, 0x03, index)?;
+                let Some(t) = items.get(pos) else {
+                    return error(field, "expected a global type");
+                };
+                let mut out = Vec::new();
+                self.globaltype(t, &mut out)?;
+                pos += 1;
+                match import {
+                    Some((module, name)) => {
+                        let mut desc = vec![0x03];
+                        desc.extend(out);
+                        self.import(module, name, desc);
+                    }
+                    None => {
+                        let mut body = Body::default();
+                        self.instrs(&mut body, items, &mut pos, &mut out)?;
+                        out.push(0x0b);
+                        self.global_defs.push(out);
+                    }
+                }
+            }
+            Some("export") => {
+                let name = string(items.first(), field)?;
+                let Some([kind, index]) = items.get(1).and_then(SExpr::list) else {
+                    return error(field, "malformed export");
+                };
+                let (kind, index) = match kind.atom() {
+                    Some("func") => (0x00, self.funcs.resolve(index)?),
+                    Some("table") => (0x01, self.tables.resolve(index)?),
+                    Some("memory") => (0x02, self.mems.resolve(index)?),
+                    Some("global") => (0x03, self.globals.resolve(index)?),
+                    _ => return error(kind, "unknown export kind"),
+                };
+                let mut out = Vec::new();
+                bytes(&mut out, name);
+                out.push(kind);
+                uleb(&mut out, index as u64);
+                self.exports.push(out);
+            }
+            Some("start") => {
+                let Some(func) = items.first() else {
+                    return error(field, "expected a function");
+                };
+                if self.start.is_some() {
+                    return error(field, "multiple start sections");
+                }
+                self.start = Some(self.funcs.resolve(func)?);
+            }
+            Some("elem") => self.elem(field, items)?,
+            Some("data") => self.data(field, items)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn func(&mut self, field: &SExpr, items: &[SExpr]) -> Result<()> {
+        let index = self.next.func;
+        self.next.func += 1;
+        let mut pos = 0;
+        let import = self.header(items, &mut pos, 0x00, index)?;
+        let (ty, names) = self.typeuse(items, &mut pos)?;
+        if let Some((module, name)) = import {
+            let mut desc = vec![0x00];
+            uleb(&mut desc, ty as u64);
+            self.import(module, name, desc);
+            return Ok(());
+        }
+        let mut out = Vec::new();
+        uleb(&mut out, ty as u64);
+        self.functions.push(out);
+
+        let mut body = Body::default();
+        let mut count = 0;
+        for name in names {
+            if let Some(name) = name {
+                if body.locals.insert(name.clone(), count).is_some() {
+                    return error(field, format!("duplicate local ${name}"));
+                }
+            }
+            count += 1;
+        }
+        let mut locals: Vec<(u32, u8)> = Vec::new();
+        while let Some(decl) = items.get(pos).and_then(|l| l.form("local")) {
+            let types = match decl {
+                [id, t] if id.id().is_some() => {
+                    let name = id.id().unwrap_or_default().to_string();
+                    if body.locals.insert(name.clone(), count).is_some() {
+                        return error(id, format!("duplicate local ${name}"));
+                    }
+                    std::slice::from_ref(t)
+                }
+                types => types,
+            };
+            for t in types {
+                let t = valtype(t)?;
+                match locals.last_mut() {
+                    Some((n, last)) if *last == t => *n += 1,
+                    _ => locals.push((1, t)),
+                }
+                count += 1;
+            }
+            pos += 1;
+        }
+
+        let mut code = Vec::new();
+        uleb(&mut code, locals.len() as u64);
+        for (n, t) in locals {
+            uleb(&mut code, n as u64);
+            code.push(t);
+        }
+        match self.instrs(&mut body, items, &mut pos, &mut code)? {
+            None => code.push(0x0b),
+            Some(_) => return error(&items[pos], "unexpected end"),
+        }
+        let mut out = Vec::new();
+        bytes(&mut out, &code);
+        self.code.push(out);
+        Ok(())
+    }
+
+    fn tabletype(
+        &self,
+        items: &[SExpr],
+        pos: &mut usize,
+        out: &mut Vec<u8>,
+        at: &SExpr,
+    ) -> Result<()> {
+        let mut limits = Vec::new();
+        self.limits(items, pos, &mut limits)?;
+        let Some(t) = items.get(*pos) else {
+            return error(at, "expected a reference type");
+        };
+        out.push(valtype(t)?);
+        out.extend(limits);
+        *pos += 1;
+        Ok(())
+    }
+
+    fn table(&mut self, field: &SExpr, items: &[SExpr]) -> Result<()> {
+        let index = self.next.table;
+        self.next.table += 1;
+        let mut pos = 0;
+        let import = self.header(items, &mut pos, 0x01, index)?;
+
+        // `(table funcref (elem ...))` sizes the table to fit its elements
+        if let Some(elems) = items.get(pos + 1).and_then(|e| e.form("elem")) {
+            let t = valtype(&items[pos])?;
+            let n = elems.len() as u64;
+            let mut out = vec![t, 0x01];
+            uleb(&mut out, n);
+            uleb(&mut out, n);
+            self.table_defs.push(out);
+
+            self.next.elem += 1;
+            let mut offset = vec![0x41, 0x00, 0x0b];
+            let mut out = Vec::new();
+            if elems.iter().all(is_index) {
+                out.push(0x02);
+                uleb(&mut out, index as u64);
+                out.append(&mut offset);
+                out.push(0x00);
+                let funcs = elems
+                    .iter()
+                    .map(|f| self.func_index(f))
+                    .collect::<Result<Vec<_>>>()?;
+                vec(&mut out, &funcs);
+            } else {
+                out.push(0x06);
+                uleb(&mut out, index as u64);
+                out.append(&mut offset);
+                out.push(t);
+                let exprs = elems
+                    .iter()
+                    .map(|e| self.const_expr(e))
+                    .collect::<Result<Vec<_>>>()?;
+                vec(&mut out, &exprs);
+            }
+            self.elem_defs.push(out);
+            return Ok(());
+        }
+
+        let mut out = Vec::new();
+        self.tabletype(items, &mut pos, &mut out, field)?;
+        match import {
+            Some((module, name)) => {
+                let mut desc = vec![0x01];
+                desc.extend(out);
+                self.import(module, name, desc);
+            }
+            None => self.table_defs.push(out),
+        }
+        Ok(())
+    }
+
+    fn func_index(&self, f: &SExpr) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        uleb(&mut out, self.funcs.resolve(f)? as u64);
+        Ok(out)
+    }
+
+    fn memory(&mut self, field: &SExpr, items: &[SExpr]) -> Result<()> {
+        let index = self.next.memory;
+        self.next.memory += 1;
+        let mut pos = 0;
+        let import = self.header(items, &mut pos, 0x02, index)?;
+
+        // `(memory (data ...))` sizes the memory to fit its data
+        if let Some(data) = items.get(pos).and_then(|d| d.form("data")) {
+            let data = data
+                .iter()
+                .map(|d| string(Some(d), field))
+                .collect::<Result<Vec<_>>>()?
+                .concat();
+            let pages = data.len().div_ceil(65536) as u64;
+            let mut out = vec![0x01];
+            uleb(&mut out, pages);
+            uleb(&mut out, pages);
+            self.memory_defs.push(out);
+
+            self.next.data += 1;
+            let mut out = vec![0x02];
+            uleb(&mut out, index as u64);
+            out.extend([0x41, 0x00, 0x0b]);
+            bytes(&mut out, &data);
+            self.data_defs.push(out);
+            return Ok(());
+        }
+
+        let mut out = Vec::new();
+        self.limits(items, &mut pos, &mut out)?;
+        match import {
+            Some((module, name)) => {
+                let mut desc = vec![0x02];
+                desc.extend(out);
+                self.import(module, name, desc);
+            }
+            None => self.memory_defs.push(out),
+        }
+        Ok(())
+    }
+
+    fn elem(&mut self, field: &SExpr, items: &[SExpr]) -> Result<()> {
+        self.next.elem += 1;
+        let mut pos = usize::from(items.first().and_then(SExpr::id).is_some());
+
+        // active, passive or declarative, and into which table
+        let mut declare = false;
+        let mut table = None;
+        let mut offset = None;
+        if items.get(pos).and_then(SExpr::atom) == Some("declare") {
+            declare = true;
+            pos += 1;
+        }
+        if let Some([t]) = items.get(pos).and_then(|t| t.form("table")) {
+            table = Some(self.tables.resolve(t)?);
+            pos += 1;
+        }
+        if let Some(o) = items
+            .get(pos)
+            .filter(|o| o.list().is_some() && !is_reftype(o))
+        {
+            offset = Some(self.const_expr(o)?);
+            pos += 1;
+        }
+
+        // the elements, function indices or expressions
+        let (reftype, funcs) = match items.get(pos) {
+            Some(f) if f.atom() == Some("func") => (None, true),
+            Some(t) if is_reftype(t) => (Some(valtype(t)?), false),
+            _ => (None, true),
+        };
+        if reftype.is_some() || items.get(pos).and_then(SExpr::atom) == Some("func") {
+            pos += 1;
+        }
+        let elems = &items[pos.min(items.len())..];
+        let list = match funcs {
+            true => elems
+                .iter()
+                .map(|f| self.func_index(f))
+                .collect::<Result<Vec<_>>>()?,
+            false => elems
+                .iter()
+                .map(|e| self.const_expr(e))
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let reftype = reftype.unwrap_or(0x70);
+
+        let mut out = Vec::new();
+        let kind = |out: &mut Vec<u8>| match funcs {
+            true => out.push(0x00),
+            false => out.push(reftype),
+        };
+        let exprs = if funcs { 0 } else { 4 };
+        match (offset, table, declare) {
+            (Some(offset), None | Some(0), _) if funcs || reftype == 0x70 => {
+                out.push(exprs);
+                out.extend(offset);
+            }
+            (Some(offset), table, _) => {
+                out.push(exprs | 2);
+                uleb(&mut out, table.unwrap_or_default() as u64);
+                out.extend(offset);
+                kind(&mut out);
+            }
+            (None, Some(_), _) => return error(field, "expected an offset"),
+            (None, None, false) => {
+                out.push(exprs | 1);
+                kind(&mut out);
+            }
+            (None, None, true) => {
+                out.push(exprs | 3);
+                kind(&mut out);
+            }
+        }
+        vec(&mut out, &list);
+        self.elem_defs.push(out);
+        Ok(())
+    }
+
+    fn data(&mut self, field: &SExpr, items: &[SExpr]) -> Result<()> {
+        self.next.data += 1;
+        let mut pos = usize::from(items.first().and_then(SExpr::id).is_some());
+        let mut memory = None;
+        if let Some([m]) = items.get(pos).and_then(|m| m.form("memory")) {
+            memory = Some(self.mems.resolve(m)?);
+            pos += 1;
+        }
+        let mut out = Vec::new();
+        match items.get(pos).filter(|o| o.list().is_some()) {
+            Some(offset) => {
+                let offset = self.const_expr(offset)?;
+                pos += 1;
+                match memory {
+                    None | Some(0) => out.push(0x00),
+                    Some(m) => {
+                        out.push(0x02);
+                        uleb(&mut out, m as u64);
+                    }
+                }
+                out.extend(offset);
+            }
+            None if memory.is_some() => return error(field, "expected an offset"),
+            None => out.push(0x01),
+        }
+        let data = items[pos..]
+            .iter()
+            .map(|d| string(Some(d), field))
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+        bytes(&mut out, &data);
+        self.data_defs.push(out);
+        Ok(())
+    }
+
+    /// Instructions in linear form, with folded ones mixed in. Stops at the
+    /// end of `items` or at an `end` or `else`, which it returns.
+    fn instrs(
+        &mut self,
+        body: &mut Body,
+        items: &[SExpr],
+        pos: &mut usize,
+        out: &mut Vec<u8>,
+    ) -> Result<Option<String>> {
+        while let Some(item) = items.get(*pos) {
+            let Some(op) = item.atom() else {
+                if item.list().is_none() {
+                    return error(item, "unexpected string");
+                }
+                *pos += 1;
+                self.folded(body, item, out)?;
+                continue;
+            };
+            if matches!(op, "end" | "else") {
+                return Ok(Some(op.to_string()));
+            }
+            *pos += 1;
+            match op {
+                "block" | "loop" | "if" => {
+                    let label = items.get(*pos).and_then(SExpr::id).map(str::to_string);
+                    if label.is_some() {
+                        *pos += 1;
+                    }
+                    out.push(match op {
+                        "block" => 0x02,
+                        "loop" => 0x03,
+                        _ => 0x04,
+                    });
+                    self.blocktype(items, pos, out)?;
+                    body.labels.push(label);
+                    let mut end = self.instrs(body, items, pos, out)?;
+                    if op == "if" && end.as_deref() == Some("else") {
+                        *pos += 1;
+                        self.end_label(body, items, pos)?;
+                        out.push(0x05);
+                        end = self.instrs(body, items, pos, out)?;
+                    }
+                    if end.as_deref() != Some("end") {
+                        return error(item, "expected end");
+                    }
+                    *pos += 1;
+                    self.end_label(body, items, pos)?;
+                    body.labels.pop();
+                    out.push(0x0b);
+                }
+                _ => self.plain(body, op, item, items, pos, out)?,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Skips the optional label after `end` or `else`, which has to match
+    /// the block's.
+    fn end_label(&self, body: &Body, items: &[SExpr], pos: &mut usize) -> Result<()> {
+        if let Some(id) = items.get(*pos).and_then(SExpr::id) {
+            if body.labels.last().and_then(Option::as_deref) != Some(id) {
+                return error(&items[*pos], "mismatching label");
+            }
+            *pos += 1;
+        }
+        Ok(())
+    }
+
+    /// One instruction in folded form, its operands emitted first.
+    fn folded(&mut self, body: &mut Body, expr: &SExpr, out: &mut Vec<u8>) -> Result<()> {
+        let Some([head, items @ ..]) = expr.list() else {
+            return error(expr, "expected an instruction");
+        };
+        let Some(op) = head.atom() else {
+            return error(head, "expected an instruction");
+        };
+        let mut pos = 0;
+        match op {
+            "block" | "loop" | "if" => {
+                let label = items.first().and_then(SExpr::id).map(str::to_string);
+                if label.is_some() {
+                    pos += 1;
+                }
+                let mut bt = Vec::new();
+                self.blocktype(items, &mut pos, &mut bt)?;
+                if op == "if" {
+                    while let Some(cond) = items
+                        .get(pos)
+                        .filter(|c| !matches!(c.head(), Some("then" | "else")))
+                    {
+                        self.folded(body, cond, out)?;
+                        pos += 1;
+                    }
+                }
+                out.push(match op {
+                    "block" => 0x02,
+                    "loop" => 0x03,
+                    _ => 0x04,
+                });
+                out.extend(bt);
+                body.labels.push(label);
+                if op == "if" {
+                    let Some(then) = items.get(pos).and_then(|t| t.form("then")) else {
+                        return error(expr, "expected then");
+                    };
+                    self.arm(body, then, expr, out)?;
+                    if let Some(els) = items.get(pos + 1).and_then(|e| e.form("else")) {
+                        out.push(0x05);
+                        self.arm(body, els, expr, out)?;
+                        pos += 1;
+                    }
+                    pos += 1;
+                } else {
+                    if self.instrs(body, items, &mut pos, out)?.is_some() {
+                        return error(expr, "unexpected end");
+                    }
+                }
+                if pos != items.len() {
+                    return error(expr, "unexpected token");
+                }
+                body.labels.pop();
+                out.push(0x0b);
+            }
+            _ => {
+                let mut imm = Vec::new();
+                self.plain(body, op, head, items, &mut pos, &mut imm)?;
+                for operand in &items[pos..] {
+                    self.folded(body, operand, out)?;
+                }
+                out.extend(imm);
+            }
+        }
+        Ok(())
+    }
+
+    fn arm(
+        &mut self,
+        body: &mut Body,
+        instrs: &[SExpr],
+        at: &SExpr,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let mut pos = 0;
+        if self.instrs(body, instrs, &mut pos, out)?.is_some() {
+            return error(at, "unexpected end");
+        }
+        Ok(())
+    }
+
+    /// An instruction without a body, its immediates read from
+    /// `items[pos..]`.
+    fn plain(
+        &mut self,
+        body: &Body,
+        op: &str,
+        at: &SExpr,
+        items: &[SExpr],
+        pos: &mut usize,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let Some(&(_, code, imm)) = INSTRS.iter().find(|(name, ..)| *name == op) else {
+            return error(at, format!("unknown operator {op}"));
+        };
+        if code > 0xff {
+            out.push(0xfc);
+            uleb(out, (code & 0xff) as u64);
+        } else {
+            out.push(code as u8);
+        }
+
+        let mut next = |what: &str| match items.get(*pos) {
+            Some(s) => {
+                *pos += 1;
+                Ok(s)
+            }
+            None => error(at, format!("expected {what}")),
+        };
+        let indices = |pos: &mut usize| {
+            let start = *pos;
+            while items.get(*pos).is_some_and(is_index) {
+                *pos += 1;
+            }
+            &items[start..*pos]
+        };
+
+        match imm {
+            Imm::None => {}
+            Imm::Label => uleb(out, body.label(next("a label")?)? as u64),
+            Imm::BrTable => {
+                let labels = indices(pos);
+                let Some((default, labels)) = labels.split_last() else {
+                    return error(at, "expected a label");
+                };
+                uleb(out, labels.len() as u64);
+                for l in labels {
+                    uleb(out, body.label(l)? as u64);
+                }
+                uleb(out, body.label(default)? as u64);
+            }
+            Imm::Func => uleb(out, self.funcs.resolve(next("a function")?)? as u64),
+            Imm::CallIndirect => {
+                let table = match indices(pos) {
+                    [] => 0,
+                    [t] => self.tables.resolve(t)?,
+                    _ => return error(at, "unexpected index"),
+                };
+                let (ty, _) = self.typeuse(items, pos)?;
+                uleb(out, ty as u64);
+                uleb(out, table as u64);
+            }
+            Imm::Local => uleb(out, body.local(next("a local")?)? as u64),
+            Imm::Global => uleb(out, self.globals.resolve(next("a global")?)? as u64),
+            Imm::Table => {
+                let table = match indices(pos) {
+                    [] => 0,
+                    [t] => self.tables.resolve(t)?,
+                    _ => return error(at, "unexpected index"),
+                };
+                uleb(out, table as u64);
+            }
+            Imm::MemArg(natural) => {
+                let (mut offset, mut align) = (0, 1 << natural);
+                while let Some(arg) = items.get(*pos).and_then(SExpr::atom) {
+                    let value = |v: &str| {
+                        parse_i64(v)
+                            .filter(|_| v.starts_with(|c: char| c.is_ascii_digit()))
+                            .and_then(|v| u32::try_from(v).ok())
+                            .map_or_else(|| error(at, "malformed memory argument"), Ok)
+                    };
+                    if let Some(v) = arg.strip_prefix("offset=") {
+                        offset = value(v)?;
+                    } else if let Some(v) = arg.strip_prefix("align=") {
+                        align = value(v)?;
+                        if !align.is_power_of_two() {
+                            return error(at, "alignment must be a power of two");
+                        }
+                    } else {
+                        break;
+                    }
+                    *pos += 1;
+                }
+                uleb(out, align.trailing_zeros() as u64);
+                uleb(out, offset as u64);
+            }
+            Imm::Mem => out.push(0x00),
+            Imm::I32 => {
+                let s = next("a number")?;
+                let v = s.atom().and_then(parse_i32);
+                let Some(v) = v else {
+                    return error(s, "malformed i32 constant");
+                };
+                sleb(out, v as i64);
+            }
+            Imm::I64 => {
+                let s = next("a number")?;
+                let Some(v) = s.atom().and_then(parse_i64) else {
+                    return error(s, "malformed i64 constant");
+                };
+                sleb(out, v);
+            }
+            Imm::F32 => {
+                let s = next("a number")?;
+                let Some(v) = s.atom().and_then(parse_f32) else {
+                    return error(s, "malformed f32 constant");
+                };
+                out.extend(v.to_bits().to_le_bytes());
+            }
+            Imm::F64 => {
+                let s = next("a number")?;
+                let Some(v) = s.atom().and_then(parse_f64) else {
+                    return error(s, "malformed f64 constant");
+                };
+                out.extend(v.to_bits().to_le_bytes());
+            }
+            Imm::RefNull => out.push(heaptype(next("a heap type")?)?),
+            Imm::Select => {
+                let (sig, _) = self.sig(items, pos)?;
+                if !sig.params.is_empty() {
+                    return error(at, "unexpected param");
+                }
+                if !sig.results.is_empty() {
+                    out.pop();
+                    out.push(0x1c);
+                    bytes(out, &sig.results);
+                }
+            }
+            Imm::MemoryInit => {
+                let data = match indices(pos) {
+                    [d] | [_, d] => self.datas.resolve(d)?,
+                    _ => return error(at, "expected a data segment"),
+                };
+                uleb(out, data as u64);
+                out.push(0x00);
+                self.data_count = true;
+            }
+            Imm::Data => {
+                uleb(out, self.datas.resolve(next("a data segment")?)? as u64);
+                self.data_count = true;
+            }
+            Imm::MemoryCopy => {
+                indices(pos);
+                out.extend([0x00, 0x00]);
+            }
+            Imm::TableInit => {
+                let (table, elem) = match indices(pos) {
+                    [e] => (0, self.elems.resolve(e)?),
+                    [t, e] => (self.tables.resolve(t)?, self.elems.resolve(e)?),
+                    _ => return error(at, "expected an elem segment"),
+                };
+                uleb(out, elem as u64);
+                uleb(out, table as u64);
+            }
+            Imm::Elem => uleb(out, self.elems.resolve(next("an elem segment")?)? as u64),
+            Imm::TableCopy => {
+                let (dst, src) = match indices(pos) {
+                    [] => (0, 0),
+                    [d, s] => (self.tables.resolve(d)?, self.tables.resolve(s)?),
+                    _ => return error(at, "expected two tables"),
+                };
+                uleb(out, dst as u64);
+                uleb(out, src as u64);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        let mut section = |id: u8, body: Vec<u8>| {
+            module.push(id);
+            bytes(&mut module, &body);
+        };
+
+        let types = self
+            .types
+            .iter()
+            .map(|t| {
+                let mut out = vec![0x60];
+                bytes(&mut out, &t.params);
+                bytes(&mut out, &t.results);
+                out
+            })
+            .collect::<Vec<_>>();
+        let mut sections = vec![
+            (1, types),
+            (2, self.imports),
+            (3, self.functions),
+            (4, self.table_defs),
+            (5, self.memory_defs),
+            (6, self.global_defs),
+            (7, self.exports),
+        ];
+        for (id, items) in sections.drain(..) {
+            if !items.is_empty() {
+                let mut out = Vec::new();
+                vec(&mut out, &items);
+                section(id, out);
+            }
+        }
+        if let Some(start) = self.start {
+            let mut out = Vec::new();
+            uleb(&mut out, start as u64);
+            section(8, out);
+        }
+        if !self.elem_defs.is_empty() {
+            let mut out = Vec::new();
+            vec(&mut out, &self.elem_defs);
+            section(9, out);
+        }
+        if self.data_count {
+            let mut out = Vec::new();
+            uleb(&mut out, self.data_defs.len() as u64);
+            section(12, out);
+        }
+        if !self.code.is_empty() {
+            let mut out = Vec::new();
+            vec(&mut out, &self.code);
+            section(10, out);
+        }
+        if !self.data_defs.is_empty() {
+            let mut out = Vec::new();
+            vec(&mut out, &self.data_defs);
+            section(11, out);
+        }
+        module
+    }
+}